@@ -0,0 +1,12 @@
+-- NULL until versioning is first configured, then 'Enabled' or 'Suspended'
+ALTER TABLE buckets ADD COLUMN IF NOT EXISTS versioning VARCHAR(16);
+
+-- Every version of an object is a row; unversioned objects use the version ID 'null'
+ALTER TABLE files ADD COLUMN IF NOT EXISTS version_id VARCHAR(64) NOT NULL DEFAULT 'null';
+ALTER TABLE files ADD COLUMN IF NOT EXISTS is_latest BOOLEAN NOT NULL DEFAULT TRUE;
+ALTER TABLE files ADD COLUMN IF NOT EXISTS is_delete_marker BOOLEAN NOT NULL DEFAULT FALSE;
+
+ALTER TABLE files DROP CONSTRAINT IF EXISTS files_filename_bucket_id_key;
+
+CREATE UNIQUE INDEX IF NOT EXISTS idx_files_version ON files(bucket_id, filename, version_id);
+CREATE UNIQUE INDEX IF NOT EXISTS idx_files_latest ON files(bucket_id, filename) WHERE is_latest;
@@ -0,0 +1,2 @@
+-- Listing walks keys in byte order within a bucket
+CREATE INDEX IF NOT EXISTS idx_files_bucket_filename ON files(bucket_id, filename COLLATE "C");
@@ -0,0 +1,2 @@
+-- SHA-256 of the object content; NULL for delete markers and older rows
+ALTER TABLE files ADD COLUMN IF NOT EXISTS etag VARCHAR(64);
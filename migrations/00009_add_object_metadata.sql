@@ -0,0 +1,3 @@
+-- User metadata and content headers given when the object was uploaded
+ALTER TABLE files ADD COLUMN IF NOT EXISTS metadata JSONB NOT NULL DEFAULT '{}';
+ALTER TABLE multipart_uploads ADD COLUMN IF NOT EXISTS metadata JSONB NOT NULL DEFAULT '{}';
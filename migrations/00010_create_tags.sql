@@ -0,0 +1,14 @@
+-- Tags on one version of an object
+CREATE TABLE IF NOT EXISTS file_tags (
+    file_id UUID NOT NULL REFERENCES files(id) ON DELETE CASCADE,
+    key VARCHAR(128) NOT NULL,
+    value VARCHAR(256) NOT NULL,
+    PRIMARY KEY (file_id, key)
+    );
+
+CREATE TABLE IF NOT EXISTS bucket_tags (
+    bucket_id UUID NOT NULL REFERENCES buckets(id) ON DELETE CASCADE,
+    key VARCHAR(128) NOT NULL,
+    value VARCHAR(256) NOT NULL,
+    PRIMARY KEY (bucket_id, key)
+    );
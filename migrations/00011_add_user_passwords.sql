@@ -0,0 +1,3 @@
+-- Argon2id password hashes in PHC string format. Users registered before
+-- passwords existed have none and must set one before they can log in.
+ALTER TABLE users ADD COLUMN IF NOT EXISTS password_hash VARCHAR(255);
@@ -0,0 +1,22 @@
+-- API keys are stored as a SHA-256 hash of the full key. The prefix is the
+-- key's first characters, kept in the clear to find the row for a key and
+-- to let users tell their keys apart.
+CREATE TABLE IF NOT EXISTS api_keys (
+    id UUID PRIMARY KEY,
+    user_id UUID NOT NULL REFERENCES users(id) ON DELETE CASCADE,
+    name VARCHAR(255) NOT NULL,
+    prefix VARCHAR(16) UNIQUE NOT NULL,
+    key_hash VARCHAR(64) NOT NULL,
+    created_at TIMESTAMPTZ NOT NULL DEFAULT NOW(),
+    last_used_at TIMESTAMPTZ,
+    expires_at TIMESTAMPTZ
+);
+
+CREATE INDEX IF NOT EXISTS idx_api_keys_user_id ON api_keys(user_id);
+
+-- Carry over each user's existing key, then drop the plaintext column
+INSERT INTO api_keys (id, user_id, name, prefix, key_hash, created_at)
+SELECT gen_random_uuid(), id, 'default', LEFT(api_key, 12), encode(sha256(convert_to(api_key, 'UTF8')), 'hex'), created_at
+FROM users;
+
+ALTER TABLE users DROP COLUMN api_key;
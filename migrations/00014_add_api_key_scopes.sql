@@ -0,0 +1,3 @@
+-- Limits on what a key can do: buckets, key prefixes and actions. Keys
+-- without a scope keep their user's full access.
+ALTER TABLE api_keys ADD COLUMN IF NOT EXISTS scope JSONB;
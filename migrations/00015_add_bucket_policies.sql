@@ -0,0 +1,2 @@
+-- Bucket policy document as set by the owner; NULL when the bucket has none
+ALTER TABLE buckets ADD COLUMN IF NOT EXISTS policy JSONB;
@@ -0,0 +1,15 @@
+-- Access to a bucket given by its owner to another user
+CREATE TABLE IF NOT EXISTS bucket_grants (
+    bucket_id UUID NOT NULL REFERENCES buckets(id) ON DELETE CASCADE,
+    user_id UUID NOT NULL REFERENCES users(id) ON DELETE CASCADE,
+    -- 'read' or 'read-write'
+    permission VARCHAR(16) NOT NULL,
+    created_at TIMESTAMPTZ NOT NULL DEFAULT NOW(),
+    PRIMARY KEY (bucket_id, user_id)
+    );
+
+CREATE INDEX IF NOT EXISTS idx_bucket_grants_user_id ON bucket_grants(user_id);
+
+-- Canned ACL of the bucket: 'private' or 'public-read', which lets anyone
+-- read its objects without credentials
+ALTER TABLE buckets ADD COLUMN IF NOT EXISTS acl VARCHAR(16) NOT NULL DEFAULT 'private';
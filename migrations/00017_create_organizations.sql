@@ -0,0 +1,25 @@
+-- Organizations own buckets on behalf of their members, so the buckets stay
+-- when a member leaves
+CREATE TABLE IF NOT EXISTS organizations (
+    id UUID PRIMARY KEY,
+    name VARCHAR(63) UNIQUE NOT NULL,
+    created_at TIMESTAMPTZ NOT NULL DEFAULT NOW()
+    );
+
+CREATE TABLE IF NOT EXISTS organization_members (
+    organization_id UUID NOT NULL REFERENCES organizations(id) ON DELETE CASCADE,
+    user_id UUID NOT NULL REFERENCES users(id) ON DELETE CASCADE,
+    -- 'owner', 'admin', 'member' or 'read-only'
+    role VARCHAR(16) NOT NULL,
+    created_at TIMESTAMPTZ NOT NULL DEFAULT NOW(),
+    PRIMARY KEY (organization_id, user_id)
+    );
+
+CREATE INDEX IF NOT EXISTS idx_organization_members_user_id ON organization_members(user_id);
+
+-- A bucket is owned by either a user or an organization, and its name is
+-- unique among the buckets of its owner
+ALTER TABLE buckets ADD COLUMN IF NOT EXISTS organization_id UUID REFERENCES organizations(id) ON DELETE CASCADE;
+ALTER TABLE buckets ALTER COLUMN user_id DROP NOT NULL;
+ALTER TABLE buckets ADD CONSTRAINT buckets_single_owner CHECK ((user_id IS NULL) <> (organization_id IS NULL));
+ALTER TABLE buckets ADD CONSTRAINT buckets_name_organization_id_key UNIQUE (name, organization_id);
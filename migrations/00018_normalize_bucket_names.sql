@@ -0,0 +1,114 @@
+-- Bucket names now have to follow the S3 naming rules. Existing names that
+-- break them are rewritten into valid ones, and the old names are recorded
+-- here, which the policy and API key rewrites below rely on. Stored
+-- objects keep their storage paths, so no data moves. Bucket policies and
+-- API key scopes that name a renamed bucket are rewritten below.
+CREATE TABLE IF NOT EXISTS bucket_renames (
+    bucket_id UUID NOT NULL REFERENCES buckets(id) ON DELETE CASCADE,
+    old_name VARCHAR(255) NOT NULL,
+    new_name VARCHAR(63) NOT NULL,
+    renamed_at TIMESTAMPTZ NOT NULL DEFAULT NOW()
+    );
+
+-- The new name is the old one lowercased, with other characters turned into
+-- hyphens, reserved prefixes and runs of dots removed, trimmed to a letter or
+-- digit at both ends and cut to 54 characters. A hyphen and the first eight
+-- hex digits of the bucket ID follow, which fixes names that were too short
+-- or looked like an IP address. Should that name already be taken, by any
+-- bucket or by another rename, the base is cut to 30 characters and the whole
+-- ID follows instead, so the renames cannot break the unique constraints.
+WITH invalid AS (
+    SELECT id, name
+    FROM buckets
+    WHERE name !~ '^[a-z0-9][a-z0-9.-]{1,61}[a-z0-9]$'
+       OR name LIKE '%..%'
+       OR name ~ '^[0-9]+\.[0-9]+\.[0-9]+\.[0-9]+$'
+       OR name LIKE 'xn--%'
+       OR name LIKE 'sthree-%'
+       OR name LIKE '%-s3alias'
+       OR name LIKE '%--ol-s3'
+), cleaned AS (
+    SELECT id, name,
+           regexp_replace(
+               regexp_replace(
+                   regexp_replace(
+                       regexp_replace(lower(name), '[^a-z0-9.-]', '-', 'g'),
+                       '^(xn--|sthree-)', ''),
+                   '\.{2,}', '.', 'g'),
+               '^[.-]+', '') AS base
+    FROM invalid
+), candidates AS (
+    SELECT id, name AS old_name,
+           COALESCE(NULLIF(regexp_replace(left(base, 54), '[.-]+$', ''), ''), 'bucket')
+               || '-' || left(replace(id::text, '-', ''), 8) AS short_name,
+           COALESCE(NULLIF(regexp_replace(left(base, 30), '[.-]+$', ''), ''), 'bucket')
+               || '-' || replace(id::text, '-', '') AS long_name
+    FROM cleaned
+), renamed AS (
+    SELECT c.id, c.old_name,
+           CASE WHEN EXISTS (SELECT 1 FROM buckets b WHERE b.name = c.short_name)
+                  OR EXISTS (SELECT 1 FROM candidates o WHERE o.short_name = c.short_name AND o.id <> c.id)
+                THEN c.long_name
+                ELSE c.short_name
+           END AS new_name
+    FROM candidates c
+), logged AS (
+    INSERT INTO bucket_renames (bucket_id, old_name, new_name)
+    SELECT id, old_name, new_name FROM renamed
+)
+UPDATE buckets b
+SET name = r.new_name
+FROM renamed r
+WHERE b.id = r.id;
+
+-- Policy resources have to name the bucket they are set on, so point those
+-- of renamed buckets at the new name. The bucket part of each resource ARN
+-- matched the old name when the policy was set, even through wildcards, and
+-- is replaced as a whole; the rest of the document is kept as it is.
+CREATE FUNCTION pg_temp.rename_policy_resources(statement JSONB, bucket TEXT) RETURNS JSONB
+LANGUAGE sql IMMUTABLE AS $$
+    SELECT CASE jsonb_typeof(statement -> 'Resource')
+        WHEN 'string' THEN jsonb_set(statement, '{Resource}', to_jsonb(
+            regexp_replace(statement ->> 'Resource', '^arn:aws:s3:::[^/]*', 'arn:aws:s3:::' || bucket)))
+        WHEN 'array' THEN jsonb_set(statement, '{Resource}', (
+            SELECT jsonb_agg(regexp_replace(resource, '^arn:aws:s3:::[^/]*', 'arn:aws:s3:::' || bucket) ORDER BY i)
+            FROM jsonb_array_elements_text(statement -> 'Resource') WITH ORDINALITY AS r(resource, i)))
+        ELSE statement
+    END
+$$;
+
+UPDATE buckets b
+SET policy = CASE jsonb_typeof(b.policy -> 'Statement')
+    WHEN 'object' THEN jsonb_set(b.policy, '{Statement}', pg_temp.rename_policy_resources(b.policy -> 'Statement', b.name))
+    WHEN 'array' THEN jsonb_set(b.policy, '{Statement}', (
+        SELECT jsonb_agg(pg_temp.rename_policy_resources(statement, b.name) ORDER BY i)
+        FROM jsonb_array_elements(b.policy -> 'Statement') WITH ORDINALITY AS s(statement, i)))
+    ELSE b.policy
+END
+FROM bucket_renames r
+WHERE r.bucket_id = b.id AND b.policy IS NOT NULL;
+
+DROP FUNCTION pg_temp.rename_policy_resources(JSONB, TEXT);
+
+-- Scoped API keys name their buckets, and would silently stop matching a
+-- renamed one. Names are replaced in the keys of users who can reach the
+-- renamed bucket, preferring their own bucket when several had the name,
+-- and qualified with the organization for its buckets.
+UPDATE api_keys k
+SET scope = jsonb_set(k.scope, '{buckets}', (
+    SELECT jsonb_agg(COALESCE((
+        SELECT COALESCE(o.name || ':', '') || r.new_name
+        FROM bucket_renames r
+        JOIN buckets b ON b.id = r.bucket_id
+        LEFT JOIN organizations o ON o.id = b.organization_id
+        WHERE r.old_name = e.name
+          AND (b.user_id = k.user_id
+               OR EXISTS (SELECT 1 FROM organization_members m
+                          WHERE m.organization_id = b.organization_id AND m.user_id = k.user_id)
+               OR EXISTS (SELECT 1 FROM bucket_grants g WHERE g.bucket_id = b.id AND g.user_id = k.user_id))
+        ORDER BY (b.user_id = k.user_id) IS TRUE DESC, r.renamed_at
+        LIMIT 1
+    ), e.name) ORDER BY e.i)
+    FROM jsonb_array_elements_text(k.scope -> 'buckets') WITH ORDINALITY AS e(name, i)))
+WHERE jsonb_typeof(k.scope -> 'buckets') = 'array'
+  AND EXISTS (SELECT 1 FROM bucket_renames r WHERE k.scope -> 'buckets' ? r.old_name);
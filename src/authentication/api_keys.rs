@@ -0,0 +1,180 @@
+use actix_web::{web, HttpRequest, HttpResponse, Responder};
+use chrono::{DateTime, Utc};
+use log::error;
+use serde::{Deserialize, Serialize};
+use sqlx::PgPool;
+use uuid::Uuid;
+
+use crate::middleware::auth::{full_access_denied, get_user_id_from_request};
+use crate::models::{ApiKey, Scope};
+
+// Matches the `api_keys.name` column
+const MAX_NAME_LENGTH: usize = 255;
+
+#[derive(Debug, Deserialize)]
+pub struct CreateApiKeyRequest {
+    name: String,
+    expires_at: Option<DateTime<Utc>>,
+    // Limit the key to some buckets, key prefixes or actions
+    scope: Option<Scope>,
+}
+
+// A key as listed; the key itself is never shown again after creation
+#[derive(Debug, Serialize)]
+pub struct ApiKeyResponse {
+    id: Uuid,
+    name: String,
+    prefix: String,
+    created_at: DateTime<Utc>,
+    last_used_at: Option<DateTime<Utc>>,
+    expires_at: Option<DateTime<Utc>>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    scope: Option<Scope>,
+}
+
+impl From<ApiKey> for ApiKeyResponse {
+    fn from(api_key: ApiKey) -> Self {
+        Self {
+            id: api_key.id,
+            name: api_key.name,
+            prefix: api_key.prefix,
+            created_at: api_key.created_at,
+            last_used_at: api_key.last_used_at,
+            expires_at: api_key.expires_at,
+            scope: api_key.scope.map(|scope| scope.0),
+        }
+    }
+}
+
+#[derive(Debug, Serialize)]
+pub struct CreateApiKeyResponse {
+    api_key: String,
+    #[serde(flatten)]
+    key: ApiKeyResponse,
+}
+
+#[derive(Debug, Serialize)]
+pub struct ApiKeyListResponse {
+    api_keys: Vec<ApiKeyResponse>,
+}
+
+pub async fn create_api_key(
+    req: HttpRequest,
+    pool: web::Data<PgPool>,
+    body: web::Json<CreateApiKeyRequest>,
+) -> impl Responder {
+    // Get user ID from request extensions (set by middleware)
+    let user_id = match get_user_id_from_request(&req) {
+        Some(id) => id,
+        None => {
+            return HttpResponse::Unauthorized().json(serde_json::json!({
+                "error": "Authentication required"
+            }));
+        }
+    };
+
+    // Scoped keys cannot mint or see other keys
+    if let Some(response) = full_access_denied(&req) {
+        return response;
+    }
+
+    let body = body.into_inner();
+    let name = body.name.trim().to_string();
+    if name.is_empty() || name.chars().count() > MAX_NAME_LENGTH {
+        return HttpResponse::BadRequest().json(serde_json::json!({
+            "error": format!("Name must be between 1 and {} characters", MAX_NAME_LENGTH)
+        }));
+    }
+    if body.expires_at.is_some_and(|expires_at| expires_at <= Utc::now()) {
+        return HttpResponse::BadRequest().json(serde_json::json!({
+            "error": "Expiry must be in the future"
+        }));
+    }
+    if let Some(Err(e)) = body.scope.as_ref().map(Scope::validate) {
+        return HttpResponse::BadRequest().json(serde_json::json!({
+            "error": e.to_string()
+        }));
+    }
+
+    let (api_key, key) = ApiKey::generate(user_id, name, body.expires_at, body.scope);
+
+    match api_key.create(pool.get_ref()).await {
+        Ok(_) => HttpResponse::Created().json(CreateApiKeyResponse {
+            api_key: key,
+            key: ApiKeyResponse::from(api_key),
+        }),
+        Err(e) => {
+            error!("Failed to create API key for user {}: {:?}", user_id, e);
+            HttpResponse::InternalServerError().json(serde_json::json!({
+                "error": "Failed to create API key"
+            }))
+        }
+    }
+}
+
+pub async fn list_api_keys(
+    req: HttpRequest,
+    pool: web::Data<PgPool>,
+) -> impl Responder {
+    // Get user ID from request extensions (set by middleware)
+    let user_id = match get_user_id_from_request(&req) {
+        Some(id) => id,
+        None => {
+            return HttpResponse::Unauthorized().json(serde_json::json!({
+                "error": "Authentication required"
+            }));
+        }
+    };
+
+    // Scoped keys cannot mint or see other keys
+    if let Some(response) = full_access_denied(&req) {
+        return response;
+    }
+
+    match ApiKey::find_by_user(&pool, user_id).await {
+        Ok(api_keys) => HttpResponse::Ok().json(ApiKeyListResponse {
+            api_keys: api_keys.into_iter().map(ApiKeyResponse::from).collect(),
+        }),
+        Err(e) => {
+            error!("Failed to list API keys for user {}: {:?}", user_id, e);
+            HttpResponse::InternalServerError().json(serde_json::json!({
+                "error": "Failed to list API keys"
+            }))
+        }
+    }
+}
+
+// Revoked keys stop working immediately
+pub async fn revoke_api_key(
+    req: HttpRequest,
+    pool: web::Data<PgPool>,
+    path: web::Path<Uuid>,
+) -> impl Responder {
+    // Get user ID from request extensions (set by middleware)
+    let user_id = match get_user_id_from_request(&req) {
+        Some(id) => id,
+        None => {
+            return HttpResponse::Unauthorized().json(serde_json::json!({
+                "error": "Authentication required"
+            }));
+        }
+    };
+
+    // Scoped keys cannot mint or see other keys
+    if let Some(response) = full_access_denied(&req) {
+        return response;
+    }
+
+    match ApiKey::revoke(&pool, user_id, path.into_inner()).await {
+        Ok(true) => HttpResponse::NoContent().finish(),
+        Ok(false) => HttpResponse::NotFound().json(serde_json::json!({
+            "error": "API key not found"
+        })),
+        Err(e) => {
+            error!("Failed to revoke API key for user {}: {:?}", user_id, e);
+            HttpResponse::InternalServerError().json(serde_json::json!({
+                "error": "Failed to revoke API key"
+            }))
+        }
+    }
+}
@@ -1,40 +1,162 @@
 use chrono::{Duration, Utc};
-use jsonwebtoken::{decode, encode, DecodingKey, EncodingKey, Header, Validation, errors::Error as JwtError};
+use jsonwebtoken::errors::ErrorKind;
+use jsonwebtoken::{decode, decode_header, encode, Algorithm, DecodingKey, EncodingKey, Header, Validation, errors::Error as JwtError};
+use log::warn;
 use serde::{Deserialize, Serialize};
-use uuid::Uuid;
 use std::sync::Arc;
+use thiserror::Error;
+use uuid::Uuid;
+
+use crate::authentication::keys::{self, Jwk, KeyError};
+use crate::config::Config;
+
+// Secret accepted only in dev mode, for running locally without setup
+const DEV_SECRET: &str = "secretkey";
 
 // JWT claims structure
-#[derive(Debug, Serialize, Deserialize)]
+#[derive(Debug, Clone, Serialize, Deserialize)]
 pub struct Claims {
     pub sub: String,       // Subject (user ID)
     pub email: String,     // User email
     pub exp: i64,          // Expiration time
     pub iat: i64,          // Issued at time
+    pub jti: Uuid,         // Token ID, for revocation
+}
+
+#[derive(Debug, Error)]
+pub enum JwtConfigError {
+    #[error("JWT_SECRET or JWT_SIGNING_KEY must be set; set DEV_MODE=true to use a development secret")]
+    MissingSecret,
+    #[error("JWT_SECRET is the well-known development secret; set DEV_MODE=true to allow it")]
+    DevSecret,
+    #[error(transparent)]
+    Key(#[from] KeyError),
+}
+
+// A key tokens are accepted from
+#[derive(Clone)]
+struct VerificationKey {
+    // None for the HMAC secret, whose tokens carry no key ID
+    kid: Option<String>,
+    algorithm: Algorithm,
+    decoding_key: DecodingKey,
 }
 
 // JWT configuration
-#[derive(Debug, Clone)]
+#[derive(Clone)]
 pub struct JwtConfig {
-    pub secret: String,
+    algorithm: Algorithm,
+    kid: Option<String>,
+    encoding_key: EncodingKey,
+    // The signing key plus older keys still accepted during a rotation
+    verification_keys: Arc<Vec<VerificationKey>>,
+    jwks: Arc<Vec<Jwk>>,
     pub expiration: i64, // In seconds
+    pub refresh_expiration: i64, // In seconds
 }
 
-impl Default for JwtConfig {
-    fn default() -> Self {
-        Self {
-            secret: "secretkey".to_string(), // In production, use environment variable
-            expiration: 24 * 60 * 60, // 24 hours in seconds
-        }
+// The HMAC secret to sign with, refusing the development secret outside
+// dev mode
+fn hmac_secret(secret: Option<&str>, dev_mode: bool) -> Result<&str, JwtConfigError> {
+    match (secret, dev_mode) {
+        (Some(DEV_SECRET), false) => Err(JwtConfigError::DevSecret),
+        (Some(secret), _) => Ok(secret),
+        (None, true) => Ok(DEV_SECRET),
+        (None, false) => Err(JwtConfigError::MissingSecret),
     }
 }
 
 impl JwtConfig {
-    pub fn new(secret: String, expiration: i64) -> Self {
-        Self { secret, expiration }
+    // Sign and verify tokens with a shared HMAC secret (HS256)
+    pub fn from_secret(secret: &str, expiration: i64, refresh_expiration: i64) -> Self {
+        let verification_key = VerificationKey {
+            kid: None,
+            algorithm: Algorithm::HS256,
+            decoding_key: DecodingKey::from_secret(secret.as_bytes()),
+        };
+
+        Self {
+            algorithm: Algorithm::HS256,
+            kid: None,
+            encoding_key: EncodingKey::from_secret(secret.as_bytes()),
+            verification_keys: Arc::new(vec![verification_key]),
+            jwks: Arc::new(Vec::new()),
+            expiration,
+            refresh_expiration,
+        }
+    }
+
+    // Sign with an RSA (RS256) or Ed25519 (EdDSA) private key. Tokens signed
+    // with any of `previous_keys` are still accepted, so keys can be rotated
+    // without logging everyone out. Each key is identified by its JWK
+    // thumbprint in the token's `kid` header.
+    pub fn from_keys(
+        signing_key: keys::PemKey,
+        previous_keys: Vec<keys::PemKey>,
+        expiration: i64,
+        refresh_expiration: i64,
+    ) -> Result<Self, KeyError> {
+        let encoding_key = signing_key
+            .encoding_key
+            .clone()
+            .ok_or_else(|| KeyError::NotPrivate(signing_key.name.clone()))?;
+
+        let mut verification_keys = Vec::new();
+        let mut jwks = Vec::new();
+        for key in std::iter::once(&signing_key).chain(&previous_keys) {
+            let kid = key.public.thumbprint();
+            // The same key listed twice is only published once
+            if verification_keys.iter().any(|key: &VerificationKey| key.kid.as_deref() == Some(&kid)) {
+                continue;
+            }
+
+            verification_keys.push(VerificationKey {
+                kid: Some(kid.clone()),
+                algorithm: key.public.algorithm(),
+                decoding_key: key.public.decoding_key().map_err(|e| KeyError::Invalid(key.name.clone(), e))?,
+            });
+            jwks.push(key.public.jwk());
+        }
+
+        Ok(Self {
+            algorithm: signing_key.public.algorithm(),
+            kid: Some(signing_key.public.thumbprint()),
+            encoding_key,
+            verification_keys: Arc::new(verification_keys),
+            jwks: Arc::new(jwks),
+            expiration,
+            refresh_expiration,
+        })
     }
 
-    // Generate a JWT token for a user
+    // Keys from JWT_SIGNING_KEY and JWT_VERIFICATION_KEYS if set, otherwise
+    // the HMAC secret from JWT_SECRET
+    pub fn from_config(config: &Config) -> Result<Self, JwtConfigError> {
+        if let Some(path) = &config.jwt_signing_key {
+            let signing_key = keys::load_key(path)?;
+            let previous_keys = config
+                .jwt_verification_keys
+                .iter()
+                .map(|path| keys::load_key(path))
+                .collect::<Result<Vec<_>, _>>()?;
+
+            return Ok(Self::from_keys(signing_key, previous_keys, config.jwt_expiration, config.refresh_token_ttl)?);
+        }
+
+        let secret = hmac_secret(config.jwt_secret.as_deref(), config.dev_mode)?;
+        if secret == DEV_SECRET {
+            warn!("Signing tokens with the development secret; never do this in production");
+        }
+
+        Ok(Self::from_secret(secret, config.jwt_expiration, config.refresh_token_ttl))
+    }
+
+    // Public keys for the JWKS endpoint
+    pub fn jwks(&self) -> &[Jwk] {
+        &self.jwks
+    }
+
+    // Generate a short-lived access token for a user
     pub fn generate_token(&self, user_id: Uuid, email: &str) -> Result<String, JwtError> {
         let now = Utc::now();
         let expires_at = now + Duration::seconds(self.expiration);
@@ -44,24 +166,95 @@ impl JwtConfig {
             email: email.to_string(),
             exp: expires_at.timestamp(),
             iat: now.timestamp(),
+            jti: Uuid::new_v4(),
         };
 
-        encode(
-            &Header::default(),
-            &claims,
-            &EncodingKey::from_secret(self.secret.as_bytes()),
-        )
+        let header = Header {
+            kid: self.kid.clone(),
+            ..Header::new(self.algorithm)
+        };
+
+        encode(&header, &claims, &self.encoding_key)
     }
 
-    // Validate a JWT token and extract claims
+    // Validate a JWT token and extract claims. The token's `kid` and `alg`
+    // must name one of our keys; the algorithm is never taken from the
+    // token alone.
     pub fn validate_token(&self, token: &str) -> Result<Claims, JwtError> {
-        let validation = Validation::default();
-        let token_data = decode::<Claims>(
-            token,
-            &DecodingKey::from_secret(self.secret.as_bytes()),
-            &validation,
-        )?;
+        let header = decode_header(token)?;
+        let key = self
+            .verification_keys
+            .iter()
+            .find(|key| key.kid == header.kid && key.algorithm == header.alg)
+            .ok_or_else(|| JwtError::from(ErrorKind::InvalidSignature))?;
+
+        let validation = Validation::new(key.algorithm);
+        let token_data = decode::<Claims>(token, &key.decoding_key, &validation)?;
 
         Ok(token_data.claims)
     }
-}
\ No newline at end of file
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use ed25519_dalek::pkcs8::EncodePrivateKey;
+    use rsa::pkcs8::LineEnding;
+
+    fn ed25519_key(seed: u8) -> keys::PemKey {
+        let pem = ed25519_dalek::SigningKey::from_bytes(&[seed; 32])
+            .to_pkcs8_pem(LineEnding::LF)
+            .unwrap();
+        keys::parse_key("test", &pem).unwrap()
+    }
+
+    #[test]
+    fn tokens_carry_a_unique_id() {
+        let config = JwtConfig::from_secret("test secret", 900, 3600);
+        let user_id = Uuid::new_v4();
+
+        let first = config.validate_token(&config.generate_token(user_id, "a@b.c").unwrap()).unwrap();
+        let second = config.validate_token(&config.generate_token(user_id, "a@b.c").unwrap()).unwrap();
+
+        assert_eq!(first.sub, user_id.to_string());
+        assert_eq!(first.exp - first.iat, config.expiration);
+        assert_ne!(first.jti, second.jti);
+    }
+
+    #[test]
+    fn rotated_keys_keep_verifying() {
+        let old = JwtConfig::from_keys(ed25519_key(1), Vec::new(), 900, 3600).unwrap();
+        let new = JwtConfig::from_keys(ed25519_key(2), vec![ed25519_key(1)], 900, 3600).unwrap();
+        let other = JwtConfig::from_keys(ed25519_key(3), Vec::new(), 900, 3600).unwrap();
+
+        let token = old.generate_token(Uuid::new_v4(), "a@b.c").unwrap();
+        let header = decode_header(&token).unwrap();
+        assert_eq!(header.alg, Algorithm::EdDSA);
+        assert_eq!(header.kid, old.kid);
+
+        assert!(new.validate_token(&token).is_ok());
+        assert!(other.validate_token(&token).is_err());
+        assert_eq!(new.jwks().len(), 2);
+
+        // Tokens signed with a secret are not accepted by key-based configs
+        let hmac = JwtConfig::from_secret("test secret", 900, 3600);
+        assert!(new.validate_token(&hmac.generate_token(Uuid::new_v4(), "a@b.c").unwrap()).is_err());
+        assert!(hmac.jwks().is_empty());
+    }
+
+    #[test]
+    fn public_keys_cannot_sign() {
+        let mut key = ed25519_key(1);
+        key.encoding_key = None;
+
+        assert!(matches!(JwtConfig::from_keys(key, Vec::new(), 900, 3600), Err(KeyError::NotPrivate(_))));
+    }
+
+    #[test]
+    fn dev_secret_requires_dev_mode() {
+        assert!(matches!(hmac_secret(None, false), Err(JwtConfigError::MissingSecret)));
+        assert!(matches!(hmac_secret(Some(DEV_SECRET), false), Err(JwtConfigError::DevSecret)));
+        assert_eq!(hmac_secret(None, true).unwrap(), DEV_SECRET);
+        assert_eq!(hmac_secret(Some("real secret"), false).unwrap(), "real secret");
+    }
+}
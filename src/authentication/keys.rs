@@ -0,0 +1,224 @@
+use actix_web::http::header::CACHE_CONTROL;
+use actix_web::{web, HttpResponse, Responder};
+use base64::engine::general_purpose::URL_SAFE_NO_PAD;
+use base64::Engine;
+use ed25519_dalek::pkcs8::{EncodePrivateKey, KeypairBytes};
+use jsonwebtoken::{Algorithm, DecodingKey, EncodingKey};
+use rsa::pkcs1::{DecodeRsaPrivateKey, DecodeRsaPublicKey};
+use rsa::pkcs8::{DecodePrivateKey, DecodePublicKey};
+use rsa::traits::PublicKeyParts;
+use rsa::{RsaPrivateKey, RsaPublicKey};
+use serde::Serialize;
+use sha2::{Digest, Sha256};
+use std::fs;
+use thiserror::Error;
+
+use crate::authentication::jwt::JwtConfig;
+
+// Shorter RSA keys are no longer considered safe
+pub const MIN_RSA_BITS: usize = 2048;
+
+// How long verifiers may cache the key set
+const JWKS_MAX_AGE: u32 = 5 * 60;
+
+#[derive(Debug, Error)]
+pub enum KeyError {
+    #[error("failed to read key file {0}: {1}")]
+    Read(String, std::io::Error),
+    #[error("{0} is not an RSA or Ed25519 key in PEM format")]
+    Unsupported(String),
+    #[error("{0} holds a public key, but signing needs the private key")]
+    NotPrivate(String),
+    #[error("RSA key {0} is shorter than {1} bits")]
+    TooShort(String, usize),
+    #[error("invalid key {0}: {1}")]
+    Invalid(String, jsonwebtoken::errors::Error),
+}
+
+// The public half of a key, which is all verifiers need
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum PublicKey {
+    Rsa { n: Vec<u8>, e: Vec<u8> },
+    Ed25519 { x: [u8; 32] },
+}
+
+impl PublicKey {
+    pub fn algorithm(&self) -> Algorithm {
+        match self {
+            PublicKey::Rsa { .. } => Algorithm::RS256,
+            PublicKey::Ed25519 { .. } => Algorithm::EdDSA,
+        }
+    }
+
+    // JWK thumbprint (RFC 7638): the hash of the required members in
+    // lexicographic order, used as the key ID so it never needs configuring
+    pub fn thumbprint(&self) -> String {
+        let members = match self {
+            PublicKey::Rsa { n, e } => format!(
+                r#"{{"e":"{}","kty":"RSA","n":"{}"}}"#,
+                URL_SAFE_NO_PAD.encode(e),
+                URL_SAFE_NO_PAD.encode(n)
+            ),
+            PublicKey::Ed25519 { x } => format!(
+                r#"{{"crv":"Ed25519","kty":"OKP","x":"{}"}}"#,
+                URL_SAFE_NO_PAD.encode(x)
+            ),
+        };
+        URL_SAFE_NO_PAD.encode(Sha256::digest(members.as_bytes()))
+    }
+
+    pub fn decoding_key(&self) -> Result<DecodingKey, jsonwebtoken::errors::Error> {
+        match self {
+            PublicKey::Rsa { n, e } => {
+                DecodingKey::from_rsa_components(&URL_SAFE_NO_PAD.encode(n), &URL_SAFE_NO_PAD.encode(e))
+            }
+            PublicKey::Ed25519 { x } => DecodingKey::from_ed_components(&URL_SAFE_NO_PAD.encode(x)),
+        }
+    }
+
+    pub fn jwk(&self) -> Jwk {
+        let (kty, crv, n, e, x) = match self {
+            PublicKey::Rsa { n, e } => ("RSA", None, Some(n.as_slice()), Some(e.as_slice()), None),
+            PublicKey::Ed25519 { x } => ("OKP", Some("Ed25519"), None, None, Some(x.as_slice())),
+        };
+        let encode = |bytes: Option<&[u8]>| bytes.map(|bytes| URL_SAFE_NO_PAD.encode(bytes));
+
+        Jwk {
+            kty,
+            usage: "sig",
+            alg: match self.algorithm() {
+                Algorithm::RS256 => "RS256",
+                _ => "EdDSA",
+            },
+            kid: self.thumbprint(),
+            crv,
+            n: encode(n),
+            e: encode(e),
+            x: encode(x),
+        }
+    }
+}
+
+// A public key as published at /.well-known/jwks.json
+#[derive(Debug, Clone, Serialize)]
+pub struct Jwk {
+    kty: &'static str,
+    #[serde(rename = "use")]
+    usage: &'static str,
+    alg: &'static str,
+    kid: String,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    crv: Option<&'static str>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    n: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    e: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    x: Option<String>,
+}
+
+// A key read from a PEM file. Private keys can also sign.
+pub struct PemKey {
+    // Where the key came from, for error messages
+    pub name: String,
+    pub public: PublicKey,
+    pub encoding_key: Option<EncodingKey>,
+}
+
+fn rsa_public(key: &RsaPublicKey) -> PublicKey {
+    PublicKey::Rsa {
+        n: key.n().to_bytes_be(),
+        e: key.e().to_bytes_be(),
+    }
+}
+
+// Parse an RSA (PKCS#1 or PKCS#8) or Ed25519 (PKCS#8) key, private or public
+fn parse_pem_key(pem: &str) -> Option<(PublicKey, Option<EncodingKey>)> {
+    let rsa_private = RsaPrivateKey::from_pkcs8_pem(pem).or_else(|_| RsaPrivateKey::from_pkcs1_pem(pem));
+    if let Ok(key) = rsa_private {
+        let encoding_key = EncodingKey::from_rsa_pem(pem.as_bytes()).ok()?;
+        return Some((rsa_public(&key.to_public_key()), Some(encoding_key)));
+    }
+
+    if let Ok(key) = ed25519_dalek::SigningKey::from_pkcs8_pem(pem) {
+        // The signer only reads PKCS#8 v1, without the embedded public key
+        let der = KeypairBytes { secret_key: key.to_bytes(), public_key: None }.to_pkcs8_der().ok()?;
+        let encoding_key = EncodingKey::from_ed_der(der.as_bytes());
+        let x = key.verifying_key().to_bytes();
+        return Some((PublicKey::Ed25519 { x }, Some(encoding_key)));
+    }
+
+    let rsa_public_key = RsaPublicKey::from_public_key_pem(pem).or_else(|_| RsaPublicKey::from_pkcs1_pem(pem));
+    if let Ok(key) = rsa_public_key {
+        return Some((rsa_public(&key), None));
+    }
+
+    if let Ok(key) = ed25519_dalek::VerifyingKey::from_public_key_pem(pem) {
+        return Some((PublicKey::Ed25519 { x: key.to_bytes() }, None));
+    }
+
+    None
+}
+
+pub fn parse_key(name: &str, pem: &str) -> Result<PemKey, KeyError> {
+    let (public, encoding_key) = parse_pem_key(pem).ok_or_else(|| KeyError::Unsupported(name.to_string()))?;
+
+    if let PublicKey::Rsa { n, .. } = &public {
+        // The modulus has no leading zero bytes, so this is its size in bits
+        let bits = n.len() * 8 - n.first().map_or(0, |byte| byte.leading_zeros() as usize);
+        if bits < MIN_RSA_BITS {
+            return Err(KeyError::TooShort(name.to_string(), MIN_RSA_BITS));
+        }
+    }
+
+    Ok(PemKey { name: name.to_string(), public, encoding_key })
+}
+
+pub fn load_key(path: &str) -> Result<PemKey, KeyError> {
+    let pem = fs::read_to_string(path).map_err(|e| KeyError::Read(path.to_string(), e))?;
+    parse_key(path, &pem)
+}
+
+// The public keys tokens are verified with, for other services to check
+// our tokens without sharing a secret. Empty when tokens are signed with
+// an HMAC secret, which must never be published.
+pub async fn jwks(jwt_config: web::Data<JwtConfig>) -> impl Responder {
+    HttpResponse::Ok()
+        .insert_header((CACHE_CONTROL, format!("public, max-age={}", JWKS_MAX_AGE)))
+        .json(serde_json::json!({
+            "keys": jwt_config.jwks()
+        }))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn thumbprint_matches_rfc_8037() {
+        // RFC 8037, appendix A.3
+        let x = URL_SAFE_NO_PAD.decode("11qYAYKxCrfVS_7TyWQHOg7hcvPapiMlrwIaaPcHURo").unwrap();
+        let key = PublicKey::Ed25519 { x: x.try_into().unwrap() };
+
+        assert_eq!(key.thumbprint(), "kPrK_qmxVWaYVA9wwBF6Iuo3vVzz7TxHCTwXBygrS4k");
+    }
+
+    #[test]
+    fn parses_private_and_public_pem() {
+        use ed25519_dalek::pkcs8::EncodePublicKey;
+        use rsa::pkcs8::LineEnding;
+
+        let signing_key = ed25519_dalek::SigningKey::from_bytes(&[7; 32]);
+        let private_pem = signing_key.to_pkcs8_pem(LineEnding::LF).unwrap();
+        let public_pem = signing_key.verifying_key().to_public_key_pem(LineEnding::LF).unwrap();
+
+        let private = parse_key("private", &private_pem).unwrap();
+        let public = parse_key("public", &public_pem).unwrap();
+
+        assert!(private.encoding_key.is_some());
+        assert!(public.encoding_key.is_none());
+        assert_eq!(private.public, public.public);
+        assert_eq!(public.public.algorithm(), Algorithm::EdDSA);
+        assert!(matches!(parse_key("junk", "not a key"), Err(KeyError::Unsupported(_))));
+    }
+}
@@ -1,20 +1,74 @@
-use actix_web::{dev::{forward_ready, Service, ServiceRequest, ServiceResponse, Transform}, Error, HttpMessage, HttpResponse, body::{BoxBody, EitherBody}, HttpRequest};
+use actix_web::{dev::{forward_ready, Service, ServiceRequest, ServiceResponse, Transform}, Error, HttpMessage, HttpResponse, ResponseError, body::{BoxBody, EitherBody}};
+use actix_web::http::header::HeaderMap;
+use actix_web::http::Method;
+use chrono::Utc;
 use futures::future::{ready, LocalBoxFuture, Ready};
+use log::error;
+use percent_encoding::percent_decode_str;
 use sqlx::PgPool;
 use std::rc::Rc;
 use uuid::Uuid;
 
-use crate::models::User;
+use crate::models::{ApiKey, RevokedToken, User};
 use crate::authentication::JwtConfig;
+use crate::authentication::chunked::verified_payload;
+use crate::authentication::sigv4::{self, Authorization, PresignedQuery, SigV4Error, SignedRequest};
+use crate::handlers::s3::error::S3Error;
+use crate::middleware::auth::Principal;
 
 // Constants for header and query param names
 const AUTHORIZATION_HEADER: &str = "Authorization";
 const BEARER_PREFIX: &str = "Bearer ";
+const API_KEY_HEADER: &str = "X-Api-Key";
+const API_KEY_SCHEME: &str = "ApiKey";
 const API_KEY_PARAM: &str = "apiKey";
 
 pub struct AuthMiddleware {
     pub pool: PgPool,
     pub jwt_config: JwtConfig,
+    // Whether the `apiKey` query parameter is accepted
+    pub allow_query_api_key: bool,
+    // Whether GET and HEAD requests without any credentials reach the
+    // handlers, which then only serve public buckets
+    pub allow_anonymous_reads: bool,
+}
+
+// An API key and where the request carried it
+#[derive(Debug, PartialEq, Eq)]
+pub enum PresentedApiKey {
+    // `X-Api-Key: <key>` or `Authorization: ApiKey <key>`
+    Header(String),
+    // `?apiKey=<key>`
+    Query(String),
+}
+
+// The API key a request presents, preferring headers over the query string
+pub fn presented_api_key(headers: &HeaderMap, query: &str) -> Option<PresentedApiKey> {
+    let header_text = |name| headers.get(name).and_then(|value| value.to_str().ok()).map(str::trim);
+
+    if let Some(key) = header_text(API_KEY_HEADER).filter(|key| !key.is_empty()) {
+        return Some(PresentedApiKey::Header(key.to_string()));
+    }
+
+    let authorization = header_text(AUTHORIZATION_HEADER)
+        .and_then(|value| value.split_once(' '))
+        .filter(|(scheme, _)| scheme.eq_ignore_ascii_case(API_KEY_SCHEME))
+        .map(|(_, key)| key.trim())
+        .filter(|key| !key.is_empty());
+    if let Some(key) = authorization {
+        return Some(PresentedApiKey::Header(key.to_string()));
+    }
+
+    query.split('&').find_map(|param| {
+        let (name, value) = param.split_once('=')?;
+        if name != API_KEY_PARAM {
+            return None;
+        }
+        // Form encoding: `+` stands for a space
+        let value = value.replace('+', " ");
+        let key = percent_decode_str(&value).decode_utf8().ok()?;
+        Some(PresentedApiKey::Query(key.into_owned()))
+    })
 }
 
 impl<S, B> Transform<S, ServiceRequest> for AuthMiddleware
@@ -34,6 +88,8 @@ where
             service: Rc::new(service),
             pool: self.pool.clone(),
             jwt_config: self.jwt_config.clone(),
+            allow_query_api_key: self.allow_query_api_key,
+            allow_anonymous_reads: self.allow_anonymous_reads,
         }))
     }
 }
@@ -42,6 +98,8 @@ pub struct AuthMiddlewareService<S> {
     service: Rc<S>,
     pool: PgPool,
     jwt_config: JwtConfig,
+    allow_query_api_key: bool,
+    allow_anonymous_reads: bool,
 }
 
 impl<S, B> Service<ServiceRequest> for AuthMiddlewareService<S>
@@ -56,14 +114,50 @@ where
 
     forward_ready!(service);
 
-    fn call(&self, req: ServiceRequest) -> Self::Future {
+    fn call(&self, mut req: ServiceRequest) -> Self::Future {
         let pool = self.pool.clone();
         let jwt_config = self.jwt_config.clone();
+        let allow_query_api_key = self.allow_query_api_key;
+        let allow_anonymous_reads = self.allow_anonymous_reads;
         let service = self.service.clone();
 
         Box::pin(async move {
 
-            // First check for JWT token in Authorization header
+            // Requests signed by S3 clients (AWS Signature Version 4)
+            let sigv4_header = req
+                .headers()
+                .get(AUTHORIZATION_HEADER)
+                .and_then(|value| value.to_str().ok())
+                .filter(|value| value.starts_with(sigv4::ALGORITHM))
+                .map(|value| value.to_string());
+
+            // Presigned URLs carry the same signature in the query string
+            let signed = match sigv4_header {
+                Some(auth_str) => Some(verify_sigv4(&pool, &mut req, &auth_str).await),
+                None => match PresignedQuery::parse(req.query_string()) {
+                    Ok(Some(presigned)) => Some(verify_presigned(&pool, &req, &presigned).await),
+                    Ok(None) => None,
+                    Err(e) => Some(Err(S3Error::from(&e))),
+                },
+            };
+
+            if let Some(signed) = signed {
+                return match signed {
+                    Ok(user_id) => {
+                        // Store the principal in request extensions
+                        req.extensions_mut().insert(Principal::full(user_id));
+                        let res = service.call(req).await?;
+                        Ok(res.map_into_left_body())
+                    }
+                    Err(e) => {
+                        // Signed requests come from S3 clients, so answer with an S3 error document
+                        let response = e.error_response();
+                        Ok(ServiceResponse::new(req.into_parts().0, response).map_into_right_body())
+                    }
+                };
+            }
+
+            // Then check for JWT token in Authorization header
             if let Some(auth_header) = req.headers().get(AUTHORIZATION_HEADER) {
                 if let Ok(auth_str) = auth_header.to_str() {
                     if auth_str.starts_with(BEARER_PREFIX) {
@@ -71,10 +165,20 @@ where
 
                         // Validate JWT token
                         if let Ok(claims) = jwt_config.validate_token(token) {
+                            // Tokens revoked by a logout stay invalid until they expire
+                            let revoked = match RevokedToken::is_revoked(&pool, claims.jti).await {
+                                Ok(revoked) => revoked,
+                                Err(e) => {
+                                    error!("Failed to check token revocation: {:?}", e);
+                                    true
+                                }
+                            };
+
                             // Extract user ID from token claims
-                            if let Ok(user_id) = Uuid::parse_str(&claims.sub) {
-                                // Store user ID in request extensions
-                                req.extensions_mut().insert(user_id);
+                            if let (false, Ok(user_id)) = (revoked, Uuid::parse_str(&claims.sub)) {
+                                // Store the principal and the claims in request extensions
+                                req.extensions_mut().insert(Principal::full(user_id));
+                                req.extensions_mut().insert(claims);
                                 let res = service.call(req).await?;
                                 // Important: Convert the response to the expected type
                                 return Ok(res.map_into_left_body());
@@ -84,30 +188,40 @@ where
                 }
             }
 
-            // If no valid JWT, fall back to API key (backward compatibility)
-            let query_string = req.query_string();
-            let api_key = query_string
-                .split('&')
-                .find_map(|param| {
-                    let parts: Vec<&str> = param.split('=').collect();
-                    if parts.len() == 2 && parts[0] == API_KEY_PARAM {
-                        Some(parts[1].to_string())
-                    } else {
-                        None
-                    }
-                });
+            // If no valid JWT, fall back to an API key
+            let api_key = match presented_api_key(req.headers(), req.query_string()) {
+                Some(PresentedApiKey::Query(_)) if !allow_query_api_key => {
+                    let response = HttpResponse::Unauthorized().json(serde_json::json!({
+                        "error": "API keys are not accepted in the query string; use the X-Api-Key header"
+                    }));
+                    return Ok(ServiceResponse::new(req.into_parts().0, response).map_into_right_body());
+                }
+                Some(PresentedApiKey::Header(key)) | Some(PresentedApiKey::Query(key)) => Some(key),
+                None => None,
+            };
 
             // If API key is found, verify it
             if let Some(api_key) = api_key {
-                if let Ok(Some(user)) = User::find_by_api_key(&pool, &api_key).await {
-                    // Store user ID in request extensions
-                    req.extensions_mut().insert(user.id);
+                if let Ok(Some(key)) = ApiKey::verify(&pool, &api_key).await {
+                    // Store the principal, with the key's scope, in request extensions
+                    req.extensions_mut().insert(Principal::from(&key));
                     let res = service.call(req).await?;
                     // Important: Convert the response to the expected type
                     return Ok(res.map_into_left_body());
                 }
             }
 
+            // Requests without any credentials go through anonymously
+            // where allowed, without a principal
+            let anonymous = allow_anonymous_reads
+                && matches!(*req.method(), Method::GET | Method::HEAD)
+                && !req.headers().contains_key(AUTHORIZATION_HEADER)
+                && presented_api_key(req.headers(), req.query_string()).is_none();
+            if anonymous {
+                let res = service.call(req).await?;
+                return Ok(res.map_into_left_body());
+            }
+
             // Neither JWT nor API key is valid
             // Create the unauthorized response
             let response = HttpResponse::Unauthorized()
@@ -127,6 +241,95 @@ where
     }
 }
 
-pub fn get_user_id_from_request(req: &HttpRequest) -> Option<Uuid> {
-    req.extensions().get::<Uuid>().copied()
-}
\ No newline at end of file
+// Look up the user owning an access key ID
+async fn find_access_key_owner(pool: &PgPool, access_key_id: &str) -> Result<User, S3Error> {
+    match User::find_by_access_key_id(pool, access_key_id).await {
+        Ok(Some(user)) if user.secret_access_key.is_some() => Ok(user),
+        Ok(_) => Err(S3Error::from(&SigV4Error::InvalidAccessKeyId)),
+        Err(e) => {
+            error!("Failed to look up access key: {:?}", e);
+            Err(S3Error::InternalError)
+        }
+    }
+}
+
+fn signed_request(req: &ServiceRequest) -> SignedRequest<'_> {
+    // Header names from actix are already lowercase
+    let headers = req
+        .headers()
+        .iter()
+        .filter_map(|(name, value)| {
+            value
+                .to_str()
+                .ok()
+                .map(|value| (name.as_str().to_string(), value.trim().to_string()))
+        })
+        .collect();
+
+    SignedRequest {
+        method: req.method().as_str(),
+        path: req.path(),
+        query: req.query_string(),
+        headers,
+    }
+}
+
+// Verify a SigV4-signed request and return the signing user's ID. When the
+// body is covered by the signature, the request payload is replaced with one
+// that checks it as it is read.
+async fn verify_sigv4(pool: &PgPool, req: &mut ServiceRequest, header: &str) -> Result<Uuid, S3Error> {
+    let authorization = Authorization::parse(header).map_err(|e| S3Error::from(&e))?;
+    let user = find_access_key_owner(pool, &authorization.credential.access_key_id).await?;
+    let secret = user.secret_access_key.as_deref().unwrap_or_default();
+
+    let check = sigv4::verify_request(&signed_request(req), &authorization, secret, Utc::now())
+        .map_err(|e| S3Error::from(&e))?;
+
+    if let Some(check) = check {
+        let payload = req.take_payload();
+        req.set_payload(verified_payload(payload, check));
+    }
+
+    Ok(user.id)
+}
+
+// Verify a request made through a presigned URL and return the signing user's ID
+async fn verify_presigned(pool: &PgPool, req: &ServiceRequest, presigned: &PresignedQuery) -> Result<Uuid, S3Error> {
+    let user = find_access_key_owner(pool, &presigned.credential.access_key_id).await?;
+    let secret = user.secret_access_key.as_deref().unwrap_or_default();
+
+    sigv4::verify_presigned(&signed_request(req), presigned, secret, Utc::now())
+        .map_err(|e| S3Error::from(&e))?;
+
+    Ok(user.id)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use actix_web::http::header::{HeaderName, HeaderValue};
+
+    fn headers(pairs: &[(&'static str, &'static str)]) -> HeaderMap {
+        let mut map = HeaderMap::new();
+        for (name, value) in pairs {
+            map.insert(HeaderName::from_static(name), HeaderValue::from_static(value));
+        }
+        map
+    }
+
+    #[test]
+    fn finds_api_key_in_headers_or_query() {
+        let header = |key: &str| Some(PresentedApiKey::Header(key.to_string()));
+        let query = |key: &str| Some(PresentedApiKey::Query(key.to_string()));
+
+        assert_eq!(presented_api_key(&headers(&[("x-api-key", " s3k_a ")]), ""), header("s3k_a"));
+        assert_eq!(presented_api_key(&headers(&[("authorization", "apikey s3k_b")]), ""), header("s3k_b"));
+        assert_eq!(presented_api_key(&headers(&[("authorization", "Bearer s3k_b")]), ""), None);
+        assert_eq!(
+            presented_api_key(&headers(&[("x-api-key", "s3k_a")]), "apiKey=s3k_c"),
+            header("s3k_a")
+        );
+        assert_eq!(presented_api_key(&HeaderMap::new(), "x=1&apiKey=s3k%5Fc"), query("s3k_c"));
+        assert_eq!(presented_api_key(&HeaderMap::new(), "apikey=s3k_c"), None);
+    }
+}
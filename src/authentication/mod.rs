@@ -1,49 +1,132 @@
+pub mod api_keys;
+pub mod chunked;
 pub mod jwt;
+pub mod keys;
 pub mod middleware;
+pub mod password;
+pub mod sigv4;
+pub mod tokens;
 
-use actix_web::{web, HttpResponse, Responder};
+use actix_web::{web, HttpRequest, HttpResponse, Responder};
+use log::error;
 use serde::{Deserialize, Serialize};
 use sqlx::PgPool;
 
-use crate::models::User;
+use crate::middleware::auth::{full_access_denied, get_user_id_from_request};
+use crate::models::{ApiKey, RefreshToken, User};
 use self::jwt::JwtConfig;
+use self::password::{hash_password, validate_password, verify_password};
 
+// Name of the API key every account is created with
+const DEFAULT_API_KEY_NAME: &str = "default";
 
 #[derive(Debug, Deserialize)]
 pub struct RegisterRequest {
     email: String,
+    password: String,
 }
 
 #[derive(Debug, Serialize)]
 pub struct RegisterResponse {
     api_key: String,
+    access_key_id: Option<String>,
+    secret_access_key: Option<String>,
+}
+
+#[derive(Debug, Serialize)]
+pub struct AccessKeyResponse {
+    access_key_id: Option<String>,
+    secret_access_key: Option<String>,
 }
 
 #[derive(Debug, Deserialize)]
 pub struct LoginRequest {
     email: String,
+    // A missing password never matches
+    #[serde(default)]
+    password: String,
 }
 
-#[derive(Debug, Serialize)]
-pub struct LoginResponse {
-    token: String,
-    user_id: String,
-    email: String,
+#[derive(Debug, Deserialize)]
+pub struct SetPasswordRequest {
+    password: String,
+}
+
+#[derive(Debug, Deserialize)]
+pub struct ChangePasswordRequest {
+    current_password: String,
+    new_password: String,
+}
+
+// Validate and hash a new password on the blocking thread pool, since
+// Argon2 is slow on purpose
+async fn hash_new_password(password: String) -> Result<String, HttpResponse> {
+    if let Err(e) = validate_password(&password) {
+        return Err(HttpResponse::BadRequest().json(serde_json::json!({
+            "error": e.to_string()
+        })));
+    }
+
+    match web::block(move || hash_password(&password)).await {
+        Ok(Ok(hash)) => Ok(hash),
+        Ok(Err(e)) => {
+            error!("Failed to hash password: {:?}", e);
+            Err(HttpResponse::InternalServerError().json(serde_json::json!({
+                "error": "Failed to set password"
+            })))
+        }
+        Err(e) => {
+            error!("Password hashing task failed: {:?}", e);
+            Err(HttpResponse::InternalServerError().json(serde_json::json!({
+                "error": "Failed to set password"
+            })))
+        }
+    }
+}
+
+// Verify a password on the blocking thread pool. A missing hash takes as
+// long to check as a real one and never matches.
+async fn check_password(password: String, hash: Option<String>) -> bool {
+    match web::block(move || verify_password(&password, hash.as_deref())).await {
+        Ok(matches) => matches,
+        Err(e) => {
+            error!("Password verification task failed: {:?}", e);
+            false
+        }
+    }
 }
 
 pub async fn register(
     pool: web::Data<PgPool>,
     req: web::Json<RegisterRequest>,
 ) -> impl Responder {
-    // Create a new user
-    let user = User::new(req.email.clone());
+    let req = req.into_inner();
+
+    let password_hash = match hash_new_password(req.password).await {
+        Ok(hash) => hash,
+        Err(response) => return response,
+    };
+
+    // Create a new user with a first API key
+    let user = User::new(req.email, password_hash);
+    let (api_key, key) = ApiKey::generate(user.id, DEFAULT_API_KEY_NAME.to_string(), None, None);
 
-    // Save the user to the database
-    match user.create(&pool).await {
+    // Save the user and key to the database
+    let created: Result<(), sqlx::Error> = async {
+        let mut tx = pool.begin().await?;
+        user.create(&mut *tx).await?;
+        api_key.create(&mut *tx).await?;
+        tx.commit().await
+    }
+    .await;
+
+    match created {
         Ok(_) => {
-            // Return the API key to the client
+            // Return the API key and access key pair to the client
             HttpResponse::Created().json(RegisterResponse {
-                api_key: user.api_key,
+                api_key: key,
+                access_key_id: user.access_key_id,
+                secret_access_key: user.secret_access_key,
             })
         }
         Err(e) => {
@@ -69,30 +152,227 @@ pub async fn login(
     jwt_config: web::Data<JwtConfig>,
     req: web::Json<LoginRequest>,
 ) -> impl Responder {
+    let req = req.into_inner();
+
     // Find user by email
     let user = match User::find_by_email(&pool, &req.email).await {
+        Ok(user) => user,
+        Err(_) => {
+            return HttpResponse::InternalServerError().json(serde_json::json!({
+                "error": "Failed to verify credentials"
+            }));
+        }
+    };
+
+    // The password is checked even without a matching account, so the
+    // response time does not tell whether the email is registered
+    let password_hash = user.as_ref().and_then(|user| user.password_hash.clone());
+    let verified = check_password(req.password, password_hash).await;
+
+    // Accounts from before passwords existed fail like any other, so the
+    // response does not tell they exist either; the error points their
+    // owners at /set-password
+    let user = match user {
+        Some(user) if verified => user,
+        _ => {
+            return HttpResponse::Unauthorized().json(serde_json::json!({
+                "error": "Invalid email or password. Accounts created before passwords existed must first set one with POST /set-password."
+            }));
+        }
+    };
+
+    // Generate an access token and a refresh token
+    let user_id = user.id;
+    match tokens::login_tokens(&pool, &jwt_config, user).await {
+        Ok(tokens) => HttpResponse::Ok().json(tokens),
+        Err(e) => {
+            error!("Failed to issue tokens for user {}: {:?}", user_id, e);
+            HttpResponse::InternalServerError().json(serde_json::json!({
+                "error": "Failed to generate authentication token"
+            }))
+        }
+    }
+}
+
+// Set the first password of an account created before passwords existed.
+// The caller proves who they are with the account's API key.
+pub async fn set_password(
+    req: HttpRequest,
+    pool: web::Data<PgPool>,
+    body: web::Json<SetPasswordRequest>,
+) -> impl Responder {
+    // Get user ID from request extensions (set by middleware)
+    let user_id = match get_user_id_from_request(&req) {
+        Some(id) => id,
+        None => {
+            return HttpResponse::Unauthorized().json(serde_json::json!({
+                "error": "Authentication required"
+            }));
+        }
+    };
+
+    // Scoped keys cannot change the account's credentials
+    if let Some(response) = full_access_denied(&req) {
+        return response;
+    }
+
+    let mut user = match User::find_by_id(&pool, user_id).await {
         Ok(Some(user)) => user,
         Ok(None) => {
+            return HttpResponse::NotFound().json(serde_json::json!({
+                "error": "User not found"
+            }));
+        }
+        Err(e) => {
+            error!("Error fetching user {}: {:?}", user_id, e);
+            return HttpResponse::InternalServerError().json(serde_json::json!({
+                "error": "Failed to fetch user"
+            }));
+        }
+    };
+
+    let password_already_set = || {
+        HttpResponse::Conflict().json(serde_json::json!({
+            "error": "Password already set; use /change-password instead"
+        }))
+    };
+    if user.password_hash.is_some() {
+        return password_already_set();
+    }
+
+    let password_hash = match hash_new_password(body.into_inner().password).await {
+        Ok(hash) => hash,
+        Err(response) => return response,
+    };
+
+    match user.set_first_password_hash(&pool, password_hash).await {
+        Ok(true) => HttpResponse::NoContent().finish(),
+        Ok(false) => password_already_set(),
+        Err(e) => {
+            error!("Failed to set password for user {}: {:?}", user_id, e);
+            HttpResponse::InternalServerError().json(serde_json::json!({
+                "error": "Failed to set password"
+            }))
+        }
+    }
+}
+
+// Replace the password after checking the current one
+pub async fn change_password(
+    req: HttpRequest,
+    pool: web::Data<PgPool>,
+    body: web::Json<ChangePasswordRequest>,
+) -> impl Responder {
+    // Get user ID from request extensions (set by middleware)
+    let user_id = match get_user_id_from_request(&req) {
+        Some(id) => id,
+        None => {
             return HttpResponse::Unauthorized().json(serde_json::json!({
-                "error": "Invalid email"
+                "error": "Authentication required"
             }));
         }
-        Err(_) => {
+    };
+
+    // Scoped keys cannot change the account's credentials
+    if let Some(response) = full_access_denied(&req) {
+        return response;
+    }
+
+    let mut user = match User::find_by_id(&pool, user_id).await {
+        Ok(Some(user)) => user,
+        Ok(None) => {
+            return HttpResponse::NotFound().json(serde_json::json!({
+                "error": "User not found"
+            }));
+        }
+        Err(e) => {
+            error!("Error fetching user {}: {:?}", user_id, e);
             return HttpResponse::InternalServerError().json(serde_json::json!({
-                "error": "Failed to verify credentials"
+                "error": "Failed to fetch user"
+            }));
+        }
+    };
+
+    if user.password_hash.is_none() {
+        return HttpResponse::Conflict().json(serde_json::json!({
+            "error": "No password set; use /set-password instead"
+        }));
+    }
+
+    let body = body.into_inner();
+    if !check_password(body.current_password, user.password_hash.clone()).await {
+        return HttpResponse::Unauthorized().json(serde_json::json!({
+            "error": "Invalid current password"
+        }));
+    }
+
+    let password_hash = match hash_new_password(body.new_password).await {
+        Ok(hash) => hash,
+        Err(response) => return response,
+    };
+
+    // A new password ends every session started with the old one
+    let changed = match user.set_password_hash(&pool, password_hash).await {
+        Ok(_) => RefreshToken::revoke_all_for_user(pool.get_ref(), user_id).await,
+        Err(e) => Err(e),
+    };
+
+    match changed {
+        Ok(_) => HttpResponse::NoContent().finish(),
+        Err(e) => {
+            error!("Failed to change password for user {}: {:?}", user_id, e);
+            HttpResponse::InternalServerError().json(serde_json::json!({
+                "error": "Failed to change password"
+            }))
+        }
+    }
+}
+
+// Issue a new access key pair for SigV4 signing; the previous pair stops working
+pub async fn rotate_access_key(
+    req: HttpRequest,
+    pool: web::Data<PgPool>,
+) -> impl Responder {
+    // Get user ID from request extensions (set by middleware)
+    let user_id = match get_user_id_from_request(&req) {
+        Some(id) => id,
+        None => {
+            return HttpResponse::Unauthorized().json(serde_json::json!({
+                "error": "Authentication required"
+            }));
+        }
+    };
+
+    // Scoped keys cannot change the account's credentials
+    if let Some(response) = full_access_denied(&req) {
+        return response;
+    }
+
+    let mut user = match User::find_by_id(&pool, user_id).await {
+        Ok(Some(user)) => user,
+        Ok(None) => {
+            return HttpResponse::NotFound().json(serde_json::json!({
+                "error": "User not found"
+            }));
+        }
+        Err(e) => {
+            error!("Error fetching user {}: {:?}", user_id, e);
+            return HttpResponse::InternalServerError().json(serde_json::json!({
+                "error": "Failed to fetch user"
             }));
         }
     };
 
-    // Generate JWT token
-    match jwt_config.generate_token(user.id, &user.email) {
-        Ok(token) => HttpResponse::Ok().json(LoginResponse {
-            token,
-            user_id: user.id.to_string(),
-            email: user.email,
+    match user.rotate_access_key(&pool).await {
+        Ok(_) => HttpResponse::Ok().json(AccessKeyResponse {
+            access_key_id: user.access_key_id,
+            secret_access_key: user.secret_access_key,
         }),
-        Err(_) => HttpResponse::InternalServerError().json(serde_json::json!({
-            "error": "Failed to generate authentication token"
-        })),
+        Err(e) => {
+            error!("Failed to rotate access key for user {}: {:?}", user_id, e);
+            HttpResponse::InternalServerError().json(serde_json::json!({
+                "error": "Failed to rotate access key"
+            }))
+        }
     }
-}
\ No newline at end of file
+}
@@ -0,0 +1,98 @@
+use argon2::password_hash::rand_core::OsRng;
+use argon2::password_hash::{PasswordHash, PasswordHasher, PasswordVerifier, SaltString};
+use argon2::Argon2;
+use std::sync::OnceLock;
+use thiserror::Error;
+
+pub const MIN_PASSWORD_LENGTH: usize = 8;
+// Bounds the hashing work a single request can cause
+pub const MAX_PASSWORD_LENGTH: usize = 128;
+
+#[derive(Debug, Error)]
+pub enum PasswordError {
+    #[error("Password must be at least {0} characters")]
+    TooShort(usize),
+    #[error("Password must be at most {0} characters")]
+    TooLong(usize),
+    #[error("failed to hash password: {0}")]
+    Hash(argon2::password_hash::Error),
+}
+
+pub fn validate_password(password: &str) -> Result<(), PasswordError> {
+    let length = password.chars().count();
+    if length < MIN_PASSWORD_LENGTH {
+        return Err(PasswordError::TooShort(MIN_PASSWORD_LENGTH));
+    }
+    if length > MAX_PASSWORD_LENGTH {
+        return Err(PasswordError::TooLong(MAX_PASSWORD_LENGTH));
+    }
+
+    Ok(())
+}
+
+// Argon2id hash of a password with a random salt, as a PHC string. Hashing
+// is deliberately slow, so call this off the async executor.
+pub fn hash_password(password: &str) -> Result<String, PasswordError> {
+    let salt = SaltString::generate(&mut OsRng);
+    Argon2::default()
+        .hash_password(password.as_bytes(), &salt)
+        .map(|hash| hash.to_string())
+        .map_err(PasswordError::Hash)
+}
+
+// Hash checked when there is no real one, so a login for an unknown email
+// or an account without a password takes as long as a real check
+fn dummy_hash() -> &'static str {
+    static DUMMY_HASH: OnceLock<String> = OnceLock::new();
+    DUMMY_HASH.get_or_init(|| hash_password("dummy password").expect("failed to hash dummy password"))
+}
+
+// Check a password against a stored hash. Argon2 compares the hashes in
+// constant time, and a missing hash costs as much as a real check but
+// never matches. Call this off the async executor.
+pub fn verify_password(password: &str, hash: Option<&str>) -> bool {
+    let (hash, exists) = match hash {
+        Some(hash) => (hash, true),
+        None => (dummy_hash(), false),
+    };
+
+    let parsed = match PasswordHash::new(hash) {
+        Ok(parsed) => parsed,
+        Err(_) => return false,
+    };
+    let matches = Argon2::default()
+        .verify_password(password.as_bytes(), &parsed)
+        .is_ok();
+
+    matches && exists
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn verifies_only_the_hashed_password() {
+        let hash = hash_password("correct horse").unwrap();
+
+        assert!(hash.starts_with("$argon2id$"));
+        assert!(verify_password("correct horse", Some(&hash)));
+        assert!(!verify_password("correct horsE", Some(&hash)));
+        assert!(!verify_password("correct horse", Some("not a hash")));
+        assert!(!verify_password("dummy password", None));
+
+        // Every hash gets its own salt
+        assert_ne!(hash, hash_password("correct horse").unwrap());
+    }
+
+    #[test]
+    fn enforces_length_limits() {
+        assert!(matches!(validate_password("short"), Err(PasswordError::TooShort(8))));
+        assert!(validate_password("long enough").is_ok());
+        assert!(validate_password(&"ü".repeat(MAX_PASSWORD_LENGTH)).is_ok());
+        assert!(matches!(
+            validate_password(&"x".repeat(MAX_PASSWORD_LENGTH + 1)),
+            Err(PasswordError::TooLong(128))
+        ));
+    }
+}
@@ -0,0 +1,205 @@
+use actix_web::{web, HttpRequest, HttpResponse, Responder};
+use chrono::{DateTime, Duration, Utc};
+use log::{error, info, warn};
+use serde::{Deserialize, Serialize};
+use sqlx::PgPool;
+use thiserror::Error;
+use uuid::Uuid;
+
+use crate::authentication::jwt::JwtConfig;
+use crate::middleware::auth::{get_claims_from_request, get_user_id_from_request};
+use crate::models::{RefreshToken, RevokedToken, User};
+
+// How often expired refresh tokens and revocations are cleaned up
+const REAPER_INTERVAL: std::time::Duration = std::time::Duration::from_secs(60 * 60);
+
+#[derive(Debug, Error)]
+pub enum TokenError {
+    #[error("refresh token is unknown, expired or revoked")]
+    Invalid,
+    // A token exchanged twice was most likely stolen
+    #[error("refresh token was already used")]
+    Reused,
+    #[error("failed to sign access token: {0}")]
+    Jwt(#[from] jsonwebtoken::errors::Error),
+    #[error(transparent)]
+    Database(#[from] sqlx::Error),
+}
+
+// An access token and the refresh token to renew it with
+#[derive(Debug, Serialize)]
+pub struct TokenResponse {
+    pub token: String,
+    pub refresh_token: String,
+    // Lifetime of the access token, in seconds
+    pub expires_in: i64,
+    pub user_id: String,
+    pub email: String,
+}
+
+#[derive(Debug, Deserialize)]
+pub struct RefreshRequest {
+    refresh_token: String,
+}
+
+#[derive(Debug, Deserialize)]
+pub struct LogoutRequest {
+    // Also end the session this refresh token belongs to
+    refresh_token: Option<String>,
+}
+
+// Sign an access token and store a new refresh token for the user. The
+// refresh token starts a new family unless it replaces one of `family_id`.
+async fn issue_tokens<'c>(
+    tx: &mut sqlx::Transaction<'c, sqlx::Postgres>,
+    jwt_config: &JwtConfig,
+    user: User,
+    family_id: Option<Uuid>,
+) -> Result<TokenResponse, TokenError> {
+    let token = jwt_config.generate_token(user.id, &user.email)?;
+
+    let ttl = Duration::seconds(jwt_config.refresh_expiration);
+    let (refresh_token, secret) = RefreshToken::issue(user.id, family_id, ttl);
+    refresh_token.create(&mut **tx).await?;
+
+    Ok(TokenResponse {
+        token,
+        refresh_token: secret,
+        expires_in: jwt_config.expiration,
+        user_id: user.id.to_string(),
+        email: user.email,
+    })
+}
+
+// Tokens for a user who just logged in
+pub async fn login_tokens(pool: &PgPool, jwt_config: &JwtConfig, user: User) -> Result<TokenResponse, TokenError> {
+    let mut tx = pool.begin().await?;
+    let tokens = issue_tokens(&mut tx, jwt_config, user, None).await?;
+    tx.commit().await?;
+
+    Ok(tokens)
+}
+
+// Exchange a refresh token for new tokens. Each refresh token works once;
+// presenting a used one again revokes its whole family, so a stolen token
+// stops working for the thief and the victim alike.
+pub async fn rotate_tokens(pool: &PgPool, jwt_config: &JwtConfig, token: &str) -> Result<TokenResponse, TokenError> {
+    let mut tx = pool.begin().await?;
+
+    let refresh_token = match RefreshToken::lock_by_token(&mut tx, token).await? {
+        Some(refresh_token) if refresh_token.is_active() => refresh_token,
+        _ => return Err(TokenError::Invalid),
+    };
+
+    if refresh_token.used_at.is_some() {
+        RefreshToken::revoke_family(&mut *tx, refresh_token.family_id).await?;
+        tx.commit().await?;
+        return Err(TokenError::Reused);
+    }
+
+    let user = match User::find_by_id(pool, refresh_token.user_id).await? {
+        Some(user) => user,
+        None => return Err(TokenError::Invalid),
+    };
+
+    refresh_token.mark_used(&mut *tx).await?;
+    let tokens = issue_tokens(&mut tx, jwt_config, user, Some(refresh_token.family_id)).await?;
+    tx.commit().await?;
+
+    Ok(tokens)
+}
+
+pub async fn refresh_token(
+    pool: web::Data<PgPool>,
+    jwt_config: web::Data<JwtConfig>,
+    body: web::Json<RefreshRequest>,
+) -> impl Responder {
+    match rotate_tokens(&pool, &jwt_config, &body.refresh_token).await {
+        Ok(tokens) => HttpResponse::Ok().json(tokens),
+        Err(TokenError::Invalid) => HttpResponse::Unauthorized().json(serde_json::json!({
+            "error": "Invalid refresh token"
+        })),
+        Err(TokenError::Reused) => {
+            warn!("Refresh token reused; revoked its session");
+            HttpResponse::Unauthorized().json(serde_json::json!({
+                "error": "Invalid refresh token"
+            }))
+        }
+        Err(e) => {
+            error!("Failed to refresh tokens: {:?}", e);
+            HttpResponse::InternalServerError().json(serde_json::json!({
+                "error": "Failed to refresh authentication token"
+            }))
+        }
+    }
+}
+
+// Revoke the access token the request was made with and, if given, the
+// session of a refresh token
+pub async fn logout(
+    req: HttpRequest,
+    pool: web::Data<PgPool>,
+    body: Option<web::Json<LogoutRequest>>,
+) -> impl Responder {
+    // Get user ID from request extensions (set by middleware)
+    let user_id = match get_user_id_from_request(&req) {
+        Some(id) => id,
+        None => {
+            return HttpResponse::Unauthorized().json(serde_json::json!({
+                "error": "Authentication required"
+            }));
+        }
+    };
+
+    // Requests made with an API key have no access token to revoke
+    if let Some(claims) = get_claims_from_request(&req) {
+        let expires_at = DateTime::<Utc>::from_timestamp(claims.exp, 0).unwrap_or_else(Utc::now);
+        if let Err(e) = RevokedToken::revoke(&pool, claims.jti, expires_at).await {
+            error!("Failed to revoke access token for user {}: {:?}", user_id, e);
+            return HttpResponse::InternalServerError().json(serde_json::json!({
+                "error": "Failed to log out"
+            }));
+        }
+    }
+
+    if let Some(token) = body.and_then(|body| body.into_inner().refresh_token) {
+        let revoked = match RefreshToken::find_by_token(&pool, &token).await {
+            // Another user's token is ignored rather than revoked
+            Ok(Some(refresh_token)) if refresh_token.user_id == user_id => {
+                RefreshToken::revoke_family(pool.get_ref(), refresh_token.family_id).await
+            }
+            Ok(_) => Ok(()),
+            Err(e) => Err(e),
+        };
+        if let Err(e) = revoked {
+            error!("Failed to revoke refresh token for user {}: {:?}", user_id, e);
+            return HttpResponse::InternalServerError().json(serde_json::json!({
+                "error": "Failed to log out"
+            }));
+        }
+    }
+
+    HttpResponse::NoContent().finish()
+}
+
+// Periodically delete expired refresh tokens and revocations of expired
+// access tokens
+pub async fn run_token_reaper(pool: PgPool) {
+    let mut interval = tokio::time::interval(REAPER_INTERVAL);
+
+    loop {
+        interval.tick().await;
+
+        let reaped = match RefreshToken::delete_expired(&pool).await {
+            Ok(refresh_tokens) => RevokedToken::delete_expired(&pool)
+                .await
+                .map(|revoked_tokens| refresh_tokens + revoked_tokens),
+            Err(e) => Err(e),
+        };
+        match reaped {
+            Ok(0) => {}
+            Ok(count) => info!("Reaped {} expired tokens", count),
+            Err(e) => error!("Failed to reap expired tokens: {:?}", e),
+        }
+    }
+}
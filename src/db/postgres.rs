@@ -14,4 +14,4 @@ pub async fn init_pool(database_url: &str) -> Result<DbPool> {
     sqlx::migrate!("./migrations").run(&pool).await?;
 
     Ok(pool)
-}
\ No newline at end of file
+}
@@ -1,14 +1,25 @@
 use actix_web::{web, HttpRequest, HttpResponse, Responder};
 use serde::{Deserialize, Serialize};
 use sqlx::PgPool;
+use log::{error, info};
 use uuid::Uuid;
 
-use crate::middleware::auth::{get_user_id_from_request};
-use crate::models::Bucket;
+use crate::config::Config;
+use crate::handlers::file::remove_file;
+use crate::handlers::multipart;
+use crate::handlers::organization::find_membership;
+use crate::handlers::s3::RESERVED_BUCKET_NAMES;
+use crate::middleware::auth::{access_denied, get_principal_from_request, get_user_id_from_request, scope_denied};
+use crate::models::{Action, Bucket, BucketAccess, CreateBucketError, File, MultipartUpload, PolicyAction, VersioningStatus};
+use crate::storage::Storage;
 
 #[derive(Debug, Deserialize)]
 pub struct CreateBucketRequest {
     bucket_name: String,
+    // Name of the organization to create the bucket in, instead of the
+    // user's own account
+    #[serde(default)]
+    organization: Option<String>,
 }
 
 #[derive(Debug, Serialize)]
@@ -20,6 +31,8 @@ pub struct CreateBucketResponse {
 #[derive(Debug, Serialize)]
 pub struct BucketListResponse {
     buckets: Vec<BucketInfo>,
+    // Buckets of other users shared with this one
+    shared_buckets: Vec<SharedBucketInfo>,
 }
 
 #[derive(Debug, Serialize)]
@@ -29,9 +42,18 @@ pub struct BucketInfo {
     created_at: chrono::DateTime<chrono::Utc>,
 }
 
+#[derive(Debug, Serialize)]
+pub struct SharedBucketInfo {
+    id: Uuid,
+    name: String,
+    access: BucketAccess,
+    created_at: chrono::DateTime<chrono::Utc>,
+}
+
 pub async fn create_bucket(
     req: HttpRequest,
     pool: web::Data<PgPool>,
+    config: web::Data<Config>,
     bucket_req: web::Json<CreateBucketRequest>,
 ) -> impl Responder {
     // Get user ID from request extensions (set by middleware)
@@ -46,49 +68,299 @@ pub async fn create_bucket(
 
     // Validate bucket name
     let bucket_name = &bucket_req.bucket_name;
-    if bucket_name.is_empty() || bucket_name.len() > 63 {
+    if let Err(e) = Bucket::validate_name(bucket_name) {
+        return HttpResponse::BadRequest().json(serde_json::json!({
+            "error": e.to_string()
+        }));
+    }
+    if RESERVED_BUCKET_NAMES.contains(&bucket_name.as_str()) {
         return HttpResponse::BadRequest().json(serde_json::json!({
-            "error": "Bucket name must be between 1 and 63 characters"
+            "error": format!("Bucket name \"{}\" is reserved", bucket_name)
         }));
     }
 
-    // Check if bucket already exists for this user
-    match Bucket::find_by_name_and_user(&pool, bucket_name, user_id).await {
-        Ok(Some(_)) => {
-            return HttpResponse::Conflict().json(serde_json::json!({
+    // Organization buckets are created by its owners and admins
+    let organization = match &bucket_req.organization {
+        Some(name) => match find_membership(&pool, name, user_id).await {
+            Ok((organization, role)) if role.bucket_access() == BucketAccess::Owner => Some(organization),
+            Ok(_) => {
+                return HttpResponse::Forbidden().json(serde_json::json!({
+                    "error": "Only owners and admins can create buckets in this organization"
+                }));
+            }
+            Err(response) => return response,
+        },
+        None => None,
+    };
+
+    let bucket = match &organization {
+        Some(organization) => Bucket::new_in_organization(bucket_name.clone(), organization),
+        None => Bucket::new(bucket_name.clone(), user_id),
+    };
+    if let Some(response) = scope_denied(&req, Action::Write, &bucket, None) {
+        return response;
+    }
+
+    // Save bucket to database. Names are only unique per owner, so another
+    // bucket of the user or organization it is created for, or any other
+    // with a global namespace, is what can have this name.
+    match bucket.create(&pool, config.global_bucket_namespace).await {
+        Ok(_) => {
+            HttpResponse::Created().json(CreateBucketResponse {
+                id: bucket.id,
+                name: bucket.name,
+            })
+        }
+        Err(CreateBucketError::NameTaken) => {
+            HttpResponse::Conflict().json(serde_json::json!({
                 "error": "Bucket with this name already exists"
+            }))
+        }
+        Err(CreateBucketError::Database(_)) => {
+            HttpResponse::InternalServerError().json(serde_json::json!({
+                "error": "Failed to create bucket"
+            }))
+        }
+    }
+}
+
+pub async fn list_buckets(
+    req: HttpRequest,
+    pool: web::Data<PgPool>,
+) -> impl Responder {
+    // Get the principal from request extensions (set by middleware)
+    let principal = match get_principal_from_request(&req) {
+        Some(principal) => principal,
+        None => {
+            return HttpResponse::Unauthorized().json(serde_json::json!({
+                "error": "Authentication required"
+            }));
+        }
+    };
+
+    if !principal.allows_action(Action::List) {
+        return HttpResponse::Forbidden().json(serde_json::json!({
+            "error": "This API key is not allowed to list"
+        }));
+    }
+
+    // Find all buckets for this user, leaving out those a scoped key cannot reach
+    let buckets = match Bucket::find_by_user_id(&pool, principal.user_id).await {
+        Ok(buckets) => buckets,
+        Err(e) => {
+            error!("Error fetching buckets: {:?}", e);
+            return HttpResponse::InternalServerError().json(serde_json::json!({
+                "error": "Failed to fetch buckets"
+            }));
+        }
+    };
+    let shared = match Bucket::find_shared_with_user(&pool, principal.user_id).await {
+        Ok(buckets) => buckets,
+        Err(e) => {
+            error!("Error fetching shared buckets: {:?}", e);
+            return HttpResponse::InternalServerError().json(serde_json::json!({
+                "error": "Failed to fetch buckets"
+            }));
+        }
+    };
+
+    // Convert buckets to response format
+    let bucket_infos = buckets
+        .into_iter()
+        .filter(|bucket| principal.allows_bucket(&bucket.qualified_name()))
+        .map(|bucket| BucketInfo {
+            id: bucket.id,
+            name: bucket.name,
+            created_at: bucket.created_at,
+        })
+        .collect();
+    let shared_infos = shared
+        .into_iter()
+        .filter(|bucket| principal.allows_bucket(&bucket.qualified_name()))
+        .map(|bucket| SharedBucketInfo {
+            id: bucket.id,
+            access: bucket.access(),
+            name: bucket.name,
+            created_at: bucket.created_at,
+        })
+        .collect();
+
+    HttpResponse::Ok().json(BucketListResponse {
+        buckets: bucket_infos,
+        shared_buckets: shared_infos,
+    })
+}
+
+#[derive(Debug, Deserialize)]
+pub struct DeleteBucketQuery {
+    bucket_name: String,
+    #[serde(default)]
+    force: bool,
+}
+
+pub async fn delete_bucket(
+    req: HttpRequest,
+    pool: web::Data<PgPool>,
+    storage: web::Data<dyn Storage + Send + Sync>,
+    query: web::Query<DeleteBucketQuery>,
+) -> impl Responder {
+    // Get user ID from request extensions (set by middleware)
+    let user_id = match get_user_id_from_request(&req) {
+        Some(id) => id,
+        None => {
+            return HttpResponse::Unauthorized().json(serde_json::json!({
+                "error": "Authentication required"
             }));
         }
+    };
+
+    // Find bucket by name and user
+    let bucket = match Bucket::find_by_name_and_user(&pool, &query.bucket_name, user_id).await {
+        Ok(Some(bucket)) => bucket,
         Ok(None) => {
-            // Create new bucket
-            let bucket = Bucket::new(bucket_name.clone(), user_id);
-
-            // Save bucket to database
-            match bucket.create(&pool).await {
-                Ok(_) => {
-                    HttpResponse::Created().json(CreateBucketResponse {
-                        id: bucket.id,
-                        name: bucket.name,
-                    })
-                }
-                Err(_) => {
-                    HttpResponse::InternalServerError().json(serde_json::json!({
-                        "error": "Failed to create bucket"
-                    }))
-                }
-            }
+            return HttpResponse::NotFound().json(serde_json::json!({
+                "error": "Bucket not found"
+            }));
         }
         Err(_) => {
+            return HttpResponse::InternalServerError().json(serde_json::json!({
+                "error": "Failed to check bucket"
+            }));
+        }
+    };
+
+    if let Some(response) = scope_denied(&req, Action::Delete, &bucket, None) {
+        return response;
+    }
+    if let Some(response) = access_denied(&req, &bucket, PolicyAction::DeleteBucket, None) {
+        return response;
+    }
+
+    // With force, remove every object and in-progress upload first so no
+    // content is orphaned on disk by the cascade on buckets.id
+    if query.force {
+        let files = match File::find_versions(&pool, bucket.id, None).await {
+            Ok(files) => files,
+            Err(e) => {
+                error!("Error fetching files for bucket {}: {:?}", bucket.id, e);
+                return HttpResponse::InternalServerError().json(serde_json::json!({
+                    "error": "Failed to fetch files"
+                }));
+            }
+        };
+
+        for file in &files {
+            if let Err(e) = remove_file(&pool, storage.get_ref(), file).await {
+                error!("Failed to delete file {}: {:?}", file.id, e);
+                return HttpResponse::InternalServerError().json(serde_json::json!({
+                    "error": "Failed to delete bucket contents"
+                }));
+            }
+        }
+
+        let uploads = match MultipartUpload::find_by_bucket_id(&pool, bucket.id).await {
+            Ok(uploads) => uploads,
+            Err(e) => {
+                error!("Error fetching uploads for bucket {}: {:?}", bucket.id, e);
+                return HttpResponse::InternalServerError().json(serde_json::json!({
+                    "error": "Failed to fetch uploads"
+                }));
+            }
+        };
+
+        for upload in &uploads {
+            if let Err(e) = multipart::abort(&pool, storage.get_ref(), upload).await {
+                error!("Failed to abort upload {}: {:?}", upload.id, e);
+                return HttpResponse::InternalServerError().json(serde_json::json!({
+                    "error": "Failed to delete bucket contents"
+                }));
+            }
+        }
+    }
+
+    match bucket.delete_if_empty(&pool).await {
+        Ok(true) => {
+            info!("Bucket deleted: {}", bucket.id);
+            HttpResponse::NoContent().finish()
+        }
+        Ok(false) => {
+            HttpResponse::Conflict().json(serde_json::json!({
+                "error": "Bucket is not empty"
+            }))
+        }
+        Err(e) => {
+            error!("Failed to delete bucket {}: {:?}", bucket.id, e);
             HttpResponse::InternalServerError().json(serde_json::json!({
-                "error": "Failed to check existing buckets"
+                "error": "Failed to delete bucket"
             }))
         }
     }
 }
 
-pub async fn list_buckets(
+#[derive(Debug, Deserialize)]
+pub struct VersioningQuery {
+    bucket_name: String,
+}
+
+#[derive(Debug, Deserialize)]
+pub struct SetVersioningRequest {
+    bucket_name: String,
+    status: VersioningStatus,
+}
+
+#[derive(Debug, Serialize)]
+pub struct VersioningResponse {
+    bucket_name: String,
+    // None while versioning has never been configured
+    status: Option<VersioningStatus>,
+}
+
+pub async fn get_versioning(
+    req: HttpRequest,
+    pool: web::Data<PgPool>,
+    query: web::Query<VersioningQuery>,
+) -> impl Responder {
+    // Get user ID from request extensions (set by middleware)
+    let user_id = match get_user_id_from_request(&req) {
+        Some(id) => id,
+        None => {
+            return HttpResponse::Unauthorized().json(serde_json::json!({
+                "error": "Authentication required"
+            }));
+        }
+    };
+
+    let bucket = match Bucket::find_by_name_and_user(&pool, &query.bucket_name, user_id).await {
+        Ok(Some(bucket)) => bucket,
+        Ok(None) => {
+            return HttpResponse::NotFound().json(serde_json::json!({
+                "error": "Bucket not found"
+            }));
+        }
+        Err(_) => {
+            return HttpResponse::InternalServerError().json(serde_json::json!({
+                "error": "Failed to check bucket"
+            }));
+        }
+    };
+
+    if let Some(response) = scope_denied(&req, Action::Read, &bucket, None) {
+        return response;
+    }
+    if let Some(response) = access_denied(&req, &bucket, PolicyAction::GetBucketVersioning, None) {
+        return response;
+    }
+
+    HttpResponse::Ok().json(VersioningResponse {
+        status: bucket.versioning_status(),
+        bucket_name: bucket.name,
+    })
+}
+
+pub async fn set_versioning(
     req: HttpRequest,
     pool: web::Data<PgPool>,
+    body: web::Json<SetVersioningRequest>,
 ) -> impl Responder {
     // Get user ID from request extensions (set by middleware)
     let user_id = match get_user_id_from_request(&req) {
@@ -100,27 +372,41 @@ pub async fn list_buckets(
         }
     };
 
-    // Find all buckets for this user
-    match Bucket::find_by_user_id(&pool, user_id).await {
-        Ok(buckets) => {
-            // Convert buckets to response format
-            let bucket_infos = buckets.into_iter().map(|bucket| {
-                BucketInfo {
-                    id: bucket.id,
-                    name: bucket.name,
-                    created_at: bucket.created_at,
-                }
-            }).collect();
-
-            HttpResponse::Ok().json(BucketListResponse {
-                buckets: bucket_infos,
+    // Find bucket by name and user
+    let mut bucket = match Bucket::find_by_name_and_user(&pool, &body.bucket_name, user_id).await {
+        Ok(Some(bucket)) => bucket,
+        Ok(None) => {
+            return HttpResponse::NotFound().json(serde_json::json!({
+                "error": "Bucket not found"
+            }));
+        }
+        Err(_) => {
+            return HttpResponse::InternalServerError().json(serde_json::json!({
+                "error": "Failed to check bucket"
+            }));
+        }
+    };
+
+    if let Some(response) = scope_denied(&req, Action::Write, &bucket, None) {
+        return response;
+    }
+    if let Some(response) = access_denied(&req, &bucket, PolicyAction::PutBucketVersioning, None) {
+        return response;
+    }
+
+    match bucket.set_versioning(&pool, body.status).await {
+        Ok(()) => {
+            info!("Versioning for bucket {} set to {}", bucket.id, body.status.as_str());
+            HttpResponse::Ok().json(VersioningResponse {
+                status: bucket.versioning_status(),
+                bucket_name: bucket.name,
             })
         }
         Err(e) => {
-            eprintln!("Error fetching buckets: {:?}", e);
+            error!("Failed to set versioning for bucket {}: {:?}", bucket.id, e);
             HttpResponse::InternalServerError().json(serde_json::json!({
-                "error": "Failed to fetch buckets"
+                "error": "Failed to update bucket versioning"
             }))
         }
     }
-}
\ No newline at end of file
+}
@@ -0,0 +1,249 @@
+use actix_web::http::header::{self, HeaderMap};
+use actix_web::HttpRequest;
+use chrono::{DateTime, Utc};
+
+use crate::models::File;
+
+// Outcome of evaluating a request's preconditions
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Precondition {
+    Proceed,
+    // Only for reads: the client's copy is current (304)
+    NotModified,
+    // 412
+    Failed,
+}
+
+// ETag header value for a stored content hash
+pub fn quoted_etag(etag: &str) -> String {
+    format!("\"{}\"", etag)
+}
+
+// Conditional request headers (RFC 9110 section 13). Dates that do not
+// parse are ignored, as the RFC requires.
+#[derive(Debug, Default)]
+pub struct Preconditions {
+    if_match: Option<String>,
+    if_none_match: Option<String>,
+    if_modified_since: Option<DateTime<Utc>>,
+    if_unmodified_since: Option<DateTime<Utc>>,
+    if_range: Option<String>,
+}
+
+impl Preconditions {
+    pub fn from_request(req: &HttpRequest) -> Self {
+        Self::from_headers(req.headers())
+    }
+
+    fn from_headers(headers: &HeaderMap) -> Self {
+        let text = |name| {
+            headers
+                .get(name)
+                .and_then(|value| value.to_str().ok())
+                .map(|value| value.trim().to_string())
+        };
+        let date = |name| text(name).and_then(|value| parse_http_date(&value));
+
+        Self {
+            if_match: text(header::IF_MATCH),
+            if_none_match: text(header::IF_NONE_MATCH),
+            if_modified_since: date(header::IF_MODIFIED_SINCE),
+            if_unmodified_since: date(header::IF_UNMODIFIED_SINCE),
+            if_range: text(header::IF_RANGE),
+        }
+    }
+
+    // Whether any header applies to writes
+    pub fn constrains_write(&self) -> bool {
+        self.if_match.is_some() || self.if_none_match.is_some() || self.if_unmodified_since.is_some()
+    }
+
+    // Preconditions of a GET or HEAD for `file`
+    pub fn check_read(&self, file: &File) -> Precondition {
+        let current = Some(file);
+
+        if let Some(if_match) = &self.if_match {
+            if !tag_matches(if_match, current, false) {
+                return Precondition::Failed;
+            }
+        } else if let Some(since) = self.if_unmodified_since {
+            if modified_since(file, since) {
+                return Precondition::Failed;
+            }
+        }
+
+        if let Some(if_none_match) = &self.if_none_match {
+            if tag_matches(if_none_match, current, true) {
+                return Precondition::NotModified;
+            }
+        } else if let Some(since) = self.if_modified_since {
+            if !modified_since(file, since) {
+                return Precondition::NotModified;
+            }
+        }
+
+        Precondition::Proceed
+    }
+
+    // Preconditions of a write over `current`, the key's latest object if
+    // it has one. `If-None-Match: *` makes the write create-only and
+    // `If-Match` makes it replace only the given version.
+    pub fn check_write(&self, current: Option<&File>) -> Precondition {
+        if let Some(if_match) = &self.if_match {
+            if !tag_matches(if_match, current, false) {
+                return Precondition::Failed;
+            }
+        } else if let (Some(since), Some(file)) = (self.if_unmodified_since, current) {
+            if modified_since(file, since) {
+                return Precondition::Failed;
+            }
+        }
+
+        if let Some(if_none_match) = &self.if_none_match {
+            if tag_matches(if_none_match, current, true) {
+                return Precondition::Failed;
+            }
+        }
+
+        Precondition::Proceed
+    }
+
+    // Whether a Range header should be honoured. With `If-Range` the range
+    // only applies while the object is unchanged; otherwise the whole
+    // object is sent.
+    pub fn range_applies(&self, file: &File) -> bool {
+        let if_range = match &self.if_range {
+            Some(if_range) => if_range,
+            None => return true,
+        };
+
+        if if_range.starts_with('"') || if_range.starts_with("W/") {
+            // If-Range requires a strong match
+            return tag_matches(if_range, Some(file), false) && !if_range.contains(',');
+        }
+
+        match parse_http_date(if_range) {
+            Some(date) => file.created_at.timestamp() == date.timestamp(),
+            None => false,
+        }
+    }
+}
+
+// HTTP dates only have second precision
+fn modified_since(file: &File, since: DateTime<Utc>) -> bool {
+    file.created_at.timestamp() > since.timestamp()
+}
+
+fn parse_http_date(value: &str) -> Option<DateTime<Utc>> {
+    DateTime::parse_from_rfc2822(value)
+        .ok()
+        .map(|date| date.with_timezone(&Utc))
+}
+
+// Match an `If-Match`-style list of entity tags, or `*`, against the current
+// object. Weak tags (`W/"..."`) only match under weak comparison.
+fn tag_matches(header: &str, current: Option<&File>, weak_comparison: bool) -> bool {
+    let file = match current {
+        Some(file) => file,
+        None => return false,
+    };
+
+    header.split(',').map(str::trim).any(|tag| {
+        if tag == "*" {
+            return true;
+        }
+
+        let (weak, tag) = match tag.strip_prefix("W/") {
+            Some(tag) => (true, tag),
+            None => (false, tag),
+        };
+        (weak_comparison || !weak) && file.etag.as_deref() == Some(tag.trim_matches('"'))
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use actix_web::http::header::{HeaderName, HeaderValue};
+    use uuid::Uuid;
+
+    fn file(etag: &str, modified: &str) -> File {
+        let mut file = File::new(
+            "key".to_string(),
+            None,
+            0,
+            Uuid::new_v4(),
+            String::new(),
+            etag.to_string(),
+        );
+        file.created_at = parse_http_date(modified).unwrap();
+        file
+    }
+
+    fn preconditions(headers: &[(HeaderName, &'static str)]) -> Preconditions {
+        let mut map = HeaderMap::new();
+        for (name, value) in headers {
+            map.insert(name.clone(), HeaderValue::from_static(value));
+        }
+        Preconditions::from_headers(&map)
+    }
+
+    const MODIFIED: &str = "Tue, 15 Nov 1994 08:12:31 GMT";
+
+    #[test]
+    fn conditional_get() {
+        let file = file("abc", MODIFIED);
+
+        let check = |headers| preconditions(headers).check_read(&file);
+        assert_eq!(check(&[]), Precondition::Proceed);
+        assert_eq!(check(&[(header::IF_NONE_MATCH, "\"abc\"")]), Precondition::NotModified);
+        assert_eq!(check(&[(header::IF_NONE_MATCH, "\"x\", W/\"abc\"")]), Precondition::NotModified);
+        assert_eq!(check(&[(header::IF_NONE_MATCH, "\"x\"")]), Precondition::Proceed);
+        assert_eq!(check(&[(header::IF_MATCH, "\"x\"")]), Precondition::Failed);
+        assert_eq!(check(&[(header::IF_MATCH, "W/\"abc\"")]), Precondition::Failed);
+        assert_eq!(check(&[(header::IF_MODIFIED_SINCE, MODIFIED)]), Precondition::NotModified);
+        assert_eq!(
+            check(&[(header::IF_MODIFIED_SINCE, "Tue, 15 Nov 1994 08:12:30 GMT")]),
+            Precondition::Proceed
+        );
+        assert_eq!(
+            check(&[(header::IF_UNMODIFIED_SINCE, "Mon, 14 Nov 1994 08:12:31 GMT")]),
+            Precondition::Failed
+        );
+
+        // If-None-Match takes precedence over If-Modified-Since
+        assert_eq!(
+            check(&[(header::IF_NONE_MATCH, "\"x\""), (header::IF_MODIFIED_SINCE, MODIFIED)]),
+            Precondition::Proceed
+        );
+    }
+
+    #[test]
+    fn conditional_put() {
+        let existing = file("abc", MODIFIED);
+
+        let create_only = preconditions(&[(header::IF_NONE_MATCH, "*")]);
+        assert_eq!(create_only.check_write(None), Precondition::Proceed);
+        assert_eq!(create_only.check_write(Some(&existing)), Precondition::Failed);
+
+        let swap = preconditions(&[(header::IF_MATCH, "\"abc\"")]);
+        assert_eq!(swap.check_write(Some(&existing)), Precondition::Proceed);
+        assert_eq!(swap.check_write(None), Precondition::Failed);
+
+        let stale = preconditions(&[(header::IF_MATCH, "\"old\"")]);
+        assert_eq!(stale.check_write(Some(&existing)), Precondition::Failed);
+        assert!(!preconditions(&[(header::IF_MODIFIED_SINCE, MODIFIED)]).constrains_write());
+    }
+
+    #[test]
+    fn if_range_needs_an_unchanged_object() {
+        let file = file("abc", MODIFIED);
+
+        assert!(preconditions(&[]).range_applies(&file));
+        assert!(preconditions(&[(header::IF_RANGE, "\"abc\"")]).range_applies(&file));
+        assert!(preconditions(&[(header::IF_RANGE, MODIFIED)]).range_applies(&file));
+        assert!(!preconditions(&[(header::IF_RANGE, "\"x\"")]).range_applies(&file));
+        assert!(!preconditions(&[(header::IF_RANGE, "W/\"abc\"")]).range_applies(&file));
+        assert!(!preconditions(&[(header::IF_RANGE, "Mon, 14 Nov 1994 08:12:31 GMT")]).range_applies(&file));
+    }
+}
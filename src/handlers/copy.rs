@@ -0,0 +1,354 @@
+use actix_web::{web, HttpRequest, HttpResponse, Responder};
+use log::{error, info};
+use serde::Deserialize;
+use sqlx::PgPool;
+use thiserror::Error;
+use uuid::Uuid;
+
+use crate::handlers::conditional::{Precondition, Preconditions};
+use crate::handlers::file::{add_delete_marker, publish_file, FileInfoResponse, PublishError};
+use crate::handlers::metadata::{check_header_value, validate_metadata};
+use crate::middleware::auth::{access_denied, get_user_id_from_request, scope_denied};
+use crate::models::{Action, Bucket, File, ObjectMetadata, PolicyAction, TagTarget};
+use crate::storage::{Storage, StorageError};
+
+#[derive(Debug, Error)]
+pub enum CopyError {
+    #[error("source and destination are the same object")]
+    SameObject,
+    #[error("source object no longer exists")]
+    SourceNotFound,
+    #[error("precondition failed")]
+    PreconditionFailed,
+    #[error("failed to remove the source object: {0}")]
+    RemoveSource(anyhow::Error),
+    #[error(transparent)]
+    Storage(#[from] StorageError),
+    #[error(transparent)]
+    Database(#[from] sqlx::Error),
+}
+
+impl From<PublishError> for CopyError {
+    fn from(err: PublishError) -> Self {
+        match err {
+            PublishError::PreconditionFailed => CopyError::PreconditionFailed,
+            PublishError::Database(e) => CopyError::Database(e),
+        }
+    }
+}
+
+// Content type and metadata to give a copy instead of the source's
+#[derive(Debug, Default)]
+pub struct Replacement {
+    pub content_type: Option<String>,
+    pub metadata: Option<ObjectMetadata>,
+}
+
+// Copy a file version to `filename` in `destination`. The content is
+// copied inside the storage backend; the copy keeps the source's tags and,
+// unless replaced, its content type and metadata. `preconditions` apply to
+// the destination key.
+pub async fn copy_object(
+    pool: &PgPool,
+    storage: &(dyn Storage + Send + Sync),
+    source: &File,
+    destination: &Bucket,
+    filename: String,
+    replacement: Replacement,
+    preconditions: &Preconditions,
+) -> Result<File, CopyError> {
+    let tags = TagTarget::File(source.id).tags(pool).await?;
+
+    let storage_path = storage
+        .copy_file(&source.storage_path, &destination.name, Uuid::new_v4(), &filename)
+        .await
+        .map_err(|e| match e {
+            StorageError::NotFound(_) => CopyError::SourceNotFound,
+            e => CopyError::Storage(e),
+        })?;
+
+    let mut file = File::new(
+        filename,
+        replacement.content_type.or_else(|| source.content_type.clone()),
+        source.size,
+        destination.id,
+        storage_path,
+        source.etag.clone().unwrap_or_default(),
+    );
+    file.metadata.0 = replacement.metadata.unwrap_or_else(|| source.metadata.0.clone());
+
+    publish_file(pool, storage, destination.versioning_status(), &mut file, preconditions).await?;
+
+    if !tags.is_empty() {
+        let mut tx = pool.begin().await?;
+        TagTarget::File(file.id).replace_tags(&mut tx, &tags).await?;
+        tx.commit().await?;
+    }
+
+    Ok(file)
+}
+
+// Move the latest version of a key to `filename` in `destination`. The row
+// is moved in place, keeping its content and tags, since every bucket
+// shares one storage backend. Buckets with versioning configured keep the
+// source's history instead: the object is copied and the source key gets a
+// delete marker.
+pub async fn move_object(
+    pool: &PgPool,
+    storage: &(dyn Storage + Send + Sync),
+    source_bucket: &Bucket,
+    mut source: File,
+    destination: &Bucket,
+    filename: String,
+    preconditions: &Preconditions,
+) -> Result<File, CopyError> {
+    if source.bucket_id == destination.id && source.filename == filename {
+        return Err(CopyError::SameObject);
+    }
+
+    if source_bucket.versioning.is_some() {
+        let source_filename = source.filename.clone();
+        let file = copy_object(pool, storage, &source, destination, filename, Replacement::default(), preconditions)
+            .await?;
+        add_delete_marker(pool, storage, source_bucket, &source_filename)
+            .await
+            .map_err(CopyError::RemoveSource)?;
+        return Ok(file);
+    }
+
+    let mut tx = pool.begin().await?;
+    File::lock_keys(&mut tx, &mut [(source.bucket_id, &source.filename), (destination.id, &filename)]).await?;
+
+    // The source may have been replaced or deleted since it was looked up
+    let current = File::lock_latest(&mut tx, source.bucket_id, &source.filename).await?;
+    if current.map(|file| file.id) != Some(source.id) {
+        return Err(CopyError::SourceNotFound);
+    }
+
+    if preconditions.constrains_write() {
+        let current = File::lock_latest(&mut tx, destination.id, &filename).await?;
+        if preconditions.check_write(current.as_ref()) == Precondition::Failed {
+            return Err(CopyError::PreconditionFailed);
+        }
+    }
+
+    let replaced = source.rename(&mut tx, destination.id, filename, destination.versioning_status()).await?;
+    tx.commit().await?;
+
+    if let Some(path) = replaced {
+        if let Err(e) = storage.delete_file(&path).await {
+            error!("Failed to remove replaced file content {}: {:?}", path, e);
+        }
+    }
+
+    Ok(source)
+}
+
+#[derive(Debug, Deserialize)]
+pub struct CopyFileRequest {
+    source_bucket: String,
+    source_filename: String,
+    source_version_id: Option<String>,
+    destination_bucket: String,
+    // Defaults to the source filename
+    destination_filename: Option<String>,
+    // Replace the source's content type or metadata on the copy
+    content_type: Option<String>,
+    metadata: Option<ObjectMetadata>,
+}
+
+#[derive(Debug, Deserialize)]
+pub struct MoveFileRequest {
+    source_bucket: String,
+    source_filename: String,
+    destination_bucket: String,
+    destination_filename: Option<String>,
+}
+
+// Look up both buckets of a copy or move for the requesting user
+async fn find_buckets(
+    req: &HttpRequest,
+    pool: &PgPool,
+    source_bucket: &str,
+    destination_bucket: &str,
+) -> Result<(Bucket, Bucket), HttpResponse> {
+    // Get user ID from request extensions (set by middleware)
+    let user_id = match get_user_id_from_request(req) {
+        Some(id) => id,
+        None => {
+            return Err(HttpResponse::Unauthorized().json(serde_json::json!({
+                "error": "Authentication required"
+            })));
+        }
+    };
+
+    let source = find_bucket(pool, source_bucket, user_id, "Source bucket not found").await?;
+    let destination = find_bucket(pool, destination_bucket, user_id, "Destination bucket not found").await?;
+    Ok((source, destination))
+}
+
+async fn find_bucket(pool: &PgPool, name: &str, user_id: Uuid, missing: &str) -> Result<Bucket, HttpResponse> {
+    match Bucket::find_by_name_and_user(pool, name, user_id).await {
+        Ok(Some(bucket)) => Ok(bucket),
+        Ok(None) => Err(HttpResponse::NotFound().json(serde_json::json!({
+            "error": missing
+        }))),
+        Err(e) => {
+            error!("Database error when checking bucket {}: {:?}", name, e);
+            Err(HttpResponse::InternalServerError().json(serde_json::json!({
+                "error": "Failed to check bucket"
+            })))
+        }
+    }
+}
+
+fn copy_error_response(err: CopyError) -> HttpResponse {
+    match err {
+        CopyError::SameObject => HttpResponse::BadRequest().json(serde_json::json!({
+            "error": "Source and destination are the same file"
+        })),
+        CopyError::SourceNotFound => HttpResponse::NotFound().json(serde_json::json!({
+            "error": "File not found"
+        })),
+        CopyError::PreconditionFailed => HttpResponse::PreconditionFailed().json(serde_json::json!({
+            "error": "Precondition failed"
+        })),
+        e => {
+            error!("Failed to copy file: {:?}", e);
+            HttpResponse::InternalServerError().json(serde_json::json!({
+                "error": "Failed to copy file"
+            }))
+        }
+    }
+}
+
+pub async fn copy_file(
+    req: HttpRequest,
+    pool: web::Data<PgPool>,
+    storage: web::Data<dyn Storage + Send + Sync>,
+    body: web::Json<CopyFileRequest>,
+) -> impl Responder {
+    let body = body.into_inner();
+    let filename = body.destination_filename.clone().unwrap_or_else(|| body.source_filename.clone());
+
+    let (source_bucket, destination) =
+        match find_buckets(&req, &pool, &body.source_bucket, &body.destination_bucket).await {
+            Ok(buckets) => buckets,
+            Err(response) => return response,
+        };
+
+    let denied = scope_denied(&req, Action::Read, &source_bucket, Some(&body.source_filename))
+        .or_else(|| scope_denied(&req, Action::Write, &destination, Some(&filename)))
+        .or_else(|| access_denied(&req, &source_bucket, PolicyAction::GetObject, Some(&body.source_filename)))
+        .or_else(|| access_denied(&req, &destination, PolicyAction::PutObject, Some(&filename)));
+    if let Some(response) = denied {
+        return response;
+    }
+
+    // Both are replayed as response headers, so they must be valid as such
+    let checked = body
+        .metadata
+        .as_ref()
+        .map_or(Ok(()), validate_metadata)
+        .and_then(|()| {
+            body.content_type
+                .as_deref()
+                .map_or(Ok(()), |content_type| check_header_value("content-type", content_type))
+        });
+    if let Err(e) = checked {
+        return HttpResponse::BadRequest().json(serde_json::json!({
+            "error": e.to_string()
+        }));
+    }
+
+    let found = match &body.source_version_id {
+        Some(version_id) => File::find_version(&pool, &body.source_filename, source_bucket.id, version_id).await,
+        None => File::find_by_filename_and_bucket(&pool, &body.source_filename, source_bucket.id).await,
+    };
+    let source = match found {
+        // Delete markers have no content to copy
+        Ok(Some(file)) if !file.is_delete_marker => file,
+        Ok(_) => {
+            return HttpResponse::NotFound().json(serde_json::json!({
+                "error": "File not found"
+            }));
+        }
+        Err(_) => {
+            return HttpResponse::InternalServerError().json(serde_json::json!({
+                "error": "Failed to fetch file info"
+            }));
+        }
+    };
+
+    let replacement = Replacement {
+        content_type: body.content_type,
+        metadata: body.metadata,
+    };
+    let preconditions = Preconditions::from_request(&req);
+
+    match copy_object(&pool, storage.get_ref(), &source, &destination, filename, replacement, &preconditions).await {
+        Ok(file) => {
+            info!(
+                "File {} copied to {}/{} ({}, version {})",
+                source.id, destination.name, file.filename, file.id, file.version_id
+            );
+            HttpResponse::Created().json(FileInfoResponse::from(file))
+        }
+        Err(e) => copy_error_response(e),
+    }
+}
+
+pub async fn move_file(
+    req: HttpRequest,
+    pool: web::Data<PgPool>,
+    storage: web::Data<dyn Storage + Send + Sync>,
+    body: web::Json<MoveFileRequest>,
+) -> impl Responder {
+    let body = body.into_inner();
+    let filename = body.destination_filename.clone().unwrap_or_else(|| body.source_filename.clone());
+
+    let (source_bucket, destination) =
+        match find_buckets(&req, &pool, &body.source_bucket, &body.destination_bucket).await {
+            Ok(buckets) => buckets,
+            Err(response) => return response,
+        };
+
+    // Moving reads and removes the source
+    let denied = scope_denied(&req, Action::Read, &source_bucket, Some(&body.source_filename))
+        .or_else(|| scope_denied(&req, Action::Delete, &source_bucket, Some(&body.source_filename)))
+        .or_else(|| scope_denied(&req, Action::Write, &destination, Some(&filename)))
+        .or_else(|| access_denied(&req, &source_bucket, PolicyAction::GetObject, Some(&body.source_filename)))
+        .or_else(|| access_denied(&req, &source_bucket, PolicyAction::DeleteObject, Some(&body.source_filename)))
+        .or_else(|| access_denied(&req, &destination, PolicyAction::PutObject, Some(&filename)));
+    if let Some(response) = denied {
+        return response;
+    }
+
+    let source = match File::find_by_filename_and_bucket(&pool, &body.source_filename, source_bucket.id).await {
+        Ok(Some(file)) => file,
+        Ok(None) => {
+            return HttpResponse::NotFound().json(serde_json::json!({
+                "error": "File not found"
+            }));
+        }
+        Err(_) => {
+            return HttpResponse::InternalServerError().json(serde_json::json!({
+                "error": "Failed to fetch file info"
+            }));
+        }
+    };
+
+    let source_id = source.id;
+    let preconditions = Preconditions::from_request(&req);
+
+    match move_object(&pool, storage.get_ref(), &source_bucket, source, &destination, filename, &preconditions).await {
+        Ok(file) => {
+            info!(
+                "File {} moved to {}/{} ({}, version {})",
+                source_id, destination.name, file.filename, file.id, file.version_id
+            );
+            HttpResponse::Ok().json(FileInfoResponse::from(file))
+        }
+        Err(e) => copy_error_response(e),
+    }
+}
@@ -1,15 +1,25 @@
 use actix_multipart::Multipart;
+use actix_web::body::SizedStream;
+use actix_web::http::header::{
+    self, Charset, ContentDisposition, DispositionParam, DispositionType, ExtendedValue,
+};
 use actix_web::{web, HttpRequest, HttpResponse, Responder};
 use futures::{StreamExt, TryStreamExt};
 use serde::{Deserialize, Serialize};
 use sqlx::PgPool;
-use std::io::Write;
 use log::{error, info};
+use thiserror::Error;
 use uuid::Uuid;
 
-use crate::models::{Bucket, File};
-use crate::storage::Storage;
-use crate::middleware::auth::get_user_id_from_request;
+use crate::config::Config;
+use crate::handlers::conditional::{quoted_etag, Precondition, Preconditions};
+use crate::handlers::listing::{self, ListOptions, MAX_KEYS_LIMIT};
+use crate::handlers::metadata::{insert_metadata_headers, metadata_from_headers};
+use crate::handlers::range::{self, PartialContent};
+use crate::handlers::s3::http_date;
+use crate::models::{Action, Bucket, File, ObjectMetadata, PolicyAction, VersioningStatus};
+use crate::storage::{ObjectUpload, Storage, StorageError};
+use crate::middleware::auth::{access_denied, get_user_id_from_request, scope_denied};
 
 #[derive(Debug, Deserialize)]
 pub struct UploadFileQuery {
@@ -22,17 +32,71 @@ pub struct FileInfoResponse {
     filename: String,
     content_type: Option<String>,
     size: i64,
+    version_id: String,
+    etag: Option<String>,
+    metadata: ObjectMetadata,
     created_at: chrono::DateTime<chrono::Utc>,
 }
 
+impl From<File> for FileInfoResponse {
+    fn from(file: File) -> Self {
+        Self {
+            id: file.id,
+            filename: file.filename,
+            content_type: file.content_type,
+            size: file.size,
+            version_id: file.version_id,
+            etag: file.etag,
+            metadata: file.metadata.0,
+            created_at: file.created_at,
+        }
+    }
+}
+
+#[derive(Debug, Serialize)]
+pub struct FileVersionResponse {
+    filename: String,
+    version_id: String,
+    is_latest: bool,
+    is_delete_marker: bool,
+    size: i64,
+    etag: Option<String>,
+    created_at: chrono::DateTime<chrono::Utc>,
+}
+
+impl From<File> for FileVersionResponse {
+    fn from(file: File) -> Self {
+        Self {
+            filename: file.filename,
+            version_id: file.version_id,
+            is_latest: file.is_latest,
+            is_delete_marker: file.is_delete_marker,
+            size: file.size,
+            etag: file.etag,
+            created_at: file.created_at,
+        }
+    }
+}
+
 #[derive(Debug, Deserialize)]
 pub struct ListFilesQuery {
     bucket_name: String,
+    prefix: Option<String>,
+    delimiter: Option<String>,
+    max_keys: Option<usize>,
+    continuation_token: Option<String>,
+    // Only list files with this tag, e.g. `tag_key=env&tag_value=staging`
+    tag_key: Option<String>,
+    tag_value: Option<String>,
 }
 
 #[derive(Debug, Serialize)]
 pub struct FileListResponse {
     files: Vec<FileInfoResponse>,
+    // Keys rolled up at the delimiter, like folders
+    common_prefixes: Vec<String>,
+    is_truncated: bool,
+    next_continuation_token: Option<String>,
 }
 
 pub async fn list_files(
@@ -50,6 +114,8 @@ pub async fn list_files(
         }
     };
 
+    let prefix = query.prefix.as_deref().unwrap_or("");
+
     // Find bucket by name and user
     let bucket = match Bucket::find_by_name_and_user(&pool, &query.bucket_name, user_id).await {
         Ok(Some(bucket)) => bucket,
@@ -65,22 +131,52 @@ pub async fn list_files(
         }
     };
 
-    // Find all files in this bucket
-    match File::find_by_bucket_id(&pool, bucket.id).await {
-        Ok(files) => {
+    // Listings are limited to the prefixes a scoped key can reach
+    if let Some(response) = scope_denied(&req, Action::List, &bucket, Some(prefix)) {
+        return response;
+    }
+    if let Some(response) = access_denied(&req, &bucket, PolicyAction::ListBucket, Some(prefix)) {
+        return response;
+    }
+
+    let max_keys = query.max_keys.unwrap_or(MAX_KEYS_LIMIT).min(MAX_KEYS_LIMIT);
+    let start = match &query.continuation_token {
+        Some(token) => match listing::decode_token(token) {
+            Some(start) => Some(start),
+            None => {
+                return HttpResponse::BadRequest().json(serde_json::json!({
+                    "error": "Invalid continuation token"
+                }));
+            }
+        },
+        None => None,
+    };
+
+    if query.tag_value.is_some() && query.tag_key.is_none() {
+        return HttpResponse::BadRequest().json(serde_json::json!({
+            "error": "tag_value requires tag_key"
+        }));
+    }
+
+    let options = ListOptions {
+        prefix,
+        delimiter: query.delimiter.as_deref(),
+        max_keys,
+        start,
+        tag: query.tag_key.as_deref().map(|key| (key, query.tag_value.as_deref())),
+    };
+
+    // Find one page of files in this bucket, in key order
+    match listing::list_objects(&pool, bucket.id, &options).await {
+        Ok(page) => {
             // Convert files to response format
-            let file_infos = files.into_iter().map(|file| {
-                FileInfoResponse {
-                    id: file.id,
-                    filename: file.filename,
-                    content_type: file.content_type,
-                    size: file.size,
-                    created_at: file.created_at,
-                }
-            }).collect();
+            let file_infos = page.files.into_iter().map(FileInfoResponse::from).collect();
 
             HttpResponse::Ok().json(FileListResponse {
                 files: file_infos,
+                common_prefixes: page.common_prefixes,
+                is_truncated: page.next_start.is_some(),
+                next_continuation_token: page.next_start.as_deref().map(listing::encode_token),
             })
         }
         Err(e) => {
@@ -96,6 +192,7 @@ pub async fn upload_file(
     req: HttpRequest,
     pool: web::Data<PgPool>,
     storage: web::Data<dyn Storage + Send + Sync>,
+    config: web::Data<Config>,
     query: web::Query<UploadFileQuery>,
     mut payload: Multipart,
 ) -> impl Responder {
@@ -129,11 +226,18 @@ pub async fn upload_file(
         }
     };
 
-    // Process the multipart upload
-    let mut field_count = 0;
+    // Metadata comes from the request headers, as with S3 PutObject
+    let metadata = match metadata_from_headers(req.headers()) {
+        Ok(metadata) => metadata,
+        Err(e) => {
+            return HttpResponse::BadRequest().json(serde_json::json!({
+                "error": e.to_string()
+            }));
+        }
+    };
 
-    while let Ok(Some(mut field)) = payload.try_next().await {
-        field_count += 1;
+    // Process the first field of the multipart upload
+    if let Ok(Some(mut field)) = payload.try_next().await {
         info!("Processing field: {:?}", field.name());
 
         let content_disposition = field.content_disposition();
@@ -149,15 +253,55 @@ pub async fn upload_file(
             }
         };
 
+        if let Some(response) = scope_denied(&req, Action::Write, &bucket, Some(&filename)) {
+            return response;
+        }
+        if let Some(response) = access_denied(&req, &bucket, PolicyAction::PutObject, Some(&filename)) {
+            return response;
+        }
+
         info!("Uploading file: {}", filename);
 
+        // Fail fast before reading the content; the conditions are checked
+        // again when the file is published
+        let preconditions = Preconditions::from_request(&req);
+        if preconditions.constrains_write() {
+            match File::find_by_filename_and_bucket(&pool, &filename, bucket.id).await {
+                Ok(current) => {
+                    if preconditions.check_write(current.as_ref()) == Precondition::Failed {
+                        return HttpResponse::PreconditionFailed().json(serde_json::json!({
+                            "error": "Precondition failed"
+                        }));
+                    }
+                }
+                Err(e) => {
+                    error!("Failed to look up current file {}: {:?}", filename, e);
+                    return HttpResponse::InternalServerError().json(serde_json::json!({
+                        "error": "Failed to fetch file info"
+                    }));
+                }
+            }
+        }
+
         // Get content-type
         let content_type = field
             .content_type()
             .map(|ct| ct.to_string());
 
-        // Read file content
-        let mut file_content = Vec::new();
+        // Generate a unique file ID
+        let file_id = Uuid::new_v4();
+
+        // Stream the content straight into storage
+        let writer = match storage.create_writer(&bucket.name, file_id, &filename).await {
+            Ok(writer) => writer,
+            Err(e) => {
+                error!("Failed to open storage for upload: {:?}", e);
+                return HttpResponse::InternalServerError().json(serde_json::json!({
+                    "error": "Failed to save file to storage"
+                }));
+            }
+        };
+        let mut upload = ObjectUpload::new(writer, config.max_object_size);
 
         while let Some(chunk) = field.next().await {
             let data = match chunk {
@@ -170,30 +314,31 @@ pub async fn upload_file(
                 }
             };
 
-            // Write chunk to buffer
-            if let Err(e) = file_content.write_all(&data) {
-                error!("Failed to write chunk to buffer: {:?}", e);
-                return HttpResponse::InternalServerError().json(serde_json::json!({
-                    "error": "Failed to process upload data"
-                }));
+            match upload.write(&data).await {
+                Ok(()) => {}
+                Err(StorageError::TooLarge(limit)) => {
+                    error!("Upload of {} exceeds the {} byte limit", filename, limit);
+                    return HttpResponse::PayloadTooLarge().json(serde_json::json!({
+                        "error": format!("File exceeds the maximum size of {} bytes", limit)
+                    }));
+                }
+                Err(e) => {
+                    error!("Failed to write chunk to storage: {:?}", e);
+                    return HttpResponse::InternalServerError().json(serde_json::json!({
+                        "error": "Failed to save file to storage"
+                    }));
+                }
             }
         }
 
-        info!("File content read, size: {} bytes", file_content.len());
-
-        // Generate a unique file ID
-        let file_id = Uuid::new_v4();
-
-        // Save file to storage
-        info!("Saving file to storage...");
-        let storage_path = match storage
-            .save_file(&bucket.name, file_id, &filename, &file_content)
-            .await
-        {
-            Ok(path) => {
-                info!("File saved to: {}", path);
-                path
-            },
+        let saved = match upload.finish().await {
+            Ok(saved) => {
+                info!(
+                    "File saved to: {} ({} bytes, sha256 {})",
+                    saved.storage_path, saved.size, saved.sha256
+                );
+                saved
+            }
             Err(e) => {
                 error!("Failed to save file to storage: {:?}", e);
                 return HttpResponse::InternalServerError().json(serde_json::json!({
@@ -203,26 +348,27 @@ pub async fn upload_file(
         };
 
         // Create file record in database
-        let file = File::new(
+        let mut file = File::new(
             filename,
             content_type,
-            file_content.len() as i64,
+            saved.size as i64,
             bucket.id,
-            storage_path,
+            saved.storage_path,
+            saved.sha256,
         );
+        file.metadata.0 = metadata;
 
         info!("Creating database record for file: {}", file.id);
 
-        match file.create(&pool).await {
-            Ok(_) => {
-                info!("File uploaded successfully: {}", file.id);
-                return HttpResponse::Created().json(FileInfoResponse {
-                    id: file.id,
-                    filename: file.filename,
-                    content_type: file.content_type,
-                    size: file.size,
-                    created_at: file.created_at,
-                });
+        match publish_file(&pool, storage.get_ref(), bucket.versioning_status(), &mut file, &preconditions).await {
+            Ok(()) => {
+                info!("File uploaded successfully: {} (version {})", file.id, file.version_id);
+                return HttpResponse::Created().json(FileInfoResponse::from(file));
+            }
+            Err(PublishError::PreconditionFailed) => {
+                return HttpResponse::PreconditionFailed().json(serde_json::json!({
+                    "error": "Precondition failed"
+                }));
             }
             Err(e) => {
                 error!("Failed to save file metadata to DB: {:?}", e);
@@ -234,16 +380,88 @@ pub async fn upload_file(
     }
 
     // If we got here, no fields were processed
-    error!("No file fields found in the upload request. Field count: {}", field_count);
+    error!("No file fields found in the upload request");
     HttpResponse::BadRequest().json(serde_json::json!({
         "error": "No file uploaded"
     }))
 }
 
+#[derive(Debug, Error)]
+pub enum PublishError {
+    #[error("precondition failed")]
+    PreconditionFailed,
+    #[error(transparent)]
+    Database(#[from] sqlx::Error),
+}
+
+// Store a new file as the latest version of its key, then remove the
+// content of any version it replaced. If the row cannot be saved, the new
+// content is removed instead so nothing is left without a row.
+pub async fn publish_file(
+    pool: &PgPool,
+    storage: &(dyn Storage + Send + Sync),
+    versioning: Option<VersioningStatus>,
+    file: &mut File,
+    preconditions: &Preconditions,
+) -> Result<(), PublishError> {
+    let published: Result<Option<String>, PublishError> = async {
+        let mut tx = pool.begin().await?;
+
+        // Checked under the key lock, so a concurrent write cannot slip in
+        // between the check and the replacement
+        if preconditions.constrains_write() {
+            let current = File::lock_latest(&mut tx, file.bucket_id, &file.filename).await?;
+            if preconditions.check_write(current.as_ref()) == Precondition::Failed {
+                return Err(PublishError::PreconditionFailed);
+            }
+        }
+
+        let replaced = file.put_version(&mut tx, versioning).await?;
+        tx.commit().await?;
+        Ok(replaced)
+    }
+    .await;
+
+    match published {
+        Ok(replaced) => {
+            if let Some(path) = replaced {
+                if let Err(e) = storage.delete_file(&path).await {
+                    error!("Failed to remove replaced file content {}: {:?}", path, e);
+                }
+            }
+            Ok(())
+        }
+        Err(e) => {
+            if let Err(e) = storage.delete_file(&file.storage_path).await {
+                error!("Failed to clean up stored file {}: {:?}", file.storage_path, e);
+            }
+            Err(e)
+        }
+    }
+}
+
 #[derive(Debug, Deserialize)]
 pub struct GetFileQuery {
     bucket_name: String,
     filename: String,
+    version_id: Option<String>,
+}
+
+// The requested version of a file, or its latest version. Delete markers
+// are only returned when `include_markers` is set.
+async fn find_file(
+    pool: &PgPool,
+    bucket_id: Uuid,
+    query: &GetFileQuery,
+    include_markers: bool,
+) -> Result<Option<File>, sqlx::Error> {
+    match &query.version_id {
+        Some(version_id) => {
+            let file = File::find_version(pool, &query.filename, bucket_id, version_id).await?;
+            Ok(file.filter(|file| include_markers || !file.is_delete_marker))
+        }
+        None => File::find_by_filename_and_bucket(pool, &query.filename, bucket_id).await,
+    }
 }
 
 pub async fn get_file_info(
@@ -276,16 +494,17 @@ pub async fn get_file_info(
         }
     };
 
+    if let Some(response) = scope_denied(&req, Action::Read, &bucket, Some(&query.filename)) {
+        return response;
+    }
+    if let Some(response) = access_denied(&req, &bucket, PolicyAction::GetObject, Some(&query.filename)) {
+        return response;
+    }
+
     // Find file by filename and bucket
-    match File::find_by_filename_and_bucket(&pool, &query.filename, bucket.id).await {
+    match find_file(&pool, bucket.id, &query, false).await {
         Ok(Some(file)) => {
-            HttpResponse::Ok().json(FileInfoResponse {
-                id: file.id,
-                filename: file.filename,
-                content_type: file.content_type,
-                size: file.size,
-                created_at: file.created_at,
-            })
+            HttpResponse::Ok().json(FileInfoResponse::from(file))
         }
         Ok(None) => {
             HttpResponse::NotFound().json(serde_json::json!({
@@ -298,4 +517,358 @@ pub async fn get_file_info(
             }))
         }
     }
-}
\ No newline at end of file
+}
+
+pub async fn download_file(
+    req: HttpRequest,
+    pool: web::Data<PgPool>,
+    storage: web::Data<dyn Storage + Send + Sync>,
+    query: web::Query<GetFileQuery>,
+) -> impl Responder {
+    // Get user ID from request extensions (set by middleware)
+    let user_id = match get_user_id_from_request(&req) {
+        Some(id) => id,
+        None => {
+            return HttpResponse::Unauthorized().json(serde_json::json!({
+                "error": "Authentication required"
+            }));
+        }
+    };
+
+    // Find bucket by name and user
+    let bucket = match Bucket::find_by_name_and_user(&pool, &query.bucket_name, user_id).await {
+        Ok(Some(bucket)) => bucket,
+        Ok(None) => {
+            return HttpResponse::NotFound().json(serde_json::json!({
+                "error": "Bucket not found"
+            }));
+        }
+        Err(_) => {
+            return HttpResponse::InternalServerError().json(serde_json::json!({
+                "error": "Failed to check bucket"
+            }));
+        }
+    };
+
+    if let Some(response) = scope_denied(&req, Action::Read, &bucket, Some(&query.filename)) {
+        return response;
+    }
+    if let Some(response) = access_denied(&req, &bucket, PolicyAction::GetObject, Some(&query.filename)) {
+        return response;
+    }
+
+    // Find file by filename and bucket
+    let file = match find_file(&pool, bucket.id, &query, false).await {
+        Ok(Some(file)) => file,
+        Ok(None) => {
+            return HttpResponse::NotFound().json(serde_json::json!({
+                "error": "File not found"
+            }));
+        }
+        Err(_) => {
+            return HttpResponse::InternalServerError().json(serde_json::json!({
+                "error": "Failed to fetch file info"
+            }));
+        }
+    };
+
+    let preconditions = Preconditions::from_request(&req);
+    match preconditions.check_read(&file) {
+        Precondition::Proceed => {}
+        Precondition::NotModified => {
+            let mut response = HttpResponse::NotModified();
+            response.insert_header((header::LAST_MODIFIED, http_date(&file.created_at)));
+            if let Some(etag) = &file.etag {
+                response.insert_header((header::ETAG, quoted_etag(etag)));
+            }
+            return response.finish();
+        }
+        Precondition::Failed => {
+            return HttpResponse::PreconditionFailed().json(serde_json::json!({
+                "error": "Precondition failed"
+            }));
+        }
+    }
+
+    let size = file.size as u64;
+    let requested = if preconditions.range_applies(&file) {
+        range::requested_ranges(&req, size)
+    } else {
+        Ok(None)
+    };
+    let ranges = match requested {
+        Ok(ranges) => ranges,
+        Err(_) => {
+            return HttpResponse::RangeNotSatisfiable()
+                .insert_header((header::CONTENT_RANGE, range::unsatisfied_range(size)))
+                .json(serde_json::json!({
+                    "error": "Requested range not satisfiable"
+                }));
+        }
+    };
+
+    let content_type = file
+        .content_type
+        .clone()
+        .unwrap_or_else(|| "application/octet-stream".to_string());
+
+    let mut response = HttpResponse::Ok();
+    response
+        .insert_header((header::CONTENT_TYPE, content_type.clone()))
+        .insert_header((header::ACCEPT_RANGES, "bytes"))
+        .insert_header((header::LAST_MODIFIED, http_date(&file.created_at)))
+        .insert_header(attachment_disposition(&file.filename));
+    if let Some(etag) = &file.etag {
+        response.insert_header((header::ETAG, quoted_etag(etag)));
+    }
+    // A stored Content-Disposition replaces the default attachment header
+    insert_metadata_headers(&mut response, &file.metadata);
+
+    // Only the requested ranges are read from storage
+    if let Some(ranges) = ranges {
+        return match PartialContent::read(storage.get_ref(), &file.storage_path, size, &content_type, &ranges).await {
+            Ok(partial) => partial.respond(response),
+            Err(e) => read_error_response(&file, e),
+        };
+    }
+
+    // Open the stored object as a stream so large files are never buffered
+    match storage.read_file_stream(&file.storage_path).await {
+        Ok(stream) => response.body(SizedStream::new(size, stream)),
+        Err(e) => read_error_response(&file, e),
+    }
+}
+
+fn read_error_response(file: &File, err: StorageError) -> HttpResponse {
+    match err {
+        StorageError::NotFound(path) => {
+            error!("File {} has no stored content at {}", file.id, path);
+            HttpResponse::NotFound().json(serde_json::json!({
+                "error": "File content not found"
+            }))
+        }
+        e => {
+            error!("Failed to open file {} from storage: {:?}", file.id, e);
+            HttpResponse::InternalServerError().json(serde_json::json!({
+                "error": "Failed to read file from storage"
+            }))
+        }
+    }
+}
+
+// Build a `Content-Disposition: attachment` header, adding the RFC 5987
+// `filename*` form when the name is not plain ASCII
+fn attachment_disposition(filename: &str) -> ContentDisposition {
+    let mut parameters = Vec::new();
+
+    if filename.is_ascii() {
+        parameters.push(DispositionParam::Filename(filename.to_string()));
+    } else {
+        let fallback: String = filename
+            .chars()
+            .map(|c| if c.is_ascii() { c } else { '_' })
+            .collect();
+        parameters.push(DispositionParam::Filename(fallback));
+        parameters.push(DispositionParam::FilenameExt(ExtendedValue {
+            charset: Charset::Ext("UTF-8".to_string()),
+            language_tag: None,
+            value: filename.as_bytes().to_vec(),
+        }));
+    }
+
+    ContentDisposition {
+        disposition: DispositionType::Attachment,
+        parameters,
+    }
+}
+
+// Remove a file version's row and its stored content together. The row is
+// deleted inside a transaction that only commits once the blob is gone, so a
+// storage failure never leaves a row pointing at missing content.
+pub async fn remove_file(
+    pool: &PgPool,
+    storage: &(dyn Storage + Send + Sync),
+    file: &File,
+) -> anyhow::Result<()> {
+    let mut tx = pool.begin().await?;
+
+    file.delete(&mut tx).await?;
+
+    if !file.is_delete_marker {
+        match storage.delete_file(&file.storage_path).await {
+            // Content already missing, the row is all that is left to remove
+            Ok(()) | Err(StorageError::NotFound(_)) => {}
+            Err(e) => return Err(e.into()),
+        }
+    }
+
+    tx.commit().await?;
+
+    Ok(())
+}
+
+// Hide a key behind a new delete marker, as a delete without a version ID
+// does in a bucket with versioning configured. With versioning suspended the
+// marker replaces the key's "null" version.
+pub async fn add_delete_marker(
+    pool: &PgPool,
+    storage: &(dyn Storage + Send + Sync),
+    bucket: &Bucket,
+    filename: &str,
+) -> anyhow::Result<File> {
+    let mut marker = File::delete_marker(filename.to_string(), bucket.id);
+
+    let mut tx = pool.begin().await?;
+    let replaced = marker.put_version(&mut tx, bucket.versioning_status()).await?;
+    tx.commit().await?;
+
+    if let Some(path) = replaced {
+        if let Err(e) = storage.delete_file(&path).await {
+            error!("Failed to remove replaced file content {}: {:?}", path, e);
+        }
+    }
+
+    Ok(marker)
+}
+
+pub async fn delete_file(
+    req: HttpRequest,
+    pool: web::Data<PgPool>,
+    storage: web::Data<dyn Storage + Send + Sync>,
+    query: web::Query<GetFileQuery>,
+) -> impl Responder {
+    // Get user ID from request extensions (set by middleware)
+    let user_id = match get_user_id_from_request(&req) {
+        Some(id) => id,
+        None => {
+            return HttpResponse::Unauthorized().json(serde_json::json!({
+                "error": "Authentication required"
+            }));
+        }
+    };
+
+    // Find bucket by name and user
+    let bucket = match Bucket::find_by_name_and_user(&pool, &query.bucket_name, user_id).await {
+        Ok(Some(bucket)) => bucket,
+        Ok(None) => {
+            return HttpResponse::NotFound().json(serde_json::json!({
+                "error": "Bucket not found"
+            }));
+        }
+        Err(_) => {
+            return HttpResponse::InternalServerError().json(serde_json::json!({
+                "error": "Failed to check bucket"
+            }));
+        }
+    };
+
+    if let Some(response) = scope_denied(&req, Action::Delete, &bucket, Some(&query.filename)) {
+        return response;
+    }
+    if let Some(response) = access_denied(&req, &bucket, PolicyAction::DeleteObject, Some(&query.filename)) {
+        return response;
+    }
+
+    // Find file by filename and bucket; a version ID may name a delete marker
+    let file = match find_file(&pool, bucket.id, &query, true).await {
+        Ok(Some(file)) => file,
+        Ok(None) => {
+            return HttpResponse::NotFound().json(serde_json::json!({
+                "error": "File not found"
+            }));
+        }
+        Err(_) => {
+            return HttpResponse::InternalServerError().json(serde_json::json!({
+                "error": "Failed to fetch file info"
+            }));
+        }
+    };
+
+    // A version ID, or a bucket without versioning, deletes the file for
+    // good; otherwise the file is hidden behind a delete marker
+    let deleted = if query.version_id.is_some() || bucket.versioning.is_none() {
+        remove_file(&pool, storage.get_ref(), &file).await.map(|_| None)
+    } else {
+        add_delete_marker(&pool, storage.get_ref(), &bucket, &file.filename).await.map(Some)
+    };
+
+    match deleted {
+        Ok(None) => {
+            info!("File deleted: {} (version {})", file.id, file.version_id);
+            HttpResponse::NoContent().finish()
+        }
+        Ok(Some(marker)) => {
+            info!("Delete marker {} added for file {}", marker.version_id, file.filename);
+            HttpResponse::NoContent().finish()
+        }
+        Err(e) => {
+            error!("Failed to delete file {}: {:?}", file.id, e);
+            HttpResponse::InternalServerError().json(serde_json::json!({
+                "error": "Failed to delete file"
+            }))
+        }
+    }
+}
+
+#[derive(Debug, Deserialize)]
+pub struct ListVersionsQuery {
+    bucket_name: String,
+    filename: Option<String>,
+}
+
+#[derive(Debug, Serialize)]
+pub struct VersionListResponse {
+    versions: Vec<FileVersionResponse>,
+}
+
+pub async fn list_versions(
+    req: HttpRequest,
+    pool: web::Data<PgPool>,
+    query: web::Query<ListVersionsQuery>,
+) -> impl Responder {
+    // Get user ID from request extensions (set by middleware)
+    let user_id = match get_user_id_from_request(&req) {
+        Some(id) => id,
+        None => {
+            return HttpResponse::Unauthorized().json(serde_json::json!({
+                "error": "Authentication required"
+            }));
+        }
+    };
+
+    let filename = query.filename.as_deref().unwrap_or("");
+
+    // Find bucket by name and user
+    let bucket = match Bucket::find_by_name_and_user(&pool, &query.bucket_name, user_id).await {
+        Ok(Some(bucket)) => bucket,
+        Ok(None) => {
+            return HttpResponse::NotFound().json(serde_json::json!({
+                "error": "Bucket not found"
+            }));
+        }
+        Err(_) => {
+            return HttpResponse::InternalServerError().json(serde_json::json!({
+                "error": "Failed to check bucket"
+            }));
+        }
+    };
+
+    if let Some(response) = scope_denied(&req, Action::List, &bucket, Some(filename)) {
+        return response;
+    }
+    if let Some(response) = access_denied(&req, &bucket, PolicyAction::ListBucketVersions, Some(filename)) {
+        return response;
+    }
+
+    match File::find_versions(&pool, bucket.id, query.filename.as_deref()).await {
+        Ok(files) => HttpResponse::Ok().json(VersionListResponse {
+            versions: files.into_iter().map(FileVersionResponse::from).collect(),
+        }),
+        Err(e) => {
+            error!("Error fetching file versions: {:?}", e);
+            HttpResponse::InternalServerError().json(serde_json::json!({
+                "error": "Failed to fetch file versions"
+            }))
+        }
+    }
+}
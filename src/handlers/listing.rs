@@ -0,0 +1,163 @@
+use sqlx::PgPool;
+use uuid::Uuid;
+
+use crate::models::File;
+
+// Most keys returned by one listing call, as in S3
+pub const MAX_KEYS_LIMIT: usize = 1000;
+
+// What to list: keys under `prefix`, optionally rolled up at `delimiter`,
+// resuming at `start` (inclusive) and returning at most `max_keys` entries.
+// With `tag`, only objects carrying that tag key (and value, if given) are
+// listed.
+pub struct ListOptions<'a> {
+    pub prefix: &'a str,
+    pub delimiter: Option<&'a str>,
+    pub max_keys: usize,
+    pub start: Option<String>,
+    pub tag: Option<(&'a str, Option<&'a str>)>,
+}
+
+// One page of a listing. Every file and common prefix counts towards
+// `max_keys`; `next_start` is set when more entries follow.
+pub struct Listing {
+    pub files: Vec<File>,
+    pub common_prefixes: Vec<String>,
+    pub next_start: Option<String>,
+}
+
+// Opaque continuation tokens carry the position the next page starts at
+pub fn encode_token(start: &str) -> String {
+    hex::encode(start.as_bytes())
+}
+
+pub fn decode_token(token: &str) -> Option<String> {
+    hex::decode(token).ok().and_then(|bytes| String::from_utf8(bytes).ok())
+}
+
+// First position after `key`. Postgres text cannot hold NUL, so no key sorts
+// between `key` and `key` followed by U+0001.
+pub fn start_after(key: &str) -> String {
+    format!("{}\u{1}", key)
+}
+
+// First position after every key that starts with `prefix`, or None when no
+// such position exists
+fn prefix_end(prefix: &str) -> Option<String> {
+    let mut chars: Vec<char> = prefix.chars().collect();
+
+    while let Some(last) = chars.pop() {
+        // Code point order is byte order in UTF-8; skip the surrogate gap
+        let next = (last as u32 + 1..=char::MAX as u32).find_map(char::from_u32);
+        if let Some(next) = next {
+            chars.push(next);
+            return Some(chars.into_iter().collect());
+        }
+    }
+
+    None
+}
+
+// The common prefix `key` rolls up into, if the delimiter occurs after the prefix
+fn common_prefix<'k>(key: &'k str, prefix: &str, delimiter: Option<&str>) -> Option<&'k str> {
+    let delimiter = delimiter.filter(|d| !d.is_empty())?;
+    let rest = key.strip_prefix(prefix)?;
+    rest.find(delimiter)
+        .map(|index| &key[..prefix.len() + index + delimiter.len()])
+}
+
+// List current objects in key order. Keys under a common prefix are skipped
+// in one step, so a page costs at most one query per common prefix.
+pub async fn list_objects(
+    pool: &PgPool,
+    bucket_id: Uuid,
+    options: &ListOptions<'_>,
+) -> Result<Listing, sqlx::Error> {
+    let mut listing = Listing {
+        files: Vec::new(),
+        common_prefixes: Vec::new(),
+        next_start: None,
+    };
+
+    let end = if options.prefix.is_empty() {
+        None
+    } else {
+        prefix_end(options.prefix)
+    };
+    let mut start = match &options.start {
+        Some(start) if start.as_str() > options.prefix => start.clone(),
+        _ => options.prefix.to_string(),
+    };
+    let mut count = 0;
+
+    loop {
+        // One extra row tells whether the page is truncated
+        let limit = (options.max_keys - count + 1) as i64;
+        let batch = File::find_page(pool, bucket_id, &start, end.as_deref(), options.tag, limit).await?;
+        let exhausted = (batch.len() as i64) < limit;
+        let mut skipped = false;
+
+        for file in batch {
+            if count == options.max_keys {
+                listing.next_start = Some(start);
+                return Ok(listing);
+            }
+
+            if let Some(prefix) = common_prefix(&file.filename, options.prefix, options.delimiter) {
+                let prefix = prefix.to_string();
+                count += 1;
+
+                // Jump past every key under this prefix and query again
+                let next = prefix_end(&prefix);
+                listing.common_prefixes.push(prefix);
+                match next {
+                    Some(next) => start = next,
+                    None => return Ok(listing),
+                }
+                skipped = true;
+                break;
+            }
+
+            start = start_after(&file.filename);
+            listing.files.push(file);
+            count += 1;
+        }
+
+        if exhausted && !skipped {
+            return Ok(listing);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn prefix_end_sorts_after_all_keys_with_prefix() {
+        assert_eq!(prefix_end("photos/").as_deref(), Some("photos0"));
+        assert_eq!(prefix_end("a\u{d7ff}").as_deref(), Some("a\u{e000}"));
+        assert_eq!(prefix_end("a\u{10ffff}").as_deref(), Some("b"));
+        assert_eq!(prefix_end("\u{10ffff}"), None);
+
+        let end = prefix_end("photos/").unwrap();
+        assert!("photos/zzz\u{10ffff}" < end.as_str());
+        assert!("photos0" >= end.as_str());
+    }
+
+    #[test]
+    fn rolls_keys_up_at_the_delimiter() {
+        assert_eq!(common_prefix("photos/2023/a.jpg", "", Some("/")), Some("photos/"));
+        assert_eq!(common_prefix("photos/2023/a.jpg", "photos/", Some("/")), Some("photos/2023/"));
+        assert_eq!(common_prefix("photos/a.jpg", "photos/", Some("/")), None);
+        assert_eq!(common_prefix("photos/a.jpg", "", None), None);
+        assert_eq!(common_prefix("a--b--c", "", Some("--")), Some("a--"));
+    }
+
+    #[test]
+    fn tokens_round_trip() {
+        let start = start_after("dir/ünïcode key");
+        assert_eq!(decode_token(&encode_token(&start)), Some(start));
+        assert_eq!(decode_token("not hex"), None);
+    }
+}
@@ -0,0 +1,197 @@
+use actix_web::http::header::{self, HeaderMap, HeaderName, HeaderValue};
+use actix_web::HttpResponseBuilder;
+use thiserror::Error;
+
+use crate::models::ObjectMetadata;
+
+// Request and response headers that carry user-defined metadata
+pub const USER_METADATA_PREFIX: &str = "x-amz-meta-";
+
+// Most bytes of metadata stored with one object, as in S3
+pub const MAX_METADATA_SIZE: usize = 2048;
+
+// Content encoding S3 clients use for signed streaming uploads. It
+// describes the request body, not the object, so it is not stored.
+const AWS_CHUNKED_ENCODING: &str = "aws-chunked";
+
+#[derive(Debug, Error)]
+pub enum MetadataError {
+    #[error("Metadata exceeds the maximum size of {0} bytes")]
+    TooLarge(usize),
+    #[error("Header {0} must be printable ASCII")]
+    InvalidValue(String),
+    #[error("Metadata key {0:?} is not a valid header name")]
+    InvalidKey(String),
+}
+
+fn header_text(headers: &HeaderMap, name: &HeaderName) -> Result<Option<String>, MetadataError> {
+    match headers.get(name) {
+        Some(value) => value
+            .to_str()
+            .map(|value| Some(value.trim().to_string()))
+            .map_err(|_| MetadataError::InvalidValue(name.to_string())),
+        None => Ok(None),
+    }
+}
+
+// Metadata given with an upload: `x-amz-meta-*` headers plus the
+// Cache-Control, Content-Encoding, Content-Language and Content-Disposition
+// headers, which are replayed on download
+pub fn metadata_from_headers(headers: &HeaderMap) -> Result<ObjectMetadata, MetadataError> {
+    let mut metadata = ObjectMetadata {
+        cache_control: header_text(headers, &header::CACHE_CONTROL)?,
+        content_encoding: header_text(headers, &header::CONTENT_ENCODING)?,
+        content_language: header_text(headers, &header::CONTENT_LANGUAGE)?,
+        content_disposition: header_text(headers, &header::CONTENT_DISPOSITION)?,
+        ..ObjectMetadata::default()
+    };
+
+    metadata.content_encoding = metadata.content_encoding.and_then(|encoding| {
+        let encodings: Vec<&str> = encoding
+            .split(',')
+            .map(str::trim)
+            .filter(|coding| !coding.is_empty() && !coding.eq_ignore_ascii_case(AWS_CHUNKED_ENCODING))
+            .collect();
+        (!encodings.is_empty()).then(|| encodings.join(", "))
+    });
+
+    for name in headers.keys() {
+        // Header names are already lowercase
+        if let Some(key) = name.as_str().strip_prefix(USER_METADATA_PREFIX) {
+            if let Some(value) = header_text(headers, name)? {
+                metadata.user.insert(key.to_string(), value);
+            }
+        }
+    }
+
+    if metadata.size() > MAX_METADATA_SIZE {
+        return Err(MetadataError::TooLarge(MAX_METADATA_SIZE));
+    }
+    Ok(metadata)
+}
+
+// A value given other than as a header, which must still be valid as one
+pub fn check_header_value(name: &str, value: &str) -> Result<(), MetadataError> {
+    match HeaderValue::from_str(value) {
+        Ok(_) => Ok(()),
+        Err(_) => Err(MetadataError::InvalidValue(name.to_string())),
+    }
+}
+
+// Check metadata given other than as headers, such as in a JSON body, so
+// that replaying it as response headers cannot fail
+pub fn validate_metadata(metadata: &ObjectMetadata) -> Result<(), MetadataError> {
+    let headers = [
+        (header::CACHE_CONTROL, &metadata.cache_control),
+        (header::CONTENT_ENCODING, &metadata.content_encoding),
+        (header::CONTENT_LANGUAGE, &metadata.content_language),
+        (header::CONTENT_DISPOSITION, &metadata.content_disposition),
+    ];
+    for (name, value) in headers {
+        if let Some(value) = value {
+            check_header_value(name.as_str(), value)?;
+        }
+    }
+
+    for (key, value) in &metadata.user {
+        let name = format!("{}{}", USER_METADATA_PREFIX, key);
+        if HeaderName::from_bytes(name.as_bytes()).is_err() {
+            return Err(MetadataError::InvalidKey(key.clone()));
+        }
+        check_header_value(&name, value)?;
+    }
+
+    if metadata.size() > MAX_METADATA_SIZE {
+        return Err(MetadataError::TooLarge(MAX_METADATA_SIZE));
+    }
+    Ok(())
+}
+
+// Replay stored metadata as response headers
+pub fn insert_metadata_headers(response: &mut HttpResponseBuilder, metadata: &ObjectMetadata) {
+    let headers = [
+        (header::CACHE_CONTROL, &metadata.cache_control),
+        (header::CONTENT_ENCODING, &metadata.content_encoding),
+        (header::CONTENT_LANGUAGE, &metadata.content_language),
+        (header::CONTENT_DISPOSITION, &metadata.content_disposition),
+    ];
+    for (name, value) in headers {
+        if let Some(value) = value {
+            response.insert_header((name, value.as_str()));
+        }
+    }
+
+    for (key, value) in &metadata.user {
+        response.insert_header((format!("{}{}", USER_METADATA_PREFIX, key), value.as_str()));
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn headers(pairs: &[(&'static str, &str)]) -> HeaderMap {
+        let mut map = HeaderMap::new();
+        for (name, value) in pairs {
+            map.append(
+                HeaderName::from_static(name),
+                HeaderValue::from_str(value).unwrap(),
+            );
+        }
+        map
+    }
+
+    #[test]
+    fn collects_user_and_content_headers() {
+        let metadata = metadata_from_headers(&headers(&[
+            ("x-amz-meta-camera", "Leica M6"),
+            ("x-amz-meta-roll", " 12 "),
+            ("cache-control", "max-age=3600"),
+            ("content-encoding", "aws-chunked, gzip"),
+            ("content-type", "image/jpeg"),
+        ]))
+        .unwrap();
+
+        assert_eq!(metadata.user.len(), 2);
+        assert_eq!(metadata.user["camera"], "Leica M6");
+        assert_eq!(metadata.user["roll"], "12");
+        assert_eq!(metadata.cache_control.as_deref(), Some("max-age=3600"));
+        assert_eq!(metadata.content_encoding.as_deref(), Some("gzip"));
+        assert_eq!(metadata.content_language, None);
+
+        let metadata = metadata_from_headers(&headers(&[("content-encoding", "aws-chunked")])).unwrap();
+        assert_eq!(metadata.content_encoding, None);
+    }
+
+    #[test]
+    fn limits_total_size() {
+        let value = "x".repeat(MAX_METADATA_SIZE - "a".len());
+        assert!(metadata_from_headers(&headers(&[("x-amz-meta-a", &value)])).is_ok());
+        assert!(matches!(
+            metadata_from_headers(&headers(&[("x-amz-meta-a", &value), ("content-language", "en")])),
+            Err(MetadataError::TooLarge(MAX_METADATA_SIZE))
+        ));
+    }
+
+    #[test]
+    fn validates_metadata_from_json() {
+        let mut metadata = ObjectMetadata::default();
+        metadata.user.insert("camera".to_string(), "Leica M6".to_string());
+        metadata.content_language = Some("en".to_string());
+        assert!(validate_metadata(&metadata).is_ok());
+
+        metadata.user.insert("bad key".to_string(), "x".to_string());
+        assert!(matches!(validate_metadata(&metadata), Err(MetadataError::InvalidKey(key)) if key == "bad key"));
+
+        metadata.user.remove("bad key");
+        metadata.user.insert("note".to_string(), "line\nbreak".to_string());
+        assert!(matches!(validate_metadata(&metadata), Err(MetadataError::InvalidValue(_))));
+
+        metadata.user.remove("note");
+        metadata.cache_control = Some("no-cache\r\n".to_string());
+        assert!(matches!(validate_metadata(&metadata), Err(MetadataError::InvalidValue(name)) if name == "cache-control"));
+
+        assert!(check_header_value("content-type", "text/plain").is_ok());
+        assert!(check_header_value("content-type", "text/plain\n").is_err());
+    }
+}
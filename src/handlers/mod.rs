@@ -1,2 +1,14 @@
 pub mod bucket;
-pub mod file;
\ No newline at end of file
+pub mod conditional;
+pub mod copy;
+pub mod file;
+pub mod listing;
+pub mod metadata;
+pub mod multipart;
+pub mod organization;
+pub mod policy;
+pub mod presign;
+pub mod range;
+pub mod s3;
+pub mod sharing;
+pub mod tagging;
\ No newline at end of file
@@ -0,0 +1,814 @@
+use actix_web::{web, HttpRequest, HttpResponse, Responder};
+use chrono::Utc;
+use futures::StreamExt;
+use log::{error, info};
+use serde::{Deserialize, Serialize};
+use sqlx::PgPool;
+use std::sync::Arc;
+use std::time::Duration;
+use thiserror::Error;
+use uuid::Uuid;
+
+use crate::config::Config;
+use crate::handlers::file::FileInfoResponse;
+use crate::handlers::metadata::metadata_from_headers;
+use crate::middleware::auth::{access_denied, get_user_id_from_request, scope_denied};
+use crate::models::{Action, Bucket, File, MultipartUpload, PolicyAction, UploadPart};
+use crate::storage::{ObjectUpload, Storage, StorageError};
+
+// S3 limits: part numbers run from 1 to 10,000 and every part except the
+// last must be at least 5 MiB
+pub const MAX_PART_NUMBER: i32 = 10_000;
+pub const MIN_PART_SIZE: i64 = 5 * 1024 * 1024;
+
+// How often the reaper looks for stale uploads
+const REAPER_INTERVAL: Duration = Duration::from_secs(60 * 60);
+
+#[derive(Debug, Deserialize)]
+pub struct MultipartQuery {
+    bucket_name: String,
+}
+
+#[derive(Debug, Deserialize)]
+pub struct InitiateUploadRequest {
+    filename: String,
+    content_type: Option<String>,
+}
+
+#[derive(Debug, Serialize)]
+pub struct InitiateUploadResponse {
+    upload_id: Uuid,
+    bucket_name: String,
+    filename: String,
+}
+
+#[derive(Debug, Serialize)]
+pub struct UploadPartResponse {
+    part_number: i32,
+    etag: String,
+    size: i64,
+}
+
+#[derive(Debug, Deserialize)]
+pub struct CompletedPart {
+    part_number: i32,
+    etag: String,
+}
+
+// Why the parts listed to complete an upload cannot be assembled
+#[derive(Debug, PartialEq, Eq, Error)]
+pub enum PartSelectionError {
+    #[error("At least one part is required")]
+    Empty,
+    #[error("Parts must be listed in ascending order of part number")]
+    Order,
+    #[error("Part {0} was not uploaded or its etag does not match")]
+    Mismatch(i32),
+    #[error("Part {0} is smaller than the minimum of {MIN_PART_SIZE} bytes")]
+    TooSmall(i32),
+    #[error("The assembled object exceeds the maximum size of {0} bytes")]
+    TooLarge(u64),
+}
+
+#[derive(Debug, Deserialize)]
+pub struct CompleteUploadRequest {
+    parts: Vec<CompletedPart>,
+}
+
+#[derive(Debug, Serialize)]
+pub struct UploadInfo {
+    upload_id: Uuid,
+    filename: String,
+    content_type: Option<String>,
+    created_at: chrono::DateTime<chrono::Utc>,
+}
+
+#[derive(Debug, Serialize)]
+pub struct UploadListResponse {
+    uploads: Vec<UploadInfo>,
+}
+
+#[derive(Debug, Serialize)]
+pub struct PartInfo {
+    part_number: i32,
+    etag: String,
+    size: i64,
+    created_at: chrono::DateTime<chrono::Utc>,
+}
+
+#[derive(Debug, Serialize)]
+pub struct PartListResponse {
+    upload_id: Uuid,
+    filename: String,
+    parts: Vec<PartInfo>,
+}
+
+// Look up an in-progress upload owned by the user, turning misses and
+// errors into the matching response
+async fn find_upload(
+    req: &HttpRequest,
+    pool: &PgPool,
+    upload_id: Uuid,
+    user_id: Uuid,
+    action: Action,
+    policy_action: PolicyAction,
+) -> Result<(MultipartUpload, Bucket), HttpResponse> {
+    let upload = match MultipartUpload::find_by_id_and_user(pool, upload_id, user_id).await {
+        Ok(Some(upload)) => upload,
+        Ok(None) => {
+            return Err(HttpResponse::NotFound().json(serde_json::json!({
+                "error": "Upload not found"
+            })));
+        }
+        Err(e) => {
+            error!("Failed to fetch upload {}: {:?}", upload_id, e);
+            return Err(HttpResponse::InternalServerError().json(serde_json::json!({
+                "error": "Failed to fetch upload"
+            })));
+        }
+    };
+
+    let bucket = match Bucket::find_by_id_and_user(pool, upload.bucket_id, user_id).await {
+        Ok(Some(bucket)) => bucket,
+        Ok(None) => {
+            return Err(HttpResponse::NotFound().json(serde_json::json!({
+                "error": "Bucket not found"
+            })));
+        }
+        Err(_) => {
+            return Err(HttpResponse::InternalServerError().json(serde_json::json!({
+                "error": "Failed to check bucket"
+            })));
+        }
+    };
+
+    if let Some(response) = scope_denied(req, action, &bucket, Some(&upload.filename)) {
+        return Err(response);
+    }
+    if let Some(response) = access_denied(req, &bucket, policy_action, Some(&upload.filename)) {
+        return Err(response);
+    }
+
+    Ok((upload, bucket))
+}
+
+// Delete an upload together with the stored content of its parts. The rows go
+// first, in one transaction; content left behind by a failed file removal is
+// only logged, as nothing refers to it any more
+pub async fn abort(
+    pool: &PgPool,
+    storage: &(dyn Storage + Send + Sync),
+    upload: &MultipartUpload,
+) -> anyhow::Result<()> {
+    let mut tx = pool.begin().await?;
+
+    let paths = UploadPart::delete_by_upload_id(&mut *tx, upload.id).await?;
+    upload.delete(&mut *tx).await?;
+
+    tx.commit().await?;
+
+    for path in &paths {
+        match storage.delete_file(path).await {
+            Ok(()) | Err(StorageError::NotFound(_)) => {}
+            Err(e) => error!("Failed to remove part content {}: {:?}", path, e),
+        }
+    }
+
+    Ok(())
+}
+
+pub async fn initiate_upload(
+    req: HttpRequest,
+    pool: web::Data<PgPool>,
+    query: web::Query<MultipartQuery>,
+    upload_req: web::Json<InitiateUploadRequest>,
+) -> impl Responder {
+    // Get user ID from request extensions (set by middleware)
+    let user_id = match get_user_id_from_request(&req) {
+        Some(id) => id,
+        None => {
+            return HttpResponse::Unauthorized().json(serde_json::json!({
+                "error": "Authentication required"
+            }));
+        }
+    };
+
+    if upload_req.filename.is_empty() || upload_req.filename.len() > 255 {
+        return HttpResponse::BadRequest().json(serde_json::json!({
+            "error": "Filename must be between 1 and 255 characters"
+        }));
+    }
+
+    // Metadata headers on the initiate request apply to the finished file
+    let metadata = match metadata_from_headers(req.headers()) {
+        Ok(metadata) => metadata,
+        Err(e) => {
+            return HttpResponse::BadRequest().json(serde_json::json!({
+                "error": e.to_string()
+            }));
+        }
+    };
+
+    // Find bucket by name and user
+    let bucket = match Bucket::find_by_name_and_user(&pool, &query.bucket_name, user_id).await {
+        Ok(Some(bucket)) => bucket,
+        Ok(None) => {
+            return HttpResponse::NotFound().json(serde_json::json!({
+                "error": "Bucket not found"
+            }));
+        }
+        Err(_) => {
+            return HttpResponse::InternalServerError().json(serde_json::json!({
+                "error": "Failed to check bucket"
+            }));
+        }
+    };
+
+    if let Some(response) = scope_denied(&req, Action::Write, &bucket, Some(&upload_req.filename)) {
+        return response;
+    }
+    if let Some(response) = access_denied(&req, &bucket, PolicyAction::PutObject, Some(&upload_req.filename)) {
+        return response;
+    }
+
+    let upload = MultipartUpload::new(
+        bucket.id,
+        upload_req.filename.clone(),
+        upload_req.content_type.clone(),
+        metadata,
+    );
+
+    match upload.create(&pool).await {
+        Ok(_) => {
+            info!("Multipart upload {} started for {}/{}", upload.id, bucket.name, upload.filename);
+            HttpResponse::Created().json(InitiateUploadResponse {
+                upload_id: upload.id,
+                bucket_name: bucket.name,
+                filename: upload.filename,
+            })
+        }
+        Err(e) => {
+            error!("Failed to create multipart upload: {:?}", e);
+            HttpResponse::InternalServerError().json(serde_json::json!({
+                "error": "Failed to start upload"
+            }))
+        }
+    }
+}
+
+pub async fn upload_part(
+    req: HttpRequest,
+    pool: web::Data<PgPool>,
+    storage: web::Data<dyn Storage + Send + Sync>,
+    config: web::Data<Config>,
+    path: web::Path<(Uuid, i32)>,
+    mut payload: web::Payload,
+) -> impl Responder {
+    // Get user ID from request extensions (set by middleware)
+    let user_id = match get_user_id_from_request(&req) {
+        Some(id) => id,
+        None => {
+            return HttpResponse::Unauthorized().json(serde_json::json!({
+                "error": "Authentication required"
+            }));
+        }
+    };
+
+    let (upload_id, part_number) = path.into_inner();
+    if !(1..=MAX_PART_NUMBER).contains(&part_number) {
+        return HttpResponse::BadRequest().json(serde_json::json!({
+            "error": format!("Part number must be between 1 and {}", MAX_PART_NUMBER)
+        }));
+    }
+
+    let (upload, bucket) = match find_upload(&req, &pool, upload_id, user_id, Action::Write, PolicyAction::PutObject).await {
+        Ok(found) => found,
+        Err(response) => return response,
+    };
+
+    // Parts are stored as standalone objects until the upload is completed
+    let part_name = format!("{}.part{}", upload.id, part_number);
+    let writer = match storage.create_writer(&bucket.name, Uuid::new_v4(), &part_name).await {
+        Ok(writer) => writer,
+        Err(e) => {
+            error!("Failed to open storage for part upload: {:?}", e);
+            return HttpResponse::InternalServerError().json(serde_json::json!({
+                "error": "Failed to save part to storage"
+            }));
+        }
+    };
+    let mut part_upload = ObjectUpload::new(writer, config.max_object_size);
+
+    while let Some(chunk) = payload.next().await {
+        let data = match chunk {
+            Ok(data) => data,
+            Err(e) => {
+                error!("Failed to read part chunk: {:?}", e);
+                return HttpResponse::InternalServerError().json(serde_json::json!({
+                    "error": "Failed to read upload data"
+                }));
+            }
+        };
+
+        match part_upload.write(&data).await {
+            Ok(()) => {}
+            Err(StorageError::TooLarge(limit)) => {
+                return HttpResponse::PayloadTooLarge().json(serde_json::json!({
+                    "error": format!("Part exceeds the maximum size of {} bytes", limit)
+                }));
+            }
+            Err(e) => {
+                error!("Failed to write part chunk to storage: {:?}", e);
+                return HttpResponse::InternalServerError().json(serde_json::json!({
+                    "error": "Failed to save part to storage"
+                }));
+            }
+        }
+    }
+
+    let saved = match part_upload.finish().await {
+        Ok(saved) => saved,
+        Err(e) => {
+            error!("Failed to save part to storage: {:?}", e);
+            return HttpResponse::InternalServerError().json(serde_json::json!({
+                "error": "Failed to save part to storage"
+            }));
+        }
+    };
+
+    let part = UploadPart::new(
+        upload.id,
+        part_number,
+        saved.size as i64,
+        saved.sha256,
+        saved.storage_path,
+    );
+
+    match part.upsert(&pool).await {
+        Ok(previous) => {
+            // A re-uploaded part replaces the earlier content
+            if let Some(previous) = previous {
+                if let Err(e) = storage.delete_file(&previous).await {
+                    error!("Failed to remove replaced part {}: {:?}", previous, e);
+                }
+            }
+
+            HttpResponse::Ok().json(UploadPartResponse {
+                part_number: part.part_number,
+                etag: part.etag,
+                size: part.size,
+            })
+        }
+        Err(e) => {
+            // Most likely the upload was completed or aborted meanwhile
+            error!("Failed to record part {} of upload {}: {:?}", part_number, upload.id, e);
+            if let Err(e) = storage.delete_file(&part.storage_path).await {
+                error!("Failed to clean up part {}: {:?}", part.storage_path, e);
+            }
+
+            HttpResponse::InternalServerError().json(serde_json::json!({
+                "error": "Failed to save part"
+            }))
+        }
+    }
+}
+
+// Resolve the parts a completion request lists against those uploaded,
+// before any content is read
+pub fn select_parts<'a>(
+    requested: &[CompletedPart],
+    uploaded: &'a [UploadPart],
+    max_size: u64,
+) -> Result<Vec<&'a UploadPart>, PartSelectionError> {
+    if requested.is_empty() {
+        return Err(PartSelectionError::Empty);
+    }
+
+    let mut selected = Vec::with_capacity(requested.len());
+    let mut total_size: u64 = 0;
+    for (index, wanted) in requested.iter().enumerate() {
+        if index > 0 && wanted.part_number <= requested[index - 1].part_number {
+            return Err(PartSelectionError::Order);
+        }
+
+        let part = uploaded
+            .iter()
+            .find(|p| p.part_number == wanted.part_number && p.etag == wanted.etag)
+            .ok_or(PartSelectionError::Mismatch(wanted.part_number))?;
+
+        let is_last = index == requested.len() - 1;
+        if !is_last && part.size < MIN_PART_SIZE {
+            return Err(PartSelectionError::TooSmall(part.part_number));
+        }
+
+        total_size += part.size as u64;
+        if total_size > max_size {
+            return Err(PartSelectionError::TooLarge(max_size));
+        }
+
+        selected.push(part);
+    }
+
+    Ok(selected)
+}
+
+pub async fn complete_upload(
+    req: HttpRequest,
+    pool: web::Data<PgPool>,
+    config: web::Data<Config>,
+    storage: web::Data<dyn Storage + Send + Sync>,
+    path: web::Path<Uuid>,
+    complete_req: web::Json<CompleteUploadRequest>,
+) -> impl Responder {
+    // Get user ID from request extensions (set by middleware)
+    let user_id = match get_user_id_from_request(&req) {
+        Some(id) => id,
+        None => {
+            return HttpResponse::Unauthorized().json(serde_json::json!({
+                "error": "Authentication required"
+            }));
+        }
+    };
+
+    let (upload, bucket) = match find_upload(&req, &pool, path.into_inner(), user_id, Action::Write, PolicyAction::PutObject).await {
+        Ok(found) => found,
+        Err(response) => return response,
+    };
+
+    let uploaded_parts = match UploadPart::find_by_upload_id(&pool, upload.id).await {
+        Ok(parts) => parts,
+        Err(e) => {
+            error!("Failed to fetch parts of upload {}: {:?}", upload.id, e);
+            return HttpResponse::InternalServerError().json(serde_json::json!({
+                "error": "Failed to fetch upload parts"
+            }));
+        }
+    };
+
+    // Resolve the requested parts against what was uploaded
+    let selected = match select_parts(&complete_req.parts, &uploaded_parts, config.max_object_size) {
+        Ok(selected) => selected,
+        Err(e @ PartSelectionError::TooLarge(_)) => {
+            return HttpResponse::PayloadTooLarge().json(serde_json::json!({
+                "error": e.to_string()
+            }));
+        }
+        Err(e) => {
+            return HttpResponse::BadRequest().json(serde_json::json!({
+                "error": e.to_string()
+            }));
+        }
+    };
+
+    // Concatenate the parts into the final object
+    let file_id = Uuid::new_v4();
+    let writer = match storage.create_writer(&bucket.name, file_id, &upload.filename).await {
+        Ok(writer) => writer,
+        Err(e) => {
+            error!("Failed to open storage for upload {}: {:?}", upload.id, e);
+            return HttpResponse::InternalServerError().json(serde_json::json!({
+                "error": "Failed to save file to storage"
+            }));
+        }
+    };
+    let mut object = ObjectUpload::new(writer, config.max_object_size);
+
+    for part in &selected {
+        let mut stream = match storage.read_file_stream(&part.storage_path).await {
+            Ok(stream) => stream,
+            Err(e) => {
+                error!("Failed to open part {} of upload {}: {:?}", part.part_number, upload.id, e);
+                return HttpResponse::InternalServerError().json(serde_json::json!({
+                    "error": "Failed to read upload parts"
+                }));
+            }
+        };
+
+        while let Some(chunk) = stream.next().await {
+            let written = match chunk {
+                Ok(data) => object.write(&data).await,
+                Err(e) => Err(e.into()),
+            };
+
+            if let Err(e) = written {
+                error!("Failed to assemble upload {}: {:?}", upload.id, e);
+                return HttpResponse::InternalServerError().json(serde_json::json!({
+                    "error": "Failed to assemble upload parts"
+                }));
+            }
+        }
+    }
+
+    let saved = match object.finish().await {
+        Ok(saved) => saved,
+        Err(e) => {
+            error!("Failed to save assembled upload {}: {:?}", upload.id, e);
+            return HttpResponse::InternalServerError().json(serde_json::json!({
+                "error": "Failed to save file to storage"
+            }));
+        }
+    };
+
+    let mut file = File::new(
+        upload.filename.clone(),
+        upload.content_type.clone(),
+        saved.size as i64,
+        bucket.id,
+        saved.storage_path,
+        saved.sha256,
+    );
+    file.metadata = upload.metadata.clone();
+
+    // Publish the file and retire the upload in one step
+    let committed: Result<Option<String>, sqlx::Error> = async {
+        let mut tx = pool.begin().await?;
+        let replaced = file.put_version(&mut tx, bucket.versioning_status()).await?;
+        upload.delete(&mut *tx).await?;
+        tx.commit().await?;
+        Ok(replaced)
+    }
+    .await;
+
+    match committed {
+        Ok(Some(replaced)) => {
+            if let Err(e) = storage.delete_file(&replaced).await {
+                error!("Failed to remove replaced file content {}: {:?}", replaced, e);
+            }
+        }
+        Ok(None) => {}
+        Err(e) => {
+            error!("Failed to save file metadata for upload {}: {:?}", upload.id, e);
+            if let Err(e) = storage.delete_file(&file.storage_path).await {
+                error!("Failed to clean up stored file {}: {:?}", file.storage_path, e);
+            }
+
+            return HttpResponse::InternalServerError().json(serde_json::json!({
+                "error": "Failed to save file metadata"
+            }));
+        }
+    }
+
+    // The parts are no longer needed, including any left out of the request
+    for part in &uploaded_parts {
+        if let Err(e) = storage.delete_file(&part.storage_path).await {
+            error!("Failed to remove part {}: {:?}", part.storage_path, e);
+        }
+    }
+
+    info!("Multipart upload {} completed as file {}", upload.id, file.id);
+    HttpResponse::Created().json(FileInfoResponse::from(file))
+}
+
+pub async fn abort_upload(
+    req: HttpRequest,
+    pool: web::Data<PgPool>,
+    storage: web::Data<dyn Storage + Send + Sync>,
+    path: web::Path<Uuid>,
+) -> impl Responder {
+    // Get user ID from request extensions (set by middleware)
+    let user_id = match get_user_id_from_request(&req) {
+        Some(id) => id,
+        None => {
+            return HttpResponse::Unauthorized().json(serde_json::json!({
+                "error": "Authentication required"
+            }));
+        }
+    };
+
+    let (upload, _) = match find_upload(&req, &pool, path.into_inner(), user_id, Action::Write, PolicyAction::AbortMultipartUpload).await {
+        Ok(found) => found,
+        Err(response) => return response,
+    };
+
+    match abort(&pool, storage.get_ref(), &upload).await {
+        Ok(()) => {
+            info!("Multipart upload {} aborted", upload.id);
+            HttpResponse::NoContent().finish()
+        }
+        Err(e) => {
+            error!("Failed to abort upload {}: {:?}", upload.id, e);
+            HttpResponse::InternalServerError().json(serde_json::json!({
+                "error": "Failed to abort upload"
+            }))
+        }
+    }
+}
+
+pub async fn list_uploads(
+    req: HttpRequest,
+    pool: web::Data<PgPool>,
+    query: web::Query<MultipartQuery>,
+) -> impl Responder {
+    // Get user ID from request extensions (set by middleware)
+    let user_id = match get_user_id_from_request(&req) {
+        Some(id) => id,
+        None => {
+            return HttpResponse::Unauthorized().json(serde_json::json!({
+                "error": "Authentication required"
+            }));
+        }
+    };
+
+    // Find bucket by name and user
+    let bucket = match Bucket::find_by_name_and_user(&pool, &query.bucket_name, user_id).await {
+        Ok(Some(bucket)) => bucket,
+        Ok(None) => {
+            return HttpResponse::NotFound().json(serde_json::json!({
+                "error": "Bucket not found"
+            }));
+        }
+        Err(_) => {
+            return HttpResponse::InternalServerError().json(serde_json::json!({
+                "error": "Failed to check bucket"
+            }));
+        }
+    };
+
+    if let Some(response) = scope_denied(&req, Action::List, &bucket, Some("")) {
+        return response;
+    }
+    if let Some(response) = access_denied(&req, &bucket, PolicyAction::ListBucketMultipartUploads, Some("")) {
+        return response;
+    }
+
+    match MultipartUpload::find_by_bucket_id(&pool, bucket.id).await {
+        Ok(uploads) => {
+            let uploads = uploads.into_iter().map(|upload| {
+                UploadInfo {
+                    upload_id: upload.id,
+                    filename: upload.filename,
+                    content_type: upload.content_type,
+                    created_at: upload.created_at,
+                }
+            }).collect();
+
+            HttpResponse::Ok().json(UploadListResponse { uploads })
+        }
+        Err(e) => {
+            error!("Error fetching uploads: {:?}", e);
+            HttpResponse::InternalServerError().json(serde_json::json!({
+                "error": "Failed to fetch uploads"
+            }))
+        }
+    }
+}
+
+pub async fn list_parts(
+    req: HttpRequest,
+    pool: web::Data<PgPool>,
+    path: web::Path<Uuid>,
+) -> impl Responder {
+    // Get user ID from request extensions (set by middleware)
+    let user_id = match get_user_id_from_request(&req) {
+        Some(id) => id,
+        None => {
+            return HttpResponse::Unauthorized().json(serde_json::json!({
+                "error": "Authentication required"
+            }));
+        }
+    };
+
+    let (upload, _) = match find_upload(&req, &pool, path.into_inner(), user_id, Action::List, PolicyAction::ListMultipartUploadParts).await {
+        Ok(found) => found,
+        Err(response) => return response,
+    };
+
+    match UploadPart::find_by_upload_id(&pool, upload.id).await {
+        Ok(parts) => {
+            let parts = parts.into_iter().map(|part| {
+                PartInfo {
+                    part_number: part.part_number,
+                    etag: part.etag,
+                    size: part.size,
+                    created_at: part.created_at,
+                }
+            }).collect();
+
+            HttpResponse::Ok().json(PartListResponse {
+                upload_id: upload.id,
+                filename: upload.filename,
+                parts,
+            })
+        }
+        Err(e) => {
+            error!("Error fetching parts of upload {}: {:?}", upload.id, e);
+            HttpResponse::InternalServerError().json(serde_json::json!({
+                "error": "Failed to fetch upload parts"
+            }))
+        }
+    }
+}
+
+// Abort every upload that was started more than `ttl` ago
+pub async fn reap_stale_uploads(
+    pool: &PgPool,
+    storage: &(dyn Storage + Send + Sync),
+    ttl: chrono::Duration,
+) -> anyhow::Result<usize> {
+    let stale = MultipartUpload::find_created_before(pool, Utc::now() - ttl).await?;
+
+    let mut reaped = 0;
+    for upload in &stale {
+        match abort(pool, storage, upload).await {
+            Ok(()) => reaped += 1,
+            Err(e) => error!("Failed to reap upload {}: {:?}", upload.id, e),
+        }
+    }
+
+    Ok(reaped)
+}
+
+// Background task that periodically cleans up abandoned multipart uploads
+pub async fn run_upload_reaper(
+    pool: PgPool,
+    storage: Arc<dyn Storage + Send + Sync>,
+    ttl: chrono::Duration,
+) {
+    let mut interval = tokio::time::interval(REAPER_INTERVAL);
+
+    loop {
+        interval.tick().await;
+
+        match reap_stale_uploads(&pool, storage.as_ref(), ttl).await {
+            Ok(0) => {}
+            Ok(count) => info!("Reaped {} stale multipart uploads", count),
+            Err(e) => error!("Failed to reap stale multipart uploads: {:?}", e),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn uploaded(part_number: i32, size: i64) -> UploadPart {
+        UploadPart {
+            upload_id: Uuid::nil(),
+            part_number,
+            size,
+            etag: format!("etag-{}", part_number),
+            storage_path: format!("parts/{}", part_number),
+            created_at: Utc::now(),
+        }
+    }
+
+    fn requested(part_number: i32) -> CompletedPart {
+        CompletedPart {
+            part_number,
+            etag: format!("etag-{}", part_number),
+        }
+    }
+
+    #[test]
+    fn selects_parts_in_order() {
+        let parts = [uploaded(3, 10), uploaded(1, MIN_PART_SIZE), uploaded(2, MIN_PART_SIZE)];
+
+        let selected = select_parts(&[requested(1), requested(3)], &parts, u64::MAX).unwrap();
+        let numbers: Vec<i32> = selected.iter().map(|part| part.part_number).collect();
+        assert_eq!(numbers, [1, 3]);
+
+        assert_eq!(select_parts(&[], &parts, u64::MAX).unwrap_err(), PartSelectionError::Empty);
+        assert_eq!(
+            select_parts(&[requested(2), requested(1)], &parts, u64::MAX).unwrap_err(),
+            PartSelectionError::Order
+        );
+        assert_eq!(
+            select_parts(&[requested(1), requested(1)], &parts, u64::MAX).unwrap_err(),
+            PartSelectionError::Order
+        );
+    }
+
+    #[test]
+    fn rejects_etag_mismatches_and_missing_parts() {
+        let parts = [uploaded(1, MIN_PART_SIZE), uploaded(2, 10)];
+        let stale = CompletedPart { part_number: 2, etag: "etag-old".to_string() };
+
+        assert_eq!(
+            select_parts(&[requested(1), stale], &parts, u64::MAX).unwrap_err(),
+            PartSelectionError::Mismatch(2)
+        );
+        assert_eq!(
+            select_parts(&[requested(1), requested(4)], &parts, u64::MAX).unwrap_err(),
+            PartSelectionError::Mismatch(4)
+        );
+    }
+
+    #[test]
+    fn checks_part_and_object_sizes() {
+        let parts = [uploaded(1, 10), uploaded(2, MIN_PART_SIZE), uploaded(3, 10)];
+
+        // Only the last part may be smaller than the minimum
+        assert_eq!(
+            select_parts(&[requested(1), requested(2)], &parts, u64::MAX).unwrap_err(),
+            PartSelectionError::TooSmall(1)
+        );
+        assert!(select_parts(&[requested(2), requested(3)], &parts, u64::MAX).is_ok());
+
+        let limit = MIN_PART_SIZE as u64 + 9;
+        assert_eq!(
+            select_parts(&[requested(2), requested(3)], &parts, limit).unwrap_err(),
+            PartSelectionError::TooLarge(limit)
+        );
+        assert!(select_parts(&[requested(2), requested(3)], &parts, limit + 1).is_ok());
+    }
+}
@@ -0,0 +1,478 @@
+use actix_web::{web, HttpRequest, HttpResponse, Responder};
+use log::{error, info};
+use serde::{Deserialize, Serialize};
+use sqlx::PgPool;
+use thiserror::Error;
+use uuid::Uuid;
+
+use crate::middleware::auth::{full_access_denied, get_principal_from_request, get_user_id_from_request};
+use crate::models::{Action, Bucket, BucketAccess, Membership, OrgRole, Organization, OrganizationMember, User};
+
+#[derive(Debug, Deserialize)]
+pub struct CreateOrganizationRequest {
+    name: String,
+}
+
+#[derive(Debug, Serialize)]
+pub struct OrganizationListResponse {
+    organizations: Vec<Membership>,
+}
+
+#[derive(Debug, Deserialize)]
+pub struct MemberRequest {
+    email: String,
+    role: OrgRole,
+}
+
+#[derive(Debug, Deserialize)]
+pub struct RemoveMemberQuery {
+    email: String,
+}
+
+#[derive(Debug, Serialize)]
+pub struct MemberListResponse {
+    organization: String,
+    members: Vec<OrganizationMember>,
+}
+
+#[derive(Debug, Serialize)]
+pub struct OrganizationBucketListResponse {
+    organization: String,
+    buckets: Vec<OrganizationBucketInfo>,
+}
+
+#[derive(Debug, Serialize)]
+pub struct OrganizationBucketInfo {
+    id: Uuid,
+    name: String,
+    access: BucketAccess,
+    created_at: chrono::DateTime<chrono::Utc>,
+}
+
+#[derive(Debug, Error)]
+pub enum MemberError {
+    #[error("Member not found")]
+    NotFound,
+    #[error("Your role cannot manage this member")]
+    Forbidden,
+    #[error("An organization must keep at least one owner")]
+    LastOwner,
+    #[error(transparent)]
+    Database(#[from] sqlx::Error),
+}
+
+enum MemberChange {
+    SetRole(OrgRole),
+    Remove,
+}
+
+// Look up an organization the user is a member of, with their role in it.
+// Organizations of others are reported as not found.
+pub async fn find_membership(
+    pool: &PgPool,
+    name: &str,
+    user_id: Uuid,
+) -> Result<(Organization, OrgRole), HttpResponse> {
+    let not_found = || HttpResponse::NotFound().json(serde_json::json!({
+        "error": "Organization not found"
+    }));
+
+    let organization = match Organization::find_by_name(pool, name).await {
+        Ok(Some(organization)) => organization,
+        Ok(None) => return Err(not_found()),
+        Err(e) => {
+            error!("Failed to look up organization {}: {:?}", name, e);
+            return Err(HttpResponse::InternalServerError().json(serde_json::json!({
+                "error": "Failed to check organization"
+            })));
+        }
+    };
+
+    match OrganizationMember::find(pool, organization.id, user_id).await {
+        Ok(Some(member)) => match member.role() {
+            Some(role) => Ok((organization, role)),
+            None => Err(not_found()),
+        },
+        Ok(None) => Err(not_found()),
+        Err(e) => {
+            error!("Failed to look up membership in {}: {:?}", organization.id, e);
+            Err(HttpResponse::InternalServerError().json(serde_json::json!({
+                "error": "Failed to check organization"
+            })))
+        }
+    }
+}
+
+async fn find_user(pool: &PgPool, email: &str) -> Result<User, HttpResponse> {
+    match User::find_by_email(pool, email).await {
+        Ok(Some(user)) => Ok(user),
+        Ok(None) => Err(HttpResponse::NotFound().json(serde_json::json!({
+            "error": "User not found"
+        }))),
+        Err(e) => {
+            error!("Failed to look up user: {:?}", e);
+            Err(HttpResponse::InternalServerError().json(serde_json::json!({
+                "error": "Failed to look up user"
+            })))
+        }
+    }
+}
+
+// Change a member's role or remove them. Members may always leave, but
+// the organization's last owner cannot be removed or demoted.
+async fn change_member(
+    pool: &PgPool,
+    organization: &Organization,
+    actor_id: Uuid,
+    actor_role: OrgRole,
+    user_id: Uuid,
+    change: MemberChange,
+) -> Result<OrganizationMember, MemberError> {
+    let mut tx = pool.begin().await?;
+    organization.lock(&mut tx).await?;
+
+    let mut member = OrganizationMember::find(&mut *tx, organization.id, user_id)
+        .await?
+        .ok_or(MemberError::NotFound)?;
+    // Only owners may touch a member whose role is not recognized
+    let current = member.role().unwrap_or(OrgRole::Owner);
+
+    let allowed = match change {
+        MemberChange::Remove => user_id == actor_id || actor_role.manages(current),
+        MemberChange::SetRole(role) => actor_role.manages(current) && actor_role.manages(role),
+    };
+    if !allowed {
+        return Err(MemberError::Forbidden);
+    }
+
+    let stays_owner = matches!(change, MemberChange::SetRole(OrgRole::Owner));
+    if current == OrgRole::Owner && !stays_owner
+        && OrganizationMember::count_owners(&mut *tx, organization.id).await? <= 1
+    {
+        return Err(MemberError::LastOwner);
+    }
+
+    match change {
+        MemberChange::SetRole(role) => member.set_role(&mut *tx, role).await?,
+        MemberChange::Remove => member.remove(&mut *tx).await?,
+    }
+    tx.commit().await?;
+
+    Ok(member)
+}
+
+fn member_error_response(e: MemberError) -> HttpResponse {
+    match e {
+        MemberError::NotFound => HttpResponse::NotFound().json(serde_json::json!({
+            "error": e.to_string()
+        })),
+        MemberError::Forbidden => HttpResponse::Forbidden().json(serde_json::json!({
+            "error": e.to_string()
+        })),
+        MemberError::LastOwner => HttpResponse::Conflict().json(serde_json::json!({
+            "error": e.to_string()
+        })),
+        MemberError::Database(e) => {
+            error!("Failed to change organization member: {:?}", e);
+            HttpResponse::InternalServerError().json(serde_json::json!({
+                "error": "Failed to change organization member"
+            }))
+        }
+    }
+}
+
+// Create an organization with the requesting user as its owner
+pub async fn create_organization(
+    req: HttpRequest,
+    pool: web::Data<PgPool>,
+    body: web::Json<CreateOrganizationRequest>,
+) -> impl Responder {
+    // Get user ID from request extensions (set by middleware)
+    let user_id = match get_user_id_from_request(&req) {
+        Some(id) => id,
+        None => {
+            return HttpResponse::Unauthorized().json(serde_json::json!({
+                "error": "Authentication required"
+            }));
+        }
+    };
+    // Scoped API keys cannot manage organizations, which would let them
+    // reach other buckets
+    if let Some(response) = full_access_denied(&req) {
+        return response;
+    }
+
+    if !Organization::is_valid_name(&body.name) {
+        return HttpResponse::BadRequest().json(serde_json::json!({
+            "error": "Organization name must be 1 to 63 lowercase letters, digits or hyphens, not starting or ending with a hyphen"
+        }));
+    }
+
+    let organization = Organization::new(body.name.clone());
+    let created = async {
+        let mut tx = pool.begin().await?;
+        organization.create(&mut tx, user_id).await?;
+        tx.commit().await
+    };
+
+    match created.await {
+        Ok(()) => {
+            info!("Organization {} created by user {}", organization.id, user_id);
+            HttpResponse::Created().json(Membership {
+                id: organization.id,
+                name: organization.name,
+                role: OrgRole::Owner.as_str().to_string(),
+                created_at: organization.created_at,
+            })
+        }
+        Err(e) if e.as_database_error().is_some_and(|db| db.is_unique_violation()) => {
+            HttpResponse::Conflict().json(serde_json::json!({
+                "error": "An organization with this name already exists"
+            }))
+        }
+        Err(e) => {
+            error!("Failed to create organization: {:?}", e);
+            HttpResponse::InternalServerError().json(serde_json::json!({
+                "error": "Failed to create organization"
+            }))
+        }
+    }
+}
+
+// Organizations the requesting user belongs to, with their role in each
+pub async fn list_organizations(
+    req: HttpRequest,
+    pool: web::Data<PgPool>,
+) -> impl Responder {
+    // Get user ID from request extensions (set by middleware)
+    let user_id = match get_user_id_from_request(&req) {
+        Some(id) => id,
+        None => {
+            return HttpResponse::Unauthorized().json(serde_json::json!({
+                "error": "Authentication required"
+            }));
+        }
+    };
+    if let Some(response) = full_access_denied(&req) {
+        return response;
+    }
+
+    match Organization::find_by_user_id(pool.get_ref(), user_id).await {
+        Ok(organizations) => HttpResponse::Ok().json(OrganizationListResponse { organizations }),
+        Err(e) => {
+            error!("Failed to fetch organizations: {:?}", e);
+            HttpResponse::InternalServerError().json(serde_json::json!({
+                "error": "Failed to fetch organizations"
+            }))
+        }
+    }
+}
+
+pub async fn list_members(
+    req: HttpRequest,
+    pool: web::Data<PgPool>,
+    path: web::Path<String>,
+) -> impl Responder {
+    // Get user ID from request extensions (set by middleware)
+    let user_id = match get_user_id_from_request(&req) {
+        Some(id) => id,
+        None => {
+            return HttpResponse::Unauthorized().json(serde_json::json!({
+                "error": "Authentication required"
+            }));
+        }
+    };
+    if let Some(response) = full_access_denied(&req) {
+        return response;
+    }
+    let (organization, _) = match find_membership(&pool, &path, user_id).await {
+        Ok(found) => found,
+        Err(response) => return response,
+    };
+
+    match OrganizationMember::find_by_organization_id(pool.get_ref(), organization.id).await {
+        Ok(members) => HttpResponse::Ok().json(MemberListResponse {
+            organization: organization.name,
+            members,
+        }),
+        Err(e) => {
+            error!("Failed to fetch members of {}: {:?}", organization.id, e);
+            HttpResponse::InternalServerError().json(serde_json::json!({
+                "error": "Failed to fetch members"
+            }))
+        }
+    }
+}
+
+// Invite an existing user into the organization with a role
+pub async fn add_member(
+    req: HttpRequest,
+    pool: web::Data<PgPool>,
+    path: web::Path<String>,
+    body: web::Json<MemberRequest>,
+) -> impl Responder {
+    // Get user ID from request extensions (set by middleware)
+    let user_id = match get_user_id_from_request(&req) {
+        Some(id) => id,
+        None => {
+            return HttpResponse::Unauthorized().json(serde_json::json!({
+                "error": "Authentication required"
+            }));
+        }
+    };
+    if let Some(response) = full_access_denied(&req) {
+        return response;
+    }
+    let (organization, role) = match find_membership(&pool, &path, user_id).await {
+        Ok(found) => found,
+        Err(response) => return response,
+    };
+
+    if !role.manages(body.role) {
+        return member_error_response(MemberError::Forbidden);
+    }
+    let user = match find_user(&pool, &body.email).await {
+        Ok(user) => user,
+        Err(response) => return response,
+    };
+
+    match OrganizationMember::add(pool.get_ref(), organization.id, user.id, body.role).await {
+        Ok(()) => {
+            info!("User {} added to organization {} as {}", user.id, organization.id, body.role.as_str());
+            HttpResponse::Created().json(serde_json::json!({
+                "organization": organization.name,
+                "email": user.email,
+                "role": body.role
+            }))
+        }
+        Err(e) if e.as_database_error().is_some_and(|db| db.is_unique_violation()) => {
+            HttpResponse::Conflict().json(serde_json::json!({
+                "error": "User is already a member"
+            }))
+        }
+        Err(e) => member_error_response(e.into()),
+    }
+}
+
+pub async fn update_member(
+    req: HttpRequest,
+    pool: web::Data<PgPool>,
+    path: web::Path<String>,
+    body: web::Json<MemberRequest>,
+) -> impl Responder {
+    // Get user ID from request extensions (set by middleware)
+    let user_id = match get_user_id_from_request(&req) {
+        Some(id) => id,
+        None => {
+            return HttpResponse::Unauthorized().json(serde_json::json!({
+                "error": "Authentication required"
+            }));
+        }
+    };
+    if let Some(response) = full_access_denied(&req) {
+        return response;
+    }
+    let (organization, role) = match find_membership(&pool, &path, user_id).await {
+        Ok(found) => found,
+        Err(response) => return response,
+    };
+    let user = match find_user(&pool, &body.email).await {
+        Ok(user) => user,
+        Err(response) => return response,
+    };
+
+    match change_member(&pool, &organization, user_id, role, user.id, MemberChange::SetRole(body.role)).await {
+        Ok(member) => {
+            info!("Role of user {} in organization {} set to {}", user.id, organization.id, member.role);
+            HttpResponse::Ok().json(member)
+        }
+        Err(e) => member_error_response(e),
+    }
+}
+
+pub async fn remove_member(
+    req: HttpRequest,
+    pool: web::Data<PgPool>,
+    path: web::Path<String>,
+    query: web::Query<RemoveMemberQuery>,
+) -> impl Responder {
+    // Get user ID from request extensions (set by middleware)
+    let user_id = match get_user_id_from_request(&req) {
+        Some(id) => id,
+        None => {
+            return HttpResponse::Unauthorized().json(serde_json::json!({
+                "error": "Authentication required"
+            }));
+        }
+    };
+    if let Some(response) = full_access_denied(&req) {
+        return response;
+    }
+    let (organization, role) = match find_membership(&pool, &path, user_id).await {
+        Ok(found) => found,
+        Err(response) => return response,
+    };
+    let user = match find_user(&pool, &query.email).await {
+        Ok(user) => user,
+        Err(response) => return response,
+    };
+
+    match change_member(&pool, &organization, user_id, role, user.id, MemberChange::Remove).await {
+        Ok(_) => {
+            info!("User {} removed from organization {}", user.id, organization.id);
+            HttpResponse::NoContent().finish()
+        }
+        Err(e) => member_error_response(e),
+    }
+}
+
+// Buckets owned by the organization, with the access the member's role gives
+pub async fn list_organization_buckets(
+    req: HttpRequest,
+    pool: web::Data<PgPool>,
+    path: web::Path<String>,
+) -> impl Responder {
+    // Get the principal from request extensions (set by middleware)
+    let principal = match get_principal_from_request(&req) {
+        Some(principal) => principal,
+        None => {
+            return HttpResponse::Unauthorized().json(serde_json::json!({
+                "error": "Authentication required"
+            }));
+        }
+    };
+
+    if !principal.allows_action(Action::List) {
+        return HttpResponse::Forbidden().json(serde_json::json!({
+            "error": "This API key is not allowed to list"
+        }));
+    }
+
+    let (organization, role) = match find_membership(&pool, &path, principal.user_id).await {
+        Ok(found) => found,
+        Err(response) => return response,
+    };
+
+    // Leave out buckets a scoped key cannot reach
+    match Bucket::find_by_organization_id(&pool, organization.id, role).await {
+        Ok(buckets) => HttpResponse::Ok().json(OrganizationBucketListResponse {
+            organization: organization.name,
+            buckets: buckets
+                .into_iter()
+                .filter(|bucket| principal.allows_bucket(&bucket.qualified_name()))
+                .map(|bucket| OrganizationBucketInfo {
+                    id: bucket.id,
+                    access: bucket.access(),
+                    name: bucket.name,
+                    created_at: bucket.created_at,
+                })
+                .collect(),
+        }),
+        Err(e) => {
+            error!("Failed to fetch buckets of organization {}: {:?}", organization.id, e);
+            HttpResponse::InternalServerError().json(serde_json::json!({
+                "error": "Failed to fetch buckets"
+            }))
+        }
+    }
+}
@@ -0,0 +1,136 @@
+use actix_web::{web, HttpRequest, HttpResponse, Responder};
+use bytes::Bytes;
+use log::{error, info};
+use serde::{Deserialize, Serialize};
+use serde_json::Value;
+use sqlx::PgPool;
+
+use crate::middleware::auth::{full_access_denied, get_user_id_from_request};
+use crate::models::{Bucket, Policy};
+
+#[derive(Debug, Deserialize)]
+pub struct BucketPolicyQuery {
+    bucket_name: String,
+}
+
+#[derive(Debug, Serialize)]
+pub struct BucketPolicyResponse {
+    bucket_name: String,
+    policy: Value,
+}
+
+// Look up a bucket owned by the requesting user whose policy is being managed.
+// Scoped API keys cannot manage policies, which decide who gets access.
+async fn find_bucket(req: &HttpRequest, pool: &PgPool, bucket_name: &str) -> Result<Bucket, HttpResponse> {
+    // Get user ID from request extensions (set by middleware)
+    let user_id = match get_user_id_from_request(req) {
+        Some(id) => id,
+        None => {
+            return Err(HttpResponse::Unauthorized().json(serde_json::json!({
+                "error": "Authentication required"
+            })));
+        }
+    };
+
+    if let Some(response) = full_access_denied(req) {
+        return Err(response);
+    }
+
+    match Bucket::find_owned_by_name(pool, bucket_name, user_id).await {
+        Ok(Some(bucket)) => Ok(bucket),
+        Ok(None) => Err(HttpResponse::NotFound().json(serde_json::json!({
+            "error": "Bucket not found"
+        }))),
+        Err(_) => Err(HttpResponse::InternalServerError().json(serde_json::json!({
+            "error": "Failed to check bucket"
+        }))),
+    }
+}
+
+pub async fn get_bucket_policy(
+    req: HttpRequest,
+    pool: web::Data<PgPool>,
+    query: web::Query<BucketPolicyQuery>,
+) -> impl Responder {
+    let bucket = match find_bucket(&req, &pool, &query.bucket_name).await {
+        Ok(bucket) => bucket,
+        Err(response) => return response,
+    };
+
+    match bucket.policy {
+        Some(policy) => HttpResponse::Ok().json(BucketPolicyResponse {
+            bucket_name: bucket.name,
+            policy,
+        }),
+        None => HttpResponse::NotFound().json(serde_json::json!({
+            "error": "Bucket has no policy"
+        })),
+    }
+}
+
+// The body is the policy document itself
+pub async fn put_bucket_policy(
+    req: HttpRequest,
+    pool: web::Data<PgPool>,
+    query: web::Query<BucketPolicyQuery>,
+    body: Bytes,
+) -> impl Responder {
+    let mut bucket = match find_bucket(&req, &pool, &query.bucket_name).await {
+        Ok(bucket) => bucket,
+        Err(response) => return response,
+    };
+
+    let document = match std::str::from_utf8(&body) {
+        Ok(document) => document,
+        Err(_) => {
+            return HttpResponse::BadRequest().json(serde_json::json!({
+                "error": "Policy is not valid UTF-8"
+            }));
+        }
+    };
+    let policy = match Policy::validate(document, &bucket.name) {
+        Ok(policy) => policy,
+        Err(e) => {
+            return HttpResponse::BadRequest().json(serde_json::json!({
+                "error": e.to_string()
+            }));
+        }
+    };
+
+    match bucket.set_policy(&pool, Some(policy)).await {
+        Ok(()) => {
+            info!("Policy of bucket {} updated", bucket.id);
+            HttpResponse::Ok().json(BucketPolicyResponse {
+                bucket_name: bucket.name,
+                policy: bucket.policy.unwrap_or_default(),
+            })
+        }
+        Err(e) => {
+            error!("Failed to set policy of bucket {}: {:?}", bucket.id, e);
+            HttpResponse::InternalServerError().json(serde_json::json!({
+                "error": "Failed to update bucket policy"
+            }))
+        }
+    }
+}
+
+pub async fn delete_bucket_policy(
+    req: HttpRequest,
+    pool: web::Data<PgPool>,
+    query: web::Query<BucketPolicyQuery>,
+) -> impl Responder {
+    let mut bucket = match find_bucket(&req, &pool, &query.bucket_name).await {
+        Ok(bucket) => bucket,
+        Err(response) => return response,
+    };
+
+    match bucket.set_policy(&pool, None).await {
+        Ok(()) => HttpResponse::NoContent().finish(),
+        Err(e) => {
+            error!("Failed to delete policy of bucket {}: {:?}", bucket.id, e);
+            HttpResponse::InternalServerError().json(serde_json::json!({
+                "error": "Failed to delete bucket policy"
+            }))
+        }
+    }
+}
@@ -0,0 +1,171 @@
+use actix_web::{web, HttpRequest, HttpResponse, Responder};
+use chrono::{DateTime, Utc};
+use log::error;
+use serde::{Deserialize, Serialize};
+use sqlx::PgPool;
+
+use crate::authentication::sigv4::{self, Credential, MAX_PRESIGNED_EXPIRES_SECONDS};
+use crate::middleware::auth::{access_denied, get_user_id_from_request, scope_denied};
+use crate::models::{Action, Bucket, PolicyAction, User};
+
+// Region and service named in the credential scope of issued URLs
+const PRESIGN_REGION: &str = "us-east-1";
+const PRESIGN_SERVICE: &str = "s3";
+
+const DEFAULT_EXPIRES_SECONDS: i64 = 3600;
+
+#[derive(Debug, Deserialize, Serialize, Clone, Copy, PartialEq)]
+#[serde(rename_all = "UPPERCASE")]
+pub enum PresignMethod {
+    Get,
+    Put,
+}
+
+impl PresignMethod {
+    fn as_str(&self) -> &'static str {
+        match self {
+            PresignMethod::Get => "GET",
+            PresignMethod::Put => "PUT",
+        }
+    }
+}
+
+#[derive(Debug, Deserialize)]
+pub struct PresignRequest {
+    bucket_name: String,
+    filename: String,
+    method: PresignMethod,
+    expires_in: Option<i64>,
+}
+
+#[derive(Debug, Serialize)]
+pub struct PresignResponse {
+    url: String,
+    method: PresignMethod,
+    expires_at: DateTime<Utc>,
+}
+
+// Issue a time-limited URL for one object on the S3 routes, signed with the
+// user's access key
+pub async fn create_presigned_url(
+    req: HttpRequest,
+    pool: web::Data<PgPool>,
+    body: web::Json<PresignRequest>,
+) -> impl Responder {
+    // Get user ID from request extensions (set by middleware)
+    let user_id = match get_user_id_from_request(&req) {
+        Some(id) => id,
+        None => {
+            return HttpResponse::Unauthorized().json(serde_json::json!({
+                "error": "Authentication required"
+            }));
+        }
+    };
+
+    let expires_in = body.expires_in.unwrap_or(DEFAULT_EXPIRES_SECONDS);
+    if !(1..=MAX_PRESIGNED_EXPIRES_SECONDS).contains(&expires_in) {
+        return HttpResponse::BadRequest().json(serde_json::json!({
+            "error": format!("expires_in must be between 1 and {} seconds", MAX_PRESIGNED_EXPIRES_SECONDS)
+        }));
+    }
+    if body.filename.is_empty() {
+        return HttpResponse::BadRequest().json(serde_json::json!({
+            "error": "filename must not be empty"
+        }));
+    }
+
+    let (action, policy_action) = match body.method {
+        PresignMethod::Get => (Action::Read, PolicyAction::GetObject),
+        PresignMethod::Put => (Action::Write, PolicyAction::PutObject),
+    };
+
+    // Find bucket by name and user
+    let bucket = match Bucket::find_by_name_and_user(&pool, &body.bucket_name, user_id).await {
+        Ok(Some(bucket)) => bucket,
+        Ok(None) => {
+            return HttpResponse::NotFound().json(serde_json::json!({
+                "error": "Bucket not found"
+            }));
+        }
+        Err(e) => {
+            error!("Database error when checking bucket: {:?}", e);
+            return HttpResponse::InternalServerError().json(serde_json::json!({
+                "error": "Failed to check bucket"
+            }));
+        }
+    };
+
+    // The URL can only do what the requesting key could do itself
+    if let Some(response) = scope_denied(&req, action, &bucket, Some(&body.filename)) {
+        return response;
+    }
+    // Nor more than the user's access and the bucket's policy allow; the
+    // request made with the URL is checked again when it arrives
+    if let Some(response) = access_denied(&req, &bucket, policy_action, Some(&body.filename)) {
+        return response;
+    }
+
+    let user = match User::find_by_id(&pool, user_id).await {
+        Ok(Some(user)) => user,
+        Ok(None) => {
+            return HttpResponse::NotFound().json(serde_json::json!({
+                "error": "User not found"
+            }));
+        }
+        Err(e) => {
+            error!("Error fetching user {}: {:?}", user_id, e);
+            return HttpResponse::InternalServerError().json(serde_json::json!({
+                "error": "Failed to fetch user"
+            }));
+        }
+    };
+
+    let (access_key_id, secret) = match (user.access_key_id, user.secret_access_key) {
+        (Some(access_key_id), Some(secret)) => (access_key_id, secret),
+        _ => {
+            return HttpResponse::Conflict().json(serde_json::json!({
+                "error": "No access key for this user; create one with POST /access-key"
+            }));
+        }
+    };
+
+    let now = Utc::now();
+    let amz_date = now.format("%Y%m%dT%H%M%SZ").to_string();
+    let credential = Credential {
+        access_key_id,
+        date: amz_date[..8].to_string(),
+        region: PRESIGN_REGION.to_string(),
+        service: PRESIGN_SERVICE.to_string(),
+    };
+
+    // The URL is only valid for the host the client reached us on. It names
+    // the bucket as requested, so that a qualified name reaches the same one.
+    let connection = req.connection_info();
+    let path = format!(
+        "/{}/{}",
+        sigv4::uri_encode(&body.bucket_name, true),
+        sigv4::uri_encode(&body.filename, false)
+    );
+
+    match sigv4::presign_query(
+        body.method.as_str(),
+        &path,
+        connection.host(),
+        &credential,
+        &secret,
+        &amz_date,
+        expires_in,
+    ) {
+        Ok(query) => HttpResponse::Ok().json(PresignResponse {
+            url: format!("{}://{}{}?{}", connection.scheme(), connection.host(), path, query),
+            method: body.method,
+            expires_at: now + chrono::Duration::seconds(expires_in),
+        }),
+        Err(e) => {
+            error!("Failed to presign URL: {:?}", e);
+            HttpResponse::InternalServerError().json(serde_json::json!({
+                "error": "Failed to create presigned URL"
+            }))
+        }
+    }
+}
@@ -0,0 +1,224 @@
+use actix_web::body::SizedStream;
+use actix_web::http::{header, StatusCode};
+use actix_web::{HttpRequest, HttpResponse, HttpResponseBuilder};
+use bytes::Bytes;
+use futures::StreamExt;
+use uuid::Uuid;
+
+use crate::storage::{ByteStream, Storage, StorageResult};
+
+// Requests with more ranges than this are served in full instead
+const MAX_RANGES: usize = 16;
+
+// Inclusive byte range of an object, already clamped to its size
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct ByteRange {
+    pub start: u64,
+    pub end: u64,
+}
+
+impl ByteRange {
+    pub fn len(&self) -> u64 {
+        self.end - self.start + 1
+    }
+
+    fn content_range(&self, size: u64) -> String {
+        format!("bytes {}-{}/{}", self.start, self.end, size)
+    }
+}
+
+// None of the requested ranges overlap the object
+#[derive(Debug, PartialEq, Eq)]
+pub struct Unsatisfiable;
+
+// `Content-Range` value sent with a 416 response
+pub fn unsatisfied_range(size: u64) -> String {
+    format!("bytes */{}", size)
+}
+
+// Parse a `Range: bytes=...` header against an object of `size` bytes.
+// Returns `Ok(None)` when the whole object should be served, which includes
+// headers that are malformed or use another unit, as HTTP allows ignoring
+// those. Ranges that start past the end are dropped; if nothing is left the
+// request is unsatisfiable.
+pub fn parse_range(value: &str, size: u64) -> Result<Option<Vec<ByteRange>>, Unsatisfiable> {
+    let specs = match value.trim().split_once('=') {
+        Some((unit, specs)) if unit.trim().eq_ignore_ascii_case("bytes") => specs,
+        _ => return Ok(None),
+    };
+
+    let specs: Vec<&str> = specs
+        .split(',')
+        .map(str::trim)
+        .filter(|spec| !spec.is_empty())
+        .collect();
+    if specs.is_empty() || specs.len() > MAX_RANGES {
+        return Ok(None);
+    }
+
+    let mut ranges = Vec::new();
+    for spec in specs {
+        let (first, last) = match spec.split_once('-') {
+            Some(bounds) => bounds,
+            None => return Ok(None),
+        };
+        let parse = |bound: &str| bound.trim().parse::<u64>().ok();
+
+        let range = match (first.trim().is_empty(), last.trim().is_empty()) {
+            // bytes=-N, the last N bytes
+            (true, false) => match parse(last) {
+                Some(0) => None,
+                Some(suffix) if size > 0 => Some(ByteRange {
+                    start: size.saturating_sub(suffix),
+                    end: size - 1,
+                }),
+                Some(_) => None,
+                None => return Ok(None),
+            },
+            // bytes=N-, from N to the end
+            (false, true) => match parse(first) {
+                Some(start) if start < size => Some(ByteRange { start, end: size - 1 }),
+                Some(_) => None,
+                None => return Ok(None),
+            },
+            // bytes=N-M
+            (false, false) => match (parse(first), parse(last)) {
+                (Some(start), Some(end)) if start > end => return Ok(None),
+                (Some(start), Some(end)) if start < size => Some(ByteRange {
+                    start,
+                    end: end.min(size - 1),
+                }),
+                (Some(_), Some(_)) => None,
+                _ => return Ok(None),
+            },
+            (true, true) => return Ok(None),
+        };
+
+        ranges.extend(range);
+    }
+
+    if ranges.is_empty() {
+        return Err(Unsatisfiable);
+    }
+    Ok(Some(ranges))
+}
+
+// Ranges requested for an object of `size` bytes, if the request has a Range header
+pub fn requested_ranges(req: &HttpRequest, size: u64) -> Result<Option<Vec<ByteRange>>, Unsatisfiable> {
+    match req.headers().get(header::RANGE).and_then(|value| value.to_str().ok()) {
+        Some(value) => parse_range(value, size),
+        None => Ok(None),
+    }
+}
+
+// Body of a 206 response: a single range, or a `multipart/byteranges`
+// document with one part per range
+pub struct PartialContent {
+    content_type: Option<String>,
+    content_range: Option<String>,
+    length: u64,
+    stream: ByteStream,
+}
+
+impl PartialContent {
+    // Open the requested ranges of a stored object. Only those bytes are
+    // read from storage.
+    pub async fn read(
+        storage: &(dyn Storage + Send + Sync),
+        path: &str,
+        size: u64,
+        content_type: &str,
+        ranges: &[ByteRange],
+    ) -> StorageResult<Self> {
+        if let [range] = ranges {
+            return Ok(Self {
+                content_type: None,
+                content_range: Some(range.content_range(size)),
+                length: range.len(),
+                stream: storage.read_range_stream(path, range.start, range.len()).await?,
+            });
+        }
+
+        let boundary = Uuid::new_v4().simple().to_string();
+        let mut parts = Vec::with_capacity(ranges.len() * 2 + 1);
+        let mut length = 0;
+
+        for range in ranges {
+            let part_header = format!(
+                "\r\n--{}\r\nContent-Type: {}\r\nContent-Range: {}\r\n\r\n",
+                boundary,
+                content_type,
+                range.content_range(size)
+            );
+            length += part_header.len() as u64 + range.len();
+            parts.push(futures::stream::once(async move { Ok(Bytes::from(part_header)) }).boxed());
+            parts.push(storage.read_range_stream(path, range.start, range.len()).await?);
+        }
+
+        let closing = format!("\r\n--{}--\r\n", boundary);
+        length += closing.len() as u64;
+        parts.push(futures::stream::once(async move { Ok(Bytes::from(closing)) }).boxed());
+
+        Ok(Self {
+            content_type: Some(format!("multipart/byteranges; boundary={}", boundary)),
+            content_range: None,
+            length,
+            stream: futures::stream::iter(parts).flatten().boxed(),
+        })
+    }
+
+    // Finish `response` as 206 Partial Content with this body
+    pub fn respond(self, mut response: HttpResponseBuilder) -> HttpResponse {
+        response.status(StatusCode::PARTIAL_CONTENT);
+        if let Some(content_type) = self.content_type {
+            response.insert_header((header::CONTENT_TYPE, content_type));
+        }
+        if let Some(content_range) = self.content_range {
+            response.insert_header((header::CONTENT_RANGE, content_range));
+        }
+        response.body(SizedStream::new(self.length, self.stream))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn range(start: u64, end: u64) -> ByteRange {
+        ByteRange { start, end }
+    }
+
+    #[test]
+    fn parses_single_and_suffix_ranges() {
+        assert_eq!(parse_range("bytes=0-99", 1000), Ok(Some(vec![range(0, 99)])));
+        assert_eq!(parse_range("bytes=900-", 1000), Ok(Some(vec![range(900, 999)])));
+        assert_eq!(parse_range("bytes=-100", 1000), Ok(Some(vec![range(900, 999)])));
+        assert_eq!(parse_range("bytes=-5000", 1000), Ok(Some(vec![range(0, 999)])));
+        assert_eq!(parse_range("bytes=990-5000", 1000), Ok(Some(vec![range(990, 999)])));
+    }
+
+    #[test]
+    fn parses_multiple_ranges() {
+        assert_eq!(
+            parse_range("bytes=0-0, 5000-6000, -1", 1000),
+            Ok(Some(vec![range(0, 0), range(999, 999)]))
+        );
+    }
+
+    #[test]
+    fn rejects_ranges_past_the_end() {
+        assert_eq!(parse_range("bytes=1000-", 1000), Err(Unsatisfiable));
+        assert_eq!(parse_range("bytes=1000-2000,-0", 1000), Err(Unsatisfiable));
+        assert_eq!(parse_range("bytes=0-", 0), Err(Unsatisfiable));
+    }
+
+    #[test]
+    fn ignores_malformed_headers() {
+        for value in ["items=0-1", "bytes=", "bytes=5-1", "bytes=a-b", "bytes=-", "bytes=1"] {
+            assert_eq!(parse_range(value, 1000), Ok(None), "{}", value);
+        }
+
+        let too_many = format!("bytes={}", vec!["0-1"; MAX_RANGES + 1].join(","));
+        assert_eq!(parse_range(&too_many, 1000), Ok(None));
+    }
+}
@@ -0,0 +1,389 @@
+use actix_web::{web, HttpRequest, HttpResponse};
+use bytes::Bytes;
+use log::{error, info};
+use serde::Deserialize;
+use sqlx::PgPool;
+
+use crate::config::Config;
+use crate::handlers::listing::{self, ListOptions, MAX_KEYS_LIMIT};
+use crate::handlers::tagging::{change_tags, TagChange};
+use crate::middleware::auth::get_principal_from_request;
+use crate::models::{Action, Bucket, CreateBucketError, File, Policy, PolicyAction, TagTarget, VersioningStatus};
+
+use super::error::S3Error;
+use super::{authenticated_user, authorize, check_access, find_bucket, find_owned_bucket, require_full_access, tag_set, xml, RESERVED_BUCKET_NAMES};
+
+#[derive(Debug, Deserialize)]
+pub struct ListObjectsQuery {
+    prefix: Option<String>,
+    delimiter: Option<String>,
+    #[serde(rename = "max-keys")]
+    max_keys: Option<i64>,
+    #[serde(rename = "continuation-token")]
+    continuation_token: Option<String>,
+    #[serde(rename = "start-after")]
+    start_after: Option<String>,
+}
+
+#[derive(Debug, Deserialize)]
+pub struct ListVersionsQuery {
+    prefix: Option<String>,
+}
+
+// GET / (ListBuckets)
+pub async fn list_buckets(
+    req: HttpRequest,
+    pool: web::Data<PgPool>,
+) -> Result<HttpResponse, S3Error> {
+    let user_id = authenticated_user(&req)?;
+
+    let principal = get_principal_from_request(&req).ok_or(S3Error::AccessDenied)?;
+    if !principal.allows_action(Action::List) {
+        return Err(S3Error::AccessDenied);
+    }
+
+    let mut buckets = Bucket::find_by_user_id(&pool, user_id).await.map_err(|e| {
+        error!("Error fetching buckets: {:?}", e);
+        S3Error::InternalError
+    })?;
+    // A key scoped to some buckets only sees those
+    buckets.retain(|bucket| principal.allows_bucket(&bucket.qualified_name()));
+
+    Ok(HttpResponse::Ok()
+        .content_type(xml::CONTENT_TYPE)
+        .body(xml::list_buckets(user_id, &buckets)))
+}
+
+// PUT /{bucket} (CreateBucket)
+pub async fn create_bucket(
+    req: HttpRequest,
+    pool: web::Data<PgPool>,
+    config: web::Data<Config>,
+    path: web::Path<String>,
+) -> Result<HttpResponse, S3Error> {
+    let user_id = authenticated_user(&req)?;
+    let bucket_name = path.into_inner();
+
+    Bucket::validate_name(&bucket_name).map_err(|e| S3Error::InvalidBucketName(e.to_string()))?;
+    if RESERVED_BUCKET_NAMES.contains(&bucket_name.as_str()) {
+        return Err(S3Error::InvalidBucketName(format!(
+            "Bucket name \"{}\" is reserved",
+            bucket_name
+        )));
+    }
+
+    let bucket = Bucket::new(bucket_name, user_id);
+    authorize(&req, Action::Write, &bucket, None)?;
+    match bucket.create(&pool, config.global_bucket_namespace).await {
+        Ok(()) => {}
+        // With a global namespace the name may be taken by anyone's bucket,
+        // otherwise only by one of the user's own
+        Err(CreateBucketError::NameTaken) if config.global_bucket_namespace => {
+            return match find_owned_bucket(&pool, &bucket.name, user_id).await {
+                Ok(_) => Err(S3Error::BucketAlreadyOwnedByYou),
+                Err(S3Error::NoSuchBucket) => Err(S3Error::BucketAlreadyExists),
+                Err(e) => Err(e),
+            };
+        }
+        Err(CreateBucketError::NameTaken) => return Err(S3Error::BucketAlreadyOwnedByYou),
+        Err(CreateBucketError::Database(e)) => {
+            error!("Failed to create bucket: {:?}", e);
+            return Err(S3Error::InternalError);
+        }
+    }
+
+    info!("Bucket created: {}", bucket.id);
+    Ok(HttpResponse::Ok()
+        .insert_header(("Location", format!("/{}", bucket.name)))
+        .finish())
+}
+
+// HEAD /{bucket} (HeadBucket)
+pub async fn head_bucket(
+    req: HttpRequest,
+    pool: web::Data<PgPool>,
+    path: web::Path<String>,
+) -> Result<HttpResponse, S3Error> {
+    let user_id = authenticated_user(&req)?;
+    let bucket = find_bucket(&pool, &path, user_id).await?;
+    authorize(&req, Action::Read, &bucket, None)?;
+    check_access(&req, &bucket, PolicyAction::ListBucket, None)?;
+
+    Ok(HttpResponse::Ok().finish())
+}
+
+// GET /{bucket} (ListObjectsV2)
+pub async fn list_objects(
+    req: HttpRequest,
+    pool: web::Data<PgPool>,
+    path: web::Path<String>,
+    query: web::Query<ListObjectsQuery>,
+) -> Result<HttpResponse, S3Error> {
+    let user_id = authenticated_user(&req)?;
+
+    let max_keys = query.max_keys.unwrap_or(MAX_KEYS_LIMIT as i64);
+    if max_keys < 0 {
+        return Err(S3Error::InvalidArgument(
+            "max-keys must be a non-negative integer".to_string(),
+        ));
+    }
+
+    // A continuation token takes precedence over start-after
+    let start = match (&query.continuation_token, &query.start_after) {
+        (Some(token), _) => Some(listing::decode_token(token).ok_or_else(|| {
+            S3Error::InvalidArgument("The continuation token provided is incorrect".to_string())
+        })?),
+        (None, Some(key)) => Some(listing::start_after(key)),
+        (None, None) => None,
+    };
+
+    let prefix = query.prefix.clone().unwrap_or_default();
+
+    let bucket = find_bucket(&pool, &path, user_id).await?;
+    authorize(&req, Action::List, &bucket, Some(&prefix))?;
+    check_access(&req, &bucket, PolicyAction::ListBucket, Some(&prefix))?;
+
+    let options = ListOptions {
+        prefix: &prefix,
+        delimiter: query.delimiter.as_deref(),
+        max_keys: (max_keys as usize).min(MAX_KEYS_LIMIT),
+        start,
+        tag: None,
+    };
+
+    let page = listing::list_objects(&pool, bucket.id, &options).await.map_err(|e| {
+        error!("Error fetching files: {:?}", e);
+        S3Error::InternalError
+    })?;
+    let next_token = page.next_start.as_deref().map(listing::encode_token);
+
+    let body = xml::list_objects_v2(&xml::ListObjectsV2 {
+        bucket: &bucket.name,
+        prefix: &prefix,
+        delimiter: query.delimiter.as_deref(),
+        max_keys,
+        continuation_token: query.continuation_token.as_deref(),
+        start_after: query.start_after.as_deref(),
+        next_continuation_token: next_token.as_deref(),
+        files: &page.files,
+        common_prefixes: &page.common_prefixes,
+    });
+
+    Ok(HttpResponse::Ok().content_type(xml::CONTENT_TYPE).body(body))
+}
+
+// DELETE /{bucket} (DeleteBucket)
+pub async fn delete_bucket(
+    req: HttpRequest,
+    pool: web::Data<PgPool>,
+    path: web::Path<String>,
+) -> Result<HttpResponse, S3Error> {
+    let user_id = authenticated_user(&req)?;
+    let bucket = find_bucket(&pool, &path, user_id).await?;
+    authorize(&req, Action::Delete, &bucket, None)?;
+    check_access(&req, &bucket, PolicyAction::DeleteBucket, None)?;
+
+    match bucket.delete_if_empty(&pool).await {
+        Ok(true) => {
+            info!("Bucket deleted: {}", bucket.id);
+            Ok(HttpResponse::NoContent().finish())
+        }
+        Ok(false) => Err(S3Error::BucketNotEmpty),
+        Err(e) => {
+            error!("Failed to delete bucket {}: {:?}", bucket.id, e);
+            Err(S3Error::InternalError)
+        }
+    }
+}
+
+// GET /{bucket}?versioning (GetBucketVersioning)
+pub async fn get_bucket_versioning(
+    req: HttpRequest,
+    pool: web::Data<PgPool>,
+    path: web::Path<String>,
+) -> Result<HttpResponse, S3Error> {
+    let user_id = authenticated_user(&req)?;
+    let bucket = find_bucket(&pool, &path, user_id).await?;
+    authorize(&req, Action::Read, &bucket, None)?;
+    check_access(&req, &bucket, PolicyAction::GetBucketVersioning, None)?;
+
+    let status = bucket.versioning_status().map(|status| status.as_str());
+    Ok(HttpResponse::Ok()
+        .content_type(xml::CONTENT_TYPE)
+        .body(xml::versioning_configuration(status)))
+}
+
+// PUT /{bucket}?versioning (PutBucketVersioning)
+pub async fn put_bucket_versioning(
+    req: HttpRequest,
+    pool: web::Data<PgPool>,
+    path: web::Path<String>,
+    body: Bytes,
+) -> Result<HttpResponse, S3Error> {
+    let user_id = authenticated_user(&req)?;
+    let mut bucket = find_bucket(&pool, &path, user_id).await?;
+    authorize(&req, Action::Write, &bucket, None)?;
+    check_access(&req, &bucket, PolicyAction::PutBucketVersioning, None)?;
+
+    let status = std::str::from_utf8(&body)
+        .ok()
+        .and_then(|body| xml::element_text(body, "Status"))
+        .and_then(VersioningStatus::parse)
+        .ok_or(S3Error::MalformedXML)?;
+
+    bucket.set_versioning(&pool, status).await.map_err(|e| {
+        error!("Failed to set versioning for bucket {}: {:?}", bucket.id, e);
+        S3Error::InternalError
+    })?;
+
+    info!("Versioning for bucket {} set to {}", bucket.id, status.as_str());
+    Ok(HttpResponse::Ok().finish())
+}
+
+// GET /{bucket}?tagging (GetBucketTagging)
+pub async fn get_bucket_tagging(
+    req: HttpRequest,
+    pool: web::Data<PgPool>,
+    path: web::Path<String>,
+) -> Result<HttpResponse, S3Error> {
+    let user_id = authenticated_user(&req)?;
+    let bucket = find_bucket(&pool, &path, user_id).await?;
+    authorize(&req, Action::Read, &bucket, None)?;
+    check_access(&req, &bucket, PolicyAction::GetBucketTagging, None)?;
+
+    let tags = TagTarget::Bucket(bucket.id).tags(pool.get_ref()).await.map_err(|e| {
+        error!("Failed to fetch tags of bucket {}: {:?}", bucket.id, e);
+        S3Error::InternalError
+    })?;
+
+    // Unlike objects, a bucket without tags has no tag set at all
+    if tags.is_empty() {
+        return Err(S3Error::NoSuchTagSet);
+    }
+
+    Ok(HttpResponse::Ok()
+        .content_type(xml::CONTENT_TYPE)
+        .body(xml::tagging(&tags)))
+}
+
+// PUT /{bucket}?tagging (PutBucketTagging)
+pub async fn put_bucket_tagging(
+    req: HttpRequest,
+    pool: web::Data<PgPool>,
+    path: web::Path<String>,
+    body: Bytes,
+) -> Result<HttpResponse, S3Error> {
+    let user_id = authenticated_user(&req)?;
+    let bucket = find_bucket(&pool, &path, user_id).await?;
+    authorize(&req, Action::Write, &bucket, None)?;
+    check_access(&req, &bucket, PolicyAction::PutBucketTagging, None)?;
+
+    let tags = tag_set(&body)?;
+    change_tags(&pool, TagTarget::Bucket(bucket.id), TagChange::Replace(tags)).await?;
+
+    Ok(HttpResponse::NoContent().finish())
+}
+
+// DELETE /{bucket}?tagging (DeleteBucketTagging)
+pub async fn delete_bucket_tagging(
+    req: HttpRequest,
+    pool: web::Data<PgPool>,
+    path: web::Path<String>,
+) -> Result<HttpResponse, S3Error> {
+    let user_id = authenticated_user(&req)?;
+    let bucket = find_bucket(&pool, &path, user_id).await?;
+    authorize(&req, Action::Write, &bucket, None)?;
+    check_access(&req, &bucket, PolicyAction::PutBucketTagging, None)?;
+
+    change_tags(&pool, TagTarget::Bucket(bucket.id), TagChange::Remove(Vec::new())).await?;
+
+    Ok(HttpResponse::NoContent().finish())
+}
+
+// GET /{bucket}?policy (GetBucketPolicy)
+pub async fn get_bucket_policy(
+    req: HttpRequest,
+    pool: web::Data<PgPool>,
+    path: web::Path<String>,
+) -> Result<HttpResponse, S3Error> {
+    let user_id = authenticated_user(&req)?;
+    require_full_access(&req)?;
+    let bucket = find_owned_bucket(&pool, &path, user_id).await?;
+
+    let policy = bucket.policy.ok_or(S3Error::NoSuchBucketPolicy)?;
+
+    Ok(HttpResponse::Ok().json(policy))
+}
+
+// PUT /{bucket}?policy (PutBucketPolicy)
+pub async fn put_bucket_policy(
+    req: HttpRequest,
+    pool: web::Data<PgPool>,
+    path: web::Path<String>,
+    body: Bytes,
+) -> Result<HttpResponse, S3Error> {
+    let user_id = authenticated_user(&req)?;
+    require_full_access(&req)?;
+    let mut bucket = find_owned_bucket(&pool, &path, user_id).await?;
+
+    let document = std::str::from_utf8(&body)
+        .map_err(|_| S3Error::MalformedPolicy("Policy is not valid UTF-8".to_string()))?;
+    let policy = Policy::validate(document, &bucket.name)
+        .map_err(|e| S3Error::MalformedPolicy(e.to_string()))?;
+
+    bucket.set_policy(&pool, Some(policy)).await.map_err(|e| {
+        error!("Failed to set policy of bucket {}: {:?}", bucket.id, e);
+        S3Error::InternalError
+    })?;
+
+    info!("Policy of bucket {} updated", bucket.id);
+    Ok(HttpResponse::NoContent().finish())
+}
+
+// DELETE /{bucket}?policy (DeleteBucketPolicy)
+pub async fn delete_bucket_policy(
+    req: HttpRequest,
+    pool: web::Data<PgPool>,
+    path: web::Path<String>,
+) -> Result<HttpResponse, S3Error> {
+    let user_id = authenticated_user(&req)?;
+    require_full_access(&req)?;
+    let mut bucket = find_owned_bucket(&pool, &path, user_id).await?;
+
+    bucket.set_policy(&pool, None).await.map_err(|e| {
+        error!("Failed to delete policy of bucket {}: {:?}", bucket.id, e);
+        S3Error::InternalError
+    })?;
+
+    Ok(HttpResponse::NoContent().finish())
+}
+
+// GET /{bucket}?versions (ListObjectVersions)
+pub async fn list_object_versions(
+    req: HttpRequest,
+    pool: web::Data<PgPool>,
+    path: web::Path<String>,
+    query: web::Query<ListVersionsQuery>,
+) -> Result<HttpResponse, S3Error> {
+    let user_id = authenticated_user(&req)?;
+    let prefix = query.prefix.clone().unwrap_or_default();
+
+    let bucket = find_bucket(&pool, &path, user_id).await?;
+    authorize(&req, Action::List, &bucket, Some(&prefix))?;
+    check_access(&req, &bucket, PolicyAction::ListBucketVersions, Some(&prefix))?;
+
+    let mut files = File::find_versions(&pool, bucket.id, None).await.map_err(|e| {
+        error!("Error fetching file versions: {:?}", e);
+        S3Error::InternalError
+    })?;
+
+    files.retain(|file| file.filename.starts_with(&prefix));
+
+    let body = xml::list_object_versions(&xml::ListObjectVersions {
+        bucket: &bucket.name,
+        prefix: &prefix,
+        files: &files,
+    });
+
+    Ok(HttpResponse::Ok().content_type(xml::CONTENT_TYPE).body(body))
+}
@@ -0,0 +1,224 @@
+use actix_web::http::{header, StatusCode};
+use actix_web::{HttpResponse, ResponseError};
+use log::error;
+use std::fmt;
+
+use crate::authentication::sigv4::SigV4Error;
+use crate::handlers::range::unsatisfied_range;
+use crate::handlers::tagging::TagError;
+
+use super::xml;
+
+// Errors reported to S3 clients as an `<Error>` document with the S3 error code
+#[derive(Debug)]
+pub enum S3Error {
+    AccessDenied,
+    AuthorizationHeaderMalformed(String),
+    BucketAlreadyExists,
+    BucketAlreadyOwnedByYou,
+    BucketNotEmpty,
+    EntityTooLarge(u64),
+    IncompleteBody,
+    InternalError,
+    InvalidAccessKeyId,
+    InvalidArgument(String),
+    InvalidBucketName(String),
+    // Carries the object size for the Content-Range header
+    InvalidRange(u64),
+    InvalidRequest(String),
+    InvalidTag(String),
+    KeyTooLongError,
+    MalformedPolicy(String),
+    MalformedXML,
+    MetadataTooLarge,
+    MethodNotAllowed,
+    NoSuchBucket,
+    NoSuchBucketPolicy,
+    NoSuchKey,
+    NoSuchTagSet,
+    NoSuchVersion,
+    PreconditionFailed,
+    RequestExpired,
+    RequestTimeTooSkewed,
+    SignatureDoesNotMatch,
+    XAmzContentSHA256Mismatch,
+}
+
+impl S3Error {
+    pub fn code(&self) -> &'static str {
+        match self {
+            S3Error::AccessDenied => "AccessDenied",
+            S3Error::AuthorizationHeaderMalformed(_) => "AuthorizationHeaderMalformed",
+            S3Error::BucketAlreadyExists => "BucketAlreadyExists",
+            S3Error::BucketAlreadyOwnedByYou => "BucketAlreadyOwnedByYou",
+            S3Error::BucketNotEmpty => "BucketNotEmpty",
+            S3Error::EntityTooLarge(_) => "EntityTooLarge",
+            S3Error::IncompleteBody => "IncompleteBody",
+            S3Error::InternalError => "InternalError",
+            S3Error::InvalidAccessKeyId => "InvalidAccessKeyId",
+            S3Error::InvalidArgument(_) => "InvalidArgument",
+            S3Error::InvalidBucketName(_) => "InvalidBucketName",
+            S3Error::InvalidRange(_) => "InvalidRange",
+            S3Error::InvalidRequest(_) => "InvalidRequest",
+            S3Error::InvalidTag(_) => "InvalidTag",
+            S3Error::KeyTooLongError => "KeyTooLongError",
+            S3Error::MalformedPolicy(_) => "MalformedPolicy",
+            S3Error::MalformedXML => "MalformedXML",
+            S3Error::MetadataTooLarge => "MetadataTooLarge",
+            S3Error::MethodNotAllowed => "MethodNotAllowed",
+            S3Error::NoSuchBucket => "NoSuchBucket",
+            S3Error::NoSuchBucketPolicy => "NoSuchBucketPolicy",
+            S3Error::NoSuchKey => "NoSuchKey",
+            S3Error::NoSuchTagSet => "NoSuchTagSet",
+            S3Error::NoSuchVersion => "NoSuchVersion",
+            S3Error::PreconditionFailed => "PreconditionFailed",
+            // S3 reports expired presigned URLs as a plain AccessDenied
+            S3Error::RequestExpired => "AccessDenied",
+            S3Error::RequestTimeTooSkewed => "RequestTimeTooSkewed",
+            S3Error::SignatureDoesNotMatch => "SignatureDoesNotMatch",
+            S3Error::XAmzContentSHA256Mismatch => "XAmzContentSHA256Mismatch",
+        }
+    }
+
+    pub fn message(&self) -> String {
+        match self {
+            S3Error::AccessDenied => "Access Denied".to_string(),
+            S3Error::AuthorizationHeaderMalformed(message) => message.clone(),
+            S3Error::BucketAlreadyExists => {
+                "The requested bucket name is not available. The bucket namespace is shared by all users of the system. Please select a different name and try again.".to_string()
+            }
+            S3Error::BucketAlreadyOwnedByYou => {
+                "Your previous request to create the named bucket succeeded and you already own it.".to_string()
+            }
+            S3Error::BucketNotEmpty => "The bucket you tried to delete is not empty.".to_string(),
+            S3Error::EntityTooLarge(limit) => {
+                format!("Your proposed upload exceeds the maximum allowed size of {} bytes.", limit)
+            }
+            S3Error::IncompleteBody => {
+                "You did not provide the number of bytes specified by the Content-Length HTTP header.".to_string()
+            }
+            S3Error::InternalError => "We encountered an internal error. Please try again.".to_string(),
+            S3Error::InvalidAccessKeyId => {
+                "The AWS access key Id you provided does not exist in our records.".to_string()
+            }
+            S3Error::InvalidArgument(message) => message.clone(),
+            S3Error::InvalidBucketName(message) => message.clone(),
+            S3Error::InvalidRange(_) => "The requested range is not satisfiable".to_string(),
+            S3Error::InvalidRequest(message) => message.clone(),
+            S3Error::InvalidTag(message) => message.clone(),
+            S3Error::KeyTooLongError => "Your key is too long.".to_string(),
+            S3Error::MalformedPolicy(message) => message.clone(),
+            S3Error::MalformedXML => {
+                "The XML you provided was not well-formed or did not validate against our published schema.".to_string()
+            }
+            S3Error::MetadataTooLarge => {
+                "Your metadata headers exceed the maximum allowed metadata size.".to_string()
+            }
+            S3Error::MethodNotAllowed => {
+                "The specified method is not allowed against this resource.".to_string()
+            }
+            S3Error::NoSuchBucket => "The specified bucket does not exist.".to_string(),
+            S3Error::NoSuchBucketPolicy => "The bucket policy does not exist".to_string(),
+            S3Error::NoSuchKey => "The specified key does not exist.".to_string(),
+            S3Error::NoSuchTagSet => "The TagSet does not exist".to_string(),
+            S3Error::NoSuchVersion => {
+                "The specified version does not exist.".to_string()
+            }
+            S3Error::PreconditionFailed => {
+                "At least one of the pre-conditions you specified did not hold".to_string()
+            }
+            S3Error::RequestExpired => "Request has expired".to_string(),
+            S3Error::RequestTimeTooSkewed => {
+                "The difference between the request time and the server's time is too large.".to_string()
+            }
+            S3Error::SignatureDoesNotMatch => {
+                "The request signature we calculated does not match the signature you provided.".to_string()
+            }
+            S3Error::XAmzContentSHA256Mismatch => {
+                "The provided 'x-amz-content-sha256' header does not match what was computed.".to_string()
+            }
+        }
+    }
+}
+
+impl fmt::Display for S3Error {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}: {}", self.code(), self.message())
+    }
+}
+
+impl ResponseError for S3Error {
+    fn status_code(&self) -> StatusCode {
+        match self {
+            S3Error::AccessDenied
+            | S3Error::InvalidAccessKeyId
+            | S3Error::RequestExpired
+            | S3Error::RequestTimeTooSkewed
+            | S3Error::SignatureDoesNotMatch => StatusCode::FORBIDDEN,
+            S3Error::BucketAlreadyExists
+            | S3Error::BucketAlreadyOwnedByYou
+            | S3Error::BucketNotEmpty => StatusCode::CONFLICT,
+            S3Error::EntityTooLarge(_) => StatusCode::PAYLOAD_TOO_LARGE,
+            S3Error::InternalError => StatusCode::INTERNAL_SERVER_ERROR,
+            S3Error::AuthorizationHeaderMalformed(_)
+            | S3Error::IncompleteBody
+            | S3Error::InvalidArgument(_)
+            | S3Error::InvalidBucketName(_)
+            | S3Error::InvalidRequest(_)
+            | S3Error::InvalidTag(_)
+            | S3Error::KeyTooLongError
+            | S3Error::MalformedPolicy(_)
+            | S3Error::MalformedXML
+            | S3Error::MetadataTooLarge
+            | S3Error::XAmzContentSHA256Mismatch => StatusCode::BAD_REQUEST,
+            S3Error::InvalidRange(_) => StatusCode::RANGE_NOT_SATISFIABLE,
+            S3Error::MethodNotAllowed => StatusCode::METHOD_NOT_ALLOWED,
+            S3Error::PreconditionFailed => StatusCode::PRECONDITION_FAILED,
+            S3Error::NoSuchBucket
+            | S3Error::NoSuchBucketPolicy
+            | S3Error::NoSuchKey
+            | S3Error::NoSuchTagSet
+            | S3Error::NoSuchVersion => StatusCode::NOT_FOUND,
+        }
+    }
+
+    fn error_response(&self) -> HttpResponse {
+        let mut response = HttpResponse::build(self.status_code());
+        if let S3Error::InvalidRange(size) = self {
+            response.insert_header((header::CONTENT_RANGE, unsatisfied_range(*size)));
+        }
+
+        response
+            .content_type(xml::CONTENT_TYPE)
+            .body(xml::error(self.code(), &self.message()))
+    }
+}
+
+impl From<&SigV4Error> for S3Error {
+    fn from(err: &SigV4Error) -> Self {
+        match err {
+            SigV4Error::Malformed(_) => S3Error::AuthorizationHeaderMalformed(err.to_string()),
+            SigV4Error::MissingHeader(_) => S3Error::InvalidRequest(err.to_string()),
+            SigV4Error::RequestTimeTooSkewed => S3Error::RequestTimeTooSkewed,
+            SigV4Error::Expired => S3Error::RequestExpired,
+            SigV4Error::InvalidAccessKeyId => S3Error::InvalidAccessKeyId,
+            SigV4Error::SignatureDoesNotMatch => S3Error::SignatureDoesNotMatch,
+            SigV4Error::ContentSha256Mismatch => S3Error::XAmzContentSHA256Mismatch,
+            SigV4Error::InvalidChunk(_) => S3Error::InvalidRequest(err.to_string()),
+            SigV4Error::IncompleteBody => S3Error::IncompleteBody,
+        }
+    }
+}
+
+impl From<TagError> for S3Error {
+    fn from(err: TagError) -> Self {
+        match err {
+            TagError::NotFound => S3Error::NoSuchKey,
+            TagError::Database(e) => {
+                error!("Failed to update tags: {:?}", e);
+                S3Error::InternalError
+            }
+            err => S3Error::InvalidTag(err.to_string()),
+        }
+    }
+}
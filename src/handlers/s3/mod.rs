@@ -0,0 +1,206 @@
+pub mod bucket;
+pub mod error;
+pub mod object;
+pub mod xml;
+
+use actix_web::{guard, web, HttpRequest};
+use chrono::{DateTime, Utc};
+use log::error;
+use sqlx::PgPool;
+use uuid::Uuid;
+
+use crate::middleware::auth::{get_principal_from_request, get_user_id_from_request, policy_decision};
+use crate::models::policy::Decision;
+use crate::models::{Action, Bucket, PolicyAction, TagSet};
+
+use self::error::S3Error;
+
+// Top-level paths served by the JSON API, which shadow buckets of the same
+// name on the path-style S3 routes
+pub const RESERVED_BUCKET_NAMES: &[&str] = &[
+    ".well-known",
+    "register",
+    "login",
+    "token",
+    "logout",
+    "set-password",
+    "change-password",
+    "access-key",
+    "api-keys",
+    "buckets",
+    "files",
+    "create-bucket",
+    "upload-file",
+    "get-file",
+    "download-file",
+    "delete-file",
+    "delete-bucket",
+    "bucket-versioning",
+    "file-versions",
+    "copy-file",
+    "move-file",
+    "file-tags",
+    "bucket-tags",
+    "bucket-policy",
+    "bucket-grants",
+    "bucket-acl",
+    "organizations",
+    "multipart-uploads",
+    "presign",
+];
+
+// Path-style S3 routes: `/`, `/{bucket}` and `/{bucket}/{key}`
+pub fn configure(cfg: &mut web::ServiceConfig) {
+    cfg.service(
+        web::resource("/")
+            .route(web::get().to(bucket::list_buckets))
+    )
+    .service(
+        web::resource("/{bucket}")
+            .route(web::put().guard(query_flag("versioning")).to(bucket::put_bucket_versioning))
+            .route(web::get().guard(query_flag("versioning")).to(bucket::get_bucket_versioning))
+            .route(web::get().guard(query_flag("versions")).to(bucket::list_object_versions))
+            .route(web::put().guard(query_flag("policy")).to(bucket::put_bucket_policy))
+            .route(web::get().guard(query_flag("policy")).to(bucket::get_bucket_policy))
+            .route(web::delete().guard(query_flag("policy")).to(bucket::delete_bucket_policy))
+            .route(web::put().guard(query_flag("tagging")).to(bucket::put_bucket_tagging))
+            .route(web::get().guard(query_flag("tagging")).to(bucket::get_bucket_tagging))
+            .route(web::delete().guard(query_flag("tagging")).to(bucket::delete_bucket_tagging))
+            .route(web::put().to(bucket::create_bucket))
+            .route(web::head().to(bucket::head_bucket))
+            .route(web::get().to(bucket::list_objects))
+            .route(web::delete().to(bucket::delete_bucket))
+    )
+    .service(
+        web::resource("/{bucket}/{key:.+}")
+            .route(web::put().guard(query_flag("tagging")).to(object::put_object_tagging))
+            .route(web::get().guard(query_flag("tagging")).to(object::get_object_tagging))
+            .route(web::delete().guard(query_flag("tagging")).to(object::delete_object_tagging))
+            .route(web::put().guard(has_header(object::COPY_SOURCE_HEADER)).to(object::copy_object))
+            .route(web::put().to(object::put_object))
+            .route(web::head().to(object::head_object))
+            .route(web::get().to(object::get_object))
+            .route(web::delete().to(object::delete_object))
+    );
+}
+
+// Matches requests whose query string carries `name`, as in `?versioning`,
+// which S3 uses to select a sub-resource of a bucket or object
+fn query_flag(name: &'static str) -> impl guard::Guard {
+    guard::fn_guard(move |ctx| {
+        ctx.head()
+            .uri
+            .query()
+            .map(|query| query.split('&').any(|param| param.split('=').next() == Some(name)))
+            .unwrap_or(false)
+    })
+}
+
+// Matches requests carrying the header `name`, as CopyObject is a PUT with
+// an `x-amz-copy-source` header
+fn has_header(name: &'static str) -> impl guard::Guard {
+    guard::fn_guard(move |ctx| ctx.head().headers().contains_key(name))
+}
+
+pub fn authenticated_user(req: &HttpRequest) -> Result<Uuid, S3Error> {
+    get_user_id_from_request(req).ok_or(S3Error::AccessDenied)
+}
+
+// Access denied unless the request's credential may perform `action` on
+// `key` in `bucket`, or on the bucket itself without a key; as with
+// `scope_denied`, the bucket must have been looked up first
+pub fn authorize(req: &HttpRequest, action: Action, bucket: &Bucket, key: Option<&str>) -> Result<(), S3Error> {
+    match get_principal_from_request(req) {
+        Some(principal) if principal.allows(action, &bucket.qualified_name(), key) => Ok(()),
+        _ => Err(S3Error::AccessDenied),
+    }
+}
+
+// Access denied for scoped API keys, which must not change who can access
+// a bucket
+pub fn require_full_access(req: &HttpRequest) -> Result<(), S3Error> {
+    match get_principal_from_request(req) {
+        Some(principal) if !principal.is_scoped() => Ok(()),
+        _ => Err(S3Error::AccessDenied),
+    }
+}
+
+// Access denied if the bucket's policy explicitly denies the request, or if
+// neither the policy allows it nor the user's access to the bucket covers
+// it, as in `access_denied`
+pub fn check_access(req: &HttpRequest, bucket: &Bucket, action: PolicyAction, key: Option<&str>) -> Result<(), S3Error> {
+    match policy_decision(req, bucket, action, key) {
+        Decision::Deny => Err(S3Error::AccessDenied),
+        Decision::Allow => Ok(()),
+        Decision::Implicit if bucket.access().allows(action) => Ok(()),
+        Decision::Implicit => Err(S3Error::AccessDenied),
+    }
+}
+
+pub async fn find_bucket(pool: &PgPool, name: &str, user_id: Uuid) -> Result<Bucket, S3Error> {
+    match Bucket::find_by_name_and_user(pool, name, user_id).await {
+        Ok(Some(bucket)) => Ok(bucket),
+        Ok(None) => Err(S3Error::NoSuchBucket),
+        Err(e) => {
+            error!("Failed to look up bucket {}: {:?}", name, e);
+            Err(S3Error::InternalError)
+        }
+    }
+}
+
+// Look up a bucket the user owns, for requests that change who can access it
+pub async fn find_owned_bucket(pool: &PgPool, name: &str, user_id: Uuid) -> Result<Bucket, S3Error> {
+    match Bucket::find_owned_by_name(pool, name, user_id).await {
+        Ok(Some(bucket)) => Ok(bucket),
+        Ok(None) => Err(S3Error::NoSuchBucket),
+        Err(e) => {
+            error!("Failed to look up bucket {}: {:?}", name, e);
+            Err(S3Error::InternalError)
+        }
+    }
+}
+
+// Look up the bucket an object is read from. Requests without credentials
+// can only read from public buckets.
+pub async fn find_readable_bucket(
+    req: &HttpRequest,
+    pool: &PgPool,
+    name: &str,
+    key: &str,
+) -> Result<Bucket, S3Error> {
+    let Some(user_id) = get_user_id_from_request(req) else {
+        return match Bucket::find_public_by_name(pool, name).await {
+            Ok(Some(bucket)) => Ok(bucket),
+            Ok(None) => Err(S3Error::AccessDenied),
+            Err(e) => {
+                error!("Failed to look up bucket {}: {:?}", name, e);
+                Err(S3Error::InternalError)
+            }
+        };
+    };
+
+    let bucket = find_bucket(pool, name, user_id).await?;
+    authorize(req, Action::Read, &bucket, Some(key))?;
+    Ok(bucket)
+}
+
+// Tag set in a `<Tagging>` request body
+pub fn tag_set(body: &[u8]) -> Result<TagSet, S3Error> {
+    let tags = std::str::from_utf8(body)
+        .ok()
+        .and_then(xml::parse_tagging)
+        .ok_or(S3Error::MalformedXML)?;
+
+    let mut tag_set = TagSet::new();
+    for (key, value) in tags {
+        if tag_set.insert(key, value).is_some() {
+            return Err(S3Error::InvalidTag("Cannot provide multiple Tags with the same key".to_string()));
+        }
+    }
+    Ok(tag_set)
+}
+
+// RFC 7231 date as used in `Last-Modified` headers
+pub fn http_date(time: &DateTime<Utc>) -> String {
+    time.format("%a, %d %b %Y %H:%M:%S GMT").to_string()
+}
@@ -0,0 +1,544 @@
+use actix_web::body::SizedStream;
+use actix_web::http::{header, StatusCode};
+use actix_web::{web, HttpRequest, HttpResponse};
+use futures::StreamExt;
+use log::{error, info};
+use percent_encoding::percent_decode_str;
+use serde::Deserialize;
+use sqlx::PgPool;
+use uuid::Uuid;
+
+use crate::authentication::chunked::signature_error;
+use crate::config::Config;
+use crate::handlers::conditional::{quoted_etag, Precondition, Preconditions};
+use crate::handlers::copy::{self, CopyError, Replacement};
+use crate::handlers::file::{add_delete_marker, publish_file, remove_file, PublishError};
+use crate::handlers::metadata::{insert_metadata_headers, metadata_from_headers, MetadataError};
+use crate::handlers::range::{requested_ranges, PartialContent};
+use crate::handlers::tagging::{change_tags, TagChange};
+use crate::models::{Action, Bucket, File, ObjectMetadata, PolicyAction, TagTarget};
+use crate::storage::{ObjectUpload, Storage, StorageError};
+
+use super::error::S3Error;
+use super::{authenticated_user, authorize, check_access, find_bucket, find_readable_bucket, http_date, tag_set, xml};
+
+// Keys are stored in `files.filename`
+const MAX_KEY_LENGTH: usize = 255;
+
+const VERSION_ID_HEADER: &str = "x-amz-version-id";
+const DELETE_MARKER_HEADER: &str = "x-amz-delete-marker";
+
+// CopyObject names its source as `/{bucket}/{key}[?versionId=...]`, with the
+// key percent-encoded
+pub const COPY_SOURCE_HEADER: &str = "x-amz-copy-source";
+const COPY_SOURCE_VERSION_ID_HEADER: &str = "x-amz-copy-source-version-id";
+// COPY (the default) keeps the source's metadata, REPLACE takes it from the
+// request like PutObject
+const METADATA_DIRECTIVE_HEADER: &str = "x-amz-metadata-directive";
+
+#[derive(Debug, Deserialize)]
+pub struct ObjectQuery {
+    #[serde(rename = "versionId")]
+    version_id: Option<String>,
+}
+
+// The latest version of a key, or the requested version. A delete marker
+// cannot be read, so asking for one by version ID is not allowed.
+async fn find_object(
+    pool: &PgPool,
+    key: &str,
+    bucket_id: Uuid,
+    version_id: Option<&str>,
+) -> Result<File, S3Error> {
+    let found = match version_id {
+        Some(version_id) => File::find_version(pool, key, bucket_id, version_id).await,
+        None => File::find_by_filename_and_bucket(pool, key, bucket_id).await,
+    };
+
+    match found {
+        Ok(Some(file)) if file.is_delete_marker => Err(S3Error::MethodNotAllowed),
+        Ok(Some(file)) => Ok(file),
+        Ok(None) if version_id.is_some() => Err(S3Error::NoSuchVersion),
+        Ok(None) => Err(S3Error::NoSuchKey),
+        Err(e) => {
+            error!("Failed to look up key {}: {:?}", key, e);
+            Err(S3Error::InternalError)
+        }
+    }
+}
+
+fn object_headers(bucket: &Bucket, file: &File) -> actix_web::HttpResponseBuilder {
+    let mut response = HttpResponse::Ok();
+    response
+        .insert_header((
+            header::CONTENT_TYPE,
+            file.content_type
+                .clone()
+                .unwrap_or_else(|| "application/octet-stream".to_string()),
+        ))
+        .insert_header((header::LAST_MODIFIED, http_date(&file.created_at)))
+        .insert_header((header::ACCEPT_RANGES, "bytes"));
+    if let Some(etag) = &file.etag {
+        response.insert_header((header::ETAG, quoted_etag(etag)));
+    }
+    insert_metadata_headers(&mut response, &file.metadata);
+
+    // Version IDs are only reported once versioning has been configured
+    if bucket.versioning.is_some() {
+        response.insert_header((VERSION_ID_HEADER, file.version_id.clone()));
+    }
+    response
+}
+
+// Evaluate the conditional headers of a GET or HEAD. Returns the 304
+// response to send instead of the object, if any.
+fn check_read(
+    preconditions: &Preconditions,
+    bucket: &Bucket,
+    file: &File,
+) -> Result<Option<HttpResponse>, S3Error> {
+    match preconditions.check_read(file) {
+        Precondition::Proceed => Ok(None),
+        Precondition::NotModified => Ok(Some(
+            object_headers(bucket, file).status(StatusCode::NOT_MODIFIED).finish(),
+        )),
+        Precondition::Failed => Err(S3Error::PreconditionFailed),
+    }
+}
+
+// Metadata given with a PUT request: the Content-Type header plus the
+// headers read by `metadata_from_headers`
+fn request_metadata(req: &HttpRequest) -> Result<(Option<String>, ObjectMetadata), S3Error> {
+    let content_type = req
+        .headers()
+        .get(header::CONTENT_TYPE)
+        .and_then(|value| value.to_str().ok())
+        .map(|value| value.to_string());
+    let metadata = metadata_from_headers(req.headers()).map_err(|e| match e {
+        MetadataError::TooLarge(_) => S3Error::MetadataTooLarge,
+        e => S3Error::InvalidArgument(e.to_string()),
+    })?;
+
+    Ok((content_type, metadata))
+}
+
+// Bucket, key and optional version ID named by an `x-amz-copy-source` header
+fn parse_copy_source(value: &str) -> Option<(String, String, Option<String>)> {
+    let (path, version_id) = match value.split_once("?versionId=") {
+        Some((path, version_id)) => (path, Some(version_id.to_string())),
+        None => (value, None),
+    };
+
+    let path = percent_decode_str(path).decode_utf8().ok()?;
+    let (bucket, key) = path.trim_start_matches('/').split_once('/')?;
+    if bucket.is_empty() || key.is_empty() {
+        return None;
+    }
+
+    Some((bucket.to_string(), key.to_string(), version_id))
+}
+
+// PUT /{bucket}/{key} (PutObject)
+pub async fn put_object(
+    req: HttpRequest,
+    pool: web::Data<PgPool>,
+    storage: web::Data<dyn Storage + Send + Sync>,
+    config: web::Data<Config>,
+    path: web::Path<(String, String)>,
+    mut payload: web::Payload,
+) -> Result<HttpResponse, S3Error> {
+    let user_id = authenticated_user(&req)?;
+    let (bucket_name, key) = path.into_inner();
+
+    if key.len() > MAX_KEY_LENGTH {
+        return Err(S3Error::KeyTooLongError);
+    }
+
+    let bucket = find_bucket(&pool, &bucket_name, user_id).await?;
+    authorize(&req, Action::Write, &bucket, Some(&key))?;
+    check_access(&req, &bucket, PolicyAction::PutObject, Some(&key))?;
+
+    // Fail fast before reading the body; the conditions are checked again
+    // when the object is published
+    let preconditions = Preconditions::from_request(&req);
+    if preconditions.constrains_write() {
+        let current = File::find_by_filename_and_bucket(&pool, &key, bucket.id)
+            .await
+            .map_err(|e| {
+                error!("Failed to look up key {}: {:?}", key, e);
+                S3Error::InternalError
+            })?;
+        if preconditions.check_write(current.as_ref()) == Precondition::Failed {
+            return Err(S3Error::PreconditionFailed);
+        }
+    }
+
+    let (content_type, metadata) = request_metadata(&req)?;
+
+    // Stream the body straight into storage
+    let writer = storage
+        .create_writer(&bucket.name, Uuid::new_v4(), &key)
+        .await
+        .map_err(|e| {
+            error!("Failed to open storage for upload: {:?}", e);
+            S3Error::InternalError
+        })?;
+    let mut upload = ObjectUpload::new(writer, config.max_object_size);
+
+    while let Some(chunk) = payload.next().await {
+        let data = chunk.map_err(|e| match signature_error(&e) {
+            // The body failed its signature or checksum check
+            Some(err) => S3Error::from(err),
+            None => {
+                error!("Failed to read request body: {:?}", e);
+                S3Error::InternalError
+            }
+        })?;
+
+        upload.write(&data).await.map_err(|e| match e {
+            StorageError::TooLarge(limit) => S3Error::EntityTooLarge(limit),
+            e => {
+                error!("Failed to write chunk to storage: {:?}", e);
+                S3Error::InternalError
+            }
+        })?;
+    }
+
+    let saved = upload.finish().await.map_err(|e| {
+        error!("Failed to save object to storage: {:?}", e);
+        S3Error::InternalError
+    })?;
+
+    let mut file = File::new(
+        key,
+        content_type,
+        saved.size as i64,
+        bucket.id,
+        saved.storage_path,
+        saved.sha256,
+    );
+    file.metadata.0 = metadata;
+
+    // Becomes the latest version, replacing the object when versioning is off
+    publish_file(&pool, storage.get_ref(), bucket.versioning_status(), &mut file, &preconditions)
+        .await
+        .map_err(|e| match e {
+            PublishError::PreconditionFailed => S3Error::PreconditionFailed,
+            e => {
+                error!("Failed to save object metadata to DB: {:?}", e);
+                S3Error::InternalError
+            }
+        })?;
+
+    info!("Object stored: {}/{} ({}, version {})", bucket.name, file.filename, file.id, file.version_id);
+
+    let mut response = HttpResponse::Ok();
+    if let Some(etag) = &file.etag {
+        response.insert_header((header::ETAG, quoted_etag(etag)));
+    }
+    if bucket.versioning.is_some() {
+        response.insert_header((VERSION_ID_HEADER, file.version_id));
+    }
+    Ok(response.finish())
+}
+
+// PUT /{bucket}/{key} with x-amz-copy-source (CopyObject)
+pub async fn copy_object(
+    req: HttpRequest,
+    pool: web::Data<PgPool>,
+    storage: web::Data<dyn Storage + Send + Sync>,
+    path: web::Path<(String, String)>,
+) -> Result<HttpResponse, S3Error> {
+    let user_id = authenticated_user(&req)?;
+    let (bucket_name, key) = path.into_inner();
+
+    if key.len() > MAX_KEY_LENGTH {
+        return Err(S3Error::KeyTooLongError);
+    }
+
+    let invalid_source = || S3Error::InvalidArgument("Invalid copy source".to_string());
+    let (source_bucket_name, source_key, source_version_id) = req
+        .headers()
+        .get(COPY_SOURCE_HEADER)
+        .and_then(|value| value.to_str().ok())
+        .and_then(parse_copy_source)
+        .ok_or_else(invalid_source)?;
+
+    // Both buckets must belong to the caller
+    let source_bucket = find_bucket(&pool, &source_bucket_name, user_id).await?;
+    let bucket = find_bucket(&pool, &bucket_name, user_id).await?;
+    authorize(&req, Action::Read, &source_bucket, Some(&source_key))?;
+    authorize(&req, Action::Write, &bucket, Some(&key))?;
+    check_access(&req, &source_bucket, PolicyAction::GetObject, Some(&source_key))?;
+    check_access(&req, &bucket, PolicyAction::PutObject, Some(&key))?;
+    let source = find_object(&pool, &source_key, source_bucket.id, source_version_id.as_deref()).await?;
+
+    let directive = req
+        .headers()
+        .get(METADATA_DIRECTIVE_HEADER)
+        .map(|value| value.to_str().unwrap_or_default());
+    let replacement = match directive {
+        None | Some("COPY") => Replacement::default(),
+        Some("REPLACE") => {
+            let (content_type, metadata) = request_metadata(&req)?;
+            Replacement {
+                content_type,
+                metadata: Some(metadata),
+            }
+        }
+        Some(_) => {
+            return Err(S3Error::InvalidArgument("Unknown metadata directive.".to_string()));
+        }
+    };
+
+    // As in S3, an object can only be copied onto itself to change its metadata
+    if source.bucket_id == bucket.id && source.filename == key && replacement.metadata.is_none() {
+        return Err(S3Error::InvalidRequest(
+            "This copy request is illegal because it is trying to copy an object to itself \
+             without changing the object's metadata."
+                .to_string(),
+        ));
+    }
+
+    let preconditions = Preconditions::from_request(&req);
+    let file = copy::copy_object(&pool, storage.get_ref(), &source, &bucket, key, replacement, &preconditions)
+        .await
+        .map_err(|e| match e {
+            CopyError::SourceNotFound => S3Error::NoSuchKey,
+            CopyError::PreconditionFailed => S3Error::PreconditionFailed,
+            e => {
+                error!("Failed to copy object {}: {:?}", source.id, e);
+                S3Error::InternalError
+            }
+        })?;
+
+    info!(
+        "Object copied: {}/{} to {}/{} ({}, version {})",
+        source_bucket.name, source.filename, bucket.name, file.filename, file.id, file.version_id
+    );
+
+    let mut response = HttpResponse::Ok();
+    if source_bucket.versioning.is_some() {
+        response.insert_header((COPY_SOURCE_VERSION_ID_HEADER, source.version_id));
+    }
+    if bucket.versioning.is_some() {
+        response.insert_header((VERSION_ID_HEADER, file.version_id.clone()));
+    }
+    Ok(response
+        .content_type(xml::CONTENT_TYPE)
+        .body(xml::copy_object_result(&file)))
+}
+
+// GET /{bucket}/{key} (GetObject)
+pub async fn get_object(
+    req: HttpRequest,
+    pool: web::Data<PgPool>,
+    storage: web::Data<dyn Storage + Send + Sync>,
+    path: web::Path<(String, String)>,
+    query: web::Query<ObjectQuery>,
+) -> Result<HttpResponse, S3Error> {
+    let (bucket_name, key) = path.into_inner();
+
+    let bucket = find_readable_bucket(&req, &pool, &bucket_name, &key).await?;
+    check_access(&req, &bucket, PolicyAction::GetObject, Some(&key))?;
+    let file = find_object(&pool, &key, bucket.id, query.version_id.as_deref()).await?;
+
+    let preconditions = Preconditions::from_request(&req);
+    if let Some(not_modified) = check_read(&preconditions, &bucket, &file)? {
+        return Ok(not_modified);
+    }
+
+    let size = file.size as u64;
+    let ranges = if preconditions.range_applies(&file) {
+        requested_ranges(&req, size).map_err(|_| S3Error::InvalidRange(size))?
+    } else {
+        None
+    };
+    let read_error = |e| match e {
+        StorageError::NotFound(_) => S3Error::NoSuchKey,
+        e => {
+            error!("Failed to open object {} from storage: {:?}", file.id, e);
+            S3Error::InternalError
+        }
+    };
+
+    // Only the requested ranges are read from storage
+    if let Some(ranges) = ranges {
+        let content_type = file.content_type.as_deref().unwrap_or("application/octet-stream");
+        let partial = PartialContent::read(storage.get_ref(), &file.storage_path, size, content_type, &ranges)
+            .await
+            .map_err(read_error)?;
+        return Ok(partial.respond(object_headers(&bucket, &file)));
+    }
+
+    let stream = storage
+        .read_file_stream(&file.storage_path)
+        .await
+        .map_err(read_error)?;
+
+    Ok(object_headers(&bucket, &file).body(SizedStream::new(size, stream)))
+}
+
+// HEAD /{bucket}/{key} (HeadObject)
+pub async fn head_object(
+    req: HttpRequest,
+    pool: web::Data<PgPool>,
+    path: web::Path<(String, String)>,
+    query: web::Query<ObjectQuery>,
+) -> Result<HttpResponse, S3Error> {
+    let (bucket_name, key) = path.into_inner();
+
+    let bucket = find_readable_bucket(&req, &pool, &bucket_name, &key).await?;
+    check_access(&req, &bucket, PolicyAction::GetObject, Some(&key))?;
+    let file = find_object(&pool, &key, bucket.id, query.version_id.as_deref()).await?;
+
+    if let Some(not_modified) = check_read(&Preconditions::from_request(&req), &bucket, &file)? {
+        return Ok(not_modified);
+    }
+
+    // Empty body that still reports the object size as Content-Length
+    let body = SizedStream::new(
+        file.size as u64,
+        futures::stream::empty::<Result<bytes::Bytes, std::io::Error>>(),
+    );
+
+    Ok(object_headers(&bucket, &file).body(body))
+}
+
+// DELETE /{bucket}/{key} (DeleteObject)
+pub async fn delete_object(
+    req: HttpRequest,
+    pool: web::Data<PgPool>,
+    storage: web::Data<dyn Storage + Send + Sync>,
+    path: web::Path<(String, String)>,
+    query: web::Query<ObjectQuery>,
+) -> Result<HttpResponse, S3Error> {
+    let user_id = authenticated_user(&req)?;
+    let (bucket_name, key) = path.into_inner();
+
+    let bucket = find_bucket(&pool, &bucket_name, user_id).await?;
+    authorize(&req, Action::Delete, &bucket, Some(&key))?;
+    check_access(&req, &bucket, PolicyAction::DeleteObject, Some(&key))?;
+    let mut response = HttpResponse::NoContent();
+
+    // In a versioned bucket a plain delete only hides the key behind a marker
+    if query.version_id.is_none() && bucket.versioning.is_some() {
+        let marker = add_delete_marker(&pool, storage.get_ref(), &bucket, &key)
+            .await
+            .map_err(|e| {
+                error!("Failed to add delete marker for {}/{}: {:?}", bucket.name, key, e);
+                S3Error::InternalError
+            })?;
+
+        info!("Delete marker added: {}/{} (version {})", bucket.name, key, marker.version_id);
+        return Ok(response
+            .insert_header((DELETE_MARKER_HEADER, "true"))
+            .insert_header((VERSION_ID_HEADER, marker.version_id))
+            .finish());
+    }
+
+    // Deleting a missing key or version succeeds, as in S3
+    let found = match &query.version_id {
+        Some(version_id) => File::find_version(&pool, &key, bucket.id, version_id).await,
+        None => File::find_by_filename_and_bucket(&pool, &key, bucket.id).await,
+    };
+    let file = match found {
+        Ok(Some(file)) => file,
+        Ok(None) => return Ok(response.finish()),
+        Err(e) => {
+            error!("Failed to look up key {}: {:?}", key, e);
+            return Err(S3Error::InternalError);
+        }
+    };
+
+    remove_file(&pool, storage.get_ref(), &file).await.map_err(|e| {
+        error!("Failed to delete object {}: {:?}", file.id, e);
+        S3Error::InternalError
+    })?;
+
+    info!("Object deleted: {}/{} ({}, version {})", bucket.name, file.filename, file.id, file.version_id);
+    if query.version_id.is_some() {
+        response.insert_header((VERSION_ID_HEADER, file.version_id));
+        if file.is_delete_marker {
+            response.insert_header((DELETE_MARKER_HEADER, "true"));
+        }
+    }
+    Ok(response.finish())
+}
+
+// GET /{bucket}/{key}?tagging (GetObjectTagging)
+pub async fn get_object_tagging(
+    req: HttpRequest,
+    pool: web::Data<PgPool>,
+    path: web::Path<(String, String)>,
+    query: web::Query<ObjectQuery>,
+) -> Result<HttpResponse, S3Error> {
+    let user_id = authenticated_user(&req)?;
+    let (bucket_name, key) = path.into_inner();
+
+    let bucket = find_bucket(&pool, &bucket_name, user_id).await?;
+    authorize(&req, Action::Read, &bucket, Some(&key))?;
+    check_access(&req, &bucket, PolicyAction::GetObjectTagging, Some(&key))?;
+    let file = find_object(&pool, &key, bucket.id, query.version_id.as_deref()).await?;
+
+    let tags = TagTarget::File(file.id).tags(pool.get_ref()).await.map_err(|e| {
+        error!("Failed to fetch tags of object {}: {:?}", file.id, e);
+        S3Error::InternalError
+    })?;
+
+    let mut response = HttpResponse::Ok();
+    if bucket.versioning.is_some() {
+        response.insert_header((VERSION_ID_HEADER, file.version_id));
+    }
+    Ok(response.content_type(xml::CONTENT_TYPE).body(xml::tagging(&tags)))
+}
+
+// PUT /{bucket}/{key}?tagging (PutObjectTagging)
+pub async fn put_object_tagging(
+    req: HttpRequest,
+    pool: web::Data<PgPool>,
+    path: web::Path<(String, String)>,
+    query: web::Query<ObjectQuery>,
+    body: bytes::Bytes,
+) -> Result<HttpResponse, S3Error> {
+    let user_id = authenticated_user(&req)?;
+    let (bucket_name, key) = path.into_inner();
+
+    let bucket = find_bucket(&pool, &bucket_name, user_id).await?;
+    authorize(&req, Action::Write, &bucket, Some(&key))?;
+    check_access(&req, &bucket, PolicyAction::PutObjectTagging, Some(&key))?;
+    let file = find_object(&pool, &key, bucket.id, query.version_id.as_deref()).await?;
+
+    let tags = tag_set(&body)?;
+    change_tags(&pool, TagTarget::File(file.id), TagChange::Replace(tags)).await?;
+
+    let mut response = HttpResponse::Ok();
+    if bucket.versioning.is_some() {
+        response.insert_header((VERSION_ID_HEADER, file.version_id));
+    }
+    Ok(response.finish())
+}
+
+// DELETE /{bucket}/{key}?tagging (DeleteObjectTagging)
+pub async fn delete_object_tagging(
+    req: HttpRequest,
+    pool: web::Data<PgPool>,
+    path: web::Path<(String, String)>,
+    query: web::Query<ObjectQuery>,
+) -> Result<HttpResponse, S3Error> {
+    let user_id = authenticated_user(&req)?;
+    let (bucket_name, key) = path.into_inner();
+
+    let bucket = find_bucket(&pool, &bucket_name, user_id).await?;
+    authorize(&req, Action::Write, &bucket, Some(&key))?;
+    check_access(&req, &bucket, PolicyAction::DeleteObjectTagging, Some(&key))?;
+    let file = find_object(&pool, &key, bucket.id, query.version_id.as_deref()).await?;
+
+    change_tags(&pool, TagTarget::File(file.id), TagChange::Remove(Vec::new())).await?;
+
+    let mut response = HttpResponse::NoContent();
+    if bucket.versioning.is_some() {
+        response.insert_header((VERSION_ID_HEADER, file.version_id));
+    }
+    Ok(response.finish())
+}
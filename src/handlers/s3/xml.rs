@@ -0,0 +1,362 @@
+use chrono::{DateTime, Utc};
+use uuid::Uuid;
+
+use crate::handlers::conditional::quoted_etag;
+use crate::models::{Bucket, File, TagSet};
+
+pub const CONTENT_TYPE: &str = "application/xml";
+
+const NAMESPACE: &str = "http://s3.amazonaws.com/doc/2006-03-01/";
+
+// Minimal writer for the flat documents the S3 API returns
+pub struct XmlBuilder {
+    out: String,
+}
+
+impl XmlBuilder {
+    pub fn new() -> Self {
+        Self {
+            out: String::from(r#"<?xml version="1.0" encoding="UTF-8"?>"#),
+        }
+    }
+
+    // Open the document element in the S3 namespace
+    pub fn root(mut self, name: &str) -> Self {
+        self.out.push_str(&format!(r#"<{} xmlns="{}">"#, name, NAMESPACE));
+        self
+    }
+
+    pub fn open(mut self, name: &str) -> Self {
+        self.out.push_str(&format!("<{}>", name));
+        self
+    }
+
+    pub fn close(mut self, name: &str) -> Self {
+        self.out.push_str(&format!("</{}>", name));
+        self
+    }
+
+    pub fn element(mut self, name: &str, value: impl AsRef<str>) -> Self {
+        self.out
+            .push_str(&format!("<{0}>{1}</{0}>", name, escape(value.as_ref())));
+        self
+    }
+
+    pub fn build(self) -> String {
+        self.out
+    }
+}
+
+pub fn escape(value: &str) -> String {
+    let mut escaped = String::with_capacity(value.len());
+    for c in value.chars() {
+        match c {
+            '&' => escaped.push_str("&amp;"),
+            '<' => escaped.push_str("&lt;"),
+            '>' => escaped.push_str("&gt;"),
+            '"' => escaped.push_str("&quot;"),
+            '\'' => escaped.push_str("&apos;"),
+            _ => escaped.push(c),
+        }
+    }
+    escaped
+}
+
+// Reverse `escape`, also decoding numeric character references
+pub fn unescape(value: &str) -> String {
+    let mut unescaped = String::with_capacity(value.len());
+    let mut rest = value;
+
+    while let Some(start) = rest.find('&') {
+        unescaped.push_str(&rest[..start]);
+        rest = &rest[start..];
+
+        let decoded = rest.find(';').and_then(|end| {
+            let c = match &rest[1..end] {
+                "amp" => Some('&'),
+                "lt" => Some('<'),
+                "gt" => Some('>'),
+                "quot" => Some('"'),
+                "apos" => Some('\''),
+                entity => entity
+                    .strip_prefix("#x")
+                    .map(|hex| u32::from_str_radix(hex, 16).ok())
+                    .unwrap_or_else(|| entity.strip_prefix('#').and_then(|dec| dec.parse().ok()))
+                    .and_then(char::from_u32),
+            };
+            c.map(|c| (c, end))
+        });
+
+        match decoded {
+            Some((c, end)) => {
+                unescaped.push(c);
+                rest = &rest[end + 1..];
+            }
+            // Not an entity; keep the ampersand as is
+            None => {
+                unescaped.push('&');
+                rest = &rest[1..];
+            }
+        }
+    }
+
+    unescaped.push_str(rest);
+    unescaped
+}
+
+// ISO 8601 timestamp as used in S3 response bodies
+pub fn timestamp(time: &DateTime<Utc>) -> String {
+    time.format("%Y-%m-%dT%H:%M:%S%.3fZ").to_string()
+}
+
+pub fn error(code: &str, message: &str) -> String {
+    XmlBuilder::new()
+        .open("Error")
+        .element("Code", code)
+        .element("Message", message)
+        .close("Error")
+        .build()
+}
+
+pub fn list_buckets(owner_id: Uuid, buckets: &[Bucket]) -> String {
+    let mut xml = XmlBuilder::new()
+        .root("ListAllMyBucketsResult")
+        .open("Owner")
+        .element("ID", owner_id.to_string())
+        .close("Owner")
+        .open("Buckets");
+
+    for bucket in buckets {
+        xml = xml
+            .open("Bucket")
+            .element("Name", &bucket.name)
+            .element("CreationDate", timestamp(&bucket.created_at))
+            .close("Bucket");
+    }
+
+    xml.close("Buckets").close("ListAllMyBucketsResult").build()
+}
+
+// Parameters and results of a ListObjectsV2 call
+pub struct ListObjectsV2<'a> {
+    pub bucket: &'a str,
+    pub prefix: &'a str,
+    pub delimiter: Option<&'a str>,
+    pub max_keys: i64,
+    pub continuation_token: Option<&'a str>,
+    pub start_after: Option<&'a str>,
+    pub next_continuation_token: Option<&'a str>,
+    pub files: &'a [File],
+    pub common_prefixes: &'a [String],
+}
+
+pub fn list_objects_v2(result: &ListObjectsV2) -> String {
+    let mut xml = XmlBuilder::new()
+        .root("ListBucketResult")
+        .element("Name", result.bucket)
+        .element("Prefix", result.prefix);
+
+    if let Some(delimiter) = result.delimiter {
+        xml = xml.element("Delimiter", delimiter);
+    }
+    if let Some(token) = result.continuation_token {
+        xml = xml.element("ContinuationToken", token);
+    }
+    if let Some(start_after) = result.start_after {
+        xml = xml.element("StartAfter", start_after);
+    }
+
+    let key_count = result.files.len() + result.common_prefixes.len();
+    xml = xml
+        .element("KeyCount", key_count.to_string())
+        .element("MaxKeys", result.max_keys.to_string())
+        .element("IsTruncated", result.next_continuation_token.is_some().to_string());
+
+    if let Some(token) = result.next_continuation_token {
+        xml = xml.element("NextContinuationToken", token);
+    }
+
+    for file in result.files {
+        xml = xml
+            .open("Contents")
+            .element("Key", &file.filename)
+            .element("LastModified", timestamp(&file.created_at));
+        if let Some(etag) = &file.etag {
+            xml = xml.element("ETag", quoted_etag(etag));
+        }
+        xml = xml
+            .element("Size", file.size.to_string())
+            .element("StorageClass", "STANDARD")
+            .close("Contents");
+    }
+
+    for prefix in result.common_prefixes {
+        xml = xml
+            .open("CommonPrefixes")
+            .element("Prefix", prefix)
+            .close("CommonPrefixes");
+    }
+
+    xml.close("ListBucketResult").build()
+}
+
+pub fn copy_object_result(file: &File) -> String {
+    XmlBuilder::new()
+        .root("CopyObjectResult")
+        .element("ETag", quoted_etag(file.etag.as_deref().unwrap_or_default()))
+        .element("LastModified", timestamp(&file.created_at))
+        .close("CopyObjectResult")
+        .build()
+}
+
+pub fn versioning_configuration(status: Option<&str>) -> String {
+    let mut xml = XmlBuilder::new().root("VersioningConfiguration");
+    if let Some(status) = status {
+        xml = xml.element("Status", status);
+    }
+    xml.close("VersioningConfiguration").build()
+}
+
+// Text of the first `<name>` element in a request document. The documents
+// S3 clients send here are small and flat, so no full parser is needed.
+pub fn element_text<'a>(document: &'a str, name: &str) -> Option<&'a str> {
+    let open = format!("<{}>", name);
+    let close = format!("</{}>", name);
+    let start = document.find(&open)? + open.len();
+    let end = document[start..].find(&close)? + start;
+    Some(document[start..end].trim())
+}
+
+// Content of every `<name>` element in a request document, in order
+pub fn elements<'a>(document: &'a str, name: &str) -> Vec<&'a str> {
+    let mut found = Vec::new();
+    let mut rest = document;
+
+    while let Some(content) = element_text(rest, name) {
+        found.push(content);
+        let close = format!("</{}>", name);
+        match rest.find(&close) {
+            Some(end) => rest = &rest[end + close.len()..],
+            None => break,
+        }
+    }
+
+    found
+}
+
+// Tags in a `<Tagging>` request document, in document order. Returns None
+// if a tag has no key.
+pub fn parse_tagging(document: &str) -> Option<Vec<(String, String)>> {
+    elements(document, "Tag")
+        .into_iter()
+        .map(|tag| {
+            let key = element_text(tag, "Key")?;
+            let value = element_text(tag, "Value").unwrap_or("");
+            Some((unescape(key), unescape(value)))
+        })
+        .collect()
+}
+
+pub fn tagging(tags: &TagSet) -> String {
+    let mut xml = XmlBuilder::new().root("Tagging").open("TagSet");
+    for (key, value) in tags {
+        xml = xml
+            .open("Tag")
+            .element("Key", key)
+            .element("Value", value)
+            .close("Tag");
+    }
+    xml.close("TagSet").close("Tagging").build()
+}
+
+// Parameters and results of a ListObjectVersions call
+pub struct ListObjectVersions<'a> {
+    pub bucket: &'a str,
+    pub prefix: &'a str,
+    pub files: &'a [File],
+}
+
+pub fn list_object_versions(result: &ListObjectVersions) -> String {
+    let mut xml = XmlBuilder::new()
+        .root("ListVersionsResult")
+        .element("Name", result.bucket)
+        .element("Prefix", result.prefix)
+        .element("IsTruncated", "false");
+
+    for file in result.files {
+        let element = if file.is_delete_marker { "DeleteMarker" } else { "Version" };
+        xml = xml
+            .open(element)
+            .element("Key", &file.filename)
+            .element("VersionId", &file.version_id)
+            .element("IsLatest", file.is_latest.to_string())
+            .element("LastModified", timestamp(&file.created_at));
+
+        if !file.is_delete_marker {
+            if let Some(etag) = &file.etag {
+                xml = xml.element("ETag", quoted_etag(etag));
+            }
+            xml = xml
+                .element("Size", file.size.to_string())
+                .element("StorageClass", "STANDARD");
+        }
+
+        xml = xml.close(element);
+    }
+
+    xml.close("ListVersionsResult").build()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn escapes_markup_characters() {
+        assert_eq!(escape(r#"a<b>&"c'"#), "a&lt;b&gt;&amp;&quot;c&apos;");
+    }
+
+    #[test]
+    fn renders_error_document() {
+        assert_eq!(
+            error("NoSuchKey", "The specified key does not exist."),
+            r#"<?xml version="1.0" encoding="UTF-8"?><Error><Code>NoSuchKey</Code><Message>The specified key does not exist.</Message></Error>"#
+        );
+    }
+
+    #[test]
+    fn reads_element_text() {
+        let body = r#"<VersioningConfiguration xmlns="http://s3.amazonaws.com/doc/2006-03-01/">
+            <Status> Enabled </Status>
+        </VersioningConfiguration>"#;
+
+        assert_eq!(element_text(body, "Status"), Some("Enabled"));
+        assert_eq!(element_text(body, "MfaDelete"), None);
+        assert_eq!(element_text("<Status>Enabled", "Status"), None);
+    }
+
+    #[test]
+    fn unescapes_entities() {
+        assert_eq!(unescape("a&lt;b&gt;&amp;&quot;c&apos;"), r#"a<b>&"c'"#);
+        assert_eq!(unescape("&#233;t&#xE9; & co;"), "été & co;");
+        assert_eq!(unescape(&escape("x & <y>")), "x & <y>");
+    }
+
+    #[test]
+    fn parses_tagging_documents() {
+        let body = r#"<Tagging><TagSet>
+            <Tag><Key>env</Key><Value>staging</Value></Tag>
+            <Tag><Key>team &amp; owner</Key><Value></Value></Tag>
+        </TagSet></Tagging>"#;
+
+        assert_eq!(
+            parse_tagging(body),
+            Some(vec![
+                ("env".to_string(), "staging".to_string()),
+                ("team & owner".to_string(), String::new()),
+            ])
+        );
+        assert_eq!(parse_tagging("<Tagging><TagSet></TagSet></Tagging>"), Some(vec![]));
+        assert_eq!(parse_tagging("<Tagging><TagSet><Tag><Value>x</Value></Tag></TagSet></Tagging>"), None);
+    }
+}
@@ -0,0 +1,212 @@
+use actix_web::{web, HttpRequest, HttpResponse, Responder};
+use log::{error, info};
+use serde::{Deserialize, Serialize};
+use sqlx::PgPool;
+use uuid::Uuid;
+
+use crate::middleware::auth::{full_access_denied, get_user_id_from_request};
+use crate::models::{Bucket, BucketAcl, BucketGrant, GrantPermission, User};
+
+#[derive(Debug, Deserialize)]
+pub struct BucketGrantsQuery {
+    bucket_name: String,
+}
+
+#[derive(Debug, Deserialize)]
+pub struct PutBucketGrantRequest {
+    bucket_name: String,
+    // Email of the user to share the bucket with
+    email: String,
+    permission: GrantPermission,
+}
+
+#[derive(Debug, Deserialize)]
+pub struct DeleteBucketGrantQuery {
+    bucket_name: String,
+    email: String,
+}
+
+#[derive(Debug, Serialize)]
+pub struct BucketGrantsResponse {
+    bucket_name: String,
+    acl: BucketAcl,
+    grants: Vec<BucketGrant>,
+}
+
+#[derive(Debug, Deserialize)]
+pub struct SetBucketAclRequest {
+    bucket_name: String,
+    acl: BucketAcl,
+}
+
+#[derive(Debug, Serialize)]
+pub struct BucketAclResponse {
+    bucket_name: String,
+    acl: BucketAcl,
+}
+
+// Look up a bucket owned by the requesting user whose sharing is being
+// managed. Grantees and scoped API keys cannot share a bucket further.
+async fn find_bucket(req: &HttpRequest, pool: &PgPool, bucket_name: &str) -> Result<(Bucket, Uuid), HttpResponse> {
+    // Get user ID from request extensions (set by middleware)
+    let user_id = match get_user_id_from_request(req) {
+        Some(id) => id,
+        None => {
+            return Err(HttpResponse::Unauthorized().json(serde_json::json!({
+                "error": "Authentication required"
+            })));
+        }
+    };
+
+    if let Some(response) = full_access_denied(req) {
+        return Err(response);
+    }
+
+    match Bucket::find_owned_by_name(pool, bucket_name, user_id).await {
+        Ok(Some(bucket)) => Ok((bucket, user_id)),
+        Ok(None) => Err(HttpResponse::NotFound().json(serde_json::json!({
+            "error": "Bucket not found"
+        }))),
+        Err(_) => Err(HttpResponse::InternalServerError().json(serde_json::json!({
+            "error": "Failed to check bucket"
+        }))),
+    }
+}
+
+// Look up the user a bucket is shared with by their email
+async fn find_grantee(pool: &PgPool, email: &str) -> Result<User, HttpResponse> {
+    match User::find_by_email(pool, email).await {
+        Ok(Some(user)) => Ok(user),
+        Ok(None) => Err(HttpResponse::NotFound().json(serde_json::json!({
+            "error": "User not found"
+        }))),
+        Err(e) => {
+            error!("Failed to look up user: {:?}", e);
+            Err(HttpResponse::InternalServerError().json(serde_json::json!({
+                "error": "Failed to look up user"
+            })))
+        }
+    }
+}
+
+// The bucket's ACL and the users it is shared with
+pub async fn list_bucket_grants(
+    req: HttpRequest,
+    pool: web::Data<PgPool>,
+    query: web::Query<BucketGrantsQuery>,
+) -> impl Responder {
+    let (bucket, _) = match find_bucket(&req, &pool, &query.bucket_name).await {
+        Ok(found) => found,
+        Err(response) => return response,
+    };
+
+    match BucketGrant::find_by_bucket_id(&pool, bucket.id).await {
+        Ok(grants) => HttpResponse::Ok().json(BucketGrantsResponse {
+            acl: bucket.acl(),
+            bucket_name: bucket.name,
+            grants,
+        }),
+        Err(e) => {
+            error!("Failed to fetch grants of bucket {}: {:?}", bucket.id, e);
+            HttpResponse::InternalServerError().json(serde_json::json!({
+                "error": "Failed to fetch bucket grants"
+            }))
+        }
+    }
+}
+
+// Share the bucket with another user, or change what an existing grant allows
+pub async fn put_bucket_grant(
+    req: HttpRequest,
+    pool: web::Data<PgPool>,
+    body: web::Json<PutBucketGrantRequest>,
+) -> impl Responder {
+    let (bucket, user_id) = match find_bucket(&req, &pool, &body.bucket_name).await {
+        Ok(found) => found,
+        Err(response) => return response,
+    };
+
+    let grantee = match find_grantee(&pool, &body.email).await {
+        Ok(user) => user,
+        Err(response) => return response,
+    };
+    if grantee.id == user_id {
+        return HttpResponse::BadRequest().json(serde_json::json!({
+            "error": "You already own this bucket"
+        }));
+    }
+
+    match BucketGrant::upsert(&pool, bucket.id, grantee.id, body.permission).await {
+        Ok(()) => {
+            info!("Bucket {} shared with user {} ({})", bucket.id, grantee.id, body.permission.as_str());
+            HttpResponse::Ok().json(serde_json::json!({
+                "bucket_name": bucket.name,
+                "email": grantee.email,
+                "permission": body.permission
+            }))
+        }
+        Err(e) => {
+            error!("Failed to share bucket {}: {:?}", bucket.id, e);
+            HttpResponse::InternalServerError().json(serde_json::json!({
+                "error": "Failed to share bucket"
+            }))
+        }
+    }
+}
+
+pub async fn delete_bucket_grant(
+    req: HttpRequest,
+    pool: web::Data<PgPool>,
+    query: web::Query<DeleteBucketGrantQuery>,
+) -> impl Responder {
+    let (bucket, _) = match find_bucket(&req, &pool, &query.bucket_name).await {
+        Ok(found) => found,
+        Err(response) => return response,
+    };
+
+    let grantee = match find_grantee(&pool, &query.email).await {
+        Ok(user) => user,
+        Err(response) => return response,
+    };
+
+    match BucketGrant::delete(&pool, bucket.id, grantee.id).await {
+        Ok(true) => HttpResponse::NoContent().finish(),
+        Ok(false) => HttpResponse::NotFound().json(serde_json::json!({
+            "error": "Bucket is not shared with this user"
+        })),
+        Err(e) => {
+            error!("Failed to unshare bucket {}: {:?}", bucket.id, e);
+            HttpResponse::InternalServerError().json(serde_json::json!({
+                "error": "Failed to unshare bucket"
+            }))
+        }
+    }
+}
+
+// Make the bucket's objects readable by anyone, or private again
+pub async fn set_bucket_acl(
+    req: HttpRequest,
+    pool: web::Data<PgPool>,
+    body: web::Json<SetBucketAclRequest>,
+) -> impl Responder {
+    let (mut bucket, _) = match find_bucket(&req, &pool, &body.bucket_name).await {
+        Ok(found) => found,
+        Err(response) => return response,
+    };
+
+    match bucket.set_acl(&pool, body.acl).await {
+        Ok(()) => {
+            info!("ACL of bucket {} set to {}", bucket.id, body.acl.as_str());
+            HttpResponse::Ok().json(BucketAclResponse {
+                bucket_name: bucket.name,
+                acl: body.acl,
+            })
+        }
+        Err(e) => {
+            error!("Failed to set ACL of bucket {}: {:?}", bucket.id, e);
+            HttpResponse::InternalServerError().json(serde_json::json!({
+                "error": "Failed to update bucket ACL"
+            }))
+        }
+    }
+}
@@ -0,0 +1,356 @@
+use actix_web::{web, HttpRequest, HttpResponse, Responder};
+use log::error;
+use serde::{Deserialize, Serialize};
+use sqlx::PgPool;
+use thiserror::Error;
+
+use crate::middleware::auth::{access_denied, get_user_id_from_request, scope_denied};
+use crate::models::{Action, Bucket, File, PolicyAction, TagSet, TagTarget};
+
+// S3 limits on tag sets
+pub const MAX_OBJECT_TAGS: usize = 10;
+pub const MAX_BUCKET_TAGS: usize = 50;
+const MAX_KEY_CHARS: usize = 128;
+const MAX_VALUE_CHARS: usize = 256;
+
+// Tag keys with this prefix are reserved, as in S3
+const RESERVED_KEY_PREFIX: &str = "aws:";
+
+#[derive(Debug, Error)]
+pub enum TagError {
+    #[error("At most {0} tags are allowed")]
+    TooMany(usize),
+    #[error("Tag key \"{0}\" must be between 1 and 128 characters and not start with \"aws:\"")]
+    InvalidKey(String),
+    #[error("Tag value for key \"{0}\" must be at most 256 characters")]
+    InvalidValue(String),
+    #[error("Tag target no longer exists")]
+    NotFound,
+    #[error(transparent)]
+    Database(#[from] sqlx::Error),
+}
+
+pub fn max_tags(target: TagTarget) -> usize {
+    match target {
+        TagTarget::File(_) => MAX_OBJECT_TAGS,
+        TagTarget::Bucket(_) => MAX_BUCKET_TAGS,
+    }
+}
+
+pub fn validate_tags(tags: &TagSet, max: usize) -> Result<(), TagError> {
+    if tags.len() > max {
+        return Err(TagError::TooMany(max));
+    }
+
+    for (key, value) in tags {
+        let key_chars = key.chars().count();
+        if key_chars == 0 || key_chars > MAX_KEY_CHARS || key.starts_with(RESERVED_KEY_PREFIX) {
+            return Err(TagError::InvalidKey(key.clone()));
+        }
+        if value.chars().count() > MAX_VALUE_CHARS {
+            return Err(TagError::InvalidValue(key.clone()));
+        }
+    }
+
+    Ok(())
+}
+
+pub enum TagChange {
+    // Use exactly these tags
+    Replace(TagSet),
+    // Add these tags, overwriting the values of existing keys
+    Merge(TagSet),
+    // Remove these keys, or every tag when empty
+    Remove(Vec<String>),
+}
+
+// Apply a change to a tag set and return the resulting tags. The target is
+// locked while the change is applied, so concurrent changes do not lose
+// each other's tags.
+pub async fn change_tags(
+    pool: &PgPool,
+    target: TagTarget,
+    change: TagChange,
+) -> Result<TagSet, TagError> {
+    let mut tx = pool.begin().await?;
+
+    if !target.lock(&mut tx).await? {
+        return Err(TagError::NotFound);
+    }
+
+    let tags = match change {
+        TagChange::Replace(tags) => tags,
+        TagChange::Merge(added) => {
+            let mut tags = target.tags(&mut *tx).await?;
+            tags.extend(added);
+            tags
+        }
+        TagChange::Remove(keys) if keys.is_empty() => TagSet::new(),
+        TagChange::Remove(keys) => {
+            let mut tags = target.tags(&mut *tx).await?;
+            tags.retain(|key, _| !keys.contains(key));
+            tags
+        }
+    };
+
+    validate_tags(&tags, max_tags(target))?;
+    target.replace_tags(&mut tx, &tags).await?;
+    tx.commit().await?;
+
+    Ok(tags)
+}
+
+#[derive(Debug, Deserialize)]
+pub struct FileTagsQuery {
+    bucket_name: String,
+    filename: String,
+    version_id: Option<String>,
+    // Only for DELETE: remove this key instead of every tag
+    key: Option<String>,
+}
+
+#[derive(Debug, Deserialize)]
+pub struct FileTagsRequest {
+    bucket_name: String,
+    filename: String,
+    version_id: Option<String>,
+    tags: TagSet,
+}
+
+#[derive(Debug, Deserialize)]
+pub struct BucketTagsQuery {
+    bucket_name: String,
+    // Only for DELETE: remove this key instead of every tag
+    key: Option<String>,
+}
+
+#[derive(Debug, Deserialize)]
+pub struct BucketTagsRequest {
+    bucket_name: String,
+    tags: TagSet,
+}
+
+#[derive(Debug, Serialize)]
+pub struct TagsResponse {
+    tags: TagSet,
+}
+
+// Resolve the bucket, and optionally the file version, that a tag request
+// refers to, turning misses and errors into the matching response
+async fn find_target(
+    req: &HttpRequest,
+    pool: &PgPool,
+    action: Action,
+    policy_action: PolicyAction,
+    bucket_name: &str,
+    file: Option<(&str, Option<&str>)>,
+) -> Result<TagTarget, HttpResponse> {
+    // Get user ID from request extensions (set by middleware)
+    let user_id = match get_user_id_from_request(req) {
+        Some(id) => id,
+        None => {
+            return Err(HttpResponse::Unauthorized().json(serde_json::json!({
+                "error": "Authentication required"
+            })));
+        }
+    };
+
+    let bucket = match Bucket::find_by_name_and_user(pool, bucket_name, user_id).await {
+        Ok(Some(bucket)) => bucket,
+        Ok(None) => {
+            return Err(HttpResponse::NotFound().json(serde_json::json!({
+                "error": "Bucket not found"
+            })));
+        }
+        Err(_) => {
+            return Err(HttpResponse::InternalServerError().json(serde_json::json!({
+                "error": "Failed to check bucket"
+            })));
+        }
+    };
+
+    if let Some(response) = scope_denied(req, action, &bucket, file.map(|(filename, _)| filename)) {
+        return Err(response);
+    }
+    if let Some(response) = access_denied(req, &bucket, policy_action, file.map(|(filename, _)| filename)) {
+        return Err(response);
+    }
+
+    let (filename, version_id) = match file {
+        Some(file) => file,
+        None => return Ok(TagTarget::Bucket(bucket.id)),
+    };
+
+    let found = match version_id {
+        Some(version_id) => File::find_version(pool, filename, bucket.id, version_id).await,
+        None => File::find_by_filename_and_bucket(pool, filename, bucket.id).await,
+    };
+    match found {
+        // Delete markers cannot be tagged
+        Ok(Some(file)) if !file.is_delete_marker => Ok(TagTarget::File(file.id)),
+        Ok(_) => Err(HttpResponse::NotFound().json(serde_json::json!({
+            "error": "File not found"
+        }))),
+        Err(_) => Err(HttpResponse::InternalServerError().json(serde_json::json!({
+            "error": "Failed to fetch file info"
+        }))),
+    }
+}
+
+async fn get_tags(pool: &PgPool, target: TagTarget) -> HttpResponse {
+    match target.tags(pool).await {
+        Ok(tags) => HttpResponse::Ok().json(TagsResponse { tags }),
+        Err(e) => {
+            error!("Failed to fetch tags of {:?}: {:?}", target, e);
+            HttpResponse::InternalServerError().json(serde_json::json!({
+                "error": "Failed to fetch tags"
+            }))
+        }
+    }
+}
+
+async fn update_tags(pool: &PgPool, target: TagTarget, change: TagChange) -> HttpResponse {
+    match change_tags(pool, target, change).await {
+        Ok(tags) => HttpResponse::Ok().json(TagsResponse { tags }),
+        Err(TagError::NotFound) => {
+            let error = match target {
+                TagTarget::File(_) => "File not found",
+                TagTarget::Bucket(_) => "Bucket not found",
+            };
+            HttpResponse::NotFound().json(serde_json::json!({ "error": error }))
+        }
+        Err(TagError::Database(e)) => {
+            error!("Failed to update tags of {:?}: {:?}", target, e);
+            HttpResponse::InternalServerError().json(serde_json::json!({
+                "error": "Failed to update tags"
+            }))
+        }
+        Err(e) => HttpResponse::BadRequest().json(serde_json::json!({
+            "error": e.to_string()
+        })),
+    }
+}
+
+pub async fn get_file_tags(
+    req: HttpRequest,
+    pool: web::Data<PgPool>,
+    query: web::Query<FileTagsQuery>,
+) -> impl Responder {
+    let file = Some((query.filename.as_str(), query.version_id.as_deref()));
+    match find_target(&req, &pool, Action::Read, PolicyAction::GetObjectTagging, &query.bucket_name, file).await {
+        Ok(target) => get_tags(&pool, target).await,
+        Err(response) => response,
+    }
+}
+
+pub async fn put_file_tags(
+    req: HttpRequest,
+    pool: web::Data<PgPool>,
+    body: web::Json<FileTagsRequest>,
+) -> impl Responder {
+    let body = body.into_inner();
+    let file = Some((body.filename.as_str(), body.version_id.as_deref()));
+    match find_target(&req, &pool, Action::Write, PolicyAction::PutObjectTagging, &body.bucket_name, file).await {
+        Ok(target) => update_tags(&pool, target, TagChange::Replace(body.tags)).await,
+        Err(response) => response,
+    }
+}
+
+pub async fn merge_file_tags(
+    req: HttpRequest,
+    pool: web::Data<PgPool>,
+    body: web::Json<FileTagsRequest>,
+) -> impl Responder {
+    let body = body.into_inner();
+    let file = Some((body.filename.as_str(), body.version_id.as_deref()));
+    match find_target(&req, &pool, Action::Write, PolicyAction::PutObjectTagging, &body.bucket_name, file).await {
+        Ok(target) => update_tags(&pool, target, TagChange::Merge(body.tags)).await,
+        Err(response) => response,
+    }
+}
+
+pub async fn delete_file_tags(
+    req: HttpRequest,
+    pool: web::Data<PgPool>,
+    query: web::Query<FileTagsQuery>,
+) -> impl Responder {
+    let file = Some((query.filename.as_str(), query.version_id.as_deref()));
+    match find_target(&req, &pool, Action::Write, PolicyAction::DeleteObjectTagging, &query.bucket_name, file).await {
+        Ok(target) => update_tags(&pool, target, TagChange::Remove(query.key.iter().cloned().collect())).await,
+        Err(response) => response,
+    }
+}
+
+pub async fn get_bucket_tags(
+    req: HttpRequest,
+    pool: web::Data<PgPool>,
+    query: web::Query<BucketTagsQuery>,
+) -> impl Responder {
+    match find_target(&req, &pool, Action::Read, PolicyAction::GetBucketTagging, &query.bucket_name, None).await {
+        Ok(target) => get_tags(&pool, target).await,
+        Err(response) => response,
+    }
+}
+
+pub async fn put_bucket_tags(
+    req: HttpRequest,
+    pool: web::Data<PgPool>,
+    body: web::Json<BucketTagsRequest>,
+) -> impl Responder {
+    let body = body.into_inner();
+    match find_target(&req, &pool, Action::Write, PolicyAction::PutBucketTagging, &body.bucket_name, None).await {
+        Ok(target) => update_tags(&pool, target, TagChange::Replace(body.tags)).await,
+        Err(response) => response,
+    }
+}
+
+pub async fn merge_bucket_tags(
+    req: HttpRequest,
+    pool: web::Data<PgPool>,
+    body: web::Json<BucketTagsRequest>,
+) -> impl Responder {
+    let body = body.into_inner();
+    match find_target(&req, &pool, Action::Write, PolicyAction::PutBucketTagging, &body.bucket_name, None).await {
+        Ok(target) => update_tags(&pool, target, TagChange::Merge(body.tags)).await,
+        Err(response) => response,
+    }
+}
+
+pub async fn delete_bucket_tags(
+    req: HttpRequest,
+    pool: web::Data<PgPool>,
+    query: web::Query<BucketTagsQuery>,
+) -> impl Responder {
+    match find_target(&req, &pool, Action::Write, PolicyAction::PutBucketTagging, &query.bucket_name, None).await {
+        Ok(target) => update_tags(&pool, target, TagChange::Remove(query.key.iter().cloned().collect())).await,
+        Err(response) => response,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn tags(pairs: &[(&str, &str)]) -> TagSet {
+        pairs.iter().map(|(k, v)| (k.to_string(), v.to_string())).collect()
+    }
+
+    #[test]
+    fn enforces_s3_tag_limits() {
+        assert!(validate_tags(&tags(&[("env", "staging"), ("team", "")]), MAX_OBJECT_TAGS).is_ok());
+
+        let many: TagSet = (0..11).map(|i| (format!("k{}", i), String::new())).collect();
+        assert!(matches!(validate_tags(&many, MAX_OBJECT_TAGS), Err(TagError::TooMany(10))));
+        assert!(validate_tags(&many, MAX_BUCKET_TAGS).is_ok());
+
+        let long_key = "ü".repeat(129);
+        assert!(matches!(validate_tags(&tags(&[(&long_key, "")]), 10), Err(TagError::InvalidKey(_))));
+        assert!(validate_tags(&tags(&[(&"ü".repeat(128), "")]), 10).is_ok());
+        assert!(matches!(validate_tags(&tags(&[("", "x")]), 10), Err(TagError::InvalidKey(_))));
+        assert!(matches!(validate_tags(&tags(&[("aws:owner", "x")]), 10), Err(TagError::InvalidKey(_))));
+        assert!(matches!(
+            validate_tags(&tags(&[("k", &"v".repeat(257))]), 10),
+            Err(TagError::InvalidValue(_))
+        ));
+    }
+}
@@ -12,15 +12,13 @@ use std::sync::Arc;
 use actix_cors::Cors;
 use crate::config::Config;
 use crate::db::postgres::init_pool;
-use crate::middleware::auth::ApiKeyMiddleware;
 use authentication::middleware::AuthMiddleware;
-use crate::handlers::{bucket, file};
+use crate::handlers::{bucket, copy, file, multipart, organization, policy, presign, s3, sharing, tagging};
+use crate::middleware::logging;
 use crate::storage::local::LocalStorage;
 use crate::storage::Storage;
-use env_logger::Env;
 use log::{error, info};
 use crate::authentication::jwt::JwtConfig;
-use crate::authentication::middleware::AuthMiddlewareService;
 
 #[actix_web::main]
 async fn main() -> std::io::Result<()> {
@@ -56,9 +54,26 @@ async fn main() -> std::io::Result<()> {
     };
 
     // Initialize JWT config
-    // In production, get this from environment variables
-    let jwt_secret = std::env::var("JWT_SECRET").unwrap_or_else(|_| "secretkey".to_string());
-    let jwt_config = JwtConfig::new(jwt_secret, 24 * 60 * 60); // 24 hours expiration
+    let jwt_config = match JwtConfig::from_config(&config) {
+        Ok(jwt_config) => jwt_config,
+        Err(err) => {
+            error!("Failed to initialize JWT signing: {}", err);
+            panic!("Failed to initialize JWT signing: {}", err);
+        }
+    };
+
+    // Clean up multipart uploads that were never completed
+    actix_web::rt::spawn(multipart::run_upload_reaper(
+        pool.clone(),
+        storage.clone(),
+        chrono::Duration::seconds(config.multipart_upload_ttl),
+    ));
+
+    // Clean up expired refresh tokens and access token revocations
+    actix_web::rt::spawn(authentication::tokens::run_token_reaper(pool.clone()));
+
+    let app_config = web::Data::new(config.clone());
+    let allow_query_api_key = config.allow_query_api_key;
 
     // Start HTTP server
     info!(
@@ -75,11 +90,16 @@ async fn main() -> std::io::Result<()> {
             .max_age(3600);
 
         App::new()
-            .wrap(Logger::new("%r %s %{User-Agent}i %D ms"))  // Add detailed logging
+            // Add detailed logging, keeping credentials in the query string out of the log
+            .wrap(
+                Logger::new("%{request}xi %s %{User-Agent}i %D ms")
+                    .custom_request_replace("request", logging::request_line)
+            )
             .wrap(cors)  // Add CORS middleware
             .app_data(web::Data::new(pool.clone()))
             .app_data(web::Data::from(storage.clone()))
             .app_data(web::Data::new(jwt_config.clone()))
+            .app_data(app_config.clone())
             .service(
                 web::resource("/register")
                     .route(web::post().to(authentication::register))
@@ -88,11 +108,82 @@ async fn main() -> std::io::Result<()> {
                 web::resource("/login")
                     .route(web::post().to(authentication::login))
             )
+            .service(
+                web::resource("/.well-known/jwks.json")
+                    .route(web::get().to(authentication::keys::jwks))
+            )
+            .service(
+                web::resource("/token/refresh")
+                    .route(web::post().to(authentication::tokens::refresh_token))
+            )
+            .service(
+                web::resource("/logout")
+                    .wrap(AuthMiddleware {
+                        pool: pool.clone(),
+                        jwt_config: jwt_config.clone(),
+                        allow_query_api_key,
+                        allow_anonymous_reads: false,
+                    })
+                    .route(web::post().to(authentication::tokens::logout))
+            )
+            .service(
+                web::resource("/set-password")
+                    .wrap(AuthMiddleware {
+                        pool: pool.clone(),
+                        jwt_config: jwt_config.clone(),
+                        allow_query_api_key,
+                        allow_anonymous_reads: false,
+                    })
+                    .route(web::post().to(authentication::set_password))
+            )
+            .service(
+                web::resource("/change-password")
+                    .wrap(AuthMiddleware {
+                        pool: pool.clone(),
+                        jwt_config: jwt_config.clone(),
+                        allow_query_api_key,
+                        allow_anonymous_reads: false,
+                    })
+                    .route(web::post().to(authentication::change_password))
+            )
+            .service(
+                web::resource("/access-key")
+                    .wrap(AuthMiddleware {
+                        pool: pool.clone(),
+                        jwt_config: jwt_config.clone(),
+                        allow_query_api_key,
+                        allow_anonymous_reads: false,
+                    })
+                    .route(web::post().to(authentication::rotate_access_key))
+            )
+            .service(
+                web::resource("/api-keys")
+                    .wrap(AuthMiddleware {
+                        pool: pool.clone(),
+                        jwt_config: jwt_config.clone(),
+                        allow_query_api_key,
+                        allow_anonymous_reads: false,
+                    })
+                    .route(web::get().to(authentication::api_keys::list_api_keys))
+                    .route(web::post().to(authentication::api_keys::create_api_key))
+            )
+            .service(
+                web::resource("/api-keys/{key_id}")
+                    .wrap(AuthMiddleware {
+                        pool: pool.clone(),
+                        jwt_config: jwt_config.clone(),
+                        allow_query_api_key,
+                        allow_anonymous_reads: false,
+                    })
+                    .route(web::delete().to(authentication::api_keys::revoke_api_key))
+            )
             .service (
                 web::resource("/buckets")
                     .wrap(AuthMiddleware {
                         pool: pool.clone(),
-                        jwt_config: jwt_config.clone()
+                        jwt_config: jwt_config.clone(),
+                        allow_query_api_key,
+                        allow_anonymous_reads: false,
                     })
                     .route(web::get().to(bucket::list_buckets))
             )
@@ -100,7 +191,9 @@ async fn main() -> std::io::Result<()> {
                 web::resource("/files")
                     .wrap(AuthMiddleware {
                         pool: pool.clone(),
-                        jwt_config: jwt_config.clone()
+                        jwt_config: jwt_config.clone(),
+                        allow_query_api_key,
+                        allow_anonymous_reads: false,
                     })
                     .route(web::get().to(file::list_files))
             )
@@ -109,6 +202,8 @@ async fn main() -> std::io::Result<()> {
                     .wrap( AuthMiddleware {
                         pool: pool.clone(),
                         jwt_config: jwt_config.clone(),
+                        allow_query_api_key,
+                        allow_anonymous_reads: false,
                     })
                     .route(web::post().to(bucket::create_bucket))
             )
@@ -117,6 +212,8 @@ async fn main() -> std::io::Result<()> {
                     .wrap( AuthMiddleware {
                         pool: pool.clone(),
                         jwt_config: jwt_config.clone(),
+                        allow_query_api_key,
+                        allow_anonymous_reads: false,
                     })
                     .route(web::post().to(file::upload_file))
             )
@@ -125,9 +222,248 @@ async fn main() -> std::io::Result<()> {
                     .wrap(AuthMiddleware {
                         pool: pool.clone(),
                         jwt_config: jwt_config.clone(),
+                        allow_query_api_key,
+                        allow_anonymous_reads: false,
                     })
                     .route(web::get().to(file::get_file_info))
             )
+            .service(
+                web::resource("/download-file")
+                    .wrap(AuthMiddleware {
+                        pool: pool.clone(),
+                        jwt_config: jwt_config.clone(),
+                        allow_query_api_key,
+                        allow_anonymous_reads: false,
+                    })
+                    .route(web::get().to(file::download_file))
+            )
+            .service(
+                web::resource("/delete-file")
+                    .wrap(AuthMiddleware {
+                        pool: pool.clone(),
+                        jwt_config: jwt_config.clone(),
+                        allow_query_api_key,
+                        allow_anonymous_reads: false,
+                    })
+                    .route(web::delete().to(file::delete_file))
+            )
+            .service(
+                web::resource("/delete-bucket")
+                    .wrap(AuthMiddleware {
+                        pool: pool.clone(),
+                        jwt_config: jwt_config.clone(),
+                        allow_query_api_key,
+                        allow_anonymous_reads: false,
+                    })
+                    .route(web::delete().to(bucket::delete_bucket))
+            )
+            .service(
+                web::resource("/file-versions")
+                    .wrap(AuthMiddleware {
+                        pool: pool.clone(),
+                        jwt_config: jwt_config.clone(),
+                        allow_query_api_key,
+                        allow_anonymous_reads: false,
+                    })
+                    .route(web::get().to(file::list_versions))
+            )
+            .service(
+                web::resource("/bucket-versioning")
+                    .wrap(AuthMiddleware {
+                        pool: pool.clone(),
+                        jwt_config: jwt_config.clone(),
+                        allow_query_api_key,
+                        allow_anonymous_reads: false,
+                    })
+                    .route(web::get().to(bucket::get_versioning))
+                    .route(web::put().to(bucket::set_versioning))
+            )
+            .service(
+                web::resource("/copy-file")
+                    .wrap(AuthMiddleware {
+                        pool: pool.clone(),
+                        jwt_config: jwt_config.clone(),
+                        allow_query_api_key,
+                        allow_anonymous_reads: false,
+                    })
+                    .route(web::post().to(copy::copy_file))
+            )
+            .service(
+                web::resource("/move-file")
+                    .wrap(AuthMiddleware {
+                        pool: pool.clone(),
+                        jwt_config: jwt_config.clone(),
+                        allow_query_api_key,
+                        allow_anonymous_reads: false,
+                    })
+                    .route(web::post().to(copy::move_file))
+            )
+            .service(
+                web::resource("/file-tags")
+                    .wrap(AuthMiddleware {
+                        pool: pool.clone(),
+                        jwt_config: jwt_config.clone(),
+                        allow_query_api_key,
+                        allow_anonymous_reads: false,
+                    })
+                    .route(web::get().to(tagging::get_file_tags))
+                    .route(web::put().to(tagging::put_file_tags))
+                    .route(web::patch().to(tagging::merge_file_tags))
+                    .route(web::delete().to(tagging::delete_file_tags))
+            )
+            .service(
+                web::resource("/bucket-tags")
+                    .wrap(AuthMiddleware {
+                        pool: pool.clone(),
+                        jwt_config: jwt_config.clone(),
+                        allow_query_api_key,
+                        allow_anonymous_reads: false,
+                    })
+                    .route(web::get().to(tagging::get_bucket_tags))
+                    .route(web::put().to(tagging::put_bucket_tags))
+                    .route(web::patch().to(tagging::merge_bucket_tags))
+                    .route(web::delete().to(tagging::delete_bucket_tags))
+            )
+            .service(
+                web::resource("/bucket-policy")
+                    .wrap(AuthMiddleware {
+                        pool: pool.clone(),
+                        jwt_config: jwt_config.clone(),
+                        allow_query_api_key,
+                        allow_anonymous_reads: false,
+                    })
+                    .route(web::get().to(policy::get_bucket_policy))
+                    .route(web::put().to(policy::put_bucket_policy))
+                    .route(web::delete().to(policy::delete_bucket_policy))
+            )
+            .service(
+                web::resource("/bucket-grants")
+                    .wrap(AuthMiddleware {
+                        pool: pool.clone(),
+                        jwt_config: jwt_config.clone(),
+                        allow_query_api_key,
+                        allow_anonymous_reads: false,
+                    })
+                    .route(web::get().to(sharing::list_bucket_grants))
+                    .route(web::put().to(sharing::put_bucket_grant))
+                    .route(web::delete().to(sharing::delete_bucket_grant))
+            )
+            .service(
+                web::resource("/bucket-acl")
+                    .wrap(AuthMiddleware {
+                        pool: pool.clone(),
+                        jwt_config: jwt_config.clone(),
+                        allow_query_api_key,
+                        allow_anonymous_reads: false,
+                    })
+                    .route(web::put().to(sharing::set_bucket_acl))
+            )
+            .service(
+                web::resource("/organizations")
+                    .wrap(AuthMiddleware {
+                        pool: pool.clone(),
+                        jwt_config: jwt_config.clone(),
+                        allow_query_api_key,
+                        allow_anonymous_reads: false,
+                    })
+                    .route(web::get().to(organization::list_organizations))
+                    .route(web::post().to(organization::create_organization))
+            )
+            .service(
+                web::resource("/organizations/{name}/members")
+                    .wrap(AuthMiddleware {
+                        pool: pool.clone(),
+                        jwt_config: jwt_config.clone(),
+                        allow_query_api_key,
+                        allow_anonymous_reads: false,
+                    })
+                    .route(web::get().to(organization::list_members))
+                    .route(web::post().to(organization::add_member))
+                    .route(web::put().to(organization::update_member))
+                    .route(web::delete().to(organization::remove_member))
+            )
+            .service(
+                web::resource("/organizations/{name}/buckets")
+                    .wrap(AuthMiddleware {
+                        pool: pool.clone(),
+                        jwt_config: jwt_config.clone(),
+                        allow_query_api_key,
+                        allow_anonymous_reads: false,
+                    })
+                    .route(web::get().to(organization::list_organization_buckets))
+            )
+            .service(
+                web::resource("/multipart-uploads")
+                    .wrap(AuthMiddleware {
+                        pool: pool.clone(),
+                        jwt_config: jwt_config.clone(),
+                        allow_query_api_key,
+                        allow_anonymous_reads: false,
+                    })
+                    .route(web::post().to(multipart::initiate_upload))
+                    .route(web::get().to(multipart::list_uploads))
+            )
+            .service(
+                web::resource("/multipart-uploads/{upload_id}")
+                    .wrap(AuthMiddleware {
+                        pool: pool.clone(),
+                        jwt_config: jwt_config.clone(),
+                        allow_query_api_key,
+                        allow_anonymous_reads: false,
+                    })
+                    .route(web::delete().to(multipart::abort_upload))
+            )
+            .service(
+                web::resource("/multipart-uploads/{upload_id}/parts")
+                    .wrap(AuthMiddleware {
+                        pool: pool.clone(),
+                        jwt_config: jwt_config.clone(),
+                        allow_query_api_key,
+                        allow_anonymous_reads: false,
+                    })
+                    .route(web::get().to(multipart::list_parts))
+            )
+            .service(
+                web::resource("/multipart-uploads/{upload_id}/parts/{part_number}")
+                    .wrap(AuthMiddleware {
+                        pool: pool.clone(),
+                        jwt_config: jwt_config.clone(),
+                        allow_query_api_key,
+                        allow_anonymous_reads: false,
+                    })
+                    .route(web::put().to(multipart::upload_part))
+            )
+            .service(
+                web::resource("/multipart-uploads/{upload_id}/complete")
+                    .wrap(AuthMiddleware {
+                        pool: pool.clone(),
+                        jwt_config: jwt_config.clone(),
+                        allow_query_api_key,
+                        allow_anonymous_reads: false,
+                    })
+                    .route(web::post().to(multipart::complete_upload))
+            )
+            .service(
+                web::resource("/presign")
+                    .wrap(AuthMiddleware {
+                        pool: pool.clone(),
+                        jwt_config: jwt_config.clone(),
+                        allow_query_api_key,
+                        allow_anonymous_reads: false,
+                    })
+                    .route(web::post().to(presign::create_presigned_url))
+            )
+            // S3-compatible API; registered last so the routes above take precedence
+            .service(
+                web::scope("")
+                    .wrap(AuthMiddleware {
+                        pool: pool.clone(),
+                        jwt_config: jwt_config.clone(),
+                        allow_query_api_key,
+                        allow_anonymous_reads: true,
+                    })
+                    .configure(s3::configure)
+            )
     })
         .bind((config.server_addr, config.server_port))?
         .run()
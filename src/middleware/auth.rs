@@ -1,18 +1,21 @@
 use actix_web::{dev::{forward_ready, Service, ServiceRequest, ServiceResponse, Transform}, Error, HttpMessage, HttpResponse, body::{BoxBody, EitherBody}, HttpRequest};
 use futures::future::{ready, LocalBoxFuture, Ready};
+use log::error;
 use sqlx::PgPool;
 use std::rc::Rc;
 use uuid::Uuid;
 
-use crate::models::User;
-
-// Constants for header and query param names
-const AUTHORIZATION_HEADER: &str = "Authorization";
-const BEARER_PREFIX: &str = "Bearer ";
-const API_KEY_PARAM: &str = "apiKey";
+use crate::authentication::middleware::{presented_api_key, PresentedApiKey};
+use crate::authentication::jwt::Claims;
+use crate::models::policy::{Decision, PolicyRequest};
+use crate::models::{Action, ApiKey, Bucket, Policy, PolicyAction, Scope};
 
+// API-key-only authentication for routes that should not accept JWTs
+#[allow(dead_code)]
 pub struct ApiKeyMiddleware {
     pub pool: PgPool,
+    // Whether the `apiKey` query parameter is accepted
+    pub allow_query_api_key: bool,
 }
 
 impl<S, B> Transform<S, ServiceRequest> for ApiKeyMiddleware
@@ -31,13 +34,16 @@ where
         ready(Ok(ApiKeyMiddlewareService {
             service: Rc::new(service),
             pool: self.pool.clone(),
+            allow_query_api_key: self.allow_query_api_key,
         }))
     }
 }
 
+#[allow(dead_code)]
 pub struct ApiKeyMiddlewareService<S> {
     service: Rc<S>,
     pool: PgPool,
+    allow_query_api_key: bool,
 }
 
 impl<S, B> Service<ServiceRequest> for ApiKeyMiddlewareService<S>
@@ -54,27 +60,22 @@ where
 
     fn call(&self, req: ServiceRequest) -> Self::Future {
         let pool = self.pool.clone();
+        let allow_query_api_key = self.allow_query_api_key;
         let service = self.service.clone();
 
         Box::pin(async move {
-            // Extract API key from query parameters
-            let query_string = req.query_string();
-            let api_key = query_string
-                .split('&')
-                .find_map(|param| {
-                    let parts: Vec<&str> = param.split('=').collect();
-                    if parts.len() == 2 && parts[0] == "apiKey" {
-                        Some(parts[1].to_string())
-                    } else {
-                        None
-                    }
-                });
+            // Extract API key from the headers or query parameters
+            let api_key = match presented_api_key(req.headers(), req.query_string()) {
+                Some(PresentedApiKey::Header(key)) => Some(key),
+                Some(PresentedApiKey::Query(key)) if allow_query_api_key => Some(key),
+                _ => None,
+            };
 
             // If API key is found, verify it
             if let Some(api_key) = api_key {
-                if let Ok(Some(user)) = User::find_by_api_key(&pool, &api_key).await {
-                    // Store user ID in request extensions
-                    req.extensions_mut().insert(user.id);
+                if let Ok(Some(key)) = ApiKey::verify(&pool, &api_key).await {
+                    // Store the principal in request extensions
+                    req.extensions_mut().insert(Principal::from(&key));
                     // Process the request with the service and map the response
                     let res = service.call(req).await?;
                     return Ok(res.map_into_left_body());
@@ -97,6 +98,123 @@ where
     }
 }
 
+// Who a request acts for and, for scoped API keys, what it may do
+#[derive(Debug, Clone)]
+pub struct Principal {
+    pub user_id: Uuid,
+    // None for credentials with the user's full access
+    pub scope: Option<Scope>,
+}
+
+impl Principal {
+    pub fn full(user_id: Uuid) -> Self {
+        Self { user_id, scope: None }
+    }
+
+    pub fn is_scoped(&self) -> bool {
+        self.scope.is_some()
+    }
+
+    pub fn allows(&self, action: Action, bucket: &str, key: Option<&str>) -> bool {
+        self.scope.as_ref().is_none_or(|scope| scope.allows(action, bucket, key))
+    }
+
+    pub fn allows_action(&self, action: Action) -> bool {
+        self.scope.as_ref().is_none_or(|scope| scope.allows_action(action))
+    }
+
+    pub fn allows_bucket(&self, bucket: &str) -> bool {
+        self.scope.as_ref().is_none_or(|scope| scope.allows_bucket(bucket))
+    }
+}
+
+impl From<&ApiKey> for Principal {
+    fn from(api_key: &ApiKey) -> Self {
+        Self {
+            user_id: api_key.user_id,
+            scope: api_key.scope.as_ref().map(|scope| scope.0.clone()),
+        }
+    }
+}
+
+pub fn get_principal_from_request(req: &HttpRequest) -> Option<Principal> {
+    req.extensions().get::<Principal>().cloned()
+}
+
 pub fn get_user_id_from_request(req: &HttpRequest) -> Option<Uuid> {
-    req.extensions().get::<Uuid>().copied()
-}
\ No newline at end of file
+    req.extensions().get::<Principal>().map(|principal| principal.user_id)
+}
+
+// A 403 response unless the request's credential may perform `action` on
+// `key` in `bucket`, or on the bucket itself without a key. Scopes name
+// buckets as `Bucket::qualified_name` does, so the bucket must have been
+// looked up first.
+pub fn scope_denied(req: &HttpRequest, action: Action, bucket: &Bucket, key: Option<&str>) -> Option<HttpResponse> {
+    match get_principal_from_request(req) {
+        Some(principal) if principal.allows(action, &bucket.qualified_name(), key) => None,
+        _ => Some(HttpResponse::Forbidden().json(serde_json::json!({
+            "error": format!("This API key is not allowed to {} here", action.as_str())
+        }))),
+    }
+}
+
+// A 403 response for scoped API keys, on routes that manage the account
+// itself and could be used to escape the scope
+pub fn full_access_denied(req: &HttpRequest) -> Option<HttpResponse> {
+    match get_principal_from_request(req) {
+        Some(principal) if !principal.is_scoped() => None,
+        _ => Some(HttpResponse::Forbidden().json(serde_json::json!({
+            "error": "This API key cannot manage the account"
+        }))),
+    }
+}
+
+// What the bucket's policy says about the request. The source address is
+// the peer's and the transport is secure only when served over TLS;
+// forwarding headers are not trusted, as any client could set them.
+pub fn policy_decision(req: &HttpRequest, bucket: &Bucket, action: PolicyAction, key: Option<&str>) -> Decision {
+    let Some(document) = &bucket.policy else {
+        return Decision::Implicit;
+    };
+    // A stored policy was valid when set, so failing to parse it now is a
+    // bug; deny rather than silently ignore it
+    let policy = match Policy::parse(document, &bucket.name) {
+        Ok(policy) => policy,
+        Err(e) => {
+            error!("Invalid policy on bucket {}: {}", bucket.id, e);
+            return Decision::Deny;
+        }
+    };
+
+    policy.evaluate(&PolicyRequest {
+        user_id: get_user_id_from_request(req),
+        action,
+        bucket: &bucket.name,
+        key,
+        source_ip: req.peer_addr().map(|addr| addr.ip()),
+        secure_transport: req.app_config().secure(),
+    })
+}
+
+// A 403 response if the bucket's policy explicitly denies the request, or
+// if neither the policy allows it nor the user's access to the bucket
+// covers it. Policies only apply once the bucket has been looked up, so an
+// Allow extends the access of users who can already see the bucket but
+// does not make it visible to anyone else.
+pub fn access_denied(req: &HttpRequest, bucket: &Bucket, action: PolicyAction, key: Option<&str>) -> Option<HttpResponse> {
+    match policy_decision(req, bucket, action, key) {
+        Decision::Deny => Some(HttpResponse::Forbidden().json(serde_json::json!({
+            "error": format!("The bucket policy denies {}", action.as_str())
+        }))),
+        Decision::Allow => None,
+        Decision::Implicit if bucket.access().allows(action) => None,
+        Decision::Implicit => Some(HttpResponse::Forbidden().json(serde_json::json!({
+            "error": format!("Your access to this bucket does not allow {}", action.as_str())
+        }))),
+    }
+}
+
+// Claims of the access token a request was authenticated with, if any
+pub fn get_claims_from_request(req: &HttpRequest) -> Option<Claims> {
+    req.extensions().get::<Claims>().cloned()
+}
@@ -0,0 +1,212 @@
+use chrono::{DateTime, Utc};
+use rand::distributions::{Alphanumeric, DistString};
+use rand::thread_rng;
+use serde::{Deserialize, Serialize};
+use sha2::{Digest, Sha256};
+use sqlx::types::Json;
+use sqlx::{FromRow, PgExecutor, PgPool};
+use subtle::ConstantTimeEq;
+use uuid::Uuid;
+
+use super::scope::Scope;
+
+// Keys look like "s3k_" followed by random letters and digits
+const KEY_PREFIX: &str = "s3k_";
+const KEY_RANDOM_LENGTH: usize = 40;
+
+// Leading characters of a key stored in the clear to look it up
+const PREFIX_LENGTH: usize = 12;
+
+#[derive(Debug, Serialize, Deserialize, FromRow)]
+pub struct ApiKey {
+    pub id: Uuid,
+    pub user_id: Uuid,
+    pub name: String,
+    pub prefix: String,
+    // SHA-256 of the whole key. Keys are long and random, so a slow
+    // password hash would only slow down every request.
+    #[serde(skip_serializing)]
+    pub key_hash: String,
+    pub created_at: DateTime<Utc>,
+    pub last_used_at: Option<DateTime<Utc>>,
+    pub expires_at: Option<DateTime<Utc>>,
+    // None for keys with their user's full access
+    pub scope: Option<Json<Scope>>,
+}
+
+fn hash_key(key: &str) -> String {
+    hex::encode(Sha256::digest(key.as_bytes()))
+}
+
+// Lookup prefix of a key; keys too short to have one cannot be valid
+fn key_prefix(key: &str) -> Option<&str> {
+    key.get(..PREFIX_LENGTH)
+}
+
+impl ApiKey {
+    // A new key for the user. The key itself is only returned here; just
+    // its hash is stored.
+    pub fn generate(
+        user_id: Uuid,
+        name: String,
+        expires_at: Option<DateTime<Utc>>,
+        scope: Option<Scope>,
+    ) -> (Self, String) {
+        let key = format!(
+            "{}{}",
+            KEY_PREFIX,
+            Alphanumeric.sample_string(&mut thread_rng(), KEY_RANDOM_LENGTH)
+        );
+
+        let api_key = Self {
+            id: Uuid::new_v4(),
+            user_id,
+            name,
+            prefix: key[..PREFIX_LENGTH].to_string(),
+            key_hash: hash_key(&key),
+            created_at: Utc::now(),
+            last_used_at: None,
+            expires_at,
+            scope: scope.map(Json),
+        };
+
+        (api_key, key)
+    }
+
+    pub fn is_expired(&self) -> bool {
+        self.expires_at.is_some_and(|expires_at| expires_at <= Utc::now())
+    }
+
+    // Whether `key` is this key, compared in constant time
+    pub fn matches(&self, key: &str) -> bool {
+        hash_key(key).as_bytes().ct_eq(self.key_hash.as_bytes()).into()
+    }
+
+    pub async fn create<'e>(&self, executor: impl PgExecutor<'e>) -> Result<(), sqlx::Error> {
+        sqlx::query!(
+            r#"
+            INSERT INTO api_keys (id, user_id, name, prefix, key_hash, created_at, last_used_at, expires_at, scope)
+            VALUES ($1, $2, $3, $4, $5, $6, $7, $8, $9)
+            "#,
+            self.id,
+            self.user_id,
+            self.name,
+            self.prefix,
+            self.key_hash,
+            self.created_at,
+            self.last_used_at,
+            self.expires_at,
+            self.scope as _
+        )
+            .execute(executor)
+            .await?;
+
+        Ok(())
+    }
+
+    // The key row for a presented key, if the key is valid and unexpired.
+    // Records the use, at most once a minute per key.
+    pub async fn verify(pool: &PgPool, key: &str) -> Result<Option<Self>, sqlx::Error> {
+        let prefix = match key_prefix(key) {
+            Some(prefix) => prefix,
+            None => return Ok(None),
+        };
+
+        let api_key = sqlx::query_as!(
+            ApiKey,
+            r#"
+            SELECT id, user_id, name, prefix, key_hash, created_at, last_used_at, expires_at,
+                   scope AS "scope: Json<Scope>"
+            FROM api_keys
+            WHERE prefix = $1
+            "#,
+            prefix
+        )
+            .fetch_optional(pool)
+            .await?;
+
+        let api_key = match api_key {
+            Some(api_key) if api_key.matches(key) && !api_key.is_expired() => api_key,
+            _ => return Ok(None),
+        };
+
+        sqlx::query!(
+            r#"
+            UPDATE api_keys
+            SET last_used_at = NOW()
+            WHERE id = $1 AND (last_used_at IS NULL OR last_used_at < NOW() - INTERVAL '1 minute')
+            "#,
+            api_key.id
+        )
+            .execute(pool)
+            .await?;
+
+        Ok(Some(api_key))
+    }
+
+    // Every key of a user, oldest first
+    pub async fn find_by_user(pool: &PgPool, user_id: Uuid) -> Result<Vec<Self>, sqlx::Error> {
+        let api_keys = sqlx::query_as!(
+            ApiKey,
+            r#"
+            SELECT id, user_id, name, prefix, key_hash, created_at, last_used_at, expires_at,
+                   scope AS "scope: Json<Scope>"
+            FROM api_keys
+            WHERE user_id = $1
+            ORDER BY created_at
+            "#,
+            user_id
+        )
+            .fetch_all(pool)
+            .await?;
+
+        Ok(api_keys)
+    }
+
+    // Delete one of the user's keys. Returns false if the user has no such key.
+    pub async fn revoke(pool: &PgPool, user_id: Uuid, id: Uuid) -> Result<bool, sqlx::Error> {
+        let result = sqlx::query!(
+            r#"
+            DELETE FROM api_keys
+            WHERE id = $1 AND user_id = $2
+            "#,
+            id,
+            user_id
+        )
+            .execute(pool)
+            .await?;
+
+        Ok(result.rows_affected() > 0)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use chrono::Duration;
+
+    #[test]
+    fn generated_key_matches_only_itself() {
+        let (api_key, key) = ApiKey::generate(Uuid::new_v4(), "ci".to_string(), None, None);
+
+        assert!(key.starts_with(KEY_PREFIX));
+        assert_eq!(key.len(), KEY_PREFIX.len() + KEY_RANDOM_LENGTH);
+        assert_eq!(key_prefix(&key), Some(api_key.prefix.as_str()));
+        assert_ne!(api_key.key_hash, key);
+        assert!(api_key.matches(&key));
+
+        let mut other = key.clone();
+        other.pop();
+        other.push('!');
+        assert!(!api_key.matches(&other));
+        assert_eq!(key_prefix("s3k_short"), None);
+    }
+
+    #[test]
+    fn expiry() {
+        let user_id = Uuid::new_v4();
+        assert!(!ApiKey::generate(user_id, "a".to_string(), None, None).0.is_expired());
+        assert!(!ApiKey::generate(user_id, "a".to_string(), Some(Utc::now() + Duration::hours(1)), None).0.is_expired());
+        assert!(ApiKey::generate(user_id, "a".to_string(), Some(Utc::now() - Duration::seconds(1)), None).0.is_expired());
+    }
+}
@@ -1,56 +1,486 @@
 use chrono::{DateTime, Utc};
 use serde::{Deserialize, Serialize};
+use serde_json::Value;
 use sqlx::{FromRow, PgPool};
+use thiserror::Error;
 use uuid::Uuid;
 
+use super::organization::{OrgRole, Organization};
+use super::policy::PolicyAction;
+
 #[derive(Debug, Serialize, Deserialize, FromRow)]
 pub struct Bucket {
     pub id: Uuid,
     pub name: String,
-    pub user_id: Uuid,
+    // Exactly one of the owning user and the owning organization is set
+    pub user_id: Option<Uuid>,
+    pub organization_id: Option<Uuid>,
+    // NULL until versioning is first configured; see `VersioningStatus`
+    pub versioning: Option<String>,
+    // Canned ACL; see `BucketAcl`
+    pub acl: String,
+    // Policy document as set by the owner; see `Policy`
+    #[serde(skip)]
+    pub policy: Option<Value>,
     pub created_at: DateTime<Utc>,
+    // Name of the owning organization, which qualifies the bucket's name
+    // as `organization:bucket`
+    pub organization: Option<String>,
+    // How the user the bucket was looked up for may use it; see `BucketAccess`
+    #[serde(skip)]
+    pub access: String,
+}
+
+// Ways a name breaks the S3 bucket naming rules, which keep names usable
+// as a DNS label in virtual-hosted-style URLs
+#[derive(Debug, PartialEq, Eq, Error)]
+pub enum BucketNameError {
+    #[error("Bucket name must be between 3 and 63 characters long")]
+    Length,
+    #[error("Bucket name can only contain lowercase letters, digits, dots and hyphens, not {0:?}")]
+    Character(char),
+    #[error("Bucket name must begin and end with a lowercase letter or digit")]
+    Boundary,
+    #[error("Bucket name must not contain two adjacent dots")]
+    AdjacentDots,
+    #[error("Bucket name must not be formatted as an IP address")]
+    IpAddress,
+    #[error("Bucket name must not start with \"{0}\"")]
+    ReservedPrefix(&'static str),
+    #[error("Bucket name must not end with \"{0}\"")]
+    ReservedSuffix(&'static str),
+}
+
+// Prefixes and suffixes S3 keeps for its own use
+const RESERVED_NAME_PREFIXES: &[&str] = &["xn--", "sthree-"];
+const RESERVED_NAME_SUFFIXES: &[&str] = &["-s3alias", "--ol-s3"];
+
+#[derive(Debug, Error)]
+pub enum CreateBucketError {
+    #[error("Bucket name is already taken")]
+    NameTaken,
+    #[error(transparent)]
+    Database(#[from] sqlx::Error),
+}
+
+// Once enabled, versioning can only be suspended, never turned off again
+#[derive(Debug, Clone, Copy, PartialEq, Serialize, Deserialize)]
+pub enum VersioningStatus {
+    Enabled,
+    Suspended,
+}
+
+impl VersioningStatus {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            VersioningStatus::Enabled => "Enabled",
+            VersioningStatus::Suspended => "Suspended",
+        }
+    }
+
+    pub fn parse(value: &str) -> Option<Self> {
+        match value {
+            "Enabled" => Some(VersioningStatus::Enabled),
+            "Suspended" => Some(VersioningStatus::Suspended),
+            _ => None,
+        }
+    }
+}
+
+// Who can read a bucket's objects besides its owner and grantees
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "kebab-case")]
+pub enum BucketAcl {
+    Private,
+    PublicRead,
+}
+
+impl BucketAcl {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            BucketAcl::Private => "private",
+            BucketAcl::PublicRead => "public-read",
+        }
+    }
+
+    pub fn parse(value: &str) -> Option<Self> {
+        match value {
+            "private" => Some(BucketAcl::Private),
+            "public-read" => Some(BucketAcl::PublicRead),
+            _ => None,
+        }
+    }
+}
+
+// What a user may do in a bucket: everything as its owner, what a grant
+// allows, or only read objects of a public bucket
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "kebab-case")]
+pub enum BucketAccess {
+    Owner,
+    ReadWrite,
+    Read,
+    PublicRead,
+}
+
+impl BucketAccess {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            BucketAccess::Owner => "owner",
+            BucketAccess::ReadWrite => "read-write",
+            BucketAccess::Read => "read",
+            BucketAccess::PublicRead => "public-read",
+        }
+    }
+
+    pub fn parse(value: &str) -> Option<Self> {
+        match value {
+            "owner" => Some(BucketAccess::Owner),
+            "read-write" => Some(BucketAccess::ReadWrite),
+            "read" => Some(BucketAccess::Read),
+            "public-read" => Some(BucketAccess::PublicRead),
+            _ => None,
+        }
+    }
+
+    // Only the owner changes the bucket itself; grantees work with its
+    // objects and can read its configuration
+    pub fn allows(&self, action: PolicyAction) -> bool {
+        use PolicyAction::*;
+
+        match self {
+            BucketAccess::Owner => true,
+            BucketAccess::ReadWrite => !matches!(action, DeleteBucket | PutBucketVersioning | PutBucketTagging),
+            BucketAccess::Read => matches!(
+                action,
+                GetObject
+                    | GetObjectTagging
+                    | ListBucket
+                    | ListBucketVersions
+                    | GetBucketVersioning
+                    | GetBucketTagging
+            ),
+            BucketAccess::PublicRead => action == GetObject,
+        }
+    }
 }
 
 impl Bucket {
+    pub fn validate_name(name: &str) -> Result<(), BucketNameError> {
+        if !(3..=63).contains(&name.len()) {
+            return Err(BucketNameError::Length);
+        }
+        if let Some(c) = name.chars().find(|c| !matches!(c, 'a'..='z' | '0'..='9' | '.' | '-')) {
+            return Err(BucketNameError::Character(c));
+        }
+
+        let alphanumeric = |b: u8| b.is_ascii_lowercase() || b.is_ascii_digit();
+        let bytes = name.as_bytes();
+        if !alphanumeric(bytes[0]) || !alphanumeric(bytes[bytes.len() - 1]) {
+            return Err(BucketNameError::Boundary);
+        }
+        if name.contains("..") {
+            return Err(BucketNameError::AdjacentDots);
+        }
+        if name.split('.').count() == 4 && name.split('.').all(|part| part.bytes().all(|b| b.is_ascii_digit())) {
+            return Err(BucketNameError::IpAddress);
+        }
+        if let Some(prefix) = RESERVED_NAME_PREFIXES.iter().find(|prefix| name.starts_with(*prefix)) {
+            return Err(BucketNameError::ReservedPrefix(prefix));
+        }
+        if let Some(suffix) = RESERVED_NAME_SUFFIXES.iter().find(|suffix| name.ends_with(*suffix)) {
+            return Err(BucketNameError::ReservedSuffix(suffix));
+        }
+
+        Ok(())
+    }
+
     pub fn new(name: String, user_id: Uuid) -> Self {
         Self {
             id: Uuid::new_v4(),
             name,
-            user_id,
+            user_id: Some(user_id),
+            organization_id: None,
+            versioning: None,
+            acl: BucketAcl::Private.as_str().to_string(),
+            policy: None,
             created_at: Utc::now(),
+            organization: None,
+            access: BucketAccess::Owner.as_str().to_string(),
+        }
+    }
+
+    pub fn new_in_organization(name: String, organization: &Organization) -> Self {
+        Self {
+            user_id: None,
+            organization_id: Some(organization.id),
+            organization: Some(organization.name.clone()),
+            ..Self::new(name, Uuid::nil())
+        }
+    }
+
+    // The name that reaches this bucket and no other of the same name,
+    // which scoped API keys are checked against
+    pub fn qualified_name(&self) -> String {
+        match &self.organization {
+            Some(organization) => format!("{}:{}", organization, self.name),
+            None => self.name.clone(),
         }
     }
 
-    pub async fn create(&self, pool: &PgPool) -> Result<(), sqlx::Error> {
+    pub fn versioning_status(&self) -> Option<VersioningStatus> {
+        self.versioning.as_deref().and_then(VersioningStatus::parse)
+    }
+
+    pub fn acl(&self) -> BucketAcl {
+        BucketAcl::parse(&self.acl).unwrap_or(BucketAcl::Private)
+    }
+
+    // Unknown values fall back to the least access
+    pub fn access(&self) -> BucketAccess {
+        BucketAccess::parse(&self.access).unwrap_or(BucketAccess::PublicRead)
+    }
+
+    pub async fn set_versioning(&mut self, pool: &PgPool, status: VersioningStatus) -> Result<(), sqlx::Error> {
         sqlx::query!(
             r#"
-            INSERT INTO buckets (id, name, user_id, created_at)
-            VALUES ($1, $2, $3, $4)
+            UPDATE buckets
+            SET versioning = $1
+            WHERE id = $2
+            "#,
+            status.as_str(),
+            self.id
+        )
+            .execute(pool)
+            .await?;
+
+        self.versioning = Some(status.as_str().to_string());
+        Ok(())
+    }
+
+    // Replace the bucket's policy, or remove it with None
+    pub async fn set_policy(&mut self, pool: &PgPool, policy: Option<Value>) -> Result<(), sqlx::Error> {
+        sqlx::query!(
+            r#"
+            UPDATE buckets
+            SET policy = $1
+            WHERE id = $2
+            "#,
+            policy,
+            self.id
+        )
+            .execute(pool)
+            .await?;
+
+        self.policy = policy;
+        Ok(())
+    }
+
+    pub async fn set_acl(&mut self, pool: &PgPool, acl: BucketAcl) -> Result<(), sqlx::Error> {
+        sqlx::query!(
+            r#"
+            UPDATE buckets
+            SET acl = $1
+            WHERE id = $2
+            "#,
+            acl.as_str(),
+            self.id
+        )
+            .execute(pool)
+            .await?;
+
+        self.acl = acl.as_str().to_string();
+        Ok(())
+    }
+
+    // Insert the bucket. Names are unique per owner, and with
+    // `global_namespace` across all buckets; creations then take a lock on
+    // the name so two of them cannot both find it free.
+    pub async fn create(&self, pool: &PgPool, global_namespace: bool) -> Result<(), CreateBucketError> {
+        let mut tx = pool.begin().await?;
+
+        if global_namespace {
+            sqlx::query!("SELECT pg_advisory_xact_lock(hashtext($1))", self.name)
+                .execute(&mut *tx)
+                .await?;
+
+            let taken = sqlx::query_scalar!(
+                r#"
+                SELECT EXISTS (SELECT 1 FROM buckets WHERE name = $1) AS "taken!"
+                "#,
+                self.name
+            )
+                .fetch_one(&mut *tx)
+                .await?;
+            if taken {
+                return Err(CreateBucketError::NameTaken);
+            }
+        }
+
+        let inserted = sqlx::query!(
+            r#"
+            INSERT INTO buckets (id, name, user_id, organization_id, created_at)
+            VALUES ($1, $2, $3, $4, $5)
             "#,
             self.id,
             self.name,
             self.user_id,
+            self.organization_id,
             self.created_at
         )
-            .execute(pool)
-            .await?;
+            .execute(&mut *tx)
+            .await;
+
+        match inserted {
+            Ok(_) => {}
+            Err(e) if e.as_database_error().is_some_and(|db| db.is_unique_violation()) => {
+                return Err(CreateBucketError::NameTaken);
+            }
+            Err(e) => return Err(e.into()),
+        }
 
+        tx.commit().await?;
         Ok(())
     }
 
+    // Split a bucket name qualified as `organization:bucket`, which picks the
+    // organization's bucket over others of the same name. Neither kind of
+    // name can contain the separator.
+    pub fn split_qualified_name(name: &str) -> (Option<&str>, &str) {
+        match name.split_once(':') {
+            Some((organization, name)) => (Some(organization), name),
+            None => (None, name),
+        }
+    }
+
+    // Look up a bucket the user can access: their own, one of an
+    // organization they belong to, one shared with them or a public one.
+    // Their own bucket wins over others of the same name, then those of
+    // their organizations; a name qualified with the organization reaches
+    // any of them.
     pub async fn find_by_name_and_user(
         pool: &PgPool,
         name: &str,
         user_id: Uuid,
     ) -> Result<Option<Self>, sqlx::Error> {
+        let (organization, name) = Self::split_qualified_name(name);
+        let bucket = sqlx::query_as!(
+            Bucket,
+            r#"
+            SELECT b.id, b.name, b.user_id, b.organization_id, b.versioning, b.acl, b.policy, b.created_at,
+                   o.name AS "organization?",
+                   CASE WHEN b.user_id = $2 THEN 'owner'
+                        WHEN m.role IN ('owner', 'admin') THEN 'owner'
+                        WHEN m.role = 'member' THEN 'read-write'
+                        WHEN m.role = 'read-only' THEN 'read'
+                        WHEN g.permission IS NOT NULL THEN g.permission
+                        ELSE 'public-read'
+                   END AS "access!"
+            FROM buckets b
+            LEFT JOIN organization_members m ON m.organization_id = b.organization_id AND m.user_id = $2
+            LEFT JOIN bucket_grants g ON g.bucket_id = b.id AND g.user_id = $2
+            LEFT JOIN organizations o ON o.id = b.organization_id
+            WHERE b.name = $1
+              AND (b.user_id = $2 OR m.user_id IS NOT NULL OR g.user_id IS NOT NULL OR b.acl = 'public-read')
+              AND ($3::text IS NULL OR o.name = $3)
+            ORDER BY (b.user_id = $2) IS TRUE DESC, m.user_id IS NOT NULL DESC, g.user_id IS NOT NULL DESC,
+                     b.created_at
+            LIMIT 1
+            "#,
+            name,
+            user_id,
+            organization
+        )
+            .fetch_optional(pool)
+            .await?;
+
+        Ok(bucket)
+    }
+
+    // Look up a bucket owned by the user or by an organization they are an
+    // owner or admin of, ignoring buckets they can only use. Names may be
+    // qualified as in `find_by_name_and_user`.
+    pub async fn find_owned_by_name(
+        pool: &PgPool,
+        name: &str,
+        user_id: Uuid,
+    ) -> Result<Option<Self>, sqlx::Error> {
+        let (organization, name) = Self::split_qualified_name(name);
         let bucket = sqlx::query_as!(
             Bucket,
             r#"
-            SELECT id, name, user_id, created_at
-            FROM buckets
-            WHERE name = $1 AND user_id = $2
+            SELECT b.id, b.name, b.user_id, b.organization_id, b.versioning, b.acl, b.policy, b.created_at,
+                   o.name AS "organization?",
+                   'owner' AS "access!"
+            FROM buckets b
+            LEFT JOIN organization_members m ON m.organization_id = b.organization_id AND m.user_id = $2
+            LEFT JOIN organizations o ON o.id = b.organization_id
+            WHERE b.name = $1 AND (b.user_id = $2 OR m.role IN ('owner', 'admin'))
+              AND ($3::text IS NULL OR o.name = $3)
+            ORDER BY (b.user_id = $2) IS TRUE DESC, b.created_at
+            LIMIT 1
             "#,
             name,
+            user_id,
+            organization
+        )
+            .fetch_optional(pool)
+            .await?;
+
+        Ok(bucket)
+    }
+
+    // Look up a public bucket for a request without credentials. Names may
+    // be qualified as in `find_by_name_and_user`.
+    pub async fn find_public_by_name(pool: &PgPool, name: &str) -> Result<Option<Self>, sqlx::Error> {
+        let (organization, name) = Self::split_qualified_name(name);
+        let bucket = sqlx::query_as!(
+            Bucket,
+            r#"
+            SELECT b.id, b.name, b.user_id, b.organization_id, b.versioning, b.acl, b.policy, b.created_at,
+                   o.name AS "organization?",
+                   'public-read' AS "access!"
+            FROM buckets b
+            LEFT JOIN organizations o ON o.id = b.organization_id
+            WHERE b.name = $1 AND b.acl = 'public-read'
+              AND ($2::text IS NULL OR o.name = $2)
+            ORDER BY b.created_at
+            LIMIT 1
+            "#,
+            name,
+            organization
+        )
+            .fetch_optional(pool)
+            .await?;
+
+        Ok(bucket)
+    }
+
+    // Look up a bucket by ID if the user owns it, belongs to its organization
+    // or it is shared with them
+    pub async fn find_by_id_and_user(
+        pool: &PgPool,
+        id: Uuid,
+        user_id: Uuid,
+    ) -> Result<Option<Self>, sqlx::Error> {
+        let bucket = sqlx::query_as!(
+            Bucket,
+            r#"
+            SELECT b.id, b.name, b.user_id, b.organization_id, b.versioning, b.acl, b.policy, b.created_at,
+                   o.name AS "organization?",
+                   CASE WHEN b.user_id = $2 THEN 'owner'
+                        WHEN m.role IN ('owner', 'admin') THEN 'owner'
+                        WHEN m.role = 'member' THEN 'read-write'
+                        WHEN m.role = 'read-only' THEN 'read'
+                        ELSE g.permission
+                   END AS "access!"
+            FROM buckets b
+            LEFT JOIN organization_members m ON m.organization_id = b.organization_id AND m.user_id = $2
+            LEFT JOIN bucket_grants g ON g.bucket_id = b.id AND g.user_id = $2
+            LEFT JOIN organizations o ON o.id = b.organization_id
+            WHERE b.id = $1 AND (b.user_id = $2 OR m.user_id IS NOT NULL OR g.user_id IS NOT NULL)
+            "#,
+            id,
             user_id
         )
             .fetch_optional(pool)
@@ -66,7 +496,8 @@ impl Bucket {
         let buckets = sqlx::query_as!(
         Bucket,
         r#"
-        SELECT id, name, user_id, created_at
+        SELECT id, name, user_id, organization_id, versioning, acl, policy, created_at,
+               NULL::text AS "organization?", 'owner' AS "access!"
         FROM buckets
         WHERE user_id = $1
         ORDER BY created_at DESC
@@ -78,4 +509,248 @@ impl Bucket {
 
         Ok(buckets)
     }
-}
\ No newline at end of file
+
+    // Buckets of others shared with the user through a grant
+    pub async fn find_shared_with_user(
+        pool: &PgPool,
+        user_id: Uuid,
+    ) -> Result<Vec<Self>, sqlx::Error> {
+        let buckets = sqlx::query_as!(
+            Bucket,
+            r#"
+            SELECT b.id, b.name, b.user_id, b.organization_id, b.versioning, b.acl, b.policy, b.created_at,
+                   o.name AS "organization?",
+                   g.permission AS "access!"
+            FROM buckets b
+            JOIN bucket_grants g ON g.bucket_id = b.id
+            LEFT JOIN organizations o ON o.id = b.organization_id
+            WHERE g.user_id = $1 AND b.user_id IS DISTINCT FROM $1
+            ORDER BY g.created_at DESC
+            "#,
+            user_id
+        )
+            .fetch_all(pool)
+            .await?;
+
+        Ok(buckets)
+    }
+
+    // Buckets of an organization, as seen by a member with `role`
+    pub async fn find_by_organization_id(
+        pool: &PgPool,
+        organization_id: Uuid,
+        role: OrgRole,
+    ) -> Result<Vec<Self>, sqlx::Error> {
+        let buckets = sqlx::query_as!(
+            Bucket,
+            r#"
+            SELECT b.id, b.name, b.user_id, b.organization_id, b.versioning, b.acl, b.policy, b.created_at,
+                   o.name AS "organization?", $2::text AS "access!"
+            FROM buckets b
+            JOIN organizations o ON o.id = b.organization_id
+            WHERE b.organization_id = $1
+            ORDER BY b.created_at DESC
+            "#,
+            organization_id,
+            role.bucket_access().as_str()
+        )
+            .fetch_all(pool)
+            .await?;
+
+        Ok(buckets)
+    }
+
+    // Delete the bucket only if it holds no files or in-progress multipart
+    // uploads. Returns false when either is still present, e.g. because an
+    // upload raced with the delete.
+    pub async fn delete_if_empty(&self, pool: &PgPool) -> Result<bool, sqlx::Error> {
+        let result = sqlx::query!(
+            r#"
+            DELETE FROM buckets
+            WHERE id = $1
+              AND NOT EXISTS (SELECT 1 FROM files WHERE bucket_id = $1)
+              AND NOT EXISTS (SELECT 1 FROM multipart_uploads WHERE bucket_id = $1)
+            "#,
+            self.id
+        )
+            .execute(pool)
+            .await?;
+
+        Ok(result.rows_affected() > 0)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use serde_json::json;
+    use sqlx::Executor;
+    use std::ops::RangeInclusive;
+
+    #[test]
+    fn access_levels() {
+        for action in PolicyAction::ALL {
+            assert!(BucketAccess::Owner.allows(*action));
+        }
+
+        assert!(BucketAccess::ReadWrite.allows(PolicyAction::PutObject));
+        assert!(BucketAccess::ReadWrite.allows(PolicyAction::AbortMultipartUpload));
+        assert!(!BucketAccess::ReadWrite.allows(PolicyAction::DeleteBucket));
+        assert!(!BucketAccess::ReadWrite.allows(PolicyAction::PutBucketVersioning));
+
+        assert!(BucketAccess::Read.allows(PolicyAction::GetObject));
+        assert!(BucketAccess::Read.allows(PolicyAction::ListBucket));
+        assert!(!BucketAccess::Read.allows(PolicyAction::PutObject));
+        assert!(!BucketAccess::Read.allows(PolicyAction::DeleteObjectTagging));
+
+        assert!(BucketAccess::PublicRead.allows(PolicyAction::GetObject));
+        assert!(!BucketAccess::PublicRead.allows(PolicyAction::ListBucket));
+        assert!(!BucketAccess::PublicRead.allows(PolicyAction::GetObjectTagging));
+    }
+
+    #[test]
+    fn names() {
+        for name in ["abc", "my-bucket", "logs.example.com", "2024-backups", &"a".repeat(63)] {
+            assert_eq!(Bucket::validate_name(name), Ok(()), "{}", name);
+        }
+
+        assert_eq!(Bucket::validate_name("ab"), Err(BucketNameError::Length));
+        assert_eq!(Bucket::validate_name(&"a".repeat(64)), Err(BucketNameError::Length));
+        assert_eq!(Bucket::validate_name("My Bucket/.."), Err(BucketNameError::Character('M')));
+        assert_eq!(Bucket::validate_name("my_bucket"), Err(BucketNameError::Character('_')));
+        assert_eq!(Bucket::validate_name("-bucket"), Err(BucketNameError::Boundary));
+        assert_eq!(Bucket::validate_name("bucket."), Err(BucketNameError::Boundary));
+        assert_eq!(Bucket::validate_name("my..bucket"), Err(BucketNameError::AdjacentDots));
+        assert_eq!(Bucket::validate_name("192.168.5.4"), Err(BucketNameError::IpAddress));
+        assert_eq!(Bucket::validate_name("192.168.5.4.5"), Ok(()));
+        assert_eq!(Bucket::validate_name("xn--bucket"), Err(BucketNameError::ReservedPrefix("xn--")));
+        assert_eq!(Bucket::validate_name("data-s3alias"), Err(BucketNameError::ReservedSuffix("-s3alias")));
+        assert_eq!(
+            Bucket::validate_name("My Bucket").unwrap_err().to_string(),
+            "Bucket name can only contain lowercase letters, digits, dots and hyphens, not 'M'"
+        );
+        assert_eq!(Bucket::validate_name("acme:logs"), Err(BucketNameError::Character(':')));
+    }
+
+    #[test]
+    fn qualified_names() {
+        assert_eq!(Bucket::split_qualified_name("logs"), (None, "logs"));
+        assert_eq!(Bucket::split_qualified_name("acme:logs"), (Some("acme"), "logs"));
+
+        assert_eq!(Bucket::new("logs".to_string(), Uuid::new_v4()).qualified_name(), "logs");
+        let organization = Organization::new("acme".to_string());
+        assert_eq!(Bucket::new_in_organization("logs".to_string(), &organization).qualified_name(), "acme:logs");
+    }
+
+    #[test]
+    fn unknown_access_falls_back_to_the_least() {
+        let mut bucket = Bucket::new("logs".to_string(), Uuid::new_v4());
+        assert_eq!(bucket.access(), BucketAccess::Owner);
+
+        bucket.access = "admin".to_string();
+        assert_eq!(bucket.access(), BucketAccess::PublicRead);
+    }
+
+    #[sqlx::test]
+    async fn public_buckets_by_qualified_name(pool: PgPool) {
+        sqlx::Executor::execute(
+            &pool,
+            r#"
+            INSERT INTO users (id, email, created_at)
+            VALUES ('00000000-0000-0000-0000-000000000001', 'a@example.com', NOW());
+            INSERT INTO organizations (id, name, created_at)
+            VALUES ('30000000-0000-0000-0000-000000000001', 'acme', NOW());
+            INSERT INTO buckets (id, name, user_id, organization_id, acl, created_at) VALUES
+                ('10000000-0000-0000-0000-000000000001', 'logs', '00000000-0000-0000-0000-000000000001', NULL,
+                 'public-read', NOW() - INTERVAL '1 day'),
+                ('10000000-0000-0000-0000-000000000002', 'logs', NULL, '30000000-0000-0000-0000-000000000001',
+                 'public-read', NOW());
+            "#,
+        )
+            .await
+            .unwrap();
+
+        let find = |name| Bucket::find_public_by_name(&pool, name);
+        let organization_bucket = find("acme:logs").await.unwrap().unwrap();
+        assert_eq!(organization_bucket.organization_id, Some(Uuid::parse_str("30000000-0000-0000-0000-000000000001").unwrap()));
+        assert_eq!(organization_bucket.access(), BucketAccess::PublicRead);
+        assert!(find("logs").await.unwrap().unwrap().user_id.is_some());
+        assert!(find("other:logs").await.unwrap().is_none());
+    }
+
+    // Apply the migrations with these versions, leaving the others out
+    async fn migrate(pool: &PgPool, versions: RangeInclusive<i64>) {
+        let migrator = sqlx::migrate!("./migrations");
+        for migration in migrator.iter().filter(|migration| versions.contains(&migration.version)) {
+            pool.execute(&*migration.sql).await.unwrap();
+        }
+    }
+
+    #[sqlx::test(migrations = false)]
+    async fn renaming_buckets_rewrites_policies_and_key_scopes(pool: PgPool) {
+        migrate(&pool, 1..=17).await;
+        pool.execute(
+            r#"
+            INSERT INTO users (id, email, created_at)
+            VALUES ('00000000-0000-0000-0000-000000000001', 'a@example.com', NOW());
+            INSERT INTO buckets (id, name, user_id, created_at, policy)
+            VALUES ('10000000-0000-0000-0000-000000000001', 'My_Bucket', '00000000-0000-0000-0000-000000000001', NOW(),
+                    '{"Statement": [
+                        {"Effect": "Deny", "Principal": "*", "Action": "s3:DeleteObject",
+                         "Resource": ["arn:aws:s3:::My_Bucket/*", "arn:aws:s3:::My_*"]},
+                        {"Effect": "Allow", "Principal": "*", "Action": "s3:GetObject",
+                         "Resource": "arn:aws:s3:::My_Bucket/public/*"}
+                    ]}');
+            INSERT INTO api_keys (id, user_id, name, prefix, key_hash, created_at, scope)
+            VALUES ('20000000-0000-0000-0000-000000000001', '00000000-0000-0000-0000-000000000001', 'ci', 'ci', 'hash',
+                    NOW(), '{"buckets": ["My_Bucket", "other"], "actions": ["read"]}');
+            "#,
+        )
+            .await
+            .unwrap();
+
+        migrate(&pool, 18..=18).await;
+
+        let (name, policy): (String, Value) = sqlx::query_as("SELECT name, policy FROM buckets")
+            .fetch_one(&pool)
+            .await
+            .unwrap();
+        assert_eq!(name, "my-bucket-10000000");
+        assert_eq!(
+            policy["Statement"][0]["Resource"],
+            json!(["arn:aws:s3:::my-bucket-10000000/*", "arn:aws:s3:::my-bucket-10000000"])
+        );
+        assert_eq!(policy["Statement"][1]["Resource"], json!("arn:aws:s3:::my-bucket-10000000/public/*"));
+        assert!(crate::models::Policy::parse(&policy, &name).is_ok());
+
+        let scope: Value = sqlx::query_scalar("SELECT scope FROM api_keys")
+            .fetch_one(&pool)
+            .await
+            .unwrap();
+        assert_eq!(scope["buckets"], json!(["my-bucket-10000000", "other"]));
+    }
+
+    #[sqlx::test(migrations = false)]
+    async fn renaming_buckets_avoids_taken_names(pool: PgPool) {
+        migrate(&pool, 1..=17).await;
+        pool.execute(
+            r#"
+            INSERT INTO users (id, email, created_at)
+            VALUES ('00000000-0000-0000-0000-000000000001', 'a@example.com', NOW());
+            INSERT INTO buckets (id, name, user_id, created_at)
+            VALUES ('30000000-0000-0000-0000-000000000001', 'Logs', '00000000-0000-0000-0000-000000000001', NOW()),
+                   ('40000000-0000-0000-0000-000000000001', 'logs-30000000', '00000000-0000-0000-0000-000000000001', NOW());
+            "#,
+        )
+            .await
+            .unwrap();
+
+        migrate(&pool, 18..=18).await;
+
+        let name: String = sqlx::query_scalar("SELECT name FROM buckets WHERE id = '30000000-0000-0000-0000-000000000001'")
+            .fetch_one(&pool)
+            .await
+            .unwrap();
+        assert_eq!(name, "logs-30000000000000000000000000000001");
+    }
+}
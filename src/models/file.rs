@@ -1,8 +1,51 @@
 use chrono::{DateTime, Utc};
 use serde::{Deserialize, Serialize};
-use sqlx::{FromRow, PgPool};
+use sqlx::types::Json;
+use sqlx::{FromRow, PgConnection, PgPool};
+use std::collections::BTreeMap;
 use uuid::Uuid;
 
+use super::VersioningStatus;
+
+// Version ID of objects written while versioning is not enabled
+pub const NULL_VERSION_ID: &str = "null";
+
+// User-defined metadata and content headers stored with an object and
+// replayed when it is downloaded
+#[derive(Debug, Clone, Default, PartialEq, Eq, Serialize, Deserialize)]
+pub struct ObjectMetadata {
+    // `x-amz-meta-*` pairs, keyed by the name after the prefix
+    #[serde(default, skip_serializing_if = "BTreeMap::is_empty")]
+    pub user: BTreeMap<String, String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub cache_control: Option<String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub content_encoding: Option<String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub content_language: Option<String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub content_disposition: Option<String>,
+}
+
+impl ObjectMetadata {
+    // Bytes counted against the metadata size limit: every user key and
+    // value plus the content header values
+    pub fn size(&self) -> usize {
+        let user: usize = self.user.iter().map(|(key, value)| key.len() + value.len()).sum();
+        let headers: usize = [
+            &self.cache_control,
+            &self.content_encoding,
+            &self.content_language,
+            &self.content_disposition,
+        ]
+        .iter()
+        .filter_map(|value| value.as_ref().map(String::len))
+        .sum();
+
+        user + headers
+    }
+}
+
 #[derive(Debug, Serialize, Deserialize, FromRow)]
 pub struct File {
     pub id: Uuid,
@@ -11,6 +54,13 @@ pub struct File {
     pub size: i64,
     pub bucket_id: Uuid,
     pub storage_path: String,
+    pub version_id: String,
+    pub is_latest: bool,
+    // Delete markers have no content and an empty storage path
+    pub is_delete_marker: bool,
+    // SHA-256 of the content, served as the ETag
+    pub etag: Option<String>,
+    pub metadata: Json<ObjectMetadata>,
     pub created_at: DateTime<Utc>,
 }
 
@@ -21,6 +71,7 @@ impl File {
         size: i64,
         bucket_id: Uuid,
         storage_path: String,
+        etag: String,
     ) -> Self {
         Self {
             id: Uuid::new_v4(),
@@ -29,15 +80,124 @@ impl File {
             size,
             bucket_id,
             storage_path,
+            version_id: NULL_VERSION_ID.to_string(),
+            is_latest: true,
+            is_delete_marker: false,
+            etag: Some(etag),
+            metadata: Json(ObjectMetadata::default()),
             created_at: Utc::now(),
         }
     }
 
-    pub async fn create(&self, pool: &PgPool) -> Result<(), sqlx::Error> {
+    pub fn delete_marker(filename: String, bucket_id: Uuid) -> Self {
+        Self {
+            is_delete_marker: true,
+            etag: None,
+            ..Self::new(filename, None, 0, bucket_id, String::new(), String::new())
+        }
+    }
+
+    // Serialize writers of one key until the end of the transaction
+    async fn lock_key(conn: &mut PgConnection, bucket_id: Uuid, filename: &str) -> Result<(), sqlx::Error> {
         sqlx::query!(
             r#"
-            INSERT INTO files (id, filename, content_type, size, bucket_id, storage_path, created_at)
-            VALUES ($1, $2, $3, $4, $5, $6, $7)
+            SELECT 1 AS locked
+            FROM pg_advisory_xact_lock(hashtextextended($1::uuid::text || '/' || $2, 0))
+            "#,
+            bucket_id,
+            filename
+        )
+            .fetch_one(conn)
+            .await?;
+
+        Ok(())
+    }
+
+    // Lock several keys, always in the same order so that two writers
+    // locking the same keys cannot deadlock
+    pub async fn lock_keys(conn: &mut PgConnection, keys: &mut [(Uuid, &str)]) -> Result<(), sqlx::Error> {
+        keys.sort_unstable();
+        for (bucket_id, filename) in keys.iter() {
+            Self::lock_key(conn, *bucket_id, filename).await?;
+        }
+
+        Ok(())
+    }
+
+    // Latest version of a key, unless it has been deleted, with the key
+    // locked against other writers until the end of the transaction
+    pub async fn lock_latest(
+        conn: &mut PgConnection,
+        bucket_id: Uuid,
+        filename: &str,
+    ) -> Result<Option<Self>, sqlx::Error> {
+        Self::lock_key(conn, bucket_id, filename).await?;
+
+        let file = sqlx::query_as!(
+            File,
+            r#"
+            SELECT id, filename, content_type, size, bucket_id, storage_path,
+                   version_id, is_latest, is_delete_marker, etag,
+                   metadata AS "metadata: Json<ObjectMetadata>", created_at
+            FROM files
+            WHERE filename = $1 AND bucket_id = $2 AND is_latest AND NOT is_delete_marker
+            "#,
+            filename,
+            bucket_id
+        )
+            .fetch_optional(conn)
+            .await?;
+
+        Ok(file)
+    }
+
+    // Store this file as the latest version of its key. With versioning
+    // enabled it gets its own version ID; otherwise it replaces the key's
+    // "null" version. Returns the storage path of a replaced version so its
+    // content can be removed. Must run inside a transaction.
+    pub async fn put_version(
+        &mut self,
+        conn: &mut PgConnection,
+        versioning: Option<VersioningStatus>,
+    ) -> Result<Option<String>, sqlx::Error> {
+        Self::lock_key(conn, self.bucket_id, &self.filename).await?;
+
+        self.version_id = match versioning {
+            Some(VersioningStatus::Enabled) => self.id.simple().to_string(),
+            _ => NULL_VERSION_ID.to_string(),
+        };
+        self.is_latest = true;
+
+        sqlx::query!(
+            r#"
+            UPDATE files
+            SET is_latest = FALSE
+            WHERE bucket_id = $1 AND filename = $2 AND is_latest
+            "#,
+            self.bucket_id,
+            self.filename
+        )
+            .execute(&mut *conn)
+            .await?;
+
+        let replaced = sqlx::query_scalar!(
+            r#"
+            DELETE FROM files
+            WHERE bucket_id = $1 AND filename = $2 AND version_id = $3
+            RETURNING storage_path
+            "#,
+            self.bucket_id,
+            self.filename,
+            self.version_id
+        )
+            .fetch_optional(&mut *conn)
+            .await?;
+
+        sqlx::query!(
+            r#"
+            INSERT INTO files (id, filename, content_type, size, bucket_id, storage_path,
+                               version_id, is_latest, is_delete_marker, etag, metadata, created_at)
+            VALUES ($1, $2, $3, $4, $5, $6, $7, $8, $9, $10, $11, $12)
             "#,
             self.id,
             self.filename,
@@ -45,14 +205,120 @@ impl File {
             self.size,
             self.bucket_id,
             self.storage_path,
+            self.version_id,
+            self.is_latest,
+            self.is_delete_marker,
+            self.etag,
+            &self.metadata as _,
             self.created_at
         )
-            .execute(pool)
+            .execute(&mut *conn)
+            .await?;
+
+        // A replaced delete marker has no content
+        Ok(replaced.filter(|path| !path.is_empty()))
+    }
+
+    // Move this version to another key, possibly in another bucket, keeping
+    // its row and content. It becomes the latest version of the destination
+    // key like a new write would, and the newest remaining version of the
+    // source key takes its place. Returns the storage path of a replaced
+    // destination version. Must run inside a transaction.
+    pub async fn rename(
+        &mut self,
+        conn: &mut PgConnection,
+        bucket_id: Uuid,
+        filename: String,
+        versioning: Option<VersioningStatus>,
+    ) -> Result<Option<String>, sqlx::Error> {
+        Self::lock_keys(conn, &mut [(self.bucket_id, &self.filename), (bucket_id, &filename)]).await?;
+
+        let version_id = match versioning {
+            Some(VersioningStatus::Enabled) => self.id.simple().to_string(),
+            _ => NULL_VERSION_ID.to_string(),
+        };
+
+        sqlx::query!(
+            r#"
+            UPDATE files
+            SET is_latest = FALSE
+            WHERE bucket_id = $1 AND filename = $2 AND is_latest
+            "#,
+            bucket_id,
+            filename
+        )
+            .execute(&mut *conn)
+            .await?;
+
+        let replaced = sqlx::query_scalar!(
+            r#"
+            DELETE FROM files
+            WHERE bucket_id = $1 AND filename = $2 AND version_id = $3
+            RETURNING storage_path
+            "#,
+            bucket_id,
+            filename,
+            version_id
+        )
+            .fetch_optional(&mut *conn)
+            .await?;
+
+        // A move counts as a new write, so it is the newest version of the
+        // destination key
+        let created_at = Utc::now();
+        sqlx::query!(
+            r#"
+            UPDATE files
+            SET bucket_id = $2, filename = $3, version_id = $4, is_latest = TRUE, created_at = $5
+            WHERE id = $1
+            "#,
+            self.id,
+            bucket_id,
+            filename,
+            version_id,
+            created_at
+        )
+            .execute(&mut *conn)
+            .await?;
+
+        if self.is_latest {
+            Self::promote_latest(conn, self.bucket_id, &self.filename).await?;
+        }
+
+        self.bucket_id = bucket_id;
+        self.filename = filename;
+        self.version_id = version_id;
+        self.is_latest = true;
+        self.created_at = created_at;
+
+        // A replaced delete marker has no content
+        Ok(replaced.filter(|path| !path.is_empty()))
+    }
+
+    // Mark the newest version of a key as its latest
+    async fn promote_latest(conn: &mut PgConnection, bucket_id: Uuid, filename: &str) -> Result<(), sqlx::Error> {
+        sqlx::query!(
+            r#"
+            UPDATE files
+            SET is_latest = TRUE
+            WHERE id = (
+                SELECT id
+                FROM files
+                WHERE bucket_id = $1 AND filename = $2
+                ORDER BY created_at DESC
+                LIMIT 1
+            )
+            "#,
+            bucket_id,
+            filename
+        )
+            .execute(conn)
             .await?;
 
         Ok(())
     }
 
+    // Latest version of a key, unless it has been deleted
     pub async fn find_by_filename_and_bucket(
         pool: &PgPool,
         filename: &str,
@@ -61,9 +327,11 @@ impl File {
         let file = sqlx::query_as!(
             File,
             r#"
-            SELECT id, filename, content_type, size, bucket_id, storage_path, created_at
+            SELECT id, filename, content_type, size, bucket_id, storage_path,
+                   version_id, is_latest, is_delete_marker, etag,
+                   metadata AS "metadata: Json<ObjectMetadata>", created_at
             FROM files
-            WHERE filename = $1 AND bucket_id = $2
+            WHERE filename = $1 AND bucket_id = $2 AND is_latest AND NOT is_delete_marker
             "#,
             filename,
             bucket_id
@@ -74,23 +342,127 @@ impl File {
         Ok(file)
     }
 
-    pub async fn find_by_bucket_id(
+    // A specific version of a key, which may be a delete marker
+    pub async fn find_version(
         pool: &PgPool,
+        filename: &str,
         bucket_id: Uuid,
+        version_id: &str,
+    ) -> Result<Option<Self>, sqlx::Error> {
+        let file = sqlx::query_as!(
+            File,
+            r#"
+            SELECT id, filename, content_type, size, bucket_id, storage_path,
+                   version_id, is_latest, is_delete_marker, etag,
+                   metadata AS "metadata: Json<ObjectMetadata>", created_at
+            FROM files
+            WHERE filename = $1 AND bucket_id = $2 AND version_id = $3
+            "#,
+            filename,
+            bucket_id,
+            version_id
+        )
+            .fetch_optional(pool)
+            .await?;
+
+        Ok(file)
+    }
+
+    // Current objects in the bucket in byte order of their keys, starting at
+    // `start` (inclusive) and stopping before `end` when one is given. With
+    // `tag`, only objects with that tag key, and value if given, are included.
+    pub async fn find_page(
+        pool: &PgPool,
+        bucket_id: Uuid,
+        start: &str,
+        end: Option<&str>,
+        tag: Option<(&str, Option<&str>)>,
+        limit: i64,
+    ) -> Result<Vec<Self>, sqlx::Error> {
+        let (tag_key, tag_value) = match tag {
+            Some((key, value)) => (Some(key), value),
+            None => (None, None),
+        };
+
+        let files = sqlx::query_as!(
+            File,
+            r#"
+            SELECT id, filename, content_type, size, bucket_id, storage_path,
+                   version_id, is_latest, is_delete_marker, etag,
+                   metadata AS "metadata: Json<ObjectMetadata>", created_at
+            FROM files
+            WHERE bucket_id = $1
+              AND filename COLLATE "C" >= $2
+              AND ($3::text IS NULL OR filename COLLATE "C" < $3)
+              AND is_latest AND NOT is_delete_marker
+              AND ($5::text IS NULL OR EXISTS (
+                  SELECT 1
+                  FROM file_tags t
+                  WHERE t.file_id = files.id AND t.key = $5 AND ($6::text IS NULL OR t.value = $6)
+              ))
+            ORDER BY filename COLLATE "C"
+            LIMIT $4
+            "#,
+            bucket_id,
+            start,
+            end,
+            limit,
+            tag_key,
+            tag_value
+        )
+            .fetch_all(pool)
+            .await?;
+
+        Ok(files)
+    }
+
+    // Every version and delete marker in the bucket, optionally for one key,
+    // newest first within each key
+    pub async fn find_versions(
+        pool: &PgPool,
+        bucket_id: Uuid,
+        filename: Option<&str>,
     ) -> Result<Vec<Self>, sqlx::Error> {
         let files = sqlx::query_as!(
-        File,
-        r#"
-        SELECT id, filename, content_type, size, bucket_id, storage_path, created_at
-        FROM files
-        WHERE bucket_id = $1
-        ORDER BY created_at DESC
-        "#,
-        bucket_id
-    )
+            File,
+            r#"
+            SELECT id, filename, content_type, size, bucket_id, storage_path,
+                   version_id, is_latest, is_delete_marker, etag,
+                   metadata AS "metadata: Json<ObjectMetadata>", created_at
+            FROM files
+            WHERE bucket_id = $1 AND ($2::text IS NULL OR filename = $2)
+            ORDER BY filename, created_at DESC
+            "#,
+            bucket_id,
+            filename
+        )
             .fetch_all(pool)
             .await?;
 
         Ok(files)
     }
-}
\ No newline at end of file
+
+    // Permanently delete this version. If it was the latest, the newest
+    // remaining version of the key takes its place. Must run inside a
+    // transaction.
+    pub async fn delete(&self, conn: &mut PgConnection) -> Result<(), sqlx::Error> {
+        Self::lock_key(conn, self.bucket_id, &self.filename).await?;
+
+        let was_latest = sqlx::query_scalar!(
+            r#"
+            DELETE FROM files
+            WHERE id = $1
+            RETURNING is_latest
+            "#,
+            self.id
+        )
+            .fetch_optional(&mut *conn)
+            .await?;
+
+        if was_latest == Some(true) {
+            Self::promote_latest(conn, self.bucket_id, &self.filename).await?;
+        }
+
+        Ok(())
+    }
+}
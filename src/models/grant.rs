@@ -0,0 +1,94 @@
+use chrono::{DateTime, Utc};
+use serde::{Deserialize, Serialize};
+use sqlx::{FromRow, PgPool};
+use uuid::Uuid;
+
+// What a grant lets another user do in a bucket
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "kebab-case")]
+pub enum GrantPermission {
+    Read,
+    ReadWrite,
+}
+
+impl GrantPermission {
+    // Stored values match those of `BucketAccess`, which a lookup through
+    // a grant takes as the user's access
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            GrantPermission::Read => "read",
+            GrantPermission::ReadWrite => "read-write",
+        }
+    }
+}
+
+// A bucket shared by its owner with another user
+#[derive(Debug, Serialize, Deserialize, FromRow)]
+pub struct BucketGrant {
+    pub bucket_id: Uuid,
+    pub user_id: Uuid,
+    // Email of the user the bucket is shared with
+    pub email: String,
+    pub permission: String,
+    pub created_at: DateTime<Utc>,
+}
+
+impl BucketGrant {
+    // Share the bucket with the user, replacing the permission of an
+    // existing grant
+    pub async fn upsert(
+        pool: &PgPool,
+        bucket_id: Uuid,
+        user_id: Uuid,
+        permission: GrantPermission,
+    ) -> Result<(), sqlx::Error> {
+        sqlx::query!(
+            r#"
+            INSERT INTO bucket_grants (bucket_id, user_id, permission, created_at)
+            VALUES ($1, $2, $3, NOW())
+            ON CONFLICT (bucket_id, user_id) DO UPDATE SET permission = EXCLUDED.permission
+            "#,
+            bucket_id,
+            user_id,
+            permission.as_str()
+        )
+            .execute(pool)
+            .await?;
+
+        Ok(())
+    }
+
+    // Returns false if the bucket was not shared with the user
+    pub async fn delete(pool: &PgPool, bucket_id: Uuid, user_id: Uuid) -> Result<bool, sqlx::Error> {
+        let result = sqlx::query!(
+            r#"
+            DELETE FROM bucket_grants
+            WHERE bucket_id = $1 AND user_id = $2
+            "#,
+            bucket_id,
+            user_id
+        )
+            .execute(pool)
+            .await?;
+
+        Ok(result.rows_affected() > 0)
+    }
+
+    pub async fn find_by_bucket_id(pool: &PgPool, bucket_id: Uuid) -> Result<Vec<Self>, sqlx::Error> {
+        let grants = sqlx::query_as!(
+            BucketGrant,
+            r#"
+            SELECT g.bucket_id, g.user_id, u.email, g.permission, g.created_at
+            FROM bucket_grants g
+            JOIN users u ON u.id = g.user_id
+            WHERE g.bucket_id = $1
+            ORDER BY g.created_at
+            "#,
+            bucket_id
+        )
+            .fetch_all(pool)
+            .await?;
+
+        Ok(grants)
+    }
+}
@@ -1,7 +1,23 @@
 pub mod user;
+pub mod api_key;
 pub mod bucket;
 pub mod file;
+pub mod grant;
+pub mod multipart;
+pub mod organization;
+pub mod policy;
+pub mod scope;
+pub mod tag;
+pub mod token;
 
 pub use user::User;
-pub use bucket::Bucket;
-pub use file::File;
\ No newline at end of file
+pub use api_key::ApiKey;
+pub use bucket::{Bucket, BucketAccess, BucketAcl, CreateBucketError, VersioningStatus};
+pub use file::{File, ObjectMetadata};
+pub use grant::{BucketGrant, GrantPermission};
+pub use multipart::{MultipartUpload, UploadPart};
+pub use organization::{Membership, OrgRole, Organization, OrganizationMember};
+pub use policy::{Policy, PolicyAction};
+pub use scope::{Action, Scope};
+pub use tag::{TagSet, TagTarget};
+pub use token::{RefreshToken, RevokedToken};
\ No newline at end of file
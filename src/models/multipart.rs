@@ -0,0 +1,244 @@
+use chrono::{DateTime, Utc};
+use serde::{Deserialize, Serialize};
+use sqlx::types::Json;
+use sqlx::{FromRow, PgExecutor, PgPool};
+use uuid::Uuid;
+
+use super::file::ObjectMetadata;
+
+#[derive(Debug, Serialize, Deserialize, FromRow)]
+pub struct MultipartUpload {
+    pub id: Uuid,
+    pub bucket_id: Uuid,
+    pub filename: String,
+    pub content_type: Option<String>,
+    // Applied to the file once the upload completes
+    pub metadata: Json<ObjectMetadata>,
+    pub created_at: DateTime<Utc>,
+}
+
+#[derive(Debug, Serialize, Deserialize, FromRow)]
+pub struct UploadPart {
+    pub upload_id: Uuid,
+    pub part_number: i32,
+    pub size: i64,
+    pub etag: String,
+    pub storage_path: String,
+    pub created_at: DateTime<Utc>,
+}
+
+impl MultipartUpload {
+    pub fn new(
+        bucket_id: Uuid,
+        filename: String,
+        content_type: Option<String>,
+        metadata: ObjectMetadata,
+    ) -> Self {
+        Self {
+            id: Uuid::new_v4(),
+            bucket_id,
+            filename,
+            content_type,
+            metadata: Json(metadata),
+            created_at: Utc::now(),
+        }
+    }
+
+    pub async fn create(&self, pool: &PgPool) -> Result<(), sqlx::Error> {
+        sqlx::query!(
+            r#"
+            INSERT INTO multipart_uploads (id, bucket_id, filename, content_type, metadata, created_at)
+            VALUES ($1, $2, $3, $4, $5, $6)
+            "#,
+            self.id,
+            self.bucket_id,
+            self.filename,
+            self.content_type,
+            &self.metadata as _,
+            self.created_at
+        )
+            .execute(pool)
+            .await?;
+
+        Ok(())
+    }
+
+    // Find an upload, but only if the given user can access its bucket as
+    // its owner, a member of its organization or through a grant
+    pub async fn find_by_id_and_user(
+        pool: &PgPool,
+        id: Uuid,
+        user_id: Uuid,
+    ) -> Result<Option<Self>, sqlx::Error> {
+        let upload = sqlx::query_as!(
+            MultipartUpload,
+            r#"
+            SELECT u.id, u.bucket_id, u.filename, u.content_type,
+                   u.metadata AS "metadata: Json<ObjectMetadata>", u.created_at
+            FROM multipart_uploads u
+            JOIN buckets b ON b.id = u.bucket_id
+            LEFT JOIN organization_members m ON m.organization_id = b.organization_id AND m.user_id = $2
+            LEFT JOIN bucket_grants g ON g.bucket_id = b.id AND g.user_id = $2
+            WHERE u.id = $1 AND (b.user_id = $2 OR m.user_id IS NOT NULL OR g.user_id IS NOT NULL)
+            "#,
+            id,
+            user_id
+        )
+            .fetch_optional(pool)
+            .await?;
+
+        Ok(upload)
+    }
+
+    pub async fn find_by_bucket_id(
+        pool: &PgPool,
+        bucket_id: Uuid,
+    ) -> Result<Vec<Self>, sqlx::Error> {
+        let uploads = sqlx::query_as!(
+            MultipartUpload,
+            r#"
+            SELECT id, bucket_id, filename, content_type,
+                   metadata AS "metadata: Json<ObjectMetadata>", created_at
+            FROM multipart_uploads
+            WHERE bucket_id = $1
+            ORDER BY filename, created_at
+            "#,
+            bucket_id
+        )
+            .fetch_all(pool)
+            .await?;
+
+        Ok(uploads)
+    }
+
+    // Uploads started before the cutoff that were never completed or aborted
+    pub async fn find_created_before(
+        pool: &PgPool,
+        cutoff: DateTime<Utc>,
+    ) -> Result<Vec<Self>, sqlx::Error> {
+        let uploads = sqlx::query_as!(
+            MultipartUpload,
+            r#"
+            SELECT id, bucket_id, filename, content_type,
+                   metadata AS "metadata: Json<ObjectMetadata>", created_at
+            FROM multipart_uploads
+            WHERE created_at < $1
+            ORDER BY created_at
+            "#,
+            cutoff
+        )
+            .fetch_all(pool)
+            .await?;
+
+        Ok(uploads)
+    }
+
+    // Delete the upload; its parts go with it through the cascade
+    pub async fn delete<'e>(
+        &self,
+        executor: impl PgExecutor<'e>,
+    ) -> Result<(), sqlx::Error> {
+        sqlx::query!(
+            r#"
+            DELETE FROM multipart_uploads
+            WHERE id = $1
+            "#,
+            self.id
+        )
+            .execute(executor)
+            .await?;
+
+        Ok(())
+    }
+}
+
+impl UploadPart {
+    pub fn new(
+        upload_id: Uuid,
+        part_number: i32,
+        size: i64,
+        etag: String,
+        storage_path: String,
+    ) -> Self {
+        Self {
+            upload_id,
+            part_number,
+            size,
+            etag,
+            storage_path,
+            created_at: Utc::now(),
+        }
+    }
+
+    // Record the part, replacing any earlier upload of the same part number.
+    // Returns the storage path of the replaced part so its content can be removed.
+    pub async fn upsert(&self, pool: &PgPool) -> Result<Option<String>, sqlx::Error> {
+        let previous = sqlx::query_scalar!(
+            r#"
+            WITH previous AS (
+                SELECT storage_path
+                FROM upload_parts
+                WHERE upload_id = $1 AND part_number = $2
+            )
+            INSERT INTO upload_parts (upload_id, part_number, size, etag, storage_path, created_at)
+            VALUES ($1, $2, $3, $4, $5, $6)
+            ON CONFLICT (upload_id, part_number) DO UPDATE
+            SET size = EXCLUDED.size,
+                etag = EXCLUDED.etag,
+                storage_path = EXCLUDED.storage_path,
+                created_at = EXCLUDED.created_at
+            RETURNING (SELECT storage_path FROM previous) AS "previous_path?"
+            "#,
+            self.upload_id,
+            self.part_number,
+            self.size,
+            self.etag,
+            self.storage_path,
+            self.created_at
+        )
+            .fetch_one(pool)
+            .await?;
+
+        Ok(previous)
+    }
+
+    pub async fn find_by_upload_id(
+        pool: &PgPool,
+        upload_id: Uuid,
+    ) -> Result<Vec<Self>, sqlx::Error> {
+        let parts = sqlx::query_as!(
+            UploadPart,
+            r#"
+            SELECT upload_id, part_number, size, etag, storage_path, created_at
+            FROM upload_parts
+            WHERE upload_id = $1
+            ORDER BY part_number
+            "#,
+            upload_id
+        )
+            .fetch_all(pool)
+            .await?;
+
+        Ok(parts)
+    }
+
+    // Delete all parts of an upload, returning their storage paths so their
+    // content can be removed once the deletion is committed
+    pub async fn delete_by_upload_id<'e>(
+        executor: impl PgExecutor<'e>,
+        upload_id: Uuid,
+    ) -> Result<Vec<String>, sqlx::Error> {
+        let paths = sqlx::query_scalar!(
+            r#"
+            DELETE FROM upload_parts
+            WHERE upload_id = $1
+            RETURNING storage_path
+            "#,
+            upload_id
+        )
+            .fetch_all(executor)
+            .await?;
+
+        Ok(paths)
+    }
+}
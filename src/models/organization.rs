@@ -0,0 +1,322 @@
+use chrono::{DateTime, Utc};
+use serde::{Deserialize, Serialize};
+use sqlx::{FromRow, PgConnection, PgExecutor};
+use uuid::Uuid;
+
+use super::bucket::BucketAccess;
+
+// What a member may do in an organization and its buckets
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "kebab-case")]
+pub enum OrgRole {
+    Owner,
+    Admin,
+    Member,
+    ReadOnly,
+}
+
+impl OrgRole {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            OrgRole::Owner => "owner",
+            OrgRole::Admin => "admin",
+            OrgRole::Member => "member",
+            OrgRole::ReadOnly => "read-only",
+        }
+    }
+
+    pub fn parse(value: &str) -> Option<Self> {
+        match value {
+            "owner" => Some(OrgRole::Owner),
+            "admin" => Some(OrgRole::Admin),
+            "member" => Some(OrgRole::Member),
+            "read-only" => Some(OrgRole::ReadOnly),
+            _ => None,
+        }
+    }
+
+    // Access to the organization's buckets. Bucket lookups compute the
+    // same in SQL.
+    pub fn bucket_access(&self) -> BucketAccess {
+        match self {
+            OrgRole::Owner | OrgRole::Admin => BucketAccess::Owner,
+            OrgRole::Member => BucketAccess::ReadWrite,
+            OrgRole::ReadOnly => BucketAccess::Read,
+        }
+    }
+
+    // Owners manage everyone; admins manage the members below them, so
+    // they can neither promote themselves nor remove an owner
+    pub fn manages(&self, role: OrgRole) -> bool {
+        match self {
+            OrgRole::Owner => true,
+            OrgRole::Admin => matches!(role, OrgRole::Member | OrgRole::ReadOnly),
+            OrgRole::Member | OrgRole::ReadOnly => false,
+        }
+    }
+}
+
+#[derive(Debug, Serialize, Deserialize, FromRow)]
+pub struct Organization {
+    pub id: Uuid,
+    // Unique across organizations; lowercase letters, digits and hyphens
+    pub name: String,
+    pub created_at: DateTime<Utc>,
+}
+
+// A user's membership, with the user's email for listings
+#[derive(Debug, Serialize, Deserialize, FromRow)]
+pub struct OrganizationMember {
+    pub organization_id: Uuid,
+    pub user_id: Uuid,
+    pub email: String,
+    pub role: String,
+    pub created_at: DateTime<Utc>,
+}
+
+// An organization together with the requesting user's role in it
+#[derive(Debug, Serialize, Deserialize, FromRow)]
+pub struct Membership {
+    pub id: Uuid,
+    pub name: String,
+    pub role: String,
+    pub created_at: DateTime<Utc>,
+}
+
+impl Organization {
+    pub fn new(name: String) -> Self {
+        Self {
+            id: Uuid::new_v4(),
+            name,
+            created_at: Utc::now(),
+        }
+    }
+
+    pub fn is_valid_name(name: &str) -> bool {
+        (1..=63).contains(&name.len())
+            && name.bytes().all(|b| b.is_ascii_lowercase() || b.is_ascii_digit() || b == b'-')
+            && !name.starts_with('-')
+            && !name.ends_with('-')
+    }
+
+    // Insert the organization with `owner_id` as its first owner. Should
+    // run inside a transaction.
+    pub async fn create(&self, conn: &mut PgConnection, owner_id: Uuid) -> Result<(), sqlx::Error> {
+        sqlx::query!(
+            r#"
+            INSERT INTO organizations (id, name, created_at)
+            VALUES ($1, $2, $3)
+            "#,
+            self.id,
+            self.name,
+            self.created_at
+        )
+            .execute(&mut *conn)
+            .await?;
+
+        OrganizationMember::add(&mut *conn, self.id, owner_id, OrgRole::Owner).await?;
+        Ok(())
+    }
+
+    pub async fn find_by_name<'e>(executor: impl PgExecutor<'e>, name: &str) -> Result<Option<Self>, sqlx::Error> {
+        let organization = sqlx::query_as!(
+            Organization,
+            r#"
+            SELECT id, name, created_at
+            FROM organizations
+            WHERE name = $1
+            "#,
+            name
+        )
+            .fetch_optional(executor)
+            .await?;
+
+        Ok(organization)
+    }
+
+    // Organizations the user is a member of, with their role
+    pub async fn find_by_user_id<'e>(executor: impl PgExecutor<'e>, user_id: Uuid) -> Result<Vec<Membership>, sqlx::Error> {
+        let memberships = sqlx::query_as!(
+            Membership,
+            r#"
+            SELECT o.id, o.name, m.role, o.created_at
+            FROM organizations o
+            JOIN organization_members m ON m.organization_id = o.id
+            WHERE m.user_id = $1
+            ORDER BY o.name
+            "#,
+            user_id
+        )
+            .fetch_all(executor)
+            .await?;
+
+        Ok(memberships)
+    }
+
+    // Lock the organization so membership changes apply one after the
+    // other, which keeps the last owner from being removed concurrently
+    pub async fn lock(&self, conn: &mut PgConnection) -> Result<(), sqlx::Error> {
+        sqlx::query!(
+            r#"
+            SELECT id FROM organizations WHERE id = $1 FOR UPDATE
+            "#,
+            self.id
+        )
+            .fetch_optional(conn)
+            .await?;
+
+        Ok(())
+    }
+}
+
+impl OrganizationMember {
+    pub fn role(&self) -> Option<OrgRole> {
+        OrgRole::parse(&self.role)
+    }
+
+    pub async fn add<'e>(
+        executor: impl PgExecutor<'e>,
+        organization_id: Uuid,
+        user_id: Uuid,
+        role: OrgRole,
+    ) -> Result<(), sqlx::Error> {
+        sqlx::query!(
+            r#"
+            INSERT INTO organization_members (organization_id, user_id, role, created_at)
+            VALUES ($1, $2, $3, NOW())
+            "#,
+            organization_id,
+            user_id,
+            role.as_str()
+        )
+            .execute(executor)
+            .await?;
+
+        Ok(())
+    }
+
+    pub async fn find<'e>(
+        executor: impl PgExecutor<'e>,
+        organization_id: Uuid,
+        user_id: Uuid,
+    ) -> Result<Option<Self>, sqlx::Error> {
+        let member = sqlx::query_as!(
+            OrganizationMember,
+            r#"
+            SELECT m.organization_id, m.user_id, u.email, m.role, m.created_at
+            FROM organization_members m
+            JOIN users u ON u.id = m.user_id
+            WHERE m.organization_id = $1 AND m.user_id = $2
+            "#,
+            organization_id,
+            user_id
+        )
+            .fetch_optional(executor)
+            .await?;
+
+        Ok(member)
+    }
+
+    pub async fn find_by_organization_id<'e>(
+        executor: impl PgExecutor<'e>,
+        organization_id: Uuid,
+    ) -> Result<Vec<Self>, sqlx::Error> {
+        let members = sqlx::query_as!(
+            OrganizationMember,
+            r#"
+            SELECT m.organization_id, m.user_id, u.email, m.role, m.created_at
+            FROM organization_members m
+            JOIN users u ON u.id = m.user_id
+            WHERE m.organization_id = $1
+            ORDER BY m.created_at
+            "#,
+            organization_id
+        )
+            .fetch_all(executor)
+            .await?;
+
+        Ok(members)
+    }
+
+    pub async fn count_owners<'e>(executor: impl PgExecutor<'e>, organization_id: Uuid) -> Result<i64, sqlx::Error> {
+        let count = sqlx::query_scalar!(
+            r#"
+            SELECT COUNT(*) AS "count!"
+            FROM organization_members
+            WHERE organization_id = $1 AND role = 'owner'
+            "#,
+            organization_id
+        )
+            .fetch_one(executor)
+            .await?;
+
+        Ok(count)
+    }
+
+    pub async fn set_role<'e>(&mut self, executor: impl PgExecutor<'e>, role: OrgRole) -> Result<(), sqlx::Error> {
+        sqlx::query!(
+            r#"
+            UPDATE organization_members
+            SET role = $1
+            WHERE organization_id = $2 AND user_id = $3
+            "#,
+            role.as_str(),
+            self.organization_id,
+            self.user_id
+        )
+            .execute(executor)
+            .await?;
+
+        self.role = role.as_str().to_string();
+        Ok(())
+    }
+
+    pub async fn remove<'e>(&self, executor: impl PgExecutor<'e>) -> Result<(), sqlx::Error> {
+        sqlx::query!(
+            r#"
+            DELETE FROM organization_members
+            WHERE organization_id = $1 AND user_id = $2
+            "#,
+            self.organization_id,
+            self.user_id
+        )
+            .execute(executor)
+            .await?;
+
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn names() {
+        assert!(Organization::is_valid_name("acme"));
+        assert!(Organization::is_valid_name("acme-data-2"));
+        assert!(!Organization::is_valid_name(""));
+        assert!(!Organization::is_valid_name("Acme"));
+        assert!(!Organization::is_valid_name("acme_data"));
+        assert!(!Organization::is_valid_name("-acme"));
+        assert!(!Organization::is_valid_name(&"a".repeat(64)));
+    }
+
+    #[test]
+    fn roles_manage_those_below_them() {
+        assert!(OrgRole::Owner.manages(OrgRole::Owner));
+        assert!(OrgRole::Admin.manages(OrgRole::Member));
+        assert!(OrgRole::Admin.manages(OrgRole::ReadOnly));
+        assert!(!OrgRole::Admin.manages(OrgRole::Admin));
+        assert!(!OrgRole::Admin.manages(OrgRole::Owner));
+        assert!(!OrgRole::Member.manages(OrgRole::ReadOnly));
+    }
+
+    #[test]
+    fn roles_give_bucket_access() {
+        assert_eq!(OrgRole::Admin.bucket_access(), BucketAccess::Owner);
+        assert_eq!(OrgRole::Member.bucket_access(), BucketAccess::ReadWrite);
+        assert_eq!(OrgRole::ReadOnly.bucket_access(), BucketAccess::Read);
+        assert_eq!(OrgRole::parse("read-only"), Some(OrgRole::ReadOnly));
+    }
+}
@@ -0,0 +1,602 @@
+use serde_json::{Map, Value};
+use std::net::IpAddr;
+use thiserror::Error;
+use uuid::Uuid;
+
+// Resources in a policy are S3 ARNs: the bucket itself or objects in it
+const ARN_PREFIX: &str = "arn:aws:s3:::";
+
+// Largest policy document accepted, as in S3
+pub const MAX_POLICY_SIZE: usize = 20 * 1024;
+
+const POLICY_VERSIONS: &[&str] = &["2012-10-17", "2008-10-17"];
+
+// The operations a policy statement can allow or deny, named as in S3
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum PolicyAction {
+    GetObject,
+    PutObject,
+    DeleteObject,
+    GetObjectTagging,
+    PutObjectTagging,
+    DeleteObjectTagging,
+    AbortMultipartUpload,
+    ListMultipartUploadParts,
+    ListBucket,
+    ListBucketVersions,
+    ListBucketMultipartUploads,
+    DeleteBucket,
+    GetBucketVersioning,
+    PutBucketVersioning,
+    GetBucketTagging,
+    PutBucketTagging,
+}
+
+impl PolicyAction {
+    pub const ALL: &'static [PolicyAction] = &[
+        PolicyAction::GetObject,
+        PolicyAction::PutObject,
+        PolicyAction::DeleteObject,
+        PolicyAction::GetObjectTagging,
+        PolicyAction::PutObjectTagging,
+        PolicyAction::DeleteObjectTagging,
+        PolicyAction::AbortMultipartUpload,
+        PolicyAction::ListMultipartUploadParts,
+        PolicyAction::ListBucket,
+        PolicyAction::ListBucketVersions,
+        PolicyAction::ListBucketMultipartUploads,
+        PolicyAction::DeleteBucket,
+        PolicyAction::GetBucketVersioning,
+        PolicyAction::PutBucketVersioning,
+        PolicyAction::GetBucketTagging,
+        PolicyAction::PutBucketTagging,
+    ];
+
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            PolicyAction::GetObject => "s3:GetObject",
+            PolicyAction::PutObject => "s3:PutObject",
+            PolicyAction::DeleteObject => "s3:DeleteObject",
+            PolicyAction::GetObjectTagging => "s3:GetObjectTagging",
+            PolicyAction::PutObjectTagging => "s3:PutObjectTagging",
+            PolicyAction::DeleteObjectTagging => "s3:DeleteObjectTagging",
+            PolicyAction::AbortMultipartUpload => "s3:AbortMultipartUpload",
+            PolicyAction::ListMultipartUploadParts => "s3:ListMultipartUploadParts",
+            PolicyAction::ListBucket => "s3:ListBucket",
+            PolicyAction::ListBucketVersions => "s3:ListBucketVersions",
+            PolicyAction::ListBucketMultipartUploads => "s3:ListBucketMultipartUploads",
+            PolicyAction::DeleteBucket => "s3:DeleteBucket",
+            PolicyAction::GetBucketVersioning => "s3:GetBucketVersioning",
+            PolicyAction::PutBucketVersioning => "s3:PutBucketVersioning",
+            PolicyAction::GetBucketTagging => "s3:GetBucketTagging",
+            PolicyAction::PutBucketTagging => "s3:PutBucketTagging",
+        }
+    }
+
+    // Object actions apply to `bucket/key` resources, the others to the
+    // bucket itself
+    pub fn is_object_action(&self) -> bool {
+        matches!(
+            self,
+            PolicyAction::GetObject
+                | PolicyAction::PutObject
+                | PolicyAction::DeleteObject
+                | PolicyAction::GetObjectTagging
+                | PolicyAction::PutObjectTagging
+                | PolicyAction::DeleteObjectTagging
+                | PolicyAction::AbortMultipartUpload
+                | PolicyAction::ListMultipartUploadParts
+        )
+    }
+}
+
+#[derive(Debug, Error)]
+pub enum PolicyError {
+    #[error("Policy must be at most {0} bytes")]
+    TooLarge(usize),
+    #[error("Policy is not valid JSON: {0}")]
+    Json(String),
+    #[error("Policy {0}")]
+    Document(String),
+    // Points at the statement by its index and, if it has one, its Sid
+    #[error("Statement[{index}]{}: {message}", sid.as_ref().map(|sid| format!(" ({})", sid)).unwrap_or_default())]
+    Statement { index: usize, sid: Option<String>, message: String },
+}
+
+// What a request is, for evaluating a policy against it
+#[derive(Debug)]
+pub struct PolicyRequest<'a> {
+    // None for anonymous requests, which only "*" statements apply to
+    pub user_id: Option<Uuid>,
+    pub action: PolicyAction,
+    pub bucket: &'a str,
+    // The object key for object actions, or the prefix of a listing
+    pub key: Option<&'a str>,
+    pub source_ip: Option<IpAddr>,
+    pub secure_transport: bool,
+}
+
+impl PolicyRequest<'_> {
+    fn resource(&self) -> String {
+        match (self.action.is_object_action(), self.key) {
+            (true, Some(key)) => format!("{}{}/{}", ARN_PREFIX, self.bucket, key),
+            _ => format!("{}{}", ARN_PREFIX, self.bucket),
+        }
+    }
+
+    // Listings expose their prefix as `s3:prefix`
+    fn prefix(&self) -> Option<&str> {
+        if self.action.is_object_action() {
+            None
+        } else {
+            self.key
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Decision {
+    // Allowed even where the user's access to the bucket would not cover it
+    Allow,
+    // An explicit deny, which wins over any allow
+    Deny,
+    // No statement applies; access falls back to bucket ownership
+    Implicit,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum Effect {
+    Allow,
+    Deny,
+}
+
+#[derive(Debug)]
+struct IpRange {
+    network: IpAddr,
+    prefix_len: u32,
+}
+
+impl IpRange {
+    // An address, or a network in CIDR notation like `10.0.0.0/8`
+    fn parse(value: &str) -> Option<Self> {
+        let (address, prefix_len) = match value.split_once('/') {
+            Some((address, prefix_len)) => (address, Some(prefix_len.parse::<u32>().ok()?)),
+            None => (value, None),
+        };
+        let network: IpAddr = address.parse().ok()?;
+        let max_len = if network.is_ipv4() { 32 } else { 128 };
+        let prefix_len = prefix_len.unwrap_or(max_len);
+
+        (prefix_len <= max_len).then_some(Self { network, prefix_len })
+    }
+
+    fn contains(&self, ip: IpAddr) -> bool {
+        match (self.network, ip.to_canonical()) {
+            (IpAddr::V4(network), IpAddr::V4(ip)) => {
+                let mask = u32::MAX.checked_shl(32 - self.prefix_len).unwrap_or(0);
+                u32::from(network) & mask == u32::from(ip) & mask
+            }
+            (IpAddr::V6(network), IpAddr::V6(ip)) => {
+                let mask = u128::MAX.checked_shl(128 - self.prefix_len).unwrap_or(0);
+                u128::from(network) & mask == u128::from(ip) & mask
+            }
+            _ => false,
+        }
+    }
+}
+
+#[derive(Debug)]
+enum Condition {
+    // `IpAddress` / `NotIpAddress` on `aws:SourceIp`
+    SourceIp { negated: bool, ranges: Vec<IpRange> },
+    // `StringEquals` / `StringLike` and their negations on `s3:prefix`
+    Prefix { negated: bool, wildcards: bool, values: Vec<String> },
+    // `Bool` on `aws:SecureTransport`
+    SecureTransport(bool),
+}
+
+impl Condition {
+    fn matches(&self, request: &PolicyRequest) -> bool {
+        match self {
+            // Requests without the key match only negated conditions
+            Condition::SourceIp { negated, ranges } => match request.source_ip {
+                Some(ip) => ranges.iter().any(|range| range.contains(ip)) != *negated,
+                None => *negated,
+            },
+            Condition::Prefix { negated, wildcards, values } => match request.prefix() {
+                Some(prefix) => {
+                    let matched = values.iter().any(|value| match wildcards {
+                        true => wildcard_match(value, prefix),
+                        false => value == prefix,
+                    });
+                    matched != *negated
+                }
+                None => *negated,
+            },
+            Condition::SecureTransport(secure) => request.secure_transport == *secure,
+        }
+    }
+}
+
+#[derive(Debug)]
+struct Statement {
+    effect: Effect,
+    // None for `"Principal": "*"`
+    principals: Option<Vec<Uuid>>,
+    actions: Vec<String>,
+    resources: Vec<String>,
+    conditions: Vec<Condition>,
+}
+
+impl Statement {
+    fn applies(&self, request: &PolicyRequest, resource: &str) -> bool {
+        let principal = self
+            .principals
+            .as_ref()
+            .is_none_or(|principals| request.user_id.is_some_and(|user_id| principals.contains(&user_id)));
+        let action = self
+            .actions
+            .iter()
+            .any(|pattern| action_matches(pattern, request.action));
+        let resource = self.resources.iter().any(|pattern| wildcard_match(pattern, resource));
+
+        principal && action && resource && self.conditions.iter().all(|condition| condition.matches(request))
+    }
+}
+
+// A bucket policy, checked to only refer to its own bucket
+#[derive(Debug)]
+pub struct Policy {
+    statements: Vec<Statement>,
+}
+
+impl Policy {
+    // Check a policy document submitted for the bucket, returning it as
+    // JSON for storing
+    pub fn validate(document: &str, bucket: &str) -> Result<Value, PolicyError> {
+        if document.len() > MAX_POLICY_SIZE {
+            return Err(PolicyError::TooLarge(MAX_POLICY_SIZE));
+        }
+        let value: Value = serde_json::from_str(document).map_err(|e| PolicyError::Json(e.to_string()))?;
+        Self::parse(&value, bucket)?;
+
+        Ok(value)
+    }
+
+    pub fn parse(document: &Value, bucket: &str) -> Result<Self, PolicyError> {
+        let document = document
+            .as_object()
+            .ok_or_else(|| PolicyError::Document("must be a JSON object".to_string()))?;
+
+        for (field, value) in document {
+            match field.as_str() {
+                "Version" => {
+                    if !value.as_str().is_some_and(|version| POLICY_VERSIONS.contains(&version)) {
+                        return Err(PolicyError::Document(format!(
+                            "Version must be one of {}",
+                            POLICY_VERSIONS.join(", ")
+                        )));
+                    }
+                }
+                "Id" | "Statement" => {}
+                _ => return Err(PolicyError::Document(format!("has unknown field {}", field))),
+            }
+        }
+
+        let statements = match document.get("Statement") {
+            Some(Value::Array(statements)) if !statements.is_empty() => statements.iter().collect(),
+            Some(statement @ Value::Object(_)) => vec![statement],
+            _ => return Err(PolicyError::Document("must have at least one Statement".to_string())),
+        };
+
+        let statements = statements
+            .into_iter()
+            .enumerate()
+            .map(|(index, statement)| {
+                let sid = statement.get("Sid").and_then(Value::as_str).map(str::to_string);
+                parse_statement(statement, bucket)
+                    .map_err(|message| PolicyError::Statement { index, sid, message })
+            })
+            .collect::<Result<_, _>>()?;
+
+        Ok(Self { statements })
+    }
+
+    // An explicit deny from any statement wins; otherwise any statement
+    // that applies allows the request
+    pub fn evaluate(&self, request: &PolicyRequest) -> Decision {
+        let resource = request.resource();
+        let mut decision = Decision::Implicit;
+
+        for statement in &self.statements {
+            if !statement.applies(request, &resource) {
+                continue;
+            }
+            match statement.effect {
+                Effect::Deny => return Decision::Deny,
+                Effect::Allow => decision = Decision::Allow,
+            }
+        }
+
+        decision
+    }
+}
+
+// Glob match where `*` matches any run of characters and `?` any one
+fn wildcard_match(pattern: &str, value: &str) -> bool {
+    let pattern: Vec<char> = pattern.chars().collect();
+    let value: Vec<char> = value.chars().collect();
+    let (mut p, mut v) = (0, 0);
+    // Where the last `*` was seen, and the value position it matched up to
+    let mut backtrack = None;
+
+    while v < value.len() {
+        match pattern.get(p) {
+            Some('*') => {
+                backtrack = Some((p, v));
+                p += 1;
+            }
+            Some(&c) if c == '?' || c == value[v] => {
+                p += 1;
+                v += 1;
+            }
+            _ => match backtrack {
+                Some((star, matched)) => {
+                    p = star + 1;
+                    v = matched + 1;
+                    backtrack = Some((star, matched + 1));
+                }
+                None => return false,
+            },
+        }
+    }
+
+    pattern[p..].iter().all(|&c| c == '*')
+}
+
+// Action names are case-insensitive, as in S3
+fn action_matches(pattern: &str, action: PolicyAction) -> bool {
+    wildcard_match(&pattern.to_ascii_lowercase(), &action.as_str().to_ascii_lowercase())
+}
+
+// A string or a list of strings
+fn strings(value: &Value, field: &str) -> Result<Vec<String>, String> {
+    let values = match value {
+        Value::String(value) => vec![value.clone()],
+        Value::Array(values) => values
+            .iter()
+            .map(|value| value.as_str().map(str::to_string))
+            .collect::<Option<Vec<_>>>()
+            .ok_or_else(|| format!("{} must be a string or a list of strings", field))?,
+        _ => return Err(format!("{} must be a string or a list of strings", field)),
+    };
+
+    if values.is_empty() {
+        return Err(format!("{} must not be empty", field));
+    }
+    Ok(values)
+}
+
+fn parse_statement(statement: &Value, bucket: &str) -> Result<Statement, String> {
+    let statement = statement.as_object().ok_or("must be a JSON object")?;
+
+    for field in statement.keys() {
+        if !["Sid", "Effect", "Principal", "Action", "Resource", "Condition"].contains(&field.as_str()) {
+            return Err(format!("unknown or unsupported field {}", field));
+        }
+    }
+
+    let effect = match statement.get("Effect").and_then(Value::as_str) {
+        Some("Allow") => Effect::Allow,
+        Some("Deny") => Effect::Deny,
+        _ => return Err("Effect must be \"Allow\" or \"Deny\"".to_string()),
+    };
+
+    let principals = match statement.get("Principal") {
+        Some(Value::String(principal)) if principal == "*" => None,
+        Some(Value::Object(principal)) if principal.len() == 1 && principal.contains_key("User") => {
+            let users = strings(&principal["User"], "Principal User")?;
+            let users = users
+                .iter()
+                .map(|user| Uuid::parse_str(user).map_err(|_| format!("Principal User {} is not a user ID", user)))
+                .collect::<Result<_, _>>()?;
+            Some(users)
+        }
+        _ => return Err("Principal must be \"*\" or {\"User\": [user IDs]}".to_string()),
+    };
+
+    let actions = strings(statement.get("Action").ok_or("Action is required")?, "Action")?;
+    for action in &actions {
+        if !PolicyAction::ALL.iter().any(|known| action_matches(action, *known)) {
+            return Err(format!("Action {} matches no supported action", action));
+        }
+    }
+
+    let resources = strings(statement.get("Resource").ok_or("Resource is required")?, "Resource")?;
+    for resource in &resources {
+        let bucket_pattern = resource
+            .strip_prefix(ARN_PREFIX)
+            .map(|path| path.split('/').next().unwrap_or(path))
+            .ok_or_else(|| format!("Resource {} must start with {}", resource, ARN_PREFIX))?;
+        if !wildcard_match(bucket_pattern, bucket) {
+            return Err(format!("Resource {} is outside of bucket {}", resource, bucket));
+        }
+    }
+
+    let conditions = match statement.get("Condition") {
+        Some(Value::Object(conditions)) => parse_conditions(conditions)?,
+        Some(_) => return Err("Condition must be a JSON object".to_string()),
+        None => Vec::new(),
+    };
+
+    Ok(Statement { effect, principals, actions, resources, conditions })
+}
+
+fn parse_conditions(conditions: &Map<String, Value>) -> Result<Vec<Condition>, String> {
+    let mut parsed = Vec::new();
+
+    for (operator, block) in conditions {
+        let block = block
+            .as_object()
+            .ok_or_else(|| format!("Condition {} must map condition keys to values", operator))?;
+
+        for (key, value) in block {
+            let condition = match (operator.as_str(), key.as_str()) {
+                (operator @ ("IpAddress" | "NotIpAddress"), "aws:SourceIp") => {
+                    let ranges = strings(value, key)?
+                        .iter()
+                        .map(|range| IpRange::parse(range).ok_or_else(|| format!("{} is not an IP address or CIDR range", range)))
+                        .collect::<Result<_, _>>()?;
+                    Condition::SourceIp { negated: operator == "NotIpAddress", ranges }
+                }
+                (
+                    operator @ ("StringEquals" | "StringNotEquals" | "StringLike" | "StringNotLike"),
+                    "s3:prefix",
+                ) => Condition::Prefix {
+                    negated: operator.contains("Not"),
+                    wildcards: operator.ends_with("Like"),
+                    values: strings(value, key)?,
+                },
+                ("Bool", "aws:SecureTransport") => {
+                    let secure = match value {
+                        Value::Bool(secure) => *secure,
+                        Value::String(secure) if secure == "true" || secure == "false" => secure == "true",
+                        _ => return Err(format!("{} must be true or false", key)),
+                    };
+                    Condition::SecureTransport(secure)
+                }
+                _ => return Err(format!("Condition {} on {} is not supported", operator, key)),
+            };
+            parsed.push(condition);
+        }
+    }
+
+    Ok(parsed)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn policy(json: &str) -> Policy {
+        Policy::parse(&Policy::validate(json, "logs").unwrap(), "logs").unwrap()
+    }
+
+    fn request(action: PolicyAction, key: Option<&str>) -> PolicyRequest<'_> {
+        PolicyRequest {
+            user_id: Some(Uuid::nil()),
+            action,
+            bucket: "logs",
+            key,
+            source_ip: Some("10.1.2.3".parse().unwrap()),
+            secure_transport: true,
+        }
+    }
+
+    #[test]
+    fn explicit_deny_wins() {
+        let policy = policy(
+            r#"{
+                "Version": "2012-10-17",
+                "Statement": [
+                    {"Effect": "Allow", "Principal": "*", "Action": "s3:*", "Resource": "arn:aws:s3:::logs/*"},
+                    {"Effect": "Deny", "Principal": "*", "Action": "s3:Delete*", "Resource": "arn:aws:s3:::logs/audit/*"}
+                ]
+            }"#,
+        );
+
+        assert_eq!(policy.evaluate(&request(PolicyAction::GetObject, Some("audit/1"))), Decision::Allow);
+        assert_eq!(policy.evaluate(&request(PolicyAction::DeleteObject, Some("audit/1"))), Decision::Deny);
+        assert_eq!(policy.evaluate(&request(PolicyAction::DeleteObject, Some("app/1"))), Decision::Allow);
+        // The bucket itself is not a `logs/*` resource
+        assert_eq!(policy.evaluate(&request(PolicyAction::DeleteBucket, None)), Decision::Implicit);
+    }
+
+    #[test]
+    fn conditions() {
+        let policy = policy(
+            r#"{
+                "Statement": [
+                    {"Sid": "HttpsOnly", "Effect": "Deny", "Principal": "*", "Action": "s3:*",
+                     "Resource": ["arn:aws:s3:::logs", "arn:aws:s3:::logs/*"],
+                     "Condition": {"Bool": {"aws:SecureTransport": "false"}}},
+                    {"Sid": "Office", "Effect": "Deny", "Principal": "*", "Action": "s3:*",
+                     "Resource": ["arn:aws:s3:::logs", "arn:aws:s3:::logs/*"],
+                     "Condition": {"NotIpAddress": {"aws:SourceIp": ["10.0.0.0/8", "::1"]}}},
+                    {"Sid": "PublicListing", "Effect": "Deny", "Principal": "*", "Action": "s3:ListBucket",
+                     "Resource": "arn:aws:s3:::logs",
+                     "Condition": {"StringNotLike": {"s3:prefix": "public/*"}}}
+                ]
+            }"#,
+        );
+
+        let allowed = request(PolicyAction::GetObject, Some("app/1"));
+        assert_eq!(policy.evaluate(&allowed), Decision::Implicit);
+
+        let insecure = PolicyRequest { secure_transport: false, ..request(PolicyAction::GetObject, Some("app/1")) };
+        assert_eq!(policy.evaluate(&insecure), Decision::Deny);
+
+        let outside = PolicyRequest { source_ip: Some("192.168.0.1".parse().unwrap()), ..request(PolicyAction::GetObject, Some("app/1")) };
+        assert_eq!(policy.evaluate(&outside), Decision::Deny);
+        let loopback = PolicyRequest { source_ip: Some("::1".parse().unwrap()), ..request(PolicyAction::GetObject, Some("app/1")) };
+        assert_eq!(policy.evaluate(&loopback), Decision::Implicit);
+
+        assert_eq!(policy.evaluate(&request(PolicyAction::ListBucket, Some("public/2024"))), Decision::Implicit);
+        assert_eq!(policy.evaluate(&request(PolicyAction::ListBucket, Some("private/"))), Decision::Deny);
+    }
+
+    #[test]
+    fn principals() {
+        let user_id = Uuid::new_v4();
+        let policy = policy(&format!(
+            r#"{{"Statement": {{"Effect": "Deny", "Principal": {{"User": "{}"}}, "Action": "s3:GetObject", "Resource": "arn:aws:s3:::logs/*"}}}}"#,
+            user_id
+        ));
+
+        assert_eq!(policy.evaluate(&request(PolicyAction::GetObject, Some("a"))), Decision::Implicit);
+        let user = PolicyRequest { user_id: Some(user_id), ..request(PolicyAction::GetObject, Some("a")) };
+        assert_eq!(policy.evaluate(&user), Decision::Deny);
+        let anonymous = PolicyRequest { user_id: None, ..request(PolicyAction::GetObject, Some("a")) };
+        assert_eq!(policy.evaluate(&anonymous), Decision::Implicit);
+    }
+
+    #[test]
+    fn errors_point_at_the_statement() {
+        let error = |json: &str| Policy::validate(json, "logs").unwrap_err().to_string();
+        let statement = |fields: &str| {
+            format!(
+                r#"{{"Statement": [{{"Effect": "Allow", "Principal": "*", "Action": "s3:GetObject", "Resource": "arn:aws:s3:::logs/*"}}, {{"Sid": "Second", {}}}]}}"#,
+                fields
+            )
+        };
+
+        assert_eq!(
+            error(&statement(r#""Effect": "Allow", "Principal": "*", "Action": "s3:GetObjct", "Resource": "arn:aws:s3:::logs/*""#)),
+            "Statement[1] (Second): Action s3:GetObjct matches no supported action"
+        );
+        assert_eq!(
+            error(&statement(r#""Effect": "Allow", "Principal": "*", "Action": "s3:GetObject", "Resource": "arn:aws:s3:::other/*""#)),
+            "Statement[1] (Second): Resource arn:aws:s3:::other/* is outside of bucket logs"
+        );
+        assert_eq!(
+            error(&statement(r#""Effect": "Allow", "Principal": "*", "Action": "s3:GetObject", "Resource": "arn:aws:s3:::logs/*", "Condition": {"IpAddress": {"aws:SourceIp": "10.0.0.0/33"}}"#)),
+            "Statement[1] (Second): 10.0.0.0/33 is not an IP address or CIDR range"
+        );
+        assert_eq!(
+            error(&statement(r#""Effect": "Permit", "Principal": "*", "Action": "s3:GetObject", "Resource": "arn:aws:s3:::logs/*""#)),
+            "Statement[1] (Second): Effect must be \"Allow\" or \"Deny\""
+        );
+        assert_eq!(error(r#"{"Statement": []}"#), "Policy must have at least one Statement");
+        assert!(error("{").starts_with("Policy is not valid JSON"));
+    }
+
+    #[test]
+    fn wildcards() {
+        assert!(wildcard_match("arn:aws:s3:::logs/*", "arn:aws:s3:::logs/a/b"));
+        assert!(wildcard_match("a*b*c", "axxbyyc"));
+        assert!(wildcard_match("a?c", "abc"));
+        assert!(!wildcard_match("a*b", "axxc"));
+        assert!(!wildcard_match("arn:aws:s3:::logs/*", "arn:aws:s3:::logs"));
+        assert!(action_matches("S3:get*", PolicyAction::GetObjectTagging));
+    }
+}
@@ -0,0 +1,133 @@
+use serde::{Deserialize, Serialize};
+use thiserror::Error;
+
+// What a credential may do to a bucket or the objects in it
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum Action {
+    // Download objects and read their details, tags and versions
+    Read,
+    // Upload, copy into and tag objects, and create or configure buckets
+    Write,
+    // List buckets and the objects in them
+    List,
+    // Delete objects and buckets
+    Delete,
+}
+
+impl Action {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            Action::Read => "read",
+            Action::Write => "write",
+            Action::List => "list",
+            Action::Delete => "delete",
+        }
+    }
+}
+
+#[derive(Debug, Error)]
+pub enum ScopeError {
+    #[error("Scope must allow at least one action")]
+    NoActions,
+    #[error("Scope must name at least one bucket, or leave out buckets to allow all")]
+    EmptyBuckets,
+    #[error("Scope must name at least one prefix, or leave out prefixes to allow all")]
+    EmptyPrefixes,
+}
+
+// Limits on what an API key can do. Keys without a scope have the full
+// access of their user.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct Scope {
+    // Names of the buckets the key can reach, qualified as
+    // `organization:bucket` for organization buckets; all of them if absent
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub buckets: Option<Vec<String>>,
+    // Object keys the key can reach must start with one of these; all keys
+    // if absent. Keys limited to prefixes cannot act on whole buckets.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub prefixes: Option<Vec<String>>,
+    pub actions: Vec<Action>,
+}
+
+impl Scope {
+    pub fn validate(&self) -> Result<(), ScopeError> {
+        if self.actions.is_empty() {
+            return Err(ScopeError::NoActions);
+        }
+        if self.buckets.as_ref().is_some_and(Vec::is_empty) {
+            return Err(ScopeError::EmptyBuckets);
+        }
+        if self.prefixes.as_ref().is_some_and(Vec::is_empty) {
+            return Err(ScopeError::EmptyPrefixes);
+        }
+
+        Ok(())
+    }
+
+    pub fn allows_action(&self, action: Action) -> bool {
+        self.actions.contains(&action)
+    }
+
+    pub fn allows_bucket(&self, bucket: &str) -> bool {
+        self.buckets.as_ref().is_none_or(|buckets| buckets.iter().any(|name| name == bucket))
+    }
+
+    // Whether `action` is allowed on `key` in `bucket`, or on the bucket
+    // itself without a key. Listings pass their prefix as the key.
+    pub fn allows(&self, action: Action, bucket: &str, key: Option<&str>) -> bool {
+        let key_allowed = match (&self.prefixes, key) {
+            (None, _) => true,
+            (Some(prefixes), Some(key)) => prefixes.iter().any(|prefix| key.starts_with(prefix.as_str())),
+            (Some(_), None) => false,
+        };
+
+        self.allows_action(action) && self.allows_bucket(bucket) && key_allowed
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn scope(json: &str) -> Scope {
+        let scope: Scope = serde_json::from_str(json).unwrap();
+        scope.validate().unwrap();
+        scope
+    }
+
+    #[test]
+    fn write_only_key_for_one_bucket() {
+        let ci = scope(r#"{"buckets": ["artifacts"], "actions": ["write"]}"#);
+
+        assert!(ci.allows(Action::Write, "artifacts", Some("build/app.tar")));
+        assert!(ci.allows(Action::Write, "artifacts", None));
+        assert!(!ci.allows(Action::Read, "artifacts", Some("build/app.tar")));
+        assert!(!ci.allows(Action::Delete, "artifacts", Some("build/app.tar")));
+        assert!(!ci.allows(Action::Write, "other", Some("build/app.tar")));
+        assert!(!ci.allows_bucket("other"));
+    }
+
+    #[test]
+    fn prefixes_limit_keys_and_listings() {
+        let scope = scope(r#"{"prefixes": ["logs/", "tmp/"], "actions": ["read", "list"]}"#);
+
+        assert!(scope.allows(Action::Read, "any", Some("logs/today")));
+        assert!(scope.allows(Action::List, "any", Some("tmp/")));
+        assert!(!scope.allows(Action::Read, "any", Some("secrets/key")));
+        assert!(!scope.allows(Action::List, "any", Some("")));
+        // Whole-bucket operations are out of reach of prefix-limited keys
+        assert!(!scope.allows(Action::Read, "any", None));
+    }
+
+    #[test]
+    fn rejects_empty_lists() {
+        let parse = |json| serde_json::from_str::<Scope>(json).unwrap().validate();
+
+        assert!(matches!(parse(r#"{"actions": []}"#), Err(ScopeError::NoActions)));
+        assert!(matches!(parse(r#"{"buckets": [], "actions": ["read"]}"#), Err(ScopeError::EmptyBuckets)));
+        assert!(matches!(parse(r#"{"prefixes": [], "actions": ["read"]}"#), Err(ScopeError::EmptyPrefixes)));
+        assert!(serde_json::from_str::<Scope>(r#"{"actions": ["admin"]}"#).is_err());
+    }
+}
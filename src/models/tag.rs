@@ -0,0 +1,141 @@
+use sqlx::{PgConnection, PgExecutor};
+use std::collections::BTreeMap;
+use uuid::Uuid;
+
+// Tags keyed by tag key; keys are unique within a set
+pub type TagSet = BTreeMap<String, String>;
+
+// What a tag set belongs to: one version of an object, or a bucket
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum TagTarget {
+    File(Uuid),
+    Bucket(Uuid),
+}
+
+struct TagRow {
+    key: String,
+    value: String,
+}
+
+impl TagTarget {
+    pub async fn tags<'e>(&self, executor: impl PgExecutor<'e>) -> Result<TagSet, sqlx::Error> {
+        let rows = match *self {
+            TagTarget::File(file_id) => {
+                sqlx::query_as!(
+                    TagRow,
+                    r#"
+                    SELECT key, value
+                    FROM file_tags
+                    WHERE file_id = $1
+                    "#,
+                    file_id
+                )
+                    .fetch_all(executor)
+                    .await?
+            }
+            TagTarget::Bucket(bucket_id) => {
+                sqlx::query_as!(
+                    TagRow,
+                    r#"
+                    SELECT key, value
+                    FROM bucket_tags
+                    WHERE bucket_id = $1
+                    "#,
+                    bucket_id
+                )
+                    .fetch_all(executor)
+                    .await?
+            }
+        };
+
+        Ok(rows.into_iter().map(|row| (row.key, row.value)).collect())
+    }
+
+    // Lock the tagged row so concurrent tag updates apply one after the
+    // other. Returns false if the row no longer exists.
+    pub async fn lock(&self, conn: &mut PgConnection) -> Result<bool, sqlx::Error> {
+        let locked = match *self {
+            TagTarget::File(file_id) => {
+                sqlx::query_scalar!(
+                    r#"
+                    SELECT id FROM files WHERE id = $1 FOR UPDATE
+                    "#,
+                    file_id
+                )
+                    .fetch_optional(conn)
+                    .await?
+            }
+            TagTarget::Bucket(bucket_id) => {
+                sqlx::query_scalar!(
+                    r#"
+                    SELECT id FROM buckets WHERE id = $1 FOR UPDATE
+                    "#,
+                    bucket_id
+                )
+                    .fetch_optional(conn)
+                    .await?
+            }
+        };
+
+        Ok(locked.is_some())
+    }
+
+    // Replace the whole tag set. Should run inside a transaction.
+    pub async fn replace_tags(&self, conn: &mut PgConnection, tags: &TagSet) -> Result<(), sqlx::Error> {
+        let keys: Vec<String> = tags.keys().cloned().collect();
+        let values: Vec<String> = tags.values().cloned().collect();
+
+        match *self {
+            TagTarget::File(file_id) => {
+                sqlx::query!(
+                    r#"
+                    DELETE FROM file_tags
+                    WHERE file_id = $1
+                    "#,
+                    file_id
+                )
+                    .execute(&mut *conn)
+                    .await?;
+
+                sqlx::query!(
+                    r#"
+                    INSERT INTO file_tags (file_id, key, value)
+                    SELECT $1, key, value
+                    FROM UNNEST($2::text[], $3::text[]) AS tags(key, value)
+                    "#,
+                    file_id,
+                    &keys,
+                    &values
+                )
+                    .execute(&mut *conn)
+                    .await?;
+            }
+            TagTarget::Bucket(bucket_id) => {
+                sqlx::query!(
+                    r#"
+                    DELETE FROM bucket_tags
+                    WHERE bucket_id = $1
+                    "#,
+                    bucket_id
+                )
+                    .execute(&mut *conn)
+                    .await?;
+
+                sqlx::query!(
+                    r#"
+                    INSERT INTO bucket_tags (bucket_id, key, value)
+                    SELECT $1, key, value
+                    FROM UNNEST($2::text[], $3::text[]) AS tags(key, value)
+                    "#,
+                    bucket_id,
+                    &keys,
+                    &values
+                )
+                    .execute(&mut *conn)
+                    .await?;
+            }
+        }
+
+        Ok(())
+    }
+}
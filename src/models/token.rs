@@ -0,0 +1,217 @@
+use chrono::{DateTime, Duration, Utc};
+use rand::distributions::{Alphanumeric, DistString};
+use rand::thread_rng;
+use sha2::{Digest, Sha256};
+use sqlx::{FromRow, PgConnection, PgExecutor, PgPool};
+use uuid::Uuid;
+
+const REFRESH_TOKEN_LENGTH: usize = 64;
+
+fn hash_token(token: &str) -> String {
+    hex::encode(Sha256::digest(token.as_bytes()))
+}
+
+#[derive(Debug, FromRow)]
+pub struct RefreshToken {
+    pub id: Uuid,
+    pub user_id: Uuid,
+    // Shared by every token that descends from one login through rotation
+    pub family_id: Uuid,
+    pub token_hash: String,
+    pub created_at: DateTime<Utc>,
+    pub expires_at: DateTime<Utc>,
+    // Set once the token has been exchanged for a new one
+    pub used_at: Option<DateTime<Utc>>,
+    pub revoked_at: Option<DateTime<Utc>>,
+}
+
+impl RefreshToken {
+    // A new token for the user, starting a new family unless one is given.
+    // The token itself is only returned here; just its hash is stored.
+    pub fn issue(user_id: Uuid, family_id: Option<Uuid>, ttl: Duration) -> (Self, String) {
+        let token = Alphanumeric.sample_string(&mut thread_rng(), REFRESH_TOKEN_LENGTH);
+        let now = Utc::now();
+
+        let refresh_token = Self {
+            id: Uuid::new_v4(),
+            user_id,
+            family_id: family_id.unwrap_or_else(Uuid::new_v4),
+            token_hash: hash_token(&token),
+            created_at: now,
+            expires_at: now + ttl,
+            used_at: None,
+            revoked_at: None,
+        };
+
+        (refresh_token, token)
+    }
+
+    // Whether the token can still be exchanged, ignoring whether it was used
+    pub fn is_active(&self) -> bool {
+        self.revoked_at.is_none() && self.expires_at > Utc::now()
+    }
+
+    pub async fn create<'e>(&self, executor: impl PgExecutor<'e>) -> Result<(), sqlx::Error> {
+        sqlx::query!(
+            r#"
+            INSERT INTO refresh_tokens (id, user_id, family_id, token_hash, created_at, expires_at, used_at, revoked_at)
+            VALUES ($1, $2, $3, $4, $5, $6, $7, $8)
+            "#,
+            self.id,
+            self.user_id,
+            self.family_id,
+            self.token_hash,
+            self.created_at,
+            self.expires_at,
+            self.used_at,
+            self.revoked_at
+        )
+            .execute(executor)
+            .await?;
+
+        Ok(())
+    }
+
+    // The row for a presented token, locked until the end of the
+    // transaction so that a token can only be exchanged once
+    pub async fn lock_by_token(conn: &mut PgConnection, token: &str) -> Result<Option<Self>, sqlx::Error> {
+        let refresh_token = sqlx::query_as!(
+            RefreshToken,
+            r#"
+            SELECT id, user_id, family_id, token_hash, created_at, expires_at, used_at, revoked_at
+            FROM refresh_tokens
+            WHERE token_hash = $1
+            FOR UPDATE
+            "#,
+            hash_token(token)
+        )
+            .fetch_optional(conn)
+            .await?;
+
+        Ok(refresh_token)
+    }
+
+    pub async fn find_by_token(pool: &PgPool, token: &str) -> Result<Option<Self>, sqlx::Error> {
+        let refresh_token = sqlx::query_as!(
+            RefreshToken,
+            r#"
+            SELECT id, user_id, family_id, token_hash, created_at, expires_at, used_at, revoked_at
+            FROM refresh_tokens
+            WHERE token_hash = $1
+            "#,
+            hash_token(token)
+        )
+            .fetch_optional(pool)
+            .await?;
+
+        Ok(refresh_token)
+    }
+
+    pub async fn mark_used<'e>(&self, executor: impl PgExecutor<'e>) -> Result<(), sqlx::Error> {
+        sqlx::query!(
+            r#"
+            UPDATE refresh_tokens
+            SET used_at = NOW()
+            WHERE id = $1
+            "#,
+            self.id
+        )
+            .execute(executor)
+            .await?;
+
+        Ok(())
+    }
+
+    // Revoke every token descending from the same login
+    pub async fn revoke_family<'e>(executor: impl PgExecutor<'e>, family_id: Uuid) -> Result<(), sqlx::Error> {
+        sqlx::query!(
+            r#"
+            UPDATE refresh_tokens
+            SET revoked_at = NOW()
+            WHERE family_id = $1 AND revoked_at IS NULL
+            "#,
+            family_id
+        )
+            .execute(executor)
+            .await?;
+
+        Ok(())
+    }
+
+    // Revoke every token of a user, logging out all of their sessions
+    pub async fn revoke_all_for_user<'e>(executor: impl PgExecutor<'e>, user_id: Uuid) -> Result<(), sqlx::Error> {
+        sqlx::query!(
+            r#"
+            UPDATE refresh_tokens
+            SET revoked_at = NOW()
+            WHERE user_id = $1 AND revoked_at IS NULL
+            "#,
+            user_id
+        )
+            .execute(executor)
+            .await?;
+
+        Ok(())
+    }
+
+    pub async fn delete_expired(pool: &PgPool) -> Result<u64, sqlx::Error> {
+        let result = sqlx::query!(
+            r#"
+            DELETE FROM refresh_tokens
+            WHERE expires_at < NOW()
+            "#
+        )
+            .execute(pool)
+            .await?;
+
+        Ok(result.rows_affected())
+    }
+}
+
+// Access tokens revoked before their expiry, by `jti`
+pub struct RevokedToken;
+
+impl RevokedToken {
+    pub async fn revoke(pool: &PgPool, jti: Uuid, expires_at: DateTime<Utc>) -> Result<(), sqlx::Error> {
+        sqlx::query!(
+            r#"
+            INSERT INTO revoked_tokens (jti, expires_at)
+            VALUES ($1, $2)
+            ON CONFLICT (jti) DO NOTHING
+            "#,
+            jti,
+            expires_at
+        )
+            .execute(pool)
+            .await?;
+
+        Ok(())
+    }
+
+    pub async fn is_revoked(pool: &PgPool, jti: Uuid) -> Result<bool, sqlx::Error> {
+        let revoked = sqlx::query_scalar!(
+            r#"
+            SELECT EXISTS (SELECT 1 FROM revoked_tokens WHERE jti = $1) AS "revoked!"
+            "#,
+            jti
+        )
+            .fetch_one(pool)
+            .await?;
+
+        Ok(revoked)
+    }
+
+    // Expired tokens are rejected anyway, so their entries can go
+    pub async fn delete_expired(pool: &PgPool) -> Result<u64, sqlx::Error> {
+        let result = sqlx::query!(
+            r#"
+            DELETE FROM revoked_tokens
+            WHERE expires_at < NOW()
+            "#
+        )
+            .execute(pool)
+            .await?;
+
+        Ok(result.rows_affected())
+    }
+}
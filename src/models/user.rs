@@ -1,65 +1,159 @@
 use chrono::{DateTime, Utc};
-use rand::{thread_rng, Rng};
+use rand::distributions::{Alphanumeric, DistString};
+use rand::thread_rng;
 use serde::{Deserialize, Serialize};
-use sha2::{Digest, Sha256};
-use sqlx::{FromRow, PgPool};
+use sqlx::{FromRow, PgExecutor, PgPool};
 use uuid::Uuid;
 
+const ACCESS_KEY_ID_PREFIX: &str = "AKIA";
+const ACCESS_KEY_ID_LENGTH: usize = 20;
+const SECRET_ACCESS_KEY_LENGTH: usize = 40;
+
 #[derive(Debug, Serialize, Deserialize, FromRow)]
 pub struct User {
     pub id: Uuid,
     pub email: String,
-    pub api_key: String,
+    pub access_key_id: Option<String>,
+    #[serde(skip_serializing)]
+    pub secret_access_key: Option<String>,
+    // Argon2id hash; accounts created before passwords existed have none
+    #[serde(skip_serializing)]
+    pub password_hash: Option<String>,
     pub created_at: DateTime<Utc>,
 }
 
 impl User {
-    pub fn new(email: String) -> Self {
-        // Generate a random API key
-        let api_key = Self::generate_api_key();
+    pub fn new(email: String, password_hash: String) -> Self {
+        // Generate an access key pair for signed (SigV4) requests
+        let (access_key_id, secret_access_key) = Self::generate_access_key();
 
         Self {
             id: Uuid::new_v4(),
             email,
-            api_key,
+            access_key_id: Some(access_key_id),
+            secret_access_key: Some(secret_access_key),
+            password_hash: Some(password_hash),
             created_at: Utc::now(),
         }
     }
 
-    fn generate_api_key() -> String {
-        let random_bytes: [u8; 32] = thread_rng().gen();
-        let mut hasher = Sha256::new();
-        hasher.update(&random_bytes);
-        let result = hasher.finalize();
-        hex::encode(result)
+    // Access key IDs look like AWS ones: "AKIA" followed by uppercase letters and digits
+    fn generate_access_key() -> (String, String) {
+        let mut rng = thread_rng();
+        let suffix = Alphanumeric
+            .sample_string(&mut rng, ACCESS_KEY_ID_LENGTH - ACCESS_KEY_ID_PREFIX.len())
+            .to_ascii_uppercase();
+        let secret = Alphanumeric.sample_string(&mut rng, SECRET_ACCESS_KEY_LENGTH);
+
+        (format!("{}{}", ACCESS_KEY_ID_PREFIX, suffix), secret)
     }
 
-    pub async fn create(&self, pool: &PgPool) -> Result<(), sqlx::Error> {
+    pub async fn create<'e>(&self, executor: impl PgExecutor<'e>) -> Result<(), sqlx::Error> {
         sqlx::query!(
             r#"
-            INSERT INTO users (id, email, api_key, created_at)
-            VALUES ($1, $2, $3, $4)
+            INSERT INTO users (id, email, access_key_id, secret_access_key, password_hash, created_at)
+            VALUES ($1, $2, $3, $4, $5, $6)
             "#,
             self.id,
             self.email,
-            self.api_key,
+            self.access_key_id,
+            self.secret_access_key,
+            self.password_hash,
             self.created_at
+        )
+            .execute(executor)
+            .await?;
+
+        Ok(())
+    }
+
+    // Replace the user's access key pair, invalidating the old one
+    pub async fn rotate_access_key(&mut self, pool: &PgPool) -> Result<(), sqlx::Error> {
+        let (access_key_id, secret_access_key) = Self::generate_access_key();
+
+        sqlx::query!(
+            r#"
+            UPDATE users
+            SET access_key_id = $1, secret_access_key = $2
+            WHERE id = $3
+            "#,
+            access_key_id,
+            secret_access_key,
+            self.id
+        )
+            .execute(pool)
+            .await?;
+
+        self.access_key_id = Some(access_key_id);
+        self.secret_access_key = Some(secret_access_key);
+        Ok(())
+    }
+
+    // Replace the user's password hash
+    pub async fn set_password_hash(&mut self, pool: &PgPool, password_hash: String) -> Result<(), sqlx::Error> {
+        sqlx::query!(
+            r#"
+            UPDATE users
+            SET password_hash = $1
+            WHERE id = $2
+            "#,
+            password_hash,
+            self.id
         )
             .execute(pool)
             .await?;
 
+        self.password_hash = Some(password_hash);
         Ok(())
     }
 
-    pub async fn find_by_api_key(pool: &PgPool, api_key: &str) -> Result<Option<Self>, sqlx::Error> {
+    // Give an account without a password its first one. Returns false if a
+    // password has been set in the meantime.
+    pub async fn set_first_password_hash(&mut self, pool: &PgPool, password_hash: String) -> Result<bool, sqlx::Error> {
+        let result = sqlx::query!(
+            r#"
+            UPDATE users
+            SET password_hash = $1
+            WHERE id = $2 AND password_hash IS NULL
+            "#,
+            password_hash,
+            self.id
+        )
+            .execute(pool)
+            .await?;
+
+        if result.rows_affected() == 0 {
+            return Ok(false);
+        }
+        self.password_hash = Some(password_hash);
+        Ok(true)
+    }
+
+    pub async fn find_by_id(pool: &PgPool, id: Uuid) -> Result<Option<Self>, sqlx::Error> {
+        let user = sqlx::query_as!(
+            User,
+            r#"
+            SELECT id, email, access_key_id, secret_access_key, password_hash, created_at
+            FROM users
+            WHERE id = $1
+            "#,
+            id
+        )
+            .fetch_optional(pool)
+            .await?;
+
+        Ok(user)
+    }
+
+    pub async fn find_by_access_key_id(pool: &PgPool, access_key_id: &str) -> Result<Option<Self>, sqlx::Error> {
         let user = sqlx::query_as!(
             User,
             r#"
-            SELECT id, email, api_key, created_at
+            SELECT id, email, access_key_id, secret_access_key, password_hash, created_at
             FROM users
-            WHERE api_key = $1
+            WHERE access_key_id = $1
             "#,
-            api_key
+            access_key_id
         )
             .fetch_optional(pool)
             .await?;
@@ -71,7 +165,7 @@ impl User {
         let user = sqlx::query_as!(
         User,
         r#"
-        SELECT id, email, api_key, created_at
+        SELECT id, email, access_key_id, secret_access_key, password_hash, created_at
         FROM users
         WHERE email = $1
         "#,
@@ -82,4 +176,4 @@ impl User {
 
         Ok(user)
     }
-}
\ No newline at end of file
+}
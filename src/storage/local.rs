@@ -0,0 +1,477 @@
+use async_trait::async_trait;
+use futures::StreamExt;
+use std::path::{Component, Path, PathBuf};
+use tokio::fs;
+use tokio::io::{AsyncReadExt, AsyncSeekExt, AsyncWriteExt, BufWriter, SeekFrom};
+use tokio_util::io::ReaderStream;
+use uuid::Uuid;
+
+use super::{ByteStream, ObjectWriter, Storage, StorageError, StorageResult};
+
+// Keep generated object names well below the usual 255 byte filename limit
+const MAX_NAME_LEN: usize = 200;
+
+// Prefix for in-progress uploads; such files are skipped by `list_files`
+const TEMP_PREFIX: &str = ".upload-";
+
+// Stores objects on the local filesystem as `<root>/<bucket>/<file_id>_<filename>`
+#[derive(Debug, Clone)]
+pub struct LocalStorage {
+    root: PathBuf,
+}
+
+impl LocalStorage {
+    pub fn new(root: &str) -> std::io::Result<Self> {
+        std::fs::create_dir_all(root)?;
+
+        Ok(Self {
+            root: PathBuf::from(root),
+        })
+    }
+
+    // Directory name for a bucket, rejecting anything that is not a plain path segment
+    fn bucket_dir_name(bucket_name: &str) -> StorageResult<String> {
+        let sanitized = sanitize_filename::sanitize(bucket_name);
+        if sanitized.is_empty() || sanitized != bucket_name {
+            return Err(StorageError::InvalidPath(bucket_name.to_string()));
+        }
+
+        Ok(sanitized)
+    }
+
+    // Object name on disk: the file ID keeps it unique, the sanitized
+    // filename keeps the layout readable when browsing the storage directory
+    fn object_name(file_id: Uuid, filename: &str) -> String {
+        let mut sanitized = sanitize_filename::sanitize(filename);
+        if sanitized.len() > MAX_NAME_LEN {
+            let mut end = MAX_NAME_LEN;
+            while !sanitized.is_char_boundary(end) {
+                end -= 1;
+            }
+            sanitized.truncate(end);
+        }
+
+        if sanitized.is_empty() {
+            file_id.to_string()
+        } else {
+            format!("{}_{}", file_id, sanitized)
+        }
+    }
+
+    // Map a storage path back to a location under the root directory.
+    // Only plain relative segments are accepted, so `..` or absolute paths
+    // can never escape the root.
+    fn resolve(&self, path: &str) -> StorageResult<PathBuf> {
+        let relative = Path::new(path);
+        let mut components = relative.components().peekable();
+
+        if components.peek().is_none() {
+            return Err(StorageError::InvalidPath(path.to_string()));
+        }
+
+        for component in components {
+            if !matches!(component, Component::Normal(_)) {
+                return Err(StorageError::InvalidPath(path.to_string()));
+            }
+        }
+
+        Ok(self.root.join(relative))
+    }
+
+    async fn open_writer(
+        &self,
+        bucket_name: &str,
+        file_id: Uuid,
+        filename: &str,
+    ) -> StorageResult<LocalObjectWriter> {
+        let bucket_dir = Self::bucket_dir_name(bucket_name)?;
+        let storage_path = format!("{}/{}", bucket_dir, Self::object_name(file_id, filename));
+        let final_path = self.resolve(&storage_path)?;
+        let temp_path = self
+            .root
+            .join(&bucket_dir)
+            .join(format!("{}{}", TEMP_PREFIX, Uuid::new_v4()));
+
+        fs::create_dir_all(self.root.join(&bucket_dir)).await?;
+        let file = fs::File::create(&temp_path).await?;
+
+        Ok(LocalObjectWriter {
+            file: BufWriter::new(file),
+            temp_path,
+            final_path,
+            storage_path,
+            finished: false,
+        })
+    }
+}
+
+// Writes into a hidden temp file next to the final location and renames it
+// into place on `finish`, so readers never observe a partially written object
+pub struct LocalObjectWriter {
+    file: BufWriter<fs::File>,
+    temp_path: PathBuf,
+    final_path: PathBuf,
+    storage_path: String,
+    finished: bool,
+}
+
+#[async_trait]
+impl ObjectWriter for LocalObjectWriter {
+    async fn write_chunk(&mut self, chunk: &[u8]) -> StorageResult<()> {
+        self.file.write_all(chunk).await?;
+        Ok(())
+    }
+
+    async fn finish(mut self: Box<Self>) -> StorageResult<String> {
+        self.file.flush().await?;
+        self.file.get_ref().sync_all().await?;
+        fs::rename(&self.temp_path, &self.final_path).await?;
+        self.finished = true;
+
+        Ok(std::mem::take(&mut self.storage_path))
+    }
+}
+
+impl Drop for LocalObjectWriter {
+    fn drop(&mut self) {
+        if !self.finished {
+            let _ = std::fs::remove_file(&self.temp_path);
+        }
+    }
+}
+
+fn map_io_error(path: &str, err: std::io::Error) -> StorageError {
+    if err.kind() == std::io::ErrorKind::NotFound {
+        StorageError::NotFound(path.to_string())
+    } else {
+        StorageError::Io(err)
+    }
+}
+
+#[async_trait]
+impl Storage for LocalStorage {
+    async fn create_writer(
+        &self,
+        bucket_name: &str,
+        file_id: Uuid,
+        filename: &str,
+    ) -> StorageResult<Box<dyn ObjectWriter>> {
+        Ok(Box::new(self.open_writer(bucket_name, file_id, filename).await?))
+    }
+
+    async fn read_file(&self, path: &str) -> StorageResult<Vec<u8>> {
+        let full_path = self.resolve(path)?;
+        fs::read(&full_path).await.map_err(|e| map_io_error(path, e))
+    }
+
+    async fn read_file_stream(&self, path: &str) -> StorageResult<ByteStream> {
+        let full_path = self.resolve(path)?;
+        let file = fs::File::open(&full_path)
+            .await
+            .map_err(|e| map_io_error(path, e))?;
+
+        Ok(ReaderStream::new(file).boxed())
+    }
+
+    async fn read_range_stream(&self, path: &str, start: u64, length: u64) -> StorageResult<ByteStream> {
+        let full_path = self.resolve(path)?;
+        let mut file = fs::File::open(&full_path)
+            .await
+            .map_err(|e| map_io_error(path, e))?;
+        file.seek(SeekFrom::Start(start)).await?;
+
+        Ok(ReaderStream::new(file.take(length)).boxed())
+    }
+
+    // Copies on disk into the temp file of a new writer, so the copy appears
+    // under its final path as atomically as an upload
+    async fn copy_file(
+        &self,
+        path: &str,
+        bucket_name: &str,
+        file_id: Uuid,
+        filename: &str,
+    ) -> StorageResult<String> {
+        let source = self.resolve(path)?;
+        let writer = self.open_writer(bucket_name, file_id, filename).await?;
+        fs::copy(&source, &writer.temp_path)
+            .await
+            .map_err(|e| map_io_error(path, e))?;
+
+        Box::new(writer).finish().await
+    }
+
+    async fn delete_file(&self, path: &str) -> StorageResult<()> {
+        let full_path = self.resolve(path)?;
+        fs::remove_file(&full_path)
+            .await
+            .map_err(|e| map_io_error(path, e))
+    }
+
+    async fn exists(&self, path: &str) -> StorageResult<bool> {
+        let full_path = self.resolve(path)?;
+        Ok(fs::try_exists(&full_path).await?)
+    }
+
+    async fn file_size(&self, path: &str) -> StorageResult<u64> {
+        let full_path = self.resolve(path)?;
+        let metadata = fs::metadata(&full_path)
+            .await
+            .map_err(|e| map_io_error(path, e))?;
+
+        Ok(metadata.len())
+    }
+
+    async fn list_files(&self, bucket_name: &str) -> StorageResult<Vec<String>> {
+        let bucket_dir = Self::bucket_dir_name(bucket_name)?;
+
+        let mut entries = match fs::read_dir(self.root.join(&bucket_dir)).await {
+            Ok(entries) => entries,
+            Err(e) if e.kind() == std::io::ErrorKind::NotFound => return Ok(Vec::new()),
+            Err(e) => return Err(e.into()),
+        };
+
+        let mut paths = Vec::new();
+        while let Some(entry) = entries.next_entry().await? {
+            if !entry.file_type().await?.is_file() {
+                continue;
+            }
+            if let Some(name) = entry.file_name().to_str() {
+                if name.starts_with(TEMP_PREFIX) {
+                    continue;
+                }
+                paths.push(format!("{}/{}", bucket_dir, name));
+            }
+        }
+        paths.sort();
+
+        Ok(paths)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::storage::ObjectUpload;
+    use tempfile::TempDir;
+
+    fn setup() -> (TempDir, LocalStorage) {
+        let dir = TempDir::new().unwrap();
+        let storage = LocalStorage::new(dir.path().to_str().unwrap()).unwrap();
+        (dir, storage)
+    }
+
+    #[tokio::test]
+    async fn save_and_read_round_trip() {
+        let (_dir, storage) = setup();
+        let id = Uuid::new_v4();
+
+        let path = storage.save_file("photos", id, "cat.png", b"meow").await.unwrap();
+
+        assert_eq!(path, format!("photos/{}_cat.png", id));
+        assert_eq!(storage.read_file(&path).await.unwrap(), b"meow");
+        assert_eq!(storage.file_size(&path).await.unwrap(), 4);
+        assert!(storage.exists(&path).await.unwrap());
+    }
+
+    #[tokio::test]
+    async fn read_stream_returns_all_bytes() {
+        let (_dir, storage) = setup();
+        let content = vec![7u8; 64 * 1024 + 13];
+        let path = storage
+            .save_file("logs", Uuid::new_v4(), "big.bin", &content)
+            .await
+            .unwrap();
+
+        let mut stream = storage.read_file_stream(&path).await.unwrap();
+        let mut read = Vec::new();
+        while let Some(chunk) = stream.next().await {
+            read.extend_from_slice(&chunk.unwrap());
+        }
+
+        assert_eq!(read, content);
+    }
+
+    #[tokio::test]
+    async fn read_range_returns_only_requested_bytes() {
+        let (_dir, storage) = setup();
+        let content: Vec<u8> = (0..=255u8).cycle().take(100_000).collect();
+        let path = storage
+            .save_file("logs", Uuid::new_v4(), "big.bin", &content)
+            .await
+            .unwrap();
+
+        for (start, length) in [(0, 1), (70_000, 20_000), (99_990, 10), (99_990, 100)] {
+            let mut stream = storage.read_range_stream(&path, start, length).await.unwrap();
+            let mut read = Vec::new();
+            while let Some(chunk) = stream.next().await {
+                read.extend_from_slice(&chunk.unwrap());
+            }
+
+            let end = (start + length).min(content.len() as u64) as usize;
+            assert_eq!(read, &content[start as usize..end]);
+        }
+    }
+
+    #[tokio::test]
+    async fn copy_creates_independent_object() {
+        let (_dir, storage) = setup();
+        let source = storage.save_file("one", Uuid::new_v4(), "a.txt", b"abc").await.unwrap();
+        let id = Uuid::new_v4();
+
+        let copy = storage.copy_file(&source, "two", id, "b.txt").await.unwrap();
+
+        assert_eq!(copy, format!("two/{}_b.txt", id));
+        storage.delete_file(&source).await.unwrap();
+        assert_eq!(storage.read_file(&copy).await.unwrap(), b"abc");
+        assert!(matches!(
+            storage.copy_file(&source, "two", Uuid::new_v4(), "c.txt").await,
+            Err(StorageError::NotFound(_))
+        ));
+        assert_eq!(storage.list_files("two").await.unwrap(), vec![copy]);
+    }
+
+    #[tokio::test]
+    async fn delete_removes_object() {
+        let (_dir, storage) = setup();
+        let path = storage
+            .save_file("photos", Uuid::new_v4(), "a.txt", b"a")
+            .await
+            .unwrap();
+
+        storage.delete_file(&path).await.unwrap();
+
+        assert!(!storage.exists(&path).await.unwrap());
+        assert!(matches!(
+            storage.delete_file(&path).await,
+            Err(StorageError::NotFound(_))
+        ));
+        assert!(matches!(
+            storage.read_file(&path).await,
+            Err(StorageError::NotFound(_))
+        ));
+    }
+
+    #[tokio::test]
+    async fn list_returns_bucket_objects_only() {
+        let (_dir, storage) = setup();
+        let a = storage.save_file("one", Uuid::new_v4(), "a", b"a").await.unwrap();
+        let b = storage.save_file("one", Uuid::new_v4(), "b", b"b").await.unwrap();
+        storage.save_file("two", Uuid::new_v4(), "c", b"c").await.unwrap();
+
+        let mut expected = vec![a, b];
+        expected.sort();
+
+        assert_eq!(storage.list_files("one").await.unwrap(), expected);
+        assert!(storage.list_files("missing").await.unwrap().is_empty());
+    }
+
+    #[tokio::test]
+    async fn filename_cannot_escape_bucket_directory() {
+        let (dir, storage) = setup();
+        let id = Uuid::new_v4();
+
+        let path = storage
+            .save_file("photos", id, "../../etc/passwd", b"x")
+            .await
+            .unwrap();
+
+        assert!(path.starts_with("photos/"));
+        assert_eq!(path.matches('/').count(), 1);
+        assert!(dir.path().join(&path).is_file());
+    }
+
+    #[tokio::test]
+    async fn rejects_traversal_in_paths_and_bucket_names() {
+        let (_dir, storage) = setup();
+
+        for path in ["../outside", "/etc/passwd", "photos/../../x", ""] {
+            assert!(matches!(
+                storage.read_file(path).await,
+                Err(StorageError::InvalidPath(_))
+            ));
+        }
+
+        assert!(matches!(
+            storage.save_file("..", Uuid::new_v4(), "a", b"a").await,
+            Err(StorageError::InvalidPath(_))
+        ));
+        assert!(matches!(
+            storage.save_file("a/b", Uuid::new_v4(), "a", b"a").await,
+            Err(StorageError::InvalidPath(_))
+        ));
+    }
+
+    #[tokio::test]
+    async fn writer_only_publishes_on_finish() {
+        let (dir, storage) = setup();
+        let mut writer = storage
+            .create_writer("photos", Uuid::new_v4(), "a.txt")
+            .await
+            .unwrap();
+        writer.write_chunk(b"hello ").await.unwrap();
+        writer.write_chunk(b"world").await.unwrap();
+
+        assert!(storage.list_files("photos").await.unwrap().is_empty());
+
+        let path = writer.finish().await.unwrap();
+
+        assert_eq!(storage.read_file(&path).await.unwrap(), b"hello world");
+        assert_eq!(storage.list_files("photos").await.unwrap(), vec![path]);
+        assert_eq!(std::fs::read_dir(dir.path().join("photos")).unwrap().count(), 1);
+    }
+
+    #[tokio::test]
+    async fn dropped_writer_leaves_nothing_behind() {
+        let (dir, storage) = setup();
+        let mut writer = storage
+            .create_writer("photos", Uuid::new_v4(), "a.txt")
+            .await
+            .unwrap();
+        writer.write_chunk(b"partial").await.unwrap();
+
+        drop(writer);
+
+        assert_eq!(std::fs::read_dir(dir.path().join("photos")).unwrap().count(), 0);
+    }
+
+    #[tokio::test]
+    async fn upload_enforces_limit_and_hashes_content() {
+        let (_dir, storage) = setup();
+        let writer = storage
+            .create_writer("photos", Uuid::new_v4(), "a.txt")
+            .await
+            .unwrap();
+        let mut upload = ObjectUpload::new(writer, 8);
+
+        upload.write(b"abc").await.unwrap();
+        assert!(matches!(
+            upload.write(b"defghi").await,
+            Err(StorageError::TooLarge(8))
+        ));
+        upload.write(b"def").await.unwrap();
+
+        let saved = upload.finish().await.unwrap();
+
+        assert_eq!(saved.size, 6);
+        assert_eq!(
+            saved.sha256,
+            "bef57ec7f53a6d40beb640a780a639c83bc29ac8a9816f1fc6c5c6dcd93c4721"
+        );
+        assert_eq!(storage.read_file(&saved.storage_path).await.unwrap(), b"abcdef");
+    }
+
+    #[tokio::test]
+    async fn long_filenames_are_truncated() {
+        let (_dir, storage) = setup();
+        let name = "é".repeat(300);
+
+        let path = storage
+            .save_file("photos", Uuid::new_v4(), &name, b"x")
+            .await
+            .unwrap();
+
+        assert!(path.len() < "photos/".len() + 37 + MAX_NAME_LEN + 1);
+        assert_eq!(storage.read_file(&path).await.unwrap(), b"x");
+    }
+}
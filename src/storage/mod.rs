@@ -0,0 +1,153 @@
+pub mod local;
+
+use async_trait::async_trait;
+use bytes::Bytes;
+use futures::stream::BoxStream;
+use futures::StreamExt;
+use sha2::{Digest, Sha256};
+use thiserror::Error;
+use uuid::Uuid;
+
+// Stream of object bytes handed back by `Storage::read_file_stream`
+pub type ByteStream = BoxStream<'static, Result<Bytes, std::io::Error>>;
+
+#[derive(Debug, Error)]
+pub enum StorageError {
+    #[error("object not found: {0}")]
+    NotFound(String),
+    #[error("invalid storage path: {0}")]
+    InvalidPath(String),
+    #[error("object exceeds the maximum size of {0} bytes")]
+    TooLarge(u64),
+    #[error("storage I/O error: {0}")]
+    Io(#[from] std::io::Error),
+}
+
+pub type StorageResult<T> = Result<T, StorageError>;
+
+// Incremental writer for a new object. Content only becomes visible under its
+// storage path once `finish` succeeds; dropping the writer before that
+// discards everything written so far.
+#[async_trait]
+pub trait ObjectWriter: Send {
+    async fn write_chunk(&mut self, chunk: &[u8]) -> StorageResult<()>;
+
+    // Commit the object and return its storage path
+    async fn finish(self: Box<Self>) -> StorageResult<String>;
+}
+
+// Backend that holds the object bytes. Paths returned by `save_file` and
+// `ObjectWriter::finish` are opaque to callers and are what gets persisted
+// in `files.storage_path`.
+#[allow(dead_code)]
+#[async_trait]
+pub trait Storage {
+    // Start writing a new object
+    async fn create_writer(
+        &self,
+        bucket_name: &str,
+        file_id: Uuid,
+        filename: &str,
+    ) -> StorageResult<Box<dyn ObjectWriter>>;
+
+    // Store the content for a new object and return its storage path
+    async fn save_file(
+        &self,
+        bucket_name: &str,
+        file_id: Uuid,
+        filename: &str,
+        content: &[u8],
+    ) -> StorageResult<String> {
+        let mut writer = self.create_writer(bucket_name, file_id, filename).await?;
+        writer.write_chunk(content).await?;
+        writer.finish().await
+    }
+
+    // Read the whole object into memory
+    async fn read_file(&self, path: &str) -> StorageResult<Vec<u8>>;
+
+    // Read the object as a stream of chunks
+    async fn read_file_stream(&self, path: &str) -> StorageResult<ByteStream>;
+
+    // Read `length` bytes of the object starting at byte `start`, without
+    // touching the rest of it
+    async fn read_range_stream(&self, path: &str, start: u64, length: u64) -> StorageResult<ByteStream>;
+
+    // Store a copy of an existing object as a new object and return the
+    // copy's storage path. The bytes never leave the backend.
+    async fn copy_file(
+        &self,
+        path: &str,
+        bucket_name: &str,
+        file_id: Uuid,
+        filename: &str,
+    ) -> StorageResult<String> {
+        let mut stream = self.read_file_stream(path).await?;
+        let mut writer = self.create_writer(bucket_name, file_id, filename).await?;
+        while let Some(chunk) = stream.next().await {
+            writer.write_chunk(&chunk?).await?;
+        }
+        writer.finish().await
+    }
+
+    async fn delete_file(&self, path: &str) -> StorageResult<()>;
+
+    async fn exists(&self, path: &str) -> StorageResult<bool>;
+
+    // Size of the stored object in bytes
+    async fn file_size(&self, path: &str) -> StorageResult<u64>;
+
+    // Storage paths of every object held for a bucket
+    async fn list_files(&self, bucket_name: &str) -> StorageResult<Vec<String>>;
+}
+
+// Result of a completed `ObjectUpload`
+#[derive(Debug)]
+pub struct SavedObject {
+    pub storage_path: String,
+    pub size: u64,
+    pub sha256: String,
+}
+
+// Streams an object into an `ObjectWriter`, enforcing a size limit and
+// computing the size and SHA-256 of the content as chunks arrive
+pub struct ObjectUpload {
+    writer: Box<dyn ObjectWriter>,
+    hasher: Sha256,
+    size: u64,
+    max_size: u64,
+}
+
+impl ObjectUpload {
+    pub fn new(writer: Box<dyn ObjectWriter>, max_size: u64) -> Self {
+        Self {
+            writer,
+            hasher: Sha256::new(),
+            size: 0,
+            max_size,
+        }
+    }
+
+    pub async fn write(&mut self, chunk: &[u8]) -> StorageResult<()> {
+        let size = self.size + chunk.len() as u64;
+        if size > self.max_size {
+            return Err(StorageError::TooLarge(self.max_size));
+        }
+
+        self.writer.write_chunk(chunk).await?;
+        self.hasher.update(chunk);
+        self.size = size;
+
+        Ok(())
+    }
+
+    pub async fn finish(self) -> StorageResult<SavedObject> {
+        let storage_path = self.writer.finish().await?;
+
+        Ok(SavedObject {
+            storage_path,
+            size: self.size,
+            sha256: hex::encode(self.hasher.finalize()),
+        })
+    }
+}